@@ -0,0 +1,99 @@
+extern crate rust_calculus;
+
+use std::collections::HashMap;
+use std::time::Instant;
+
+use rust_calculus::expression::batch;
+use rust_calculus::expression::bytecode;
+use rust_calculus::expression::eval::eval_postfix_expr;
+use rust_calculus::expression::jit;
+use rust_calculus::expression::parallel;
+use rust_calculus::expression::parse::{self, parse_input};
+
+const POINTS: usize = 200_000;
+
+const EXPRESSIONS: &[&str] = &["x*x+3*x+2",
+                               "sin(x)*exp(x/10)+sqrt(abs(x))",
+                               "x^3-2*x*y+y^2/(1+x*x)",
+                               "logbase(abs(x)+2,2)*cos(y)+tanh(x*y)"];
+
+fn main() {
+    let (numeric_regex, function_regex) = parse::token_regexes();
+    let xs: Vec<f64> = (0..POINTS).map(|i| i as f64 / POINTS as f64 * 20.0 - 10.0).collect();
+    let ys: Vec<f64> = (0..POINTS).map(|i| (i % 97) as f64 / 10.0).collect();
+    let vars = vec!["x".to_owned(), "y".to_owned()];
+
+    println!("{} points per expression, ns per point", POINTS);
+    println!("{:<40} {:>12} {:>12} {:>12} {:>12} {:>12}",
+             "expression",
+             "postfix",
+             "bytecode",
+             "batch",
+             "parallel",
+             "jit");
+    for input in EXPRESSIONS {
+        let expr = parse_input(input, &numeric_regex, &function_regex).1.unwrap();
+        let program = bytecode::compile::<f64>(&expr, &vars).unwrap();
+
+        let start = Instant::now();
+        let mut map: HashMap<String, f64> = HashMap::new();
+        let mut postfix: Vec<f64> = Vec::with_capacity(POINTS);
+        for i in 0..POINTS {
+            map.insert("x".to_owned(), xs[i]);
+            map.insert("y".to_owned(), ys[i]);
+            postfix.push(eval_postfix_expr(&expr, &map));
+        }
+        let postfix_time = start.elapsed();
+
+        let start = Instant::now();
+        let mut machine = bytecode::Machine::new(&program);
+        let mut compiled: Vec<f64> = Vec::with_capacity(POINTS);
+        for i in 0..POINTS {
+            compiled.push(machine.run(&program, &[xs[i], ys[i]]));
+        }
+        let compiled_time = start.elapsed();
+
+        let start = Instant::now();
+        let batched = batch::eval_batch(&program, &[&xs, &ys]).unwrap();
+        let batch_time = start.elapsed();
+
+        let start = Instant::now();
+        let threaded = parallel::eval_parallel(&program, &[&xs, &ys], 0).unwrap();
+        let parallel_time = start.elapsed();
+
+        let mut native = jit::compile(&expr, &vars).unwrap();
+        let start = Instant::now();
+        let mut jitted: Vec<f64> = Vec::with_capacity(POINTS);
+        for i in 0..POINTS {
+            jitted.push(native.call(&[xs[i], ys[i]]));
+        }
+        let jit_time = start.elapsed();
+
+        for i in 0..POINTS {
+            assert!(same(postfix[i], compiled[i]) && same(postfix[i], batched[i]) &&
+                    same(postfix[i], threaded[i]) && close(postfix[i], jitted[i]),
+                    "{} disagrees at x = {}, y = {}",
+                    input,
+                    xs[i],
+                    ys[i]);
+        }
+
+        let per_point = |d: std::time::Duration| d.as_secs_f64() * 1e9 / POINTS as f64;
+        println!("{:<40} {:>12.1} {:>12.1} {:>12.1} {:>12.1} {:>12.1}",
+                 input,
+                 per_point(postfix_time),
+                 per_point(compiled_time),
+                 per_point(batch_time),
+                 per_point(parallel_time),
+                 per_point(jit_time));
+    }
+}
+
+fn same(a: f64, b: f64) -> bool {
+    a == b || (a.is_nan() && b.is_nan())
+}
+
+// Native code may fuse or reorder float operations.
+fn close(a: f64, b: f64) -> bool {
+    same(a, b) || (a - b).abs() <= 1e-12 * a.abs().max(1.0)
+}
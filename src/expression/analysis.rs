@@ -0,0 +1,634 @@
+use expression;
+use expression::bytecode::{self, Bound};
+use expression::derivative;
+use expression::limit::{self, Direction, LimitValue, Point};
+use expression::optimize;
+use expression::solve;
+use expression::symbolic;
+
+pub type Expression = expression::Expression;
+
+use std::collections::HashMap;
+use std::f64;
+use std::fmt;
+
+const SAMPLES: usize = 2000;
+// Points closer than this fraction of the interval are the same point.
+const SAME: f64 = 1e-9;
+// The k-th derivative counts as zero below this times the typical size of f
+// over width^k.
+const ZERO: f64 = 1e-10;
+// Signs are compared this fraction of the interval to either side.
+const STEP: f64 = 1e-6;
+// Each step closer to a pole must grow |f| by at least this factor.
+const GROWTH: f64 = 5.0;
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum Kind {
+    Zero,
+    LocalMinimum,
+    LocalMaximum,
+    // A critical point that is not an extremum.
+    Critical,
+    Inflection,
+    VerticalAsymptote,
+    // A single undefined point with a finite limit, as sin(x)/x at 0.
+    Hole,
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Kind::Zero => write!(f, "zero"),
+            Kind::LocalMinimum => write!(f, "local minimum"),
+            Kind::LocalMaximum => write!(f, "local maximum"),
+            Kind::Critical => write!(f, "critical point"),
+            Kind::Inflection => write!(f, "inflection point"),
+            Kind::VerticalAsymptote => write!(f, "vertical asymptote"),
+            Kind::Hole => write!(f, "hole"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Feature {
+    pub x: f64,
+    pub value: f64,
+    pub slope: f64,
+    pub curvature: f64,
+    pub kinds: Vec<Kind>,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Asymptote {
+    Vertical(f64),
+    // y = c as x goes to the given end.
+    Horizontal(f64, Point),
+    // y = m x + c.
+    Oblique(f64, f64, Point),
+}
+
+// Open intervals (p, q).
+pub type Intervals = Vec<(f64, f64)>;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Analysis {
+    pub derivative: Expression,
+    pub second_derivative: Expression,
+    // Sorted by x.
+    pub features: Vec<Feature>,
+    pub asymptotes: Vec<Asymptote>,
+    pub increasing: Intervals,
+    pub decreasing: Intervals,
+    pub concave_up: Intervals,
+    pub concave_down: Intervals,
+    pub undefined: Intervals,
+    // Absolute extrema on [a, b] as (x, f(x)), when f is bounded there.
+    pub minimum: Option<(f64, f64)>,
+    pub maximum: Option<(f64, f64)>,
+}
+
+// f, f' and f'' bound to one variable, with the interval and the size below
+// which each counts as zero.
+struct Curve {
+    derivatives: [Bound; 3],
+    noise: [f64; 3],
+    a: f64,
+    b: f64,
+}
+
+impl Curve {
+    fn eval(&mut self, order: usize, x: f64) -> f64 {
+        self.derivatives[order].eval1(x)
+    }
+
+    fn is_zero(&self, order: usize, y: f64) -> bool {
+        y.abs() <= self.noise[order]
+    }
+
+    fn same(&self, x: f64, y: f64) -> bool {
+        (x - y).abs() <= SAME * (self.b - self.a)
+    }
+
+    // The roots across which the derivative of this order changes sign, the
+    // same way a short and a longer step to either side so that rounding
+    // noise where it vanishes identically does not count. With `edge` a root
+    // at the end of the domain, such as sqrt(x) at 0, is kept as well.
+    fn crossings(&mut self, order: usize, roots: &[solve::Root], edge: bool) -> Vec<f64> {
+        let width = self.b - self.a;
+        let mut found = Vec::new();
+        for root in roots {
+            let mut signs = Vec::new();
+            let mut large = false;
+            for &step in &[STEP, 1000.0 * STEP] {
+                let left = self.eval(order, root.x - step * width);
+                let right = self.eval(order, root.x + step * width);
+                signs.push((left.signum(), right.signum(), left.is_nan() || right.is_nan()));
+                large = !self.is_zero(order, left) && !self.is_zero(order, right);
+            }
+            let crossing = signs[0].0 * signs[0].1 < 0.0 && signs[0] == signs[1] && large;
+            if crossing || edge && signs[0].2 {
+                found.push(self.settle(order, root.x));
+            }
+        }
+        found
+    }
+
+    // A root moved onto a nearby fraction when that is no worse.
+    fn settle(&mut self, order: usize, x: f64) -> f64 {
+        let tidied = tidy(x, self.b - self.a);
+        if self.eval(order, tidied).abs() <= self.eval(order, x).abs() { tidied } else { x }
+    }
+
+    // The pole of f between two samples: where 1/f changes sign, or 1/f'
+    // for a pole of even order, else the largest |f| found by Brent's method.
+    fn locate_pole(&mut self, low: f64, high: f64) -> f64 {
+        let width = self.b - self.a;
+        for order in 0..2 {
+            let g_low = 1.0 / self.eval(order, low);
+            if g_low * (1.0 / self.eval(order, high)) >= 0.0 {
+                continue;
+            }
+            let (mut low, mut high) = (low, high);
+            for _ in 0..100 {
+                let middle = 0.5 * (low + high);
+                if middle <= low || middle >= high {
+                    break;
+                }
+                if g_low * (1.0 / self.eval(order, middle)) > 0.0 {
+                    low = middle;
+                } else {
+                    high = middle;
+                }
+            }
+            return tidy(0.5 * (low + high), width);
+        }
+        let f = &mut self.derivatives[0];
+        let peak = optimize::brent(&mut |x| {
+                                       let y = f.eval1(x);
+                                       if y.is_nan() { f64::INFINITY } else { -y.abs() }
+                                   },
+                                   low,
+                                   high);
+        tidy(peak.x[0], width)
+    }
+
+    // A pole when |f| keeps growing on the way in from either side, or
+    // failing that when a one-sided limit is infinite.
+    fn is_pole(&mut self,
+               expr: &Expression,
+               var: &str,
+               x: f64,
+               values: &HashMap<String, f64>)
+               -> bool {
+        let width = self.b - self.a;
+        for side in &[-1.0, 1.0] {
+            let sizes: Vec<f64> = [1e-5, 1e-7, 1e-9]
+                .iter()
+                .map(|h| self.eval(0, x + side * h * width).abs())
+                .collect();
+            if sizes.iter().all(|s| s.is_finite()) && sizes[1] >= GROWTH * sizes[0] &&
+               sizes[2] >= GROWTH * sizes[1] {
+                return true;
+            }
+        }
+        [-1.0, 1.0].iter().any(|&side| {
+            matches!(limit::one_sided(expr, var, x, side, values),
+                     LimitValue::PosInf | LimitValue::NegInf)
+        })
+    }
+
+    // Second derivative test, or when it is inconclusive a comparison with
+    // the values on either side.
+    fn classify(&mut self, x: f64) -> Kind {
+        let (fx, slope, curvature) = (self.eval(0, x), self.eval(1, x), self.eval(2, x));
+        if self.is_zero(1, slope) && curvature.is_finite() && !self.is_zero(2, curvature) {
+            return if curvature > 0.0 { Kind::LocalMinimum } else { Kind::LocalMaximum };
+        }
+        let step = STEP * (self.b - self.a);
+        if x - step < self.a || x + step > self.b {
+            return Kind::Critical;
+        }
+        let (left, right) = (self.eval(0, x - step), self.eval(0, x + step));
+        if left > fx && right > fx {
+            Kind::LocalMinimum
+        } else if left < fx && right < fx {
+            Kind::LocalMaximum
+        } else {
+            Kind::Critical
+        }
+    }
+
+    // The sign chart of the derivative of this order between consecutive
+    // breaks, skipping stretches where f is undefined. Each interval is
+    // sampled off centre, since the middle of [-1, 1] is where x^2 vanishes. Neighbouring intervals
+    // of the same sign are joined unless a barrier separates them.
+    fn chart(&mut self,
+             order: usize,
+             breaks: &[f64],
+             barriers: &[f64])
+             -> (Intervals, Intervals) {
+        let mut positive: Intervals = Vec::new();
+        let mut negative: Intervals = Vec::new();
+        // The sign of the interval before, if it ended where this one starts.
+        let mut previous: Option<bool> = None;
+        for pair in breaks.windows(2) {
+            let (p, q) = (pair[0], pair[1]);
+            let middle = p + 0.4142135623730951 * (q - p);
+            let y = self.eval(order, middle);
+            if self.eval(0, middle).is_nan() || y.is_nan() || self.is_zero(order, y) {
+                previous = None;
+                continue;
+            }
+            let up = y > 0.0;
+            let intervals = if up { &mut positive } else { &mut negative };
+            if previous == Some(up) && !barriers.contains(&p) {
+                intervals.last_mut().unwrap().1 = q;
+            } else {
+                intervals.push((p, q));
+            }
+            previous = Some(up);
+        }
+        (positive, negative)
+    }
+}
+
+// The median of the finite nonzero |y|, or 1 when there are none.
+fn typical(ys: &[f64]) -> f64 {
+    let mut sizes: Vec<f64> =
+        ys.iter().filter(|y| y.is_finite() && **y != 0.0).map(|y| y.abs()).collect();
+    if sizes.is_empty() {
+        return 1.0;
+    }
+    sizes.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    sizes[sizes.len() / 2]
+}
+
+// x rounded to a nearby fraction with a small denominator, so refined poles
+// and ends of the domain land on 1/2 rather than 0.49999999999.
+fn tidy(x: f64, width: f64) -> f64 {
+    let tolerance = 1e-10 * width.max(x.abs());
+    for denominator in 1..1000 {
+        let candidate = (x * denominator as f64).round() / denominator as f64;
+        if (candidate - x).abs() <= tolerance {
+            // No -0 in the output.
+            return candidate + 0.0;
+        }
+    }
+    x
+}
+
+// The horizontal or oblique asymptote as x goes to `end`, if any.
+fn end_behaviour(expr: &Expression,
+                 var: &str,
+                 end: Point,
+                 values: &HashMap<String, f64>)
+                 -> Option<Asymptote> {
+    match limit::limit(expr, var, end, Direction::Both, values) {
+        LimitValue::Finite(c) => Some(Asymptote::Horizontal(c, end)),
+        LimitValue::PosInf | LimitValue::NegInf => {
+            let x = symbolic::variable(var);
+            let ratio = symbolic::div(expr.clone(), x.clone());
+            let m = match limit::limit(&ratio, var, end, Direction::Both, values) {
+                LimitValue::Finite(m) if m != 0.0 => m,
+                _ => return None,
+            };
+            let rest = symbolic::sub(expr.clone(), symbolic::mul(symbolic::number(m), x));
+            match limit::limit(&rest, var, end, Direction::Both, values) {
+                LimitValue::Finite(c) => Some(Asymptote::Oblique(m, c, end)),
+                _ => None,
+            }
+        }
+        LimitValue::Undefined => None,
+    }
+}
+
+// Zeros, critical points, inflection points, poles and the sign charts of f'
+// and f'' for `expr` on [a, b], with horizontal and oblique asymptotes from
+// the limits at infinity. Critical points are the roots of f', together with
+// roots of f'' where f' vanishes so that x^3 at 0 is found, and points where
+// f' is undefined but f is not.
+pub fn analyze(expr: &Expression,
+               var: &str,
+               a: f64,
+               b: f64,
+               values: &HashMap<String, f64>)
+               -> Result<Analysis, String> {
+    let valid = a.is_finite() && b.is_finite() && a < b;
+    if !valid {
+        return Err(format!("Expected an interval with a < b but got [{}, {}]", a, b));
+    }
+    let free = [var.to_owned()];
+    let first = derivative::differentiate(expr, var);
+    let second = derivative::differentiate(&first, var);
+    let width = b - a;
+    let xs: Vec<f64> = (0..SAMPLES + 1).map(|i| a + width * i as f64 / SAMPLES as f64).collect();
+    let mut f = bytecode::bind(expr, &free, values)?;
+    let ys: Vec<f64> = xs.iter().map(|&x| f.eval1(x)).collect();
+    let scale = typical(&ys);
+    let mut curve = Curve {
+        derivatives: [f,
+                      bytecode::bind(&first, &free, values)?,
+                      bytecode::bind(&second, &free, values)?],
+        noise: [ZERO * scale, ZERO * scale / width, ZERO * scale / (width * width)],
+        a,
+        b,
+    };
+
+    // Poles: infinite samples, and local maxima of |f| that pass the pole
+    // test once located.
+    let mut poles: Vec<f64> = Vec::new();
+    for i in 0..SAMPLES + 1 {
+        let candidate = if ys[i].is_infinite() {
+            xs[i]
+        } else if i > 0 && i < SAMPLES && ys[i].is_finite() && ys[i].abs() > ys[i - 1].abs() &&
+                  ys[i].abs() >= ys[i + 1].abs() {
+            curve.locate_pole(xs[i - 1], xs[i + 1])
+        } else {
+            continue;
+        };
+        if !poles.iter().any(|&p| curve.same(p, candidate)) &&
+           curve.is_pole(expr, var, candidate, values) {
+            poles.push(candidate);
+        }
+    }
+
+    // Where f is undefined: runs of NaN samples, with ends found by
+    // bisection. A single undefined sample with a finite limit is a hole.
+    let mut undefined: Intervals = Vec::new();
+    let mut holes: Vec<(f64, f64)> = Vec::new();
+    let mut edges: Vec<f64> = Vec::new();
+    let mut i = 0;
+    while i <= SAMPLES {
+        if !ys[i].is_nan() {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i <= SAMPLES && ys[i].is_nan() {
+            i += 1;
+        }
+        if i - start == 1 && start > 0 && i <= SAMPLES {
+            let x = tidy(xs[start], width);
+            if let LimitValue::Finite(y) =
+                limit::limit(expr, var, Point::Finite(x), Direction::Both, values) {
+                holes.push((x, y));
+                continue;
+            }
+        }
+        let mut edge = |defined: f64, undefined: f64| {
+            let (mut inside, mut outside) = (defined, undefined);
+            for _ in 0..100 {
+                let middle = 0.5 * (inside + outside);
+                if middle == inside || middle == outside {
+                    break;
+                }
+                if curve.eval(0, middle).is_nan() {
+                    outside = middle;
+                } else {
+                    inside = middle;
+                }
+            }
+            tidy(inside, width)
+        };
+        let low = if start == 0 { a } else { edge(xs[start - 1], xs[start]) };
+        let high = if i > SAMPLES { b } else { edge(xs[i], xs[i - 1]) };
+        undefined.push((low, high));
+        edges.extend(&[low, high]);
+    }
+
+    let singular: Vec<f64> = poles.iter().chain(holes.iter().map(|h| &h.0)).cloned().collect();
+    let defined = |curve: &mut Curve, x: &f64| {
+        curve.eval(0, *x).is_finite() && !singular.iter().any(|&p| curve.same(p, *x))
+    };
+    let roots = solve::roots(expr, var, a, b, values)?;
+    let zeros: Vec<f64> =
+        curve.crossings(0, &roots, true).into_iter().filter(|x| defined(&mut curve, x)).collect();
+    let roots = solve::roots(&second, var, a, b, values)?;
+    let inflections: Vec<f64> = curve.crossings(2, &roots, false)
+        .into_iter()
+        .filter(|x| defined(&mut curve, x) && !curve.same(*x, a) && !curve.same(*x, b))
+        .collect();
+    let roots = solve::roots(&first, var, a, b, values)?;
+    let mut critical: Vec<f64> =
+        curve.crossings(1, &roots, true).into_iter().filter(|x| defined(&mut curve, x)).collect();
+    for &x in &inflections {
+        let slope = curve.eval(1, x);
+        if curve.is_zero(1, slope) {
+            critical.push(x);
+        }
+    }
+    for i in 1..SAMPLES {
+        let slopes: Vec<f64> =
+            [xs[i - 1], xs[i], xs[i + 1]].iter().map(|&x| curve.eval(1, x)).collect();
+        let x = tidy(xs[i], width);
+        if !slopes[1].is_finite() && slopes[0].is_finite() && slopes[2].is_finite() &&
+           defined(&mut curve, &x) {
+            critical.push(x);
+        }
+    }
+
+    let mut kinds: Vec<(f64, Kind)> = Vec::new();
+    for &x in &zeros {
+        kinds.push((x, Kind::Zero));
+    }
+    for &x in &critical {
+        let value = curve.eval(0, x);
+        if curve.is_zero(0, value) {
+            kinds.push((x, Kind::Zero));
+        }
+        kinds.push((x, curve.classify(x)));
+    }
+    for &x in &inflections {
+        kinds.push((x, Kind::Inflection));
+    }
+    for &x in &poles {
+        kinds.push((x, Kind::VerticalAsymptote));
+    }
+    for &(x, _) in &holes {
+        kinds.push((x, Kind::Hole));
+    }
+    kinds.sort_by(|p, q| p.0.partial_cmp(&q.0).unwrap());
+    let mut features: Vec<Feature> = Vec::new();
+    for (x, kind) in kinds {
+        if let Some(last) = features.last_mut() {
+            if curve.same(last.x, x) {
+                if !last.kinds.contains(&kind) {
+                    last.kinds.push(kind);
+                }
+                continue;
+            }
+        }
+        features.push(Feature {
+            x,
+            value: 0.0,
+            slope: 0.0,
+            curvature: 0.0,
+            kinds: vec![kind],
+        });
+    }
+    for feature in &mut features {
+        feature.kinds.sort();
+        let x = feature.x;
+        let kinds = feature.kinds.clone();
+        let has = |kind: Kind| kinds.contains(&kind);
+        if has(Kind::VerticalAsymptote) || has(Kind::Hole) {
+            feature.value = holes.iter().find(|h| h.0 == x).map_or(f64::NAN, |h| h.1);
+            feature.slope = f64::NAN;
+            feature.curvature = f64::NAN;
+            continue;
+        }
+        // Values known to vanish print as 0 rather than rounding noise.
+        let (value, slope, curvature) = (curve.eval(0, x), curve.eval(1, x), curve.eval(2, x));
+        feature.value = if has(Kind::Zero) { 0.0 } else { value };
+        let stationary = has(Kind::LocalMinimum) || has(Kind::LocalMaximum) ||
+                         has(Kind::Critical);
+        feature.slope = if stationary && curve.is_zero(1, slope) {
+            0.0
+        } else if stationary {
+            // A corner, where f' jumps.
+            f64::NAN
+        } else {
+            slope
+        };
+        feature.curvature = if curve.is_zero(2, curvature) { 0.0 } else { curvature };
+    }
+
+    // Sign charts, broken at every feature and never joined across a pole,
+    // a hole or an end of the domain.
+    let mut barriers: Vec<f64> = singular.clone();
+    barriers.extend(&edges);
+    let breaks = |points: &[f64]| {
+        let mut breaks: Vec<f64> = vec![a, b];
+        breaks.extend(points);
+        breaks.extend(&barriers);
+        breaks.sort_by(|p, q| p.partial_cmp(q).unwrap());
+        breaks.dedup_by(|p, q| (*p - *q).abs() <= SAME * width);
+        breaks
+    };
+    let (increasing, decreasing) = curve.chart(1, &breaks(&critical), &barriers);
+    let (concave_up, concave_down) = curve.chart(2, &breaks(&inflections), &barriers);
+
+    let mut asymptotes: Vec<Asymptote> = poles.iter().map(|&x| Asymptote::Vertical(x)).collect();
+    for &end in &[Point::NegInf, Point::PosInf] {
+        if let Some(asymptote) = end_behaviour(expr, var, end, values) {
+            asymptotes.push(asymptote);
+        }
+    }
+
+    // Absolute extrema from the ends and the critical points, when f is
+    // defined and bounded on all of [a, b].
+    let (mut minimum, mut maximum) = (None, None);
+    if poles.is_empty() && undefined.is_empty() {
+        let mut candidates: Vec<(f64, f64)> = vec![(a, ys[0]), (b, ys[SAMPLES])];
+        candidates.extend(features.iter()
+            .filter(|f| {
+                f.kinds.iter().any(|&k| {
+                    k == Kind::LocalMinimum || k == Kind::LocalMaximum || k == Kind::Critical
+                })
+            })
+            .map(|f| (f.x, f.value)));
+        if candidates.iter().all(|c| c.1.is_finite()) {
+            minimum = candidates.iter().cloned().fold(None, |best: Option<(f64, f64)>, c| {
+                match best {
+                    Some(best) if best.1 <= c.1 => Some(best),
+                    _ => Some(c),
+                }
+            });
+            maximum = candidates.iter().cloned().fold(None, |best: Option<(f64, f64)>, c| {
+                match best {
+                    Some(best) if best.1 >= c.1 => Some(best),
+                    _ => Some(c),
+                }
+            });
+        }
+    }
+
+    Ok(Analysis {
+        derivative: first,
+        second_derivative: second,
+        features,
+        asymptotes,
+        increasing,
+        decreasing,
+        concave_up,
+        concave_down,
+        undefined,
+        minimum,
+        maximum,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use expression::parse::parse;
+
+    fn analysis(expr: &str, a: f64, b: f64) -> Analysis {
+        analyze(&parse(expr), "x", a, b, &HashMap::new()).unwrap()
+    }
+
+    fn kinds_at(analysis: &Analysis, x: f64) -> Vec<Kind> {
+        let feature = analysis.features.iter().find(|f| (f.x - x).abs() <= 1e-7);
+        feature.map_or(Vec::new(), |f| f.kinds.clone())
+    }
+
+    #[test]
+    fn rational_functions() {
+        let found = analysis("(x^2-1)/(x-2)", -5.0, 5.0);
+        let (low, high) = (2.0 - 3f64.sqrt(), 2.0 + 3f64.sqrt());
+        assert_eq!(kinds_at(&found, -1.0), vec![Kind::Zero]);
+        assert_eq!(kinds_at(&found, 1.0), vec![Kind::Zero]);
+        assert_eq!(kinds_at(&found, low), vec![Kind::LocalMaximum]);
+        assert_eq!(kinds_at(&found, high), vec![Kind::LocalMinimum]);
+        assert_eq!(kinds_at(&found, 2.0), vec![Kind::VerticalAsymptote]);
+        assert_eq!(found.features.len(), 5);
+        assert_eq!(found.increasing.len(), 2);
+        // Sign charts are never joined across the pole.
+        assert_eq!((found.decreasing[0].1, found.decreasing[1].0), (2.0, 2.0));
+        assert_eq!(found.asymptotes[0], Asymptote::Vertical(2.0));
+        for (asymptote, end) in found.asymptotes[1..].iter().zip(&[Point::NegInf, Point::PosInf]) {
+            match *asymptote {
+                Asymptote::Oblique(m, c, at) if at == *end => {
+                    assert!((m - 1.0).abs() <= 1e-12 && (c - 2.0).abs() <= 1e-12);
+                }
+                ref other => panic!("expected y = x + 2 but got {:?}", other),
+            }
+        }
+        assert_eq!(found.minimum, None);
+        assert_eq!(found.maximum, None);
+    }
+
+    #[test]
+    fn flat_critical_points_and_extrema_on_the_interval() {
+        let found = analysis("x^3", -1.0, 2.0);
+        assert_eq!(kinds_at(&found, 0.0), vec![Kind::Zero, Kind::Critical, Kind::Inflection]);
+        assert_eq!(found.features.len(), 1);
+        // f' keeps its sign through 0, so one interval.
+        assert_eq!(found.increasing, vec![(-1.0, 2.0)]);
+        assert_eq!(found.concave_down, vec![(-1.0, 0.0)]);
+        assert_eq!(found.concave_up, vec![(0.0, 2.0)]);
+        assert_eq!(found.minimum, Some((-1.0, -1.0)));
+        assert_eq!(found.maximum, Some((2.0, 8.0)));
+    }
+
+    #[test]
+    fn holes_and_undefined_intervals() {
+        let found = analysis("sin(x)/x", -1.0, 1.0);
+        let hole = found.features.iter().find(|f| f.kinds.contains(&Kind::Hole)).unwrap();
+        assert_eq!((hole.x, hole.value), (0.0, 1.0));
+        assert!(found.asymptotes.contains(&Asymptote::Horizontal(0.0, Point::PosInf)));
+        let found = analysis("sqrt(x)*ln(x)", -1.0, 3.0);
+        assert_eq!(found.undefined, vec![(-1.0, 0.0)]);
+        assert_eq!(kinds_at(&found, (-2f64).exp()), vec![Kind::LocalMinimum]);
+        assert_eq!(kinds_at(&found, 1.0), vec![Kind::Zero, Kind::Inflection]);
+        assert_eq!(found.minimum, None);
+    }
+
+    #[test]
+    fn intervals_must_be_ordered() {
+        let values = HashMap::new();
+        assert!(analyze(&parse("x"), "x", 1.0, -1.0, &values).is_err());
+        assert!(analyze(&parse("x"), "x", 0.0, f64::INFINITY, &values).is_err());
+    }
+}
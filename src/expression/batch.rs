@@ -0,0 +1,175 @@
+use expression::bytecode::{Instruction, Program};
+use expression::eval;
+use expression::enums;
+
+// Points are processed in blocks of this many lanes so the column stack stays
+// small and in cache however many points are evaluated.
+const BLOCK: usize = 256;
+
+// Evaluates `program` once per row. `columns` is indexed by slot (see
+// Program::slots) and every column must either hold one value per row or a
+// single value that is used for every row.
+pub fn eval_batch(program: &Program, columns: &[&[f64]]) -> Result<Vec<f64>, String> {
+    let rows = batch_len(program, columns)?;
+    let mut out: Vec<f64> = vec![0.0; rows];
+    BatchMachine::new(program).run(program, columns, &mut out);
+    Ok(out)
+}
+
+pub fn batch_len(program: &Program, columns: &[&[f64]]) -> Result<usize, String> {
+    if columns.len() != program.slots().len() {
+        return Err(format!("Expected {} columns ({}) but got {}",
+                           program.slots().len(),
+                           program.slots().join(", "),
+                           columns.len()));
+    }
+    let rows = columns.iter().map(|c| c.len()).max().unwrap_or(1);
+    for (column, name) in columns.iter().zip(program.slots()) {
+        if column.len() != rows && column.len() != 1 {
+            return Err(format!("Column {} has {} values, expected {} or 1",
+                               name,
+                               column.len(),
+                               rows));
+        }
+    }
+    Ok(rows)
+}
+
+// Column-wise counterpart of bytecode::Machine: each stack entry is a block of
+// lanes and every instruction is applied across the whole block at once.
+#[derive(Debug, Clone)]
+pub struct BatchMachine {
+    stack: Vec<f64>,
+}
+
+impl BatchMachine {
+    pub fn new(program: &Program) -> Self {
+        BatchMachine { stack: vec![0.0; program.stack_size() * BLOCK] }
+    }
+
+    // `columns` must already have been checked with batch_len, `out` holds one
+    // value per row.
+    pub fn run(&mut self, program: &Program, columns: &[&[f64]], out: &mut [f64]) {
+        if self.stack.len() < program.stack_size() * BLOCK {
+            self.stack.resize(program.stack_size() * BLOCK, 0.0);
+        }
+        let mut start: usize = 0;
+        while start < out.len() {
+            let lanes = BLOCK.min(out.len() - start);
+            let result = self.run_block(program, columns, start, lanes);
+            out[start..start + lanes].copy_from_slice(result);
+            start += lanes;
+        }
+    }
+
+    fn run_block(&mut self,
+                 program: &Program,
+                 columns: &[&[f64]],
+                 start: usize,
+                 lanes: usize)
+                 -> &[f64] {
+        let mut top: usize = 0;
+        for instruction in program.code() {
+            match *instruction {
+                Instruction::Push(x) => {
+                    row(&mut self.stack, top, lanes).fill(x);
+                    top += 1;
+                }
+                Instruction::Load(slot) => {
+                    let column = columns[slot];
+                    let dest = row(&mut self.stack, top, lanes);
+                    if column.len() == 1 {
+                        dest.fill(column[0]);
+                    } else {
+                        dest.copy_from_slice(&column[start..start + lanes]);
+                    }
+                    top += 1;
+                }
+                Instruction::Negate => map(row(&mut self.stack, top - 1, lanes), |x| -x),
+                Instruction::Binary(op) => {
+                    let (lhs, rhs) = rows(&mut self.stack, top - 2, lanes);
+                    match op {
+                        enums::Operator::Add => zip(lhs, rhs, |x, y| x + y),
+                        enums::Operator::Sub => zip(lhs, rhs, |x, y| x - y),
+                        enums::Operator::Mul => zip(lhs, rhs, |x, y| x * y),
+                        enums::Operator::Div => zip(lhs, rhs, |x, y| x / y),
+                        enums::Operator::Mod => zip(lhs, rhs, |x, y| x % y),
+                        enums::Operator::Pow => zip(lhs, rhs, f64::powf),
+                        enums::Operator::Negate => zip(lhs, rhs, |_, y| -y),
+                    }
+                    top -= 1;
+                }
+                Instruction::Func(f) => {
+                    map(row(&mut self.stack, top - 1, lanes),
+                        |x| eval::eval_function(&f, x))
+                }
+                Instruction::BinaryFunc(f) => {
+                    let (lhs, rhs) = rows(&mut self.stack, top - 2, lanes);
+                    zip(lhs, rhs, |x, y| eval::eval_binary_function(&f, x, y));
+                    top -= 1;
+                }
+            }
+        }
+        &self.stack[..lanes]
+    }
+}
+
+fn row(stack: &mut [f64], index: usize, lanes: usize) -> &mut [f64] {
+    &mut stack[index * BLOCK..index * BLOCK + lanes]
+}
+
+// Row `index` and the row above it.
+fn rows(stack: &mut [f64], index: usize, lanes: usize) -> (&mut [f64], &[f64]) {
+    let (lower, upper) = stack.split_at_mut((index + 1) * BLOCK);
+    (&mut lower[index * BLOCK..index * BLOCK + lanes], &upper[..lanes])
+}
+
+fn map<F: Fn(f64) -> f64>(dest: &mut [f64], f: F) {
+    for x in dest.iter_mut() {
+        *x = f(*x);
+    }
+}
+
+fn zip<F: Fn(f64, f64) -> f64>(dest: &mut [f64], other: &[f64], f: F) {
+    for (x, y) in dest.iter_mut().zip(other) {
+        *x = f(*x, *y);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use expression::bytecode::compile;
+    use expression::parse::parse;
+
+    #[test]
+    fn batches_match_single_evaluation() {
+        let vars = ["x".to_owned(), "y".to_owned()];
+        let program = compile::<f64>(&parse("sin(x)*y+x^2/(1+y*y)"), &vars).unwrap();
+        // More rows than one block, and not a multiple of it.
+        let xs: Vec<f64> = (0..1000).map(|i| i as f64 / 100.0 - 5.0).collect();
+        let ys: Vec<f64> = (0..1000).map(|i| (i % 7) as f64 - 3.0).collect();
+        let out = eval_batch(&program, &[&xs, &ys]).unwrap();
+        assert_eq!(out.len(), xs.len());
+        for i in 0..xs.len() {
+            assert_eq!(out[i], program.eval(&[xs[i], ys[i]]));
+        }
+    }
+
+    #[test]
+    fn single_values_are_broadcast() {
+        let vars = ["x".to_owned(), "a".to_owned()];
+        let program = compile::<f64>(&parse("a*x"), &vars).unwrap();
+        let out = eval_batch(&program, &[&[1.0, 2.0, 3.0], &[2.0]]).unwrap();
+        assert_eq!(out, vec![2.0, 4.0, 6.0]);
+        assert_eq!(eval_batch(&program, &[&[1.5], &[2.0]]).unwrap(), vec![3.0]);
+    }
+
+    #[test]
+    fn mismatched_columns_are_errors() {
+        let vars = ["x".to_owned(), "y".to_owned()];
+        let program = compile::<f64>(&parse("x+y"), &vars).unwrap();
+        assert!(eval_batch(&program, &[&[1.0, 2.0]]).is_err());
+        assert!(eval_batch(&program, &[&[1.0, 2.0], &[1.0, 2.0, 3.0]]).is_err());
+    }
+}
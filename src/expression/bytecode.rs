@@ -0,0 +1,258 @@
+use expression;
+use expression::enums;
+use expression::eval;
+use expression::numeric::Numeric;
+
+pub type Expression = expression::Expression;
+
+use std::collections::HashMap;
+
+// Postfix expressions compiled ahead of time: literals are parsed once,
+// variables are resolved to slot indices and the stack depth is known, so
+// running a program never allocates.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Instruction<T> {
+    Push(T),
+    Load(usize),
+    Negate,
+    Binary(enums::Operator),
+    Func(enums::Function),
+    BinaryFunc(enums::Function),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Program<T = f64> {
+    code: Vec<Instruction<T>>,
+    slots: Vec<String>,
+    stack_size: usize,
+}
+
+// `vars` fixes the slot order of the given variables, any other variable in
+// the expression is given the next free slot.
+pub fn compile<T: Numeric>(expr: &Expression, vars: &[String]) -> Result<Program<T>, String> {
+    let mut slots: Vec<String> = vars.to_vec();
+    let mut code: Vec<Instruction<T>> = Vec::with_capacity(expr.len());
+    let mut depth: usize = 0;
+    let mut stack_size: usize = 0;
+    for token in expr.iter() {
+        let (instruction, pops) = match *token {
+            enums::Token::Literal(ref x) => {
+                match x.parse::<f64>() {
+                    Ok(_) => (Instruction::Push(T::from_literal(x)), 0),
+                    Err(_) => return Err(format!("Invalid numeric literal: {}", x)),
+                }
+            }
+            enums::Token::Const(ref x) => (Instruction::Push(eval::eval_constant(x)), 0),
+            enums::Token::Var(ref x) => {
+                let slot = match slots.iter().position(|s| s == x) {
+                    Some(slot) => slot,
+                    None => {
+                        slots.push(x.clone());
+                        slots.len() - 1
+                    }
+                };
+                (Instruction::Load(slot), 0)
+            }
+            enums::Token::Op(enums::Operator::Negate) => (Instruction::Negate, 1),
+            enums::Token::Op(op) => (Instruction::Binary(op), 2),
+            enums::Token::Func(ref f) => {
+                match *f {
+                    enums::Function::Max | enums::Function::LogBase => {
+                        (Instruction::BinaryFunc(*f), 2)
+                    }
+                    _ => (Instruction::Func(*f), 1),
+                }
+            }
+            enums::Token::Unknown(ref x) => return Err(format!("Cannot compile unknown token: {}", x)),
+            _ => continue,
+        };
+        if depth < pops {
+            return Err("Malformed Expression, an operator is missing an operand".to_owned());
+        }
+        depth = depth - pops + 1;
+        stack_size = stack_size.max(depth);
+        code.push(instruction);
+    }
+    if depth != 1 {
+        return Err(format!("Malformed Expression, left {} values on the stack", depth));
+    }
+    Ok(Program {
+        code,
+        slots,
+        stack_size,
+    })
+}
+
+impl<T: Numeric> Program<T> {
+    pub fn code(&self) -> &[Instruction<T>] {
+        self.code.as_slice()
+    }
+
+    pub fn slots(&self) -> &[String] {
+        self.slots.as_slice()
+    }
+
+    pub fn slot(&self, name: &str) -> Option<usize> {
+        self.slots.iter().position(|s| s == name)
+    }
+
+    pub fn stack_size(&self) -> usize {
+        self.stack_size
+    }
+
+    // Convenience for one-off calls, loops should keep a Machine around.
+    pub fn eval(&self, args: &[T]) -> T {
+        Machine::new(self).run(self, args)
+    }
+}
+
+// Scratch space for running programs, sized for the deepest program it has
+// been handed so repeated runs reuse the same buffer.
+#[derive(Debug, Clone)]
+pub struct Machine<T = f64> {
+    stack: Vec<T>,
+}
+
+impl<T: Numeric> Machine<T> {
+    pub fn new(program: &Program<T>) -> Self {
+        Machine { stack: vec![T::zero(); program.stack_size] }
+    }
+
+    // `args` is indexed by slot, see Program::slots.
+    pub fn run(&mut self, program: &Program<T>, args: &[T]) -> T {
+        if self.stack.len() < program.stack_size {
+            self.stack.resize(program.stack_size, T::zero());
+        }
+        let stack = self.stack.as_mut_slice();
+        let mut top: usize = 0;
+        for instruction in &program.code {
+            match *instruction {
+                Instruction::Push(x) => {
+                    stack[top] = x;
+                    top += 1;
+                }
+                Instruction::Load(slot) => {
+                    stack[top] = args[slot];
+                    top += 1;
+                }
+                Instruction::Negate => stack[top - 1] = -stack[top - 1],
+                Instruction::Binary(ref op) => {
+                    top -= 1;
+                    stack[top - 1] = eval::eval_operator(op, stack[top - 1], stack[top]);
+                }
+                Instruction::Func(ref f) => stack[top - 1] = eval::eval_function(f, stack[top - 1]),
+                Instruction::BinaryFunc(ref f) => {
+                    top -= 1;
+                    stack[top - 1] = eval::eval_binary_function(f, stack[top - 1], stack[top]);
+                }
+            }
+        }
+        stack[0]
+    }
+}
+
+// A compiled expression of the `free` variables (slots 0..free.len()) with
+// every other variable fixed to its value in `values`, or 0 like
+// eval::eval_postfix_expr when it has none.
+#[derive(Debug, Clone)]
+pub struct Bound {
+    program: Program,
+    machine: Machine,
+    args: Vec<f64>,
+    free: usize,
+}
+
+pub fn bind(expr: &Expression,
+            free: &[String],
+            values: &HashMap<String, f64>)
+            -> Result<Bound, String> {
+    let program = compile::<f64>(expr, free)?;
+    let args: Vec<f64> = program.slots()
+        .iter()
+        .map(|s| *values.get(s).unwrap_or(&0.0))
+        .collect();
+    Ok(Bound {
+        machine: Machine::new(&program),
+        program,
+        args,
+        free: free.len(),
+    })
+}
+
+impl Bound {
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
+
+    // Slot values with the free ones left at whatever was evaluated last.
+    pub fn args(&self) -> &[f64] {
+        self.args.as_slice()
+    }
+
+    pub fn eval(&mut self, point: &[f64]) -> f64 {
+        self.args[..self.free].copy_from_slice(&point[..self.free]);
+        self.machine.run(&self.program, &self.args)
+    }
+
+    // For expressions of a single free variable.
+    pub fn eval1(&mut self, x: f64) -> f64 {
+        if self.free > 0 {
+            self.args[0] = x;
+        }
+        self.machine.run(&self.program, &self.args)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use expression::dual::Dual;
+    use expression::eval::eval_postfix_expr;
+    use expression::parse::parse;
+
+    use std::collections::HashMap;
+
+    #[test]
+    fn programs_match_the_postfix_evaluator() {
+        let vars = ["x".to_owned(), "y".to_owned()];
+        for input in &["x*x+3*x+2", "sin(x)*exp(y/10)+sqrt(abs(x))", "logbase(8,2)^y*max(x,y)",
+                       "x%3+recip(y)*tanh(x*y)"] {
+            let expr = parse(input);
+            let program = compile::<f64>(&expr, &vars).unwrap();
+            let mut machine = Machine::new(&program);
+            for &(x, y) in &[(0.5, 1.0), (-2.25, 3.5), (7.0, -0.125)] {
+                let mut values = HashMap::new();
+                values.insert("x".to_owned(), x);
+                values.insert("y".to_owned(), y);
+                assert_eq!(machine.run(&program, &[x, y]), eval_postfix_expr(&expr, &values));
+            }
+        }
+    }
+
+    #[test]
+    fn slots_follow_the_given_order() {
+        let program = compile::<f64>(&parse("z*y+x"), &["x".to_owned()]).unwrap();
+        assert_eq!(program.slots(), &["x".to_owned(), "z".to_owned(), "y".to_owned()][..]);
+        assert_eq!(program.slot("y"), Some(2));
+        assert_eq!(program.stack_size(), 2);
+        assert_eq!(program.eval(&[1.0, 2.0, 3.0]), 7.0);
+    }
+
+    #[test]
+    fn programs_run_on_other_backends() {
+        let program = compile::<Dual>(&parse("x^3+2*x"), &["x".to_owned()]).unwrap();
+        assert_eq!(program.eval(&[Dual::variable(2.0)]), Dual::new(12.0, 14.0));
+    }
+
+    #[test]
+    fn malformed_expressions_do_not_compile() {
+        let missing = Expression::new(vec![enums::Token::Var("x".to_owned()),
+                                           enums::Token::Op(enums::Operator::Add)]);
+        assert!(compile::<f64>(&missing, &[]).is_err());
+        let extra = Expression::new(vec![enums::Token::Var("x".to_owned()),
+                                         enums::Token::Var("y".to_owned())]);
+        assert!(compile::<f64>(&extra, &[]).is_err());
+        let literal = Expression::new(vec![enums::Token::Literal("2x".to_owned())]);
+        assert!(compile::<f64>(&literal, &[]).is_err());
+    }
+}
@@ -0,0 +1,1055 @@
+use regex::Regex;
+
+use expression;
+use expression::analysis::{self, Asymptote};
+use expression::bytecode;
+use expression::definite;
+use expression::dsolve;
+use expression::enums;
+use expression::eval::eval_postfix_expr;
+use expression::fourier;
+use expression::limit::{self, Direction, Point};
+use expression::linear::{self, LinearSolution};
+use expression::montecarlo::{self, Sampler};
+use expression::multiple;
+use expression::multivariate;
+use expression::ode;
+use expression::optimize;
+use expression::parse::parse_input;
+use expression::polynomial::{self, PolyRoot};
+use expression::series;
+use expression::solve;
+use expression::summation;
+use expression::symbolic;
+use expression::system;
+
+pub type Expression = expression::Expression;
+
+use std::collections::HashMap;
+
+// REPL commands look like function calls, e.g. solve(x^2-2,x,1). Arguments
+// are split on top level commas, `[a,b,...]` is a list and `lhs=rhs` an
+// equation where one is expected.
+pub struct Context<'a> {
+    pub numeric_regex: &'a Regex,
+    pub function_regex: &'a Regex,
+    pub variables: &'a HashMap<String, f64>,
+}
+
+pub const COMMANDS: &[&str] = &["solve", "roots", "coeffs", "polyroots", "factor",
+                                    "linsolve", "diff", "grad", "jacobian", "hessian",
+                                    "limit", "taylor", "pade", "fourier", "ode", "dsolve",
+                                    "int", "int2", "int3", "mcint", "sum", "prod", "minimize",
+                                    "maximize", "analyze"];
+
+// None when `input` is not a command and should be evaluated as usual.
+pub fn run_command(input: &str, context: &Context) -> Option<Result<String, String>> {
+    let (name, inner) = split_call(input)?;
+    if !COMMANDS.contains(&name) {
+        return None;
+    }
+    let args = split_args(inner);
+    Some(match name {
+        "solve" => solve_command(&args, context),
+        "roots" => roots_command(&args, context),
+        "coeffs" => coeffs_command(&args, context),
+        "polyroots" => polyroots_command(&args, context),
+        "factor" => factor_command(&args, context),
+        "linsolve" => linsolve_command(&args, context),
+        "diff" => diff_command(&args, context),
+        "grad" => grad_command(&args, context),
+        "jacobian" => jacobian_command(&args, context),
+        "hessian" => hessian_command(&args, context),
+        "limit" => limit_command(&args, context),
+        "taylor" => taylor_command(&args, context),
+        "pade" => pade_command(&args, context),
+        "fourier" => fourier_command(&args, context),
+        "ode" => ode_command(&args, context),
+        "dsolve" => dsolve_command(&args, context),
+        "int" => int_command(&args, context),
+        "int2" => multiple_command("int2", 2, &args, context),
+        "int3" => multiple_command("int3", 3, &args, context),
+        "mcint" => mcint_command(&args, context),
+        "sum" => summation_command("sum", &args, context),
+        "prod" => summation_command("prod", &args, context),
+        "minimize" => optimize_command("minimize", &args, context),
+        "maximize" => optimize_command("maximize", &args, context),
+        "analyze" => analyze_command(&args, context),
+        _ => unreachable!(),
+    })
+}
+
+// Splits `name(inner)` where the parenthesis opened after the name is the
+// one closing the input.
+pub fn split_call(input: &str) -> Option<(&str, &str)> {
+    let open = input.find('(')?;
+    let name = &input[..open];
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') ||
+       !input.ends_with(')') {
+        return None;
+    }
+    let mut depth: usize = 0;
+    for (i, c) in input.char_indices().skip(open) {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return if i == input.len() - 1 {
+                        Some((name, &input[open + 1..i]))
+                    } else {
+                        None
+                    };
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+// Splits on commas that are not nested inside () or [].
+pub fn split_args(inner: &str) -> Vec<&str> {
+    let mut args: Vec<&str> = Vec::new();
+    if inner.is_empty() {
+        return args;
+    }
+    let mut depth: i32 = 0;
+    let mut start: usize = 0;
+    for (i, c) in inner.char_indices() {
+        match c {
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth -= 1,
+            ',' if depth == 0 => {
+                args.push(&inner[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    args.push(&inner[start..]);
+    args
+}
+
+pub fn expect_args(name: &str, args: &[&str], usage: &[usize], form: &str) -> Result<(), String> {
+    if usage.contains(&args.len()) {
+        Ok(())
+    } else {
+        Err(format!("{} expects arguments like {}", name, form))
+    }
+}
+
+impl<'a> Context<'a> {
+    pub fn expr(&self, arg: &str) -> Result<Expression, String> {
+        if arg.is_empty() {
+            return Err("Missing expression".to_owned());
+        }
+        if arg.contains('=') || arg.contains('[') {
+            return Err(format!("Expected an expression but got {}", arg));
+        }
+        let expr = parse_input(arg, self.numeric_regex, self.function_regex).1?;
+        // The symbolic routines assume every operator has its operands.
+        bytecode::compile::<f64>(&expr, &[])?;
+        Ok(expr)
+    }
+
+    // `lhs=rhs` becomes lhs - rhs, anything else is an expression.
+    pub fn equation(&self, arg: &str) -> Result<Expression, String> {
+        match arg.find('=') {
+            Some(i) => self.expr(&format!("({})-({})", &arg[..i], &arg[i + 1..])),
+            None => self.expr(arg),
+        }
+    }
+
+    pub fn value(&self, arg: &str) -> Result<f64, String> {
+        let expr = self.expr(arg)?;
+        Ok(eval_postfix_expr(&expr, self.variables))
+    }
+
+    // An end of an interval of integration: an expression, inf or -inf.
+    pub fn end(&self, arg: &str) -> Result<definite::End, String> {
+        match arg {
+            "inf" | "+inf" => Ok(definite::End::PosInf),
+            "-inf" => Ok(definite::End::NegInf),
+            _ => Ok(definite::End::Finite(self.expr(arg)?)),
+        }
+    }
+
+    pub fn var(&self, arg: &str) -> Result<String, String> {
+        let mut chars = arg.chars();
+        let valid = match chars.next() {
+            Some(c) => c.is_ascii_alphabetic() && chars.all(|c| c.is_ascii_alphanumeric()),
+            None => false,
+        };
+        if !valid {
+            return Err(format!("Expected a variable name but got {}", arg));
+        }
+        if enums::is_constant(arg) {
+            return Err(format!("{} is a constant, not a variable", arg));
+        }
+        Ok(arg.to_owned())
+    }
+
+    pub fn is_list(&self, arg: &str) -> bool {
+        arg.starts_with('[') && arg.ends_with(']')
+    }
+
+    pub fn list<'b>(&self, arg: &'b str) -> Result<Vec<&'b str>, String> {
+        if !self.is_list(arg) {
+            return Err(format!("Expected a list like [a,b] but got {}", arg));
+        }
+        Ok(split_args(&arg[1..arg.len() - 1]))
+    }
+
+    pub fn equations(&self, arg: &str) -> Result<Vec<Expression>, String> {
+        self.list(arg)?.iter().map(|e| self.equation(e)).collect()
+    }
+
+    pub fn vars(&self, arg: &str) -> Result<Vec<String>, String> {
+        self.list(arg)?.iter().map(|v| self.var(v)).collect()
+    }
+
+    pub fn values(&self, arg: &str) -> Result<Vec<f64>, String> {
+        self.list(arg)?.iter().map(|v| self.value(v)).collect()
+    }
+
+    // The variables with `vars` set to the point `[a,b,...]`.
+    pub fn point(&self, vars: &[String], arg: &str) -> Result<HashMap<String, f64>, String> {
+        let coordinates = self.values(arg)?;
+        if coordinates.len() != vars.len() {
+            return Err(format!("Expected {} coordinates but got {}", vars.len(), arg));
+        }
+        let mut values = self.variables.clone();
+        for (var, x) in vars.iter().zip(coordinates) {
+            values.insert(var.clone(), x);
+        }
+        Ok(values)
+    }
+
+    // The variables with the assignments `[x=a,y=b,...]` applied.
+    pub fn assignments(&self, arg: &str) -> Result<HashMap<String, f64>, String> {
+        let mut values = self.variables.clone();
+        for assignment in self.list(arg)? {
+            let (var, value) = match assignment.find('=') {
+                Some(i) => (self.var(&assignment[..i])?, self.value(&assignment[i + 1..])?),
+                None => {
+                    return Err(format!("Expected an assignment like x=1 but got {}", assignment))
+                }
+            };
+            values.insert(var, value);
+        }
+        Ok(values)
+    }
+
+    // `[a,b]` evaluated to a numeric interval.
+    pub fn interval(&self, arg: &str) -> Result<(f64, f64), String> {
+        let bounds = self.list(arg)?;
+        if bounds.len() != 2 {
+            return Err(format!("Expected an interval like [a,b] but got {}", arg));
+        }
+        Ok((self.value(bounds[0])?, self.value(bounds[1])?))
+    }
+
+    pub fn degree(&self, arg: &str) -> Result<usize, String> {
+        let n = self.value(arg)?;
+        if n < 0.0 || n.fract() != 0.0 {
+            return Err(format!("The degree must be a non-negative integer, got {}", n));
+        }
+        Ok(n as usize)
+    }
+
+    // `dy/dx=expr` as (y, x, expr).
+    pub fn derivative_equation(&self, arg: &str) -> Result<(String, String, Expression), String> {
+        let form = || format!("Expected an equation like dy/dx=f(x,y) but got {}", arg);
+        let i = arg.find('=').ok_or_else(form)?;
+        let (lhs, rhs) = (&arg[..i], &arg[i + 1..]);
+        let slash = lhs.find('/').ok_or_else(form)?;
+        let (top, bottom) = (&lhs[..slash], &lhs[slash + 1..]);
+        if !top.starts_with('d') || !bottom.starts_with('d') {
+            return Err(form());
+        }
+        let unknown = self.var(&top[1..]).map_err(|_| form())?;
+        let var = self.var(&bottom[1..]).map_err(|_| form())?;
+        Ok((unknown, var, self.expr(rhs)?))
+    }
+
+    // `y(a)=b` as (y, a, b).
+    pub fn initial_value(&self, arg: &str) -> Result<(String, f64, f64), String> {
+        let form = || format!("Expected an initial value like y(0)=1 but got {}", arg);
+        let i = arg.find('=').ok_or_else(form)?;
+        let (lhs, rhs) = (&arg[..i], &arg[i + 1..]);
+        let (name, inner) = split_call(lhs).ok_or_else(form)?;
+        Ok((self.var(name).map_err(|_| form())?, self.value(inner)?, self.value(rhs)?))
+    }
+}
+
+fn solve_command(args: &[&str], context: &Context) -> Result<String, String> {
+    expect_args("solve",
+                args,
+                &[3],
+                "solve(expr, x, guess), solve(lhs=rhs, x, [a,b]) or solve([f,g], [x,y], [x0,y0])")?;
+    if context.is_list(args[0]) {
+        return solve_system_command(args, context);
+    }
+    let expr = context.equation(args[0])?;
+    let var = context.var(args[1])?;
+    let root = if context.is_list(args[2]) {
+        let (a, b) = context.interval(args[2])?;
+        solve::solve_bracketed(&expr, &var, a, b, context.variables)?
+    } else {
+        let guess = context.value(args[2])?;
+        solve::solve(&expr, &var, guess, context.variables)?
+    };
+    Ok(format!("{} = {}", var, root.x))
+}
+
+fn solve_system_command(args: &[&str], context: &Context) -> Result<String, String> {
+    let exprs = context.equations(args[0])?;
+    let vars = context.vars(args[1])?;
+    let guess = context.values(args[2])?;
+    let solution = system::solve_system(&exprs, &vars, &guess, context.variables)?;
+    let mut lines: Vec<String> =
+        vars.iter().zip(&solution.x).map(|(v, x)| format!("{} = {}", v, x)).collect();
+    lines.push(format!("{}: {}, {} iterations, |F| = {:e}",
+                       if solution.converged { "Converged" } else { "Not converged" },
+                       solution.method,
+                       solution.iterations,
+                       solution.residual_norm()));
+    Ok(lines.join("\n"))
+}
+
+fn linsolve_command(args: &[&str], context: &Context) -> Result<String, String> {
+    expect_args("linsolve", args, &[2], "linsolve([a*x+y=1, x-b*y=0], [x,y])")?;
+    let exprs = context.equations(args[0])?;
+    let vars = context.vars(args[1])?;
+    match linear::solve_linear_system(&exprs, &vars)? {
+        LinearSolution::Unique(solutions) => {
+            let lines: Vec<String> =
+                vars.iter().zip(&solutions).map(|(v, s)| format!("{} = {}", v, s)).collect();
+            Ok(lines.join("\n"))
+        }
+        LinearSolution::Inconsistent => Ok("The system has no solution".to_owned()),
+        LinearSolution::Underdetermined => {
+            Ok("The system has infinitely many solutions".to_owned())
+        }
+    }
+}
+
+fn roots_command(args: &[&str], context: &Context) -> Result<String, String> {
+    expect_args("roots", args, &[4], "roots(expr, x, a, b)")?;
+    let expr = context.equation(args[0])?;
+    let var = context.var(args[1])?;
+    let a = context.value(args[2])?;
+    let b = context.value(args[3])?;
+    let roots = solve::roots(&expr, &var, a, b, context.variables)?;
+    if roots.is_empty() {
+        return Ok(format!("No sign changes in [{}, {}]", a, b));
+    }
+    let lines: Vec<String> = roots.iter().map(|r| format!("{} = {}", var, r.x)).collect();
+    Ok(lines.join("\n"))
+}
+
+fn polynomial_arg(name: &str,
+                  args: &[&str],
+                  context: &Context)
+                  -> Result<(Vec<f64>, String), String> {
+    expect_args(name, args, &[2], &format!("{}(expr, x)", name))?;
+    let expr = context.equation(args[0])?;
+    let var = context.var(args[1])?;
+    match polynomial::coefficients(&expr, &var, context.variables) {
+        Some(coeffs) => Ok((coeffs, var)),
+        None => Err(format!("{} is not a polynomial in {}", expr, var)),
+    }
+}
+
+fn coeffs_command(args: &[&str], context: &Context) -> Result<String, String> {
+    let (coeffs, var) = polynomial_arg("coeffs", args, context)?;
+    let lines: Vec<String> = coeffs.iter()
+        .enumerate()
+        .rev()
+        .map(|(i, c)| format!("{}^{}: {}", var, i, c))
+        .collect();
+    Ok(lines.join("\n"))
+}
+
+fn polyroots_command(args: &[&str], context: &Context) -> Result<String, String> {
+    let (coeffs, var) = polynomial_arg("polyroots", args, context)?;
+    if polynomial::degree(&coeffs) == 0 {
+        return Err(format!("Constant polynomial {} has no roots to find", coeffs[0]));
+    }
+    let lines: Vec<String> = polynomial::roots(&coeffs)
+        .iter()
+        .map(|&(root, multiplicity)| {
+            let value = match root {
+                PolyRoot::Exact(r) => format!("{}", r),
+                PolyRoot::Approx(z) => format!("{}", z),
+            };
+            if multiplicity > 1 {
+                format!("{} = {} (multiplicity {})", var, value, multiplicity)
+            } else {
+                format!("{} = {}", var, value)
+            }
+        })
+        .collect();
+    Ok(lines.join("\n"))
+}
+
+fn factor_command(args: &[&str], context: &Context) -> Result<String, String> {
+    let (coeffs, var) = polynomial_arg("factor", args, context)?;
+    match polynomial::factor(&coeffs) {
+        Some(factorization) => Ok(polynomial::factorization_to_string(&factorization, &var)),
+        None => Err("Coefficients are not exactly representable as fractions".to_owned()),
+    }
+}
+
+// diff(f, x, y, ...) optionally followed by a point [x=a, y=b].
+fn diff_command(args: &[&str], context: &Context) -> Result<String, String> {
+    if args.len() < 2 {
+        return Err("diff expects arguments like diff(f, x, y) or diff(f, x, [x=1])".to_owned());
+    }
+    let expr = context.expr(args[0])?;
+    let (vars, point) = match args.last() {
+        Some(last) if context.is_list(last) => (&args[1..args.len() - 1], Some(*last)),
+        _ => (&args[1..], None),
+    };
+    let vars: Vec<String> = vars.iter().map(|v| context.var(v)).collect::<Result<_, _>>()?;
+    let derivative = multivariate::partial(&expr, &vars);
+    match point {
+        Some(point) => {
+            let values = context.assignments(point)?;
+            Ok(format!("{}", eval_postfix_expr(&derivative, &values)))
+        }
+        None => Ok(format!("{}", derivative)),
+    }
+}
+
+fn grad_command(args: &[&str], context: &Context) -> Result<String, String> {
+    expect_args("grad", args, &[2, 3], "grad(f, [x,y]) or grad(f, [x,y], [1,2])")?;
+    let expr = context.expr(args[0])?;
+    let vars = context.vars(args[1])?;
+    let gradient = multivariate::gradient(&expr, &vars);
+    if args.len() == 3 {
+        let values = context.point(&vars, args[2])?;
+        return Ok(multivariate::format_vector(&multivariate::eval_vector(&gradient, &values)));
+    }
+    Ok(multivariate::format_vector(&gradient))
+}
+
+fn jacobian_command(args: &[&str], context: &Context) -> Result<String, String> {
+    expect_args("jacobian",
+                args,
+                &[2, 3],
+                "jacobian([f,g], [x,y]) or jacobian([f,g], [x,y], [1,2])")?;
+    let mut exprs: Vec<Expression> = Vec::new();
+    for arg in context.list(args[0])? {
+        exprs.push(context.expr(arg)?);
+    }
+    let vars = context.vars(args[1])?;
+    let jacobian = multivariate::jacobian(&exprs, &vars);
+    if args.len() == 3 {
+        let values = context.point(&vars, args[2])?;
+        return Ok(multivariate::format_matrix(&multivariate::eval_matrix(&jacobian, &values)));
+    }
+    Ok(multivariate::format_matrix(&jacobian))
+}
+
+fn hessian_command(args: &[&str], context: &Context) -> Result<String, String> {
+    expect_args("hessian", args, &[2, 3], "hessian(f, [x,y]) or hessian(f, [x,y], [1,2])")?;
+    let expr = context.expr(args[0])?;
+    let vars = context.vars(args[1])?;
+    let hessian = multivariate::hessian(&expr, &vars);
+    if args.len() == 3 {
+        let values = context.point(&vars, args[2])?;
+        return Ok(multivariate::format_matrix(&multivariate::eval_matrix(&hessian, &values)));
+    }
+    Ok(multivariate::format_matrix(&hessian))
+}
+
+fn limit_command(args: &[&str], context: &Context) -> Result<String, String> {
+    expect_args("limit",
+                args,
+                &[3, 4],
+                "limit(expr, x, a), limit(expr, x, a, +) or limit(expr, x, inf)")?;
+    let expr = context.expr(args[0])?;
+    let var = context.var(args[1])?;
+    let point = match args[2] {
+        "inf" | "+inf" => Point::PosInf,
+        "-inf" => Point::NegInf,
+        a => Point::Finite(context.value(a)?),
+    };
+    let direction = match args.get(3) {
+        None => Direction::Both,
+        Some(&"+") | Some(&"right") => Direction::Right,
+        Some(&"-") | Some(&"left") => Direction::Left,
+        Some(other) => return Err(format!("Expected + or - for the side but got {}", other)),
+    };
+    if direction != Direction::Both && !matches!(point, Point::Finite(_)) {
+        return Err("Limits at infinity are already one-sided".to_owned());
+    }
+    Ok(format!("{}", limit::limit(&expr, &var, point, direction, context.variables)))
+}
+
+fn taylor_command(args: &[&str], context: &Context) -> Result<String, String> {
+    expect_args("taylor",
+                args,
+                &[4, 5],
+                "taylor(expr, x, a, n) or taylor(expr, x, a, n, [lo,hi])")?;
+    let expr = context.expr(args[0])?;
+    let var = context.var(args[1])?;
+    let a = context.value(args[2])?;
+    let n = context.degree(args[3])?;
+    let approximation = series::taylor(&expr, &var, a, n, context.variables)?;
+    if args.len() == 4 {
+        return Ok(format!("{}", approximation));
+    }
+    let (lo, hi) = context.interval(args[4])?;
+    let bound = series::remainder_bound(&expr, &var, a, n, lo, hi, context.variables)?;
+    Ok(format!("{}\n|R{}({})| <= {} on [{}, {}]", approximation, n, var, bound, lo, hi))
+}
+
+fn pade_command(args: &[&str], context: &Context) -> Result<String, String> {
+    expect_args("pade", args, &[5], "pade(expr, x, a, m, n)")?;
+    let expr = context.expr(args[0])?;
+    let var = context.var(args[1])?;
+    let a = context.value(args[2])?;
+    let m = context.degree(args[3])?;
+    let n = context.degree(args[4])?;
+    Ok(format!("{}", series::pade(&expr, &var, a, m, n, context.variables)?))
+}
+
+fn fourier_command(args: &[&str], context: &Context) -> Result<String, String> {
+    expect_args("fourier",
+                args,
+                &[4, 5],
+                "fourier(expr, x, period, n) or fourier(expr, x, period, n, start)")?;
+    let expr = context.expr(args[0])?;
+    let var = context.var(args[1])?;
+    let period = context.value(args[2])?;
+    let n = context.degree(args[3])?;
+    let start = if args.len() == 5 {
+        context.value(args[4])?
+    } else {
+        -period / 2.0
+    };
+    let series = fourier::fourier(&expr, &var, period, n, start, context.variables)?;
+    let a: Vec<Expression> = series.cosines.iter().map(|c| fourier::closed_form(*c)).collect();
+    let b: Vec<Expression> = series.sines.iter().map(|c| fourier::closed_form(*c)).collect();
+    let mut output = format!("{}\na = {}\nb = {}",
+                             fourier::to_expression(&series, &var),
+                             multivariate::format_vector(&a),
+                             multivariate::format_vector(&b));
+    let total = 2 * n + 1;
+    if series.exact < total {
+        output.push_str(&format!("\n{} of {} coefficients by quadrature",
+                                 total - series.exact,
+                                 total));
+    }
+    Ok(output)
+}
+
+fn ode_command(args: &[&str], context: &Context) -> Result<String, String> {
+    expect_args("ode",
+                args,
+                &[3, 4, 5],
+                "ode(dy/dx=f, y(x0)=y0, x_end), ode(dy/dx=f, y(x0)=y0, x_end, samples, stiff) or \
+                 ode([dy/dx=f,dz/dx=g], [y(x0)=y0,z(x0)=z0], x_end)")?;
+    let equations = if context.is_list(args[0]) {
+        context.list(args[0])?
+    } else {
+        vec![args[0]]
+    };
+    let conditions = if context.is_list(args[1]) {
+        context.list(args[1])?
+    } else {
+        vec![args[1]]
+    };
+    let mut var = String::new();
+    let mut unknowns = Vec::new();
+    let mut exprs = Vec::new();
+    for equation in equations {
+        let (unknown, x, expr) = context.derivative_equation(equation)?;
+        if !var.is_empty() && x != var {
+            return Err(format!("Every derivative must be with respect to {}", var));
+        }
+        if unknowns.contains(&unknown) {
+            return Err(format!("{} has more than one equation", unknown));
+        }
+        var = x;
+        unknowns.push(unknown);
+        exprs.push(expr);
+    }
+    let mut x0 = None;
+    let mut y0 = vec![None; unknowns.len()];
+    for condition in conditions {
+        let (name, at, value) = context.initial_value(condition)?;
+        if x0.is_some_and(|x0| x0 != at) {
+            return Err("Every initial value must be given at the same point".to_owned());
+        }
+        x0 = Some(at);
+        match unknowns.iter().position(|u| *u == name) {
+            Some(i) => y0[i] = Some(value),
+            None => return Err(format!("{} has no equation", name)),
+        }
+    }
+    let y0 = unknowns.iter()
+        .zip(y0)
+        .map(|(u, y)| y.ok_or_else(|| format!("Missing an initial value for {}", u)))
+        .collect::<Result<Vec<f64>, String>>()?;
+    let x_end = context.value(args[2])?;
+    let samples = if args.len() >= 4 {
+        context.degree(args[3])?
+    } else {
+        10
+    };
+    if samples == 0 {
+        return Err("At least one sample interval is needed".to_owned());
+    }
+    // Dormand-Prince switches to Rosenbrock by itself when needed.
+    let method = match args.get(4) {
+        None | Some(&"auto") | Some(&"rk45") => ode::Method::DormandPrince,
+        Some(&"stiff") | Some(&"rosenbrock") => ode::Method::Rosenbrock,
+        Some(other) => {
+            return Err(format!("Expected auto or stiff for the method but got {}", other))
+        }
+    };
+    let mut system = ode::System::new(&exprs, &var, &unknowns, context.variables)?;
+    let solution = ode::solve(&mut system, x0.unwrap_or(0.0), &y0, x_end, samples, method)?;
+    let mut rows = vec![Some(&var).into_iter().chain(&unknowns).cloned().collect::<Vec<_>>()];
+    for (x, y) in solution.xs.iter().zip(&solution.ys) {
+        rows.push(Some(x).into_iter().chain(y).map(|v| format_sample(*v)).collect());
+    }
+    let mut output = format_table(&rows);
+    if let Some(x) = solution.switched {
+        output.push_str(&format!("Stiff from {} = {}, switched to {}\n", var, x, solution.method));
+    }
+    output.push_str(&format!("{}: {} steps ({} rejected), {} evaluations",
+                             solution.method,
+                             solution.steps,
+                             solution.rejected,
+                             solution.evaluations));
+    if solution.jacobians > 0 {
+        output.push_str(&format!(", {} Jacobians", solution.jacobians));
+    }
+    Ok(output)
+}
+
+fn dsolve_command(args: &[&str], context: &Context) -> Result<String, String> {
+    expect_args("dsolve",
+                args,
+                &[1, 3],
+                "dsolve(dy/dx=x*y), dsolve(d2y/dx2+y=0) or dsolve(y''+2*y'+y=sin(t), y, t)")?;
+    let (unknown, var) = if args.len() == 3 {
+        (context.var(args[1])?, context.var(args[2])?)
+    } else {
+        leibniz_names(args[0]).unwrap_or_else(|| ("y".to_owned(), "x".to_owned()))
+    };
+    // y'', d2y/dx2, y' and dy/dx become variables before parsing.
+    let first = dsolve::derivative_name(&unknown, 1);
+    let second = dsolve::derivative_name(&unknown, 2);
+    let equation = args[0]
+        .replace(&format!("d2{}/d{}2", unknown, var), &second)
+        .replace(&format!("{}''", unknown), &second)
+        .replace(&format!("d{}/d{}", unknown, var), &first)
+        .replace(&format!("{}'", unknown), &first);
+    if equation.contains('\'') {
+        return Err(format!("Expected derivatives of {} with respect to {}", unknown, var));
+    }
+    let solution = dsolve::dsolve(&context.equation(&equation)?, &unknown, &var)?;
+    Ok(format!("{}\n({})", solution, solution.kind))
+}
+
+// y and x from the first dy/dx or d2y/dx2 in the equation.
+fn leibniz_names(equation: &str) -> Option<(String, String)> {
+    let re = Regex::new(r"d2?([a-z][a-z0-9]*)/d([a-z][a-z0-9]*?)2?(?:[^a-z0-9]|$)").unwrap();
+    let captures = re.captures(equation)?;
+    Some((captures[1].to_owned(), captures[2].to_owned()))
+}
+
+// int(f, x, a, b) and int(f, x, a, b, pv) for the principal value, where a
+// and b may be inf or -inf. An exact value is printed with its decimal.
+fn int_command(args: &[&str], context: &Context) -> Result<String, String> {
+    expect_args("int", args, &[4, 5], "int(f, x, a, b), int(f, x, 0, inf) or int(f, x, a, b, pv)")?;
+    let expr = context.expr(args[0])?;
+    let var = context.var(args[1])?;
+    let a = context.end(args[2])?;
+    let b = context.end(args[3])?;
+    let principal = match args.get(4) {
+        None => false,
+        Some(&"pv") => true,
+        Some(other) => return Err(format!("Expected pv but got {}", other)),
+    };
+    let mut free: Vec<String> = symbolic::variables(&expr);
+    for end in &[&a, &b] {
+        if let definite::End::Finite(ref e) = **end {
+            free.extend(symbolic::variables(e));
+        }
+    }
+    if free.iter().any(|v| *v != var && !context.variables.contains_key(v)) {
+        if principal {
+            return Err("A principal value needs numeric limits".to_owned());
+        }
+        let closed = definite::closed_integral(&expr, &var, &a, &b, context.variables)
+            .ok_or_else(|| {
+                "No closed form is known for this integral, give numeric limits".to_owned()
+            })?;
+        return Ok(format!("{}", closed));
+    }
+    let result = definite::integrate(&expr, &var, &a, &b, principal, context.variables)?;
+    let quadrature = result.numeric.quadrature;
+    let mut output = match result.exact {
+        Some(ref exact) if exact.to_string() != result.value.to_string() => {
+            format!("{}\n{}", exact, result.value)
+        }
+        Some(_) => result.value.to_string(),
+        None => {
+            format!("{}\nerror <= {:.1e}, {} evaluations",
+                    quadrature.value,
+                    quadrature.error,
+                    quadrature.evaluations)
+        }
+    };
+    if !result.numeric.singularities.is_empty() {
+        let points: Vec<String> =
+            result.numeric.singularities.iter().map(|x| x.to_string()).collect();
+        output.push_str(&format!("\nSingular at {} = {}", var, points.join(", ")));
+        if principal {
+            output.push_str(", principal value");
+        }
+    }
+    if result.exact.is_none() && quadrature.error > 1e-8 * quadrature.value.abs().max(1.0) {
+        output.push_str("\nThe tolerance was not met");
+    }
+    Ok(output)
+}
+
+// int2(f, x, a, b, y, g1, g2) and int3 with a third variable and limits
+// after those, the limits of each variable depending on the ones before.
+fn multiple_command(name: &str,
+                    dimensions: usize,
+                    args: &[&str],
+                    context: &Context)
+                    -> Result<String, String> {
+    let form = if dimensions == 2 {
+        "int2(f, x, a, b, y, g1(x), g2(x))"
+    } else {
+        "int3(f, x, a, b, y, g1(x), g2(x), z, h1(x,y), h2(x,y))"
+    };
+    expect_args(name, args, &[1 + 3 * dimensions], form)?;
+    let expr = context.expr(args[0])?;
+    let mut vars = Vec::with_capacity(dimensions);
+    let mut limits = Vec::with_capacity(dimensions);
+    for triple in args[1..].chunks(3) {
+        vars.push(context.var(triple[0])?);
+        limits.push((context.expr(triple[1])?, context.expr(triple[2])?));
+    }
+    let result = multiple::integrate(&expr, &vars, &limits, context.variables)?;
+    let mut output = format!("{}\nerror <= {:.1e}, {} evaluations",
+                             result.value,
+                             result.error,
+                             result.evaluations);
+    if result.error > 1e-8 * result.value.abs().max(1.0) {
+        output.push_str("\nThe tolerance was not met, the integrand may be singular");
+    }
+    Ok(output)
+}
+
+// mcint(f, [x,y,...], [[a,b],[c,d],...], samples[, method[, seed]]), where a
+// single [a,b] applies to every variable and the method is sobol (the
+// default up to its dimension limit), halton or random.
+fn mcint_command(args: &[&str], context: &Context) -> Result<String, String> {
+    expect_args("mcint",
+                args,
+                &[4, 5, 6],
+                "mcint(f, [x,y], [[a,b],[c,d]], samples) or mcint(f, [x,y], [a,b], samples, \
+                 halton, seed)")?;
+    let expr = context.expr(args[0])?;
+    let vars = context.vars(args[1])?;
+    let bounds = if context.list(args[2])?.iter().all(|b| context.is_list(b)) {
+        context.list(args[2])?
+            .iter()
+            .map(|b| context.interval(b))
+            .collect::<Result<Vec<_>, String>>()?
+    } else {
+        vec![context.interval(args[2])?; vars.len()]
+    };
+    let samples = context.degree(args[3])?;
+    let sampler = match args.get(4) {
+        None if vars.len() > montecarlo::MAX_SOBOL_DIMENSIONS => Sampler::Halton,
+        None | Some(&"sobol") => Sampler::Sobol,
+        Some(&"halton") => Sampler::Halton,
+        Some(&"random") => Sampler::Random,
+        Some(other) => {
+            return Err(format!("Expected sobol, halton or random for the method but got {}",
+                               other))
+        }
+    };
+    let seed = match args.get(5) {
+        Some(seed) => context.degree(seed)? as u64,
+        None => 1,
+    };
+    let estimate =
+        montecarlo::integrate(&expr, &vars, &bounds, samples, sampler, seed, context.variables)?;
+    let mut output = format!("{} +- {:.1e} (standard error)\n{} samples, {}",
+                             estimate.value,
+                             estimate.standard_error,
+                             estimate.samples,
+                             sampler);
+    if estimate.replicates > 1 {
+        output.push_str(&format!(" in {} randomized replicates", estimate.replicates));
+    }
+    Ok(output)
+}
+
+// sum(f, k, a, b) and prod(f, k, a, b) for an integer a. b may be inf, or
+// contain unassigned variables when the sum has a closed form.
+fn summation_command(name: &str, args: &[&str], context: &Context) -> Result<String, String> {
+    let form = format!("{0}(f, k, 1, 10), {0}(f, k, 1, inf) or {0}(f, k, 0, n)", name);
+    expect_args(name, args, &[4], &form)?;
+    let expr = context.expr(args[0])?;
+    let index = context.var(args[1])?;
+    let a = context.value(args[2])?;
+    if !(a.is_finite() && a.fract() == 0.0 && a.abs() < 1e15) {
+        return Err(format!("The lower limit must be an integer, got {}", a));
+    }
+    let a = a as i64;
+    let product = name == "prod";
+    let b = match context.end(args[3])? {
+        definite::End::Finite(b) => b,
+        definite::End::NegInf => return Err("The upper limit cannot be -inf".to_owned()),
+        definite::End::PosInf => {
+            if !product {
+                if let Some(closed) =
+                    summation::closed_sum(&expr, &index, a, None, context.variables) {
+                    let value = eval_postfix_expr(&closed, context.variables);
+                    return Ok(exact_and_decimal(&closed, value));
+                }
+            }
+            let series = if product {
+                summation::infinite_product(&expr, &index, a, context.variables)?
+            } else {
+                summation::infinite_sum(&expr, &index, a, context.variables)?
+            };
+            let mut output = format!("{}\nerror <= {:.1e}, {} terms, {}",
+                                     series.value,
+                                     series.error,
+                                     series.terms,
+                                     series.method);
+            if series.error > 1e-8 * series.value.abs().max(1.0) {
+                output.push_str("\nThe tolerance was not met");
+            }
+            return Ok(output);
+        }
+    };
+    let free: Vec<String> = symbolic::variables(&b)
+        .into_iter()
+        .filter(|v| !context.variables.contains_key(v))
+        .collect();
+    if !free.is_empty() {
+        if product {
+            return Err("Products need a numeric upper limit".to_owned());
+        }
+        let closed = summation::closed_sum(&expr, &index, a, Some(&b), context.variables)
+            .ok_or_else(|| {
+                "No closed form is known for this sum, give a numeric upper limit".to_owned()
+            })?;
+        if let (1, Some(coeffs)) =
+            (free.len(), polynomial::coefficients(&closed, &free[0], context.variables)) {
+            if let Some(factorization) = polynomial::factor(&coeffs) {
+                return Ok(polynomial::factorization_to_string(&factorization, &free[0]));
+            }
+        }
+        return Ok(format!("{}", closed));
+    }
+    let b = context.value(args[3])?;
+    if !(b.is_finite() && b.fract() == 0.0 && b.abs() < 1e15) {
+        return Err(format!("The upper limit must be an integer, got {}", b));
+    }
+    let value = if product {
+        summation::product(&expr, &index, a, b as i64, context.variables)?
+    } else {
+        summation::sum(&expr, &index, a, b as i64, context.variables)?
+    };
+    Ok(exact_and_decimal(&series::fraction(value), value))
+}
+
+// minimize(f, [x,y], [x0,y0][, bfgs|neldermead]) from a starting point,
+// minimize(f, x, x0) in one variable and minimize(f, x, [a,b]) on an
+// interval with Brent's method; maximize takes the same arguments.
+fn optimize_command(name: &str, args: &[&str], context: &Context) -> Result<String, String> {
+    let form = format!("{0}(f, [x,y], [x0,y0]), {0}(f, [x,y], [x0,y0], neldermead) or \
+                        {0}(f, x, [a,b])",
+                       name);
+    expect_args(name, args, &[3, 4], &form)?;
+    let expr = context.expr(args[0])?;
+    let maximize = name == "maximize";
+    let (vars, result) = if context.is_list(args[1]) || !context.is_list(args[2]) {
+        let vars = if context.is_list(args[1]) {
+            context.vars(args[1])?
+        } else {
+            vec![context.var(args[1])?]
+        };
+        let start = if context.is_list(args[2]) {
+            context.values(args[2])?
+        } else {
+            vec![context.value(args[2])?]
+        };
+        let method = match args.get(3) {
+            None => None,
+            Some(&"bfgs") => Some(optimize::Method::Bfgs),
+            Some(&"neldermead") | Some(&"nelder-mead") => Some(optimize::Method::NelderMead),
+            Some(other) => {
+                return Err(format!("Expected bfgs or neldermead for the method but got {}",
+                                   other))
+            }
+        };
+        let result = if maximize {
+            optimize::maximize(&expr, &vars, &start, method, context.variables)?
+        } else {
+            optimize::minimize(&expr, &vars, &start, method, context.variables)?
+        };
+        (vars, result)
+    } else {
+        expect_args(name, args, &[3], &form)?;
+        let var = context.var(args[1])?;
+        let (a, b) = context.interval(args[2])?;
+        let result = if maximize {
+            optimize::maximize_bounded(&expr, &var, a, b, context.variables)?
+        } else {
+            optimize::minimize_bounded(&expr, &var, a, b, context.variables)?
+        };
+        (vec![var], result)
+    };
+    let mut lines: Vec<String> =
+        vars.iter().zip(&result.x).map(|(v, x)| format!("{} = {}", v, x)).collect();
+    lines.push(format!("{} = {}", if maximize { "max" } else { "min" }, result.value));
+    lines.push(format!("{}: {}, {} iterations, {} evaluations",
+                       if result.converged { "Converged" } else { "Not converged" },
+                       result.method,
+                       result.iterations,
+                       result.evaluations));
+    Ok(lines.join("\n"))
+}
+
+// analyze(f, x, [a,b]): the derivatives, a table of the notable points and
+// the intervals where f rises, falls and bends each way.
+fn analyze_command(args: &[&str], context: &Context) -> Result<String, String> {
+    expect_args("analyze", args, &[3], "analyze(f, x, [a,b])")?;
+    let expr = context.expr(args[0])?;
+    let var = context.var(args[1])?;
+    let (a, b) = context.interval(args[2])?;
+    let analysis = analysis::analyze(&expr, &var, a, b, context.variables)?;
+    let mut output = format!("f'({0}) = {1}\nf''({0}) = {2}\n\n",
+                             var,
+                             analysis.derivative,
+                             analysis.second_derivative);
+    if analysis.features.is_empty() {
+        output.push_str(&format!("No zeros, critical points or inflection points in [{}, {}]\n",
+                                 a,
+                                 b));
+    } else {
+        let mut rows = vec![vec![var.clone(),
+                                 format!("f({})", var),
+                                 format!("f'({})", var),
+                                 format!("f''({})", var)]];
+        for feature in &analysis.features {
+            rows.push([feature.x, feature.value, feature.slope, feature.curvature]
+                .iter()
+                .map(|&v| if v.is_nan() { "undefined".to_owned() } else { format_sample(v) })
+                .collect());
+        }
+        let table = format_table(&rows);
+        for (i, line) in table.lines().enumerate() {
+            output.push_str(line);
+            if i > 0 {
+                let kinds: Vec<String> =
+                    analysis.features[i - 1].kinds.iter().map(|k| k.to_string()).collect();
+                output.push_str(&format!("  {}", kinds.join(", ")));
+            }
+            output.push('\n');
+        }
+    }
+    let charts = [("Undefined", &analysis.undefined),
+                  ("Increasing", &analysis.increasing),
+                  ("Decreasing", &analysis.decreasing),
+                  ("Concave up", &analysis.concave_up),
+                  ("Concave down", &analysis.concave_down)];
+    let mut lines: Vec<String> = Vec::new();
+    for &(name, intervals) in &charts {
+        if !intervals.is_empty() {
+            let intervals: Vec<String> = intervals.iter()
+                .map(|&(p, q)| format!("({}, {})", format_sample(p), format_sample(q)))
+                .collect();
+            lines.push(format!("{} on {}", name, intervals.join(", ")));
+        }
+    }
+    for asymptote in &analysis.asymptotes {
+        let end = |point: limit::Point| if point == Point::PosInf { "inf" } else { "-inf" };
+        lines.push(match *asymptote {
+            Asymptote::Vertical(x) => format!("Vertical asymptote {} = {}", var, format_sample(x)),
+            Asymptote::Horizontal(c, point) => {
+                format!("Horizontal asymptote y = {} as {} -> {}",
+                        format_sample(c),
+                        var,
+                        end(point))
+            }
+            Asymptote::Oblique(m, c, point) => {
+                let slope = match format_sample(m).as_str() {
+                    "1" => var.clone(),
+                    "-1" => format!("-{}", var),
+                    m => format!("{}*{}", m, var),
+                };
+                let intercept = match format_sample(c.abs()).as_str() {
+                    "0" => String::new(),
+                    c_abs if c < 0.0 => format!(" - {}", c_abs),
+                    c_abs => format!(" + {}", c_abs),
+                };
+                format!("Oblique asymptote y = {}{} as {} -> {}", slope, intercept, var, end(point))
+            }
+        });
+    }
+    if let Some((x, y)) = analysis.minimum {
+        lines.push(format!("Absolute minimum f({}) = {}", format_sample(x), format_sample(y)));
+    }
+    if let Some((x, y)) = analysis.maximum {
+        lines.push(format!("Absolute maximum f({}) = {}", format_sample(x), format_sample(y)));
+    }
+    output.push_str(&lines.join("\n"));
+    Ok(output.trim_end().to_owned())
+}
+
+// An exact value and, unless it is the same, its decimal.
+fn exact_and_decimal(exact: &Expression, value: f64) -> String {
+    if exact.to_string() == value.to_string() {
+        value.to_string()
+    } else {
+        format!("{}\n{}", exact, value)
+    }
+}
+
+// Ten significant digits, in scientific notation when very large or small.
+fn format_sample(x: f64) -> String {
+    if x == 0.0 || (1e-4..1e10).contains(&x.abs()) {
+        let digits = (9 - x.abs().log10().floor().max(0.0) as i32).max(0) as usize;
+        let fixed = format!("{:.*}", digits, x);
+        let fixed = if fixed.contains('.') { fixed.trim_end_matches('0') } else { &fixed };
+        fixed.trim_end_matches('.').to_owned()
+    } else {
+        format!("{:.9e}", x)
+    }
+}
+
+// Right aligned columns, one line per row.
+fn format_table(rows: &[Vec<String>]) -> String {
+    let columns = rows.iter().map(|r| r.len()).max().unwrap_or(0);
+    let widths: Vec<usize> = (0..columns)
+        .map(|j| rows.iter().filter_map(|r| r.get(j)).map(|c| c.len()).max().unwrap_or(0))
+        .collect();
+    let mut output = String::new();
+    for row in rows {
+        let cells: Vec<String> =
+            row.iter().zip(&widths).map(|(c, w)| format!("{:>w$}", c, w = w)).collect();
+        output.push_str(&cells.join("  "));
+        output.push('\n');
+    }
+    output
+}
@@ -0,0 +1,201 @@
+use expression::numeric::Numeric;
+
+use std::f64;
+use std::fmt;
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Complex {
+    pub re: f64,
+    pub im: f64,
+}
+
+impl Complex {
+    pub fn new(re: f64, im: f64) -> Self {
+        Complex { re, im }
+    }
+
+    pub fn i() -> Self {
+        Complex::new(0.0, 1.0)
+    }
+
+    pub fn conj(self) -> Self {
+        Complex::new(self.re, -self.im)
+    }
+
+    pub fn norm(self) -> f64 {
+        self.re.hypot(self.im)
+    }
+
+    pub fn arg(self) -> f64 {
+        self.im.atan2(self.re)
+    }
+
+    pub fn from_polar(r: f64, theta: f64) -> Self {
+        Complex::new(r * theta.cos(), r * theta.sin())
+    }
+
+    pub fn scale(self, k: f64) -> Self {
+        Complex::new(self.re * k, self.im * k)
+    }
+}
+
+impl fmt::Display for Complex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.im == 0.0 {
+            write!(f, "{}", self.re)
+        } else if self.re == 0.0 {
+            write!(f, "{}i", self.im)
+        } else if self.im < 0.0 {
+            write!(f, "{} - {}i", self.re, -self.im)
+        } else {
+            write!(f, "{} + {}i", self.re, self.im)
+        }
+    }
+}
+
+impl Add for Complex {
+    type Output = Complex;
+    fn add(self, other: Complex) -> Complex {
+        Complex::new(self.re + other.re, self.im + other.im)
+    }
+}
+
+impl Sub for Complex {
+    type Output = Complex;
+    fn sub(self, other: Complex) -> Complex {
+        Complex::new(self.re - other.re, self.im - other.im)
+    }
+}
+
+impl Mul for Complex {
+    type Output = Complex;
+    fn mul(self, other: Complex) -> Complex {
+        Complex::new(self.re * other.re - self.im * other.im,
+                     self.re * other.im + self.im * other.re)
+    }
+}
+
+impl Div for Complex {
+    type Output = Complex;
+    fn div(self, other: Complex) -> Complex {
+        let denom = other.re * other.re + other.im * other.im;
+        Complex::new((self.re * other.re + self.im * other.im) / denom,
+                     (self.im * other.re - self.re * other.im) / denom)
+    }
+}
+
+impl Neg for Complex {
+    type Output = Complex;
+    fn neg(self) -> Complex {
+        Complex::new(-self.re, -self.im)
+    }
+}
+
+impl Numeric for Complex {
+    fn from_f64(x: f64) -> Self {
+        Complex::new(x, 0.0)
+    }
+
+    fn pi() -> Self {
+        Complex::from_f64(f64::consts::PI)
+    }
+
+    fn e() -> Self {
+        Complex::from_f64(f64::consts::E)
+    }
+
+    fn rem(self, other: Self) -> Self {
+        let q = self / other;
+        self - other * Complex::new(q.re.trunc(), q.im.trunc())
+    }
+
+    fn pow(self, other: Self) -> Self {
+        if self.re == 0.0 && self.im == 0.0 {
+            if other.re == 0.0 && other.im == 0.0 {
+                return Complex::one();
+            }
+            return Complex::zero();
+        }
+        (other * self.ln()).exp()
+    }
+
+    // Complex numbers are unordered, compare on the real axis.
+    fn max(self, other: Self) -> Self {
+        if other.re > self.re { other } else { self }
+    }
+
+    fn abs(self) -> Self {
+        Complex::from_f64(self.norm())
+    }
+
+    fn exp(self) -> Self {
+        Complex::from_polar(self.re.exp(), self.im)
+    }
+
+    fn sqrt(self) -> Self {
+        Complex::from_polar(self.norm().sqrt(), self.arg() / 2.0)
+    }
+
+    fn ln(self) -> Self {
+        Complex::new(self.norm().ln(), self.arg())
+    }
+
+    fn sin(self) -> Self {
+        Complex::new(self.re.sin() * self.im.cosh(),
+                     self.re.cos() * self.im.sinh())
+    }
+
+    fn cos(self) -> Self {
+        Complex::new(self.re.cos() * self.im.cosh(),
+                     -self.re.sin() * self.im.sinh())
+    }
+
+    fn tan(self) -> Self {
+        self.sin() / self.cos()
+    }
+
+    fn asin(self) -> Self {
+        let i = Complex::i();
+        -i * (i * self + (Complex::one() - self * self).sqrt()).ln()
+    }
+
+    fn acos(self) -> Self {
+        let i = Complex::i();
+        -i * (self + i * (Complex::one() - self * self).sqrt()).ln()
+    }
+
+    fn atan(self) -> Self {
+        let i = Complex::i();
+        let one = Complex::one();
+        (i / Complex::from_f64(2.0)) * ((one - i * self).ln() - (one + i * self).ln())
+    }
+
+    fn sinh(self) -> Self {
+        Complex::new(self.re.sinh() * self.im.cos(),
+                     self.re.cosh() * self.im.sin())
+    }
+
+    fn cosh(self) -> Self {
+        Complex::new(self.re.cosh() * self.im.cos(),
+                     self.re.sinh() * self.im.sin())
+    }
+
+    fn tanh(self) -> Self {
+        self.sinh() / self.cosh()
+    }
+
+    fn asinh(self) -> Self {
+        (self + (self * self + Complex::one()).sqrt()).ln()
+    }
+
+    fn acosh(self) -> Self {
+        let one = Complex::one();
+        (self + (self + one).sqrt() * (self - one).sqrt()).ln()
+    }
+
+    fn atanh(self) -> Self {
+        let one = Complex::one();
+        ((one + self).ln() - (one - self).ln()).scale(0.5)
+    }
+}
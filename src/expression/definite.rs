@@ -0,0 +1,441 @@
+use expression;
+use expression::bytecode;
+use expression::enums::{self, Function};
+use expression::eval::{eval_function, eval_postfix_expr};
+use expression::fourier::closed_form;
+use expression::integrate::antiderivative;
+use expression::limit::{self, Direction, LimitValue, Point};
+use expression::quadrature::{self, Improper};
+use expression::rational::Rational;
+use expression::series::{fraction, snap};
+use expression::symbolic::{self, apply, div, mul, number, pow};
+
+pub type Expression = expression::Expression;
+
+use std::collections::{BTreeMap, HashMap};
+use std::f64::consts::PI;
+
+const TOLERANCE: f64 = 1e-10;
+
+// An end of the interval of integration.
+#[derive(Debug, Clone)]
+pub enum End {
+    Finite(Expression),
+    PosInf,
+    NegInf,
+}
+
+impl End {
+    fn value(&self, values: &HashMap<String, f64>) -> f64 {
+        match *self {
+            End::Finite(ref e) => eval_postfix_expr(e, values),
+            End::PosInf => f64::INFINITY,
+            End::NegInf => f64::NEG_INFINITY,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Definite {
+    // F(b) - F(a) when the antiderivative F agrees with the quadrature.
+    pub exact: Option<Expression>,
+    pub value: f64,
+    pub numeric: Improper,
+}
+
+// The integral of `expr` over [a, b]. It is computed by quadrature, where
+// either end may be infinite, integrable singularities of the integrand are
+// split off and divergence is an error; with `principal` singularities inside
+// the interval give the Cauchy principal value. When an antiderivative is
+// found, F(b) - F(a) in closed form is kept if it matches the quadrature,
+// which it does not when F jumps inside the interval.
+pub fn integrate(expr: &Expression,
+                 var: &str,
+                 a: &End,
+                 b: &End,
+                 principal: bool,
+                 values: &HashMap<String, f64>)
+                 -> Result<Definite, String> {
+    let mut f = bytecode::bind(expr, &[var.to_owned()], values)?;
+    let numeric = quadrature::integrate_improper(|x| f.eval1(x),
+                                                 a.value(values),
+                                                 b.value(values),
+                                                 TOLERANCE,
+                                                 principal)?;
+    let exact = fundamental(expr, var, a, b, values).and_then(|exact| {
+        let value = eval_postfix_expr(&exact, values);
+        let scale = numeric.quadrature.value.abs().max(1.0);
+        let agrees = (value - numeric.quadrature.value).abs() <=
+                     (1e-8 * scale).max(10.0 * numeric.quadrature.error);
+        if agrees { Some((exact, value)) } else { None }
+    });
+    Ok(match exact {
+        Some((exact, value)) => {
+            Definite {
+                exact: Some(exact),
+                value,
+                numeric,
+            }
+        }
+        None => {
+            Definite {
+                exact: None,
+                value: numeric.quadrature.value,
+                numeric,
+            }
+        }
+    })
+}
+
+// F(b) - F(a), with the limit of F at an infinite end.
+fn fundamental(expr: &Expression,
+               var: &str,
+               a: &End,
+               b: &End,
+               values: &HashMap<String, f64>)
+               -> Option<Expression> {
+    let difference = closed_integral(expr, var, a, b, values)?;
+    if !eval_postfix_expr(&difference, values).is_finite() {
+        return None;
+    }
+    Some(difference)
+}
+
+// F(b) - F(a) as an expression, unchecked, for limits or integrands with
+// unassigned variables that quadrature cannot evaluate.
+pub fn closed_integral(expr: &Expression,
+                       var: &str,
+                       a: &End,
+                       b: &End,
+                       values: &HashMap<String, f64>)
+                       -> Option<Expression> {
+    let primitive = antiderivative(expr, var)?;
+    let at = |end: &End| -> Option<Expression> {
+        let point = match *end {
+            End::Finite(ref e) => return Some(symbolic::substitute(&primitive, var, e)),
+            End::PosInf => Point::PosInf,
+            End::NegInf => Point::NegInf,
+        };
+        match limit::limit(&primitive, var, point, Direction::Both, values) {
+            LimitValue::Finite(x) => Some(closed_form(x)),
+            _ => None,
+        }
+    };
+    Some(exact(&symbolic::sub(at(b)?, at(a)?)))
+}
+
+// `expr` with exact values of functions filled in and like terms collected,
+// e.g. -cos(pi) + cos(0) as 2.
+pub fn exact(expr: &Expression) -> Expression {
+    let evaluated = evaluate(expr);
+    collect(&evaluated).unwrap_or(evaluated)
+}
+
+// Rebuilds `expr` with the functions that have exact values at their
+// arguments replaced by them, e.g. cos(pi/3) by 1/2 or atan(1) by pi/4.
+fn evaluate(expr: &Expression) -> Expression {
+    let mut args: Vec<Expression> = symbolic::operands(expr).iter().map(evaluate).collect();
+    match *symbolic::root(expr) {
+        enums::Token::Func(f) if args.len() == 1 => {
+            let u = args.pop().unwrap();
+            special(f, &u).unwrap_or_else(|| apply(f, u))
+        }
+        enums::Token::Op(enums::Operator::Pow) if args.len() == 2 => {
+            surd_power(&args[0], &args[1]).unwrap_or_else(|| pow(args[0].clone(), args[1].clone()))
+        }
+        ref token => symbolic::combine(token, args).unwrap_or_else(|| expr.clone()),
+    }
+}
+
+fn special(f: Function, u: &Expression) -> Option<Expression> {
+    match f {
+        Function::Exp if *symbolic::root(u) == enums::Token::Func(Function::Ln) => {
+            return symbolic::operands(u).pop();
+        }
+        Function::Ln if *symbolic::root(u) == enums::Token::Func(Function::Exp) => {
+            return symbolic::operands(u).pop();
+        }
+        _ => {}
+    }
+    if !symbolic::is_numeric(u) {
+        return None;
+    }
+    let v = eval_postfix_expr(u, &HashMap::new());
+    match f {
+        Function::Sin | Function::Cos | Function::Tan | Function::Csc | Function::Sec |
+        Function::Cot => {
+            // Multiples of pi/6 and pi/4, as twelfths of pi.
+            let q = Rational::approximate(v / PI, 12, 1e-12)?;
+            if 12 % q.denom() != 0 || q.denom() == 12 {
+                return None;
+            }
+            let k = (q * Rational::from_integer(12)).numer();
+            let (sine, cosine) = (sine_twelfths(k), sine_twelfths(k + 6));
+            let zero = |e: &Expression| symbolic::is_number(e, 0.0);
+            match f {
+                Function::Sin => Some(sine),
+                Function::Cos => Some(cosine),
+                Function::Tan if !zero(&cosine) => Some(div(sine, cosine)),
+                Function::Cot if !zero(&sine) => Some(div(cosine, sine)),
+                Function::Sec if !zero(&cosine) => Some(div(number(1.0), cosine)),
+                Function::Csc if !zero(&sine) => Some(div(number(1.0), sine)),
+                _ => None,
+            }
+        }
+        Function::Asin | Function::Acos | Function::Atan | Function::Acsc | Function::Asec |
+        Function::Acot => {
+            let angle: f64 = eval_function(&f, v);
+            let q = Rational::approximate(angle / PI, 12, 1e-12)?;
+            if 12 % q.denom() != 0 {
+                return None;
+            }
+            Some(mul(fraction(q.to_f64()), symbolic::constant(enums::Constant::Pi)))
+        }
+        Function::Abs => Some(if v < 0.0 { symbolic::neg(u.clone()) } else { u.clone() }),
+        Function::Sqrt => surd(snap(v)?),
+        Function::Ln if v > 0.0 => {
+            // ln(p^k / q^j) = k ln(p) - j ln(q) for the smallest such p, q,
+            // halved for the square root of a fraction.
+            let (q, scale) = match snap(v) {
+                Some(q) => (q, 1.0),
+                None => (snap(v * v)?, 0.5),
+            };
+            let (p, k) = perfect_power(q.numer())?;
+            let (r, j) = perfect_power(q.denom())?;
+            let log = |base: i128, k: i128| if base == 1 {
+                number(0.0)
+            } else {
+                mul(fraction(scale * k as f64), apply(Function::Ln, number(base as f64)))
+            };
+            Some(symbolic::sub(log(p, k), log(r, j)))
+        }
+        _ => None,
+    }
+}
+
+// sin(k pi / 12) for k a multiple of 2 or 3.
+fn sine_twelfths(k: i128) -> Expression {
+    let k = k.rem_euclid(24);
+    let (sign, k) = if k > 12 { (-1.0, k - 12) } else { (1.0, k) };
+    let k = if k > 6 { 12 - k } else { k };
+    let value = match k {
+        0 => number(0.0),
+        2 => div(number(1.0), number(2.0)),
+        3 => div(apply(Function::Sqrt, number(2.0)), number(2.0)),
+        4 => div(apply(Function::Sqrt, number(3.0)), number(2.0)),
+        _ => number(1.0),
+    };
+    if sign < 0.0 { symbolic::neg(value) } else { value }
+}
+
+// n = p^k with k as large as possible, None when n is too large to factor.
+fn perfect_power(n: i128) -> Option<(i128, i128)> {
+    if n > 1 << 40 {
+        return None;
+    }
+    for k in (2..41).rev() {
+        let p = (n as f64).powf(1.0 / k as f64).round() as i128;
+        if p > 1 && (0..k).try_fold(1i128, |m, _| m.checked_mul(p)) == Some(n) {
+            return Some((p, k as i128));
+        }
+    }
+    Some((n, 1))
+}
+
+// sqrt(q) with square factors taken out, sqrt(p/q) = sqrt(p q)/q.
+fn surd(q: Rational) -> Option<Expression> {
+    if q.numer() < 0 || q.numer() > 1 << 40 || q.denom() > 1 << 20 {
+        return None;
+    }
+    let mut inside = q.numer() * q.denom();
+    let mut outside = 1;
+    let mut d = 2;
+    while d * d <= inside {
+        while inside % (d * d) == 0 {
+            inside /= d * d;
+            outside *= d;
+        }
+        d += 1;
+    }
+    let coefficient = fraction(outside as f64 / q.denom() as f64);
+    Some(mul(coefficient, apply(Function::Sqrt, number(inside as f64))))
+}
+
+// q^(k/2) as q^floor(k/2) sqrt(q)^(k mod 2), e.g. 2^1.5 = 2 sqrt(2).
+fn surd_power(base: &Expression, exponent: &Expression) -> Option<Expression> {
+    let q = snap(symbolic::as_number(base)?).filter(|q| q.numer() > 0)?;
+    let e = symbolic::as_number(exponent)?;
+    if (2.0 * e).fract() != 0.0 || e.fract() == 0.0 || e.abs() > 16.0 {
+        return None;
+    }
+    let whole = e.floor();
+    let power = fraction(q.to_f64().powi(whole as i32));
+    Some(mul(power, surd(q)?))
+}
+
+// A sum of rational multiples of products of powers of atoms, the parts
+// that do not simplify further like pi, sqrt(2), ln(3) or exp(1).
+type Monomial = Vec<(String, i32)>;
+type Combination = BTreeMap<Monomial, Rational>;
+
+// `expr` with like terms collected and rational coefficients added up, so
+// 8/3 - 1/3 becomes 7/3 and 2*ln(2) - 2 + 1 becomes 2*ln(2) - 1.
+fn collect(expr: &Expression) -> Option<Expression> {
+    let mut atoms: HashMap<String, Expression> = HashMap::new();
+    let combination = linear(expr, &mut atoms)?;
+    let mut terms: Vec<(Monomial, Rational)> = combination.into_iter()
+        .filter(|&(_, c)| !c.is_zero())
+        .collect();
+    if terms.iter().any(|&(_, c)| !c.is_valid()) {
+        return None;
+    }
+    // Constants last, but a positive term first: 1 - exp(-2) over -exp(-2) + 1.
+    terms.sort_by_key(|(m, _)| m.is_empty());
+    if let Some(i) = terms.iter().position(|&(_, c)| c.numer() > 0) {
+        let first = terms.remove(i);
+        terms.insert(0, first);
+    }
+    let mut result: Option<Expression> = None;
+    for (monomial, c) in terms {
+        let (mut above, mut below) = (number(c.numer().abs() as f64), number(c.denom() as f64));
+        for (atom, k) in monomial {
+            let factor = pow(atoms[&atom].clone(), number(k.abs() as f64));
+            if k > 0 {
+                above = mul(above, factor);
+            } else {
+                below = mul(below, factor);
+            }
+        }
+        let term = div(above, below);
+        result = Some(match result {
+            None if c.numer() < 0 => symbolic::neg(term),
+            None => term,
+            Some(sum) if c.numer() < 0 => symbolic::sub(sum, term),
+            Some(sum) => symbolic::add(sum, term),
+        });
+    }
+    Some(result.unwrap_or_else(|| number(0.0)))
+}
+
+fn linear(expr: &Expression, atoms: &mut HashMap<String, Expression>) -> Option<Combination> {
+    if let Some(x) = symbolic::as_number(expr) {
+        let mut c = Combination::new();
+        c.insert(Vec::new(), snap(x)?);
+        return Some(c);
+    }
+    let args = symbolic::operands(expr);
+    match *symbolic::root(expr) {
+        enums::Token::Op(enums::Operator::Add) => {
+            Some(sum(linear(&args[0], atoms)?, linear(&args[1], atoms)?, Rational::one()))
+        }
+        enums::Token::Op(enums::Operator::Sub) => {
+            Some(sum(linear(&args[0], atoms)?, linear(&args[1], atoms)?, -Rational::one()))
+        }
+        enums::Token::Op(enums::Operator::Negate) => {
+            Some(sum(Combination::new(), linear(&args[0], atoms)?, -Rational::one()))
+        }
+        enums::Token::Op(enums::Operator::Mul) => {
+            Some(product(&linear(&args[0], atoms)?, &linear(&args[1], atoms)?))
+        }
+        enums::Token::Op(enums::Operator::Div) => {
+            let denominator = linear(&args[1], atoms)?;
+            match reciprocal(&denominator) {
+                Some(r) => Some(product(&linear(&args[0], atoms)?, &r)),
+                None => Some(atom(expr, atoms)),
+            }
+        }
+        enums::Token::Op(enums::Operator::Pow) => {
+            let k = symbolic::as_number(&args[1]).filter(|k| k.fract() == 0.0 && k.abs() <= 8.0);
+            let k = match k {
+                Some(k) => k as i32,
+                None => return Some(atom(expr, atoms)),
+            };
+            let mut base = linear(&args[0], atoms)?;
+            if k < 0 {
+                base = match reciprocal(&base) {
+                    Some(r) => r,
+                    None => return Some(atom(expr, atoms)),
+                };
+            }
+            let mut result = Combination::new();
+            result.insert(Vec::new(), Rational::one());
+            for _ in 0..k.abs() {
+                result = product(&result, &base);
+            }
+            Some(result)
+        }
+        _ => Some(atom(expr, atoms)),
+    }
+}
+
+fn atom(expr: &Expression, atoms: &mut HashMap<String, Expression>) -> Combination {
+    let name = symbolic::to_infix(expr);
+    atoms.insert(name.clone(), expr.clone());
+    let mut c = Combination::new();
+    c.insert(vec![(name, 1)], Rational::one());
+    c
+}
+
+// a + s b
+fn sum(mut a: Combination, b: Combination, s: Rational) -> Combination {
+    for (monomial, c) in b {
+        let entry = a.entry(monomial).or_insert_with(Rational::zero);
+        *entry = *entry + s * c;
+    }
+    a
+}
+
+fn product(a: &Combination, b: &Combination) -> Combination {
+    let mut result = Combination::new();
+    for (ma, ca) in a {
+        for (mb, cb) in b {
+            let mut powers: BTreeMap<String, i32> = ma.iter().cloned().collect();
+            for (atom, k) in mb {
+                *powers.entry(atom.clone()).or_insert(0) += *k;
+            }
+            let monomial: Monomial = powers.into_iter().filter(|&(_, k)| k != 0).collect();
+            let entry = result.entry(monomial).or_insert_with(Rational::zero);
+            *entry = *entry + *ca * *cb;
+        }
+    }
+    result
+}
+
+// 1/c for a single term c, None for sums.
+fn reciprocal(c: &Combination) -> Option<Combination> {
+    let terms: Vec<(&Monomial, &Rational)> = c.iter().filter(|&(_, c)| !c.is_zero()).collect();
+    if terms.len() != 1 {
+        return None;
+    }
+    let (monomial, c) = terms[0];
+    let mut result = Combination::new();
+    result.insert(monomial.iter().map(|&(ref atom, k)| (atom.clone(), -k)).collect(),
+                  c.recip());
+    Some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use expression::parse::parse;
+
+    fn end(bound: &str) -> End {
+        End::Finite(parse(bound))
+    }
+
+    #[test]
+    fn singular_ends_keep_the_exact_value() {
+        let values = HashMap::new();
+        let result =
+            integrate(&parse("1/sqrt(x)"), "x", &end("0"), &end("1"), false, &values).unwrap();
+        assert_eq!(result.exact.map(|e| e.to_string()), Some("2".to_owned()));
+        assert_eq!(result.value, 2.0);
+    }
+
+    #[test]
+    fn unassigned_limits_stay_symbolic() {
+        let values = HashMap::new();
+        let closed = closed_integral(&parse("1/(1+x^2)"), "x", &end("0"), &end("a"), &values);
+        assert_eq!(closed.map(|e| e.to_string()), Some("atan(a)".to_owned()));
+    }
+}
@@ -0,0 +1,163 @@
+use expression;
+use expression::enums;
+use expression::symbolic::{self, add, apply, div, mul, neg, number, pow, sub};
+
+pub type Expression = expression::Expression;
+
+// Symbolic derivative of `expr` with respect to `var`.
+pub fn differentiate(expr: &Expression, var: &str) -> Expression {
+    if expr.is_empty() || !symbolic::contains_var(expr, var) {
+        return number(0.0);
+    }
+    let mut args = symbolic::operands(expr);
+    match *symbolic::root(expr) {
+        enums::Token::Var(ref x) => number(if x == var { 1.0 } else { 0.0 }),
+        enums::Token::Op(enums::Operator::Negate) => neg(differentiate(&args[0], var)),
+        enums::Token::Op(op) => {
+            let b = args.pop().unwrap();
+            let a = args.pop().unwrap();
+            differentiate_operator(op, a, b, var)
+        }
+        enums::Token::Func(f @ enums::Function::Max) |
+        enums::Token::Func(f @ enums::Function::LogBase) => {
+            let b = args.pop().unwrap();
+            let a = args.pop().unwrap();
+            differentiate_binary_function(f, a, b, var)
+        }
+        enums::Token::Func(f) => {
+            let a = args.pop().unwrap();
+            let da = differentiate(&a, var);
+            mul(function_derivative(f, a), da)
+        }
+        _ => number(0.0),
+    }
+}
+
+fn differentiate_operator(op: enums::Operator,
+                          a: Expression,
+                          b: Expression,
+                          var: &str)
+                          -> Expression {
+    let da = differentiate(&a, var);
+    let db = differentiate(&b, var);
+    match op {
+        enums::Operator::Add => add(da, db),
+        enums::Operator::Sub => sub(da, db),
+        enums::Operator::Mul => add(mul(da, b), mul(a, db)),
+        enums::Operator::Div => {
+            if !symbolic::contains_var(&b, var) {
+                return div(da, b);
+            }
+            div(sub(mul(da, b.clone()), mul(a, db)), pow(b, number(2.0)))
+        }
+        // a % b == a - b*trunc(a/b) and trunc(a/b) == (a - a % b)/b is
+        // piecewise constant.
+        enums::Operator::Mod => {
+            let quotient = div(sub(a.clone(), symbolic::rem(a, b.clone())), b);
+            sub(da, mul(db, quotient))
+        }
+        enums::Operator::Pow => {
+            if !symbolic::contains_var(&b, var) {
+                let exponent = sub(b.clone(), number(1.0));
+                return mul(mul(b, pow(a, exponent)), da);
+            }
+            let ln_a = apply(enums::Function::Ln, a.clone());
+            if !symbolic::contains_var(&a, var) {
+                return mul(mul(pow(a, b), ln_a), db);
+            }
+            let inner = add(mul(db, ln_a), div(mul(b.clone(), da), a.clone()));
+            mul(pow(a, b), inner)
+        }
+        enums::Operator::Negate => neg(db),
+    }
+}
+
+fn differentiate_binary_function(f: enums::Function,
+                                 a: Expression,
+                                 b: Expression,
+                                 var: &str)
+                                 -> Expression {
+    match f {
+        // logbase(a, b) == ln(a)/ln(b)
+        enums::Function::LogBase => {
+            let rewritten = div(apply(enums::Function::Ln, a), apply(enums::Function::Ln, b));
+            differentiate(&rewritten, var)
+        }
+        // max(a, b) == (a + b + abs(a - b))/2
+        _ => {
+            let da = differentiate(&a, var);
+            let db = differentiate(&b, var);
+            let diff = sub(a, b);
+            let sign = div(diff.clone(), apply(enums::Function::Abs, diff));
+            div(add(add(da.clone(), db.clone()), mul(sub(da, db), sign)),
+                number(2.0))
+        }
+    }
+}
+
+// d/du f(u), the caller applies the chain rule.
+fn function_derivative(f: enums::Function, u: Expression) -> Expression {
+    let one = || number(1.0);
+    let square = |e: &Expression| pow(e.clone(), number(2.0));
+    let call = |g: enums::Function, e: &Expression| apply(g, e.clone());
+    match f {
+        enums::Function::Abs => div(u.clone(), call(enums::Function::Abs, &u)),
+        enums::Function::Exp => call(enums::Function::Exp, &u),
+        enums::Function::Sqrt => div(one(), mul(number(2.0), call(enums::Function::Sqrt, &u))),
+        enums::Function::Ln => div(one(), u),
+        enums::Function::Log => div(one(), mul(u, apply(enums::Function::Ln, number(10.0)))),
+        enums::Function::Recip => neg(div(one(), square(&u))),
+        enums::Function::Sin => call(enums::Function::Cos, &u),
+        enums::Function::Cos => neg(call(enums::Function::Sin, &u)),
+        enums::Function::Tan => square(&call(enums::Function::Sec, &u)),
+        enums::Function::Csc => {
+            neg(mul(call(enums::Function::Csc, &u), call(enums::Function::Cot, &u)))
+        }
+        enums::Function::Sec => mul(call(enums::Function::Sec, &u), call(enums::Function::Tan, &u)),
+        enums::Function::Cot => neg(square(&call(enums::Function::Csc, &u))),
+        enums::Function::Asin => {
+            div(one(), call(enums::Function::Sqrt, &sub(one(), square(&u))))
+        }
+        enums::Function::Acos => {
+            neg(div(one(), call(enums::Function::Sqrt, &sub(one(), square(&u)))))
+        }
+        enums::Function::Atan => div(one(), add(one(), square(&u))),
+        enums::Function::Acot => neg(div(one(), add(one(), square(&u)))),
+        enums::Function::Asec => {
+            div(one(),
+                mul(call(enums::Function::Abs, &u),
+                    call(enums::Function::Sqrt, &sub(square(&u), one()))))
+        }
+        enums::Function::Acsc => {
+            neg(div(one(),
+                    mul(call(enums::Function::Abs, &u),
+                        call(enums::Function::Sqrt, &sub(square(&u), one())))))
+        }
+        enums::Function::Sinh => call(enums::Function::Cosh, &u),
+        enums::Function::Cosh => call(enums::Function::Sinh, &u),
+        enums::Function::Tanh => square(&call(enums::Function::Sech, &u)),
+        enums::Function::Csch => {
+            neg(mul(call(enums::Function::Csch, &u), call(enums::Function::Coth, &u)))
+        }
+        enums::Function::Sech => {
+            neg(mul(call(enums::Function::Sech, &u), call(enums::Function::Tanh, &u)))
+        }
+        enums::Function::Coth => neg(square(&call(enums::Function::Csch, &u))),
+        enums::Function::Asinh => {
+            div(one(), call(enums::Function::Sqrt, &add(square(&u), one())))
+        }
+        enums::Function::Acosh => {
+            div(one(), call(enums::Function::Sqrt, &sub(square(&u), one())))
+        }
+        enums::Function::Atanh | enums::Function::Acoth => div(one(), sub(one(), square(&u))),
+        enums::Function::Acsch => {
+            neg(div(one(),
+                    mul(call(enums::Function::Abs, &u),
+                        call(enums::Function::Sqrt, &add(one(), square(&u))))))
+        }
+        enums::Function::Asech => {
+            neg(div(one(), mul(u.clone(), call(enums::Function::Sqrt, &sub(one(), square(&u))))))
+        }
+        enums::Function::Max | enums::Function::LogBase => number(0.0), // binary, see above
+    }
+}
@@ -0,0 +1,740 @@
+use expression;
+use expression::derivative::differentiate;
+use expression::enums;
+use expression::eval::eval_postfix_expr;
+use expression::integrate::antiderivative;
+use expression::linalg;
+use expression::rational::Rational;
+use expression::series::{fraction, snap};
+use expression::symbolic::{self, add, apply, div, mul, neg, number, pow, sub};
+
+pub type Expression = expression::Expression;
+
+use std::collections::HashMap;
+use std::fmt;
+
+// Closed form general solutions of
+//   separable equations        y' = g(x) h(y),
+//   first order linear ones    a(x) y' + b(x) y = c(x),
+//   and constant coefficient   a y'' + b y' + c y = g(x),
+// with the integration constants c1 and c2. Derivatives of y appear in the
+// equation as the variables named by `derivative_name`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Kind {
+    Separable,
+    Linear,
+    ConstantCoefficient,
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Kind::Separable => write!(f, "separable"),
+            Kind::Linear => write!(f, "first order linear"),
+            Kind::ConstantCoefficient => write!(f, "second order linear, constant coefficients"),
+        }
+    }
+}
+
+// lhs = rhs, where lhs is y itself unless y could not be isolated.
+#[derive(Debug, Clone)]
+pub struct GeneralSolution {
+    pub lhs: Expression,
+    pub rhs: Expression,
+    pub kind: Kind,
+}
+
+impl fmt::Display for GeneralSolution {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} = {}", self.lhs, self.rhs)
+    }
+}
+
+const FIRST: &str = "c1";
+const SECOND: &str = "c2";
+
+// Names without digits so that they parse as variables.
+pub fn derivative_name(unknown: &str, order: usize) -> String {
+    format!("{}{}", unknown, "prime".repeat(order))
+}
+
+// Solves `equation` = 0 for the function `unknown` of `var`.
+pub fn dsolve(equation: &Expression,
+              unknown: &str,
+              var: &str)
+              -> Result<GeneralSolution, String> {
+    let first = derivative_name(unknown, 1);
+    let second = derivative_name(unknown, 2);
+    let equation = symbolic::simplify(equation);
+    if symbolic::contains_var(&equation, &second) {
+        return constant_coefficient(&equation, unknown, var);
+    }
+    if !symbolic::contains_var(&equation, &first) {
+        return Err(format!("The equation has no derivative of {}", unknown));
+    }
+    let derivatives = [unknown, first.as_str()];
+    let slope = differentiate(&equation, &first);
+    let coefficient = differentiate(&equation, unknown);
+    if is_free_of(&slope, &derivatives) && is_free_of(&coefficient, &derivatives) {
+        return linear(&equation, &slope, &coefficient, unknown, var);
+    }
+    if !is_free_of(&slope, &[&first]) {
+        return Err(format!("The equation is not linear in {}", first));
+    }
+    // y' = f(x, y) from a(x, y) y' + r(x, y) = 0.
+    let rest = symbolic::substitute(&equation, &first, &number(0.0));
+    separable(&neg(div(rest, slope)), unknown, var)
+}
+
+fn is_free_of(expr: &Expression, vars: &[&str]) -> bool {
+    vars.iter().all(|v| !symbolic::contains_var(expr, v))
+}
+
+// y = (integral of mu q + c1) / mu with the integrating factor
+// mu = exp(integral of p) for y' + p y = q.
+fn linear(equation: &Expression,
+          slope: &Expression,
+          coefficient: &Expression,
+          unknown: &str,
+          var: &str)
+          -> Result<GeneralSolution, String> {
+    let zero = number(0.0);
+    let rest = symbolic::substitute(&symbolic::substitute(equation, unknown, &zero),
+                                    &derivative_name(unknown, 1),
+                                    &zero);
+    let p = div(coefficient.clone(), slope.clone());
+    let q = neg(div(rest, slope.clone()));
+    let integral = antiderivative(&p, var)
+        .ok_or_else(|| format!("Could not integrate {} for the integrating factor", p))?;
+    let factor = exponential(&integral);
+    let is_exp = *symbolic::root(&factor) == enums::Token::Func(enums::Function::Exp);
+    let homogeneous = if is_exp {
+        mul(constant(FIRST), exponential(&neg(integral.clone())))
+    } else {
+        div(constant(FIRST), factor.clone())
+    };
+    let rhs = if symbolic::is_number(&q, 0.0) {
+        homogeneous
+    } else {
+        let product = antiderivative(&times_exp(&q, &integral), var)
+            .ok_or_else(|| format!("Could not integrate {} times {}", q, factor))?;
+        let particular = if is_exp {
+            times_exp(&product, &neg(integral))
+        } else {
+            mul(product, pow(factor, number(-1.0)))
+        };
+        add(particular, homogeneous)
+    };
+    Ok(GeneralSolution {
+        lhs: symbolic::variable(unknown),
+        rhs,
+        kind: Kind::Linear,
+    })
+}
+
+// Splits f(x, y) = g(x) h(y) factor by factor, then integrates
+// dy / h(y) = g(x) dx.
+fn separable(f: &Expression, unknown: &str, var: &str) -> Result<GeneralSolution, String> {
+    let (g, h) = split(f, unknown, var).ok_or_else(|| "The equation is not separable".to_owned())?;
+    let left = antiderivative(&reciprocal(&h), unknown)
+        .ok_or_else(|| format!("Could not integrate 1/({}) with respect to {}", h, unknown))?;
+    let right = antiderivative(&g, var)
+        .ok_or_else(|| format!("Could not integrate {} with respect to {}", g, var))?;
+    let (lhs, rhs) = match isolate(&left, unknown, Side::Additive(right.clone())) {
+        Some(rhs) => (symbolic::variable(unknown), rhs),
+        None => (left, add(right, constant(FIRST))),
+    };
+    Ok(GeneralSolution {
+        lhs,
+        rhs,
+        kind: Kind::Separable,
+    })
+}
+
+// (g(x), h(y)) with f = g h, through products, quotients and exp(u + v).
+fn split(f: &Expression, unknown: &str, var: &str) -> Option<(Expression, Expression)> {
+    if !symbolic::contains_var(f, unknown) {
+        return Some((f.clone(), number(1.0)));
+    }
+    if !symbolic::contains_var(f, var) {
+        return Some((number(1.0), f.clone()));
+    }
+    let mut args = symbolic::operands(f);
+    match *symbolic::root(f) {
+        enums::Token::Op(enums::Operator::Negate) => {
+            let (g, h) = split(&args[0], unknown, var)?;
+            Some((neg(g), h))
+        }
+        enums::Token::Op(op) if op == enums::Operator::Mul || op == enums::Operator::Div => {
+            let (gb, hb) = split(&args.pop().unwrap(), unknown, var)?;
+            let (ga, ha) = split(&args.pop().unwrap(), unknown, var)?;
+            Some(if op == enums::Operator::Mul {
+                (mul(ga, gb), mul(ha, hb))
+            } else {
+                (div(ga, gb), div(ha, hb))
+            })
+        }
+        enums::Token::Func(enums::Function::Exp) => {
+            let argument = args.pop().unwrap();
+            let mut terms = symbolic::operands(&argument);
+            let sign = match *symbolic::root(&argument) {
+                enums::Token::Op(enums::Operator::Add) => 1.0,
+                enums::Token::Op(enums::Operator::Sub) => -1.0,
+                _ => return None,
+            };
+            let v = terms.pop().unwrap();
+            let u = terms.pop().unwrap();
+            let v = mul(number(sign), v);
+            let (g, h) = if symbolic::contains_var(&u, unknown) { (v, u) } else { (u, v) };
+            if symbolic::contains_var(&g, unknown) || symbolic::contains_var(&h, var) {
+                return None;
+            }
+            Some((exponential(&g), exponential(&h)))
+        }
+        _ => None,
+    }
+}
+
+// 1/e, turning quotients over and exp(u) into exp(-u).
+fn reciprocal(e: &Expression) -> Expression {
+    let mut args = symbolic::operands(e);
+    match *symbolic::root(e) {
+        enums::Token::Op(enums::Operator::Div) => {
+            let b = args.pop().unwrap();
+            div(b, args.pop().unwrap())
+        }
+        enums::Token::Func(enums::Function::Exp) => exponential(&neg(args.pop().unwrap())),
+        _ => div(number(1.0), e.clone()),
+    }
+}
+
+// The right hand side while y is peeled out of G(y) = F(x) + c1. As long as
+// c1 is still added on its own it can absorb constant factors and turn
+// exp(F + c1) into c1 exp(F).
+#[derive(Clone)]
+enum Side {
+    Additive(Expression),
+    General(Expression),
+}
+
+impl Side {
+    fn map<F: Fn(Expression) -> Expression>(self, f: F) -> Side {
+        match self {
+            Side::Additive(e) => Side::Additive(f(e)),
+            Side::General(e) => Side::General(f(e)),
+        }
+    }
+
+    fn general(self) -> Expression {
+        match self {
+            Side::Additive(e) => add(e, constant(FIRST)),
+            Side::General(e) => e,
+        }
+    }
+}
+
+// Solves lhs = side for `unknown` when it occurs once, through sums,
+// products, powers and invertible functions. None otherwise.
+fn isolate(lhs: &Expression, unknown: &str, side: Side) -> Option<Expression> {
+    if *lhs == symbolic::variable(unknown) {
+        return Some(side.general());
+    }
+    let mut args = symbolic::operands(lhs);
+    let inside: Vec<bool> = args.iter().map(|a| symbolic::contains_var(a, unknown)).collect();
+    if inside.iter().filter(|&&i| i).count() != 1 {
+        return None;
+    }
+    match *symbolic::root(lhs) {
+        enums::Token::Op(enums::Operator::Negate) => {
+            isolate(&args[0], unknown, side.map(neg))
+        }
+        enums::Token::Op(op) => {
+            let b = args.pop().unwrap();
+            let a = args.pop().unwrap();
+            let (u, k) = if inside[0] { (a, b) } else { (b, a) };
+            let additive = matches!(side, Side::Additive(_));
+            let side = match (op, inside[0]) {
+                // c1 absorbs constant terms.
+                (enums::Operator::Add, _) |
+                (enums::Operator::Sub, true) if additive => side,
+                (enums::Operator::Sub, false) if additive => side.map(neg),
+                (enums::Operator::Add, _) => side.map(|e| sub(e, k.clone())),
+                (enums::Operator::Sub, true) => side.map(|e| add(e, k.clone())),
+                (enums::Operator::Sub, false) => side.map(|e| sub(k.clone(), e)),
+                (enums::Operator::Mul, _) => side.map(|e| div(e, k.clone())),
+                (enums::Operator::Div, true) => side.map(|e| mul(e, k.clone())),
+                (enums::Operator::Div, false) => Side::General(div(k, side.general())),
+                (enums::Operator::Pow, true) if symbolic::is_number(&k, 2.0) => {
+                    Side::General(apply(enums::Function::Sqrt, side.general()))
+                }
+                (enums::Operator::Pow, true) => {
+                    Side::General(pow(side.general(), div(number(1.0), k)))
+                }
+                _ => return None,
+            };
+            isolate(&u, unknown, side)
+        }
+        enums::Token::Func(f) if args.len() == 1 => {
+            let side = match (f, side) {
+                (enums::Function::Ln, Side::Additive(e)) => {
+                    Side::General(mul(constant(FIRST), exponential(&e)))
+                }
+                (enums::Function::Ln, side) => Side::General(exponential(&side.general())),
+                (f, side) => Side::General(apply(inverse(f)?, side.general())),
+            };
+            isolate(&args[0], unknown, side)
+        }
+        _ => None,
+    }
+}
+
+fn inverse(f: enums::Function) -> Option<enums::Function> {
+    use expression::enums::Function::*;
+    Some(match f {
+        Exp => Ln,
+        Sin => Asin,
+        Cos => Acos,
+        Tan => Atan,
+        Asin => Sin,
+        Acos => Cos,
+        Atan => Tan,
+        Sinh => Asinh,
+        Asinh => Sinh,
+        Tanh => Atanh,
+        Atanh => Tanh,
+        _ => return None,
+    })
+}
+
+// Roots of a r^2 + b r + c = 0 give the homogeneous solutions y1, y2. A
+// right hand side g(x) made of polynomials, exponentials, sines and cosines
+// gets a particular solution by undetermined coefficients, anything else by
+// variation of parameters with the Wronskian of y1 and y2.
+fn constant_coefficient(equation: &Expression,
+                        unknown: &str,
+                        var: &str)
+                        -> Result<GeneralSolution, String> {
+    let names = [derivative_name(unknown, 2), derivative_name(unknown, 1), unknown.to_owned()];
+    let mut coefficients = Vec::with_capacity(3);
+    let mut rest = equation.clone();
+    for name in &names {
+        let c = symbolic::as_number(&symbolic::simplify(&differentiate(equation, name)))
+            .ok_or_else(|| {
+                "Only linear second order equations with constant coefficients can be solved"
+                    .to_owned()
+            })?;
+        coefficients.push(c);
+        rest = symbolic::substitute(&rest, name, &number(0.0));
+    }
+    let (a, b, c) = (coefficients[0], coefficients[1], coefficients[2]);
+    let g = symbolic::simplify(&neg(rest));
+    let x = symbolic::variable(var);
+    let alpha = -b / (2.0 * a);
+    let discriminant = b * b - 4.0 * a * c;
+    let scale = (b * b).max((4.0 * a * c).abs());
+    // y_i = m_i exp(r_i x). W = w exp(2 alpha x) is their Wronskian and d the
+    // square root of |discriminant| / (2a)^2.
+    let d = (discriminant.abs() / (4.0 * a * a)).sqrt();
+    let radical = surd(d * d);
+    let rate = |center: f64, sign: f64| {
+        if sign == 0.0 {
+            fraction(center)
+        } else if snap(d).is_some() {
+            fraction(center + sign * d)
+        } else if sign > 0.0 {
+            add(fraction(center), radical.clone())
+        } else {
+            sub(fraction(center), radical.clone())
+        }
+    };
+    let (basis, w) = if discriminant.abs() <= 1e-12 * scale {
+        ([(number(1.0), 0.0), (x.clone(), 0.0)], 1.0)
+    } else if discriminant > 0.0 {
+        ([(number(1.0), 1.0), (number(1.0), -1.0)], -2.0 * d)
+    } else {
+        let argument = mul(radical.clone(), x.clone());
+        ([(apply(enums::Function::Cos, argument.clone()), 0.0),
+          (apply(enums::Function::Sin, argument), 0.0)],
+         d)
+    };
+    let y: Vec<Expression> =
+        basis.iter().map(|&(ref m, s)| mul(m.clone(), exp_linear(&rate(alpha, s), &x))).collect();
+    let homogeneous = add(mul(constant(FIRST), y[0].clone()), mul(constant(SECOND), y[1].clone()));
+    if symbolic::is_number(&g, 0.0) {
+        return Ok(GeneralSolution {
+            lhs: symbolic::variable(unknown),
+            rhs: homogeneous,
+            kind: Kind::ConstantCoefficient,
+        });
+    }
+    let particular = match undetermined(&g, &coefficients, var) {
+        Some(particular) => particular,
+        None => {
+            // u1' = -y2 g / (a W) and u2' = y1 g / (a W), with the
+            // exponentials of y_i g / W merged so that the integrator sees a
+            // single one.
+            let u = basis.iter()
+                .map(|&(ref m, s)| {
+                    let integrand =
+                        div(mul(m.clone(), times_exp(&g, &mul(rate(-alpha, s), x.clone()))),
+                            fraction(a * w));
+                    antiderivative(&integrand, var).ok_or_else(|| {
+                        format!("Could not integrate {} for a particular solution", integrand)
+                    })
+                })
+                .collect::<Result<Vec<_>, String>>()?;
+            sub(mul(y[1].clone(), u[0].clone()), mul(y[0].clone(), u[1].clone()))
+        }
+    };
+    Ok(GeneralSolution {
+        lhs: symbolic::variable(unknown),
+        rhs: add(homogeneous, particular),
+        kind: Kind::ConstantCoefficient,
+    })
+}
+
+// sqrt(r) for a rational r with square factors taken out, e.g. sqrt(3)/2
+// for 3/4, or the decimal square root when r is not rational.
+fn surd(r: f64) -> Expression {
+    let fraction = match snap(r) {
+        Some(fraction) => fraction,
+        None => return number(r.sqrt()),
+    };
+    // sqrt(p/q) = sqrt(p q)/q.
+    let (p, q) = (fraction.numer(), fraction.denom());
+    if p * q > 1_000_000_000 {
+        return number(r.sqrt());
+    }
+    let mut inside = p * q;
+    let mut outside = 1;
+    let mut k = 2;
+    while k * k <= inside {
+        if inside % (k * k) == 0 {
+            inside /= k * k;
+            outside *= k;
+        } else {
+            k += 1;
+        }
+    }
+    let root = if inside == 1 {
+        number(outside as f64)
+    } else {
+        mul(number(outside as f64), apply(enums::Function::Sqrt, number(inside as f64)))
+    };
+    div(root, number(q as f64))
+}
+
+// x^j exp(rate x) cos(frequency x) and sin(frequency x) for j up to degree.
+#[derive(Debug, PartialEq, Clone, Copy)]
+struct Mode {
+    rate: f64,
+    frequency: f64,
+    degree: usize,
+}
+
+// The modes g is a combination of, None unless it is built from
+// polynomials, exp, sin, cos, sinh and cosh of linear arguments.
+fn modes(g: &Expression, var: &str) -> Option<Vec<Mode>> {
+    let constant = Mode {
+        rate: 0.0,
+        frequency: 0.0,
+        degree: 0,
+    };
+    if !symbolic::contains_var(g, var) {
+        return Some(vec![constant]);
+    }
+    let slope = |u: &Expression| {
+        let derivative = symbolic::simplify(&differentiate(u, var));
+        symbolic::as_number(&derivative)
+    };
+    let mut args = symbolic::operands(g);
+    Some(match *symbolic::root(g) {
+        enums::Token::Var(_) => vec![Mode { degree: 1, ..constant }],
+        enums::Token::Op(enums::Operator::Negate) => modes(&args[0], var)?,
+        enums::Token::Op(enums::Operator::Add) |
+        enums::Token::Op(enums::Operator::Sub) => {
+            let mut result = modes(&args[0], var)?;
+            result.extend(modes(&args[1], var)?);
+            result
+        }
+        enums::Token::Op(enums::Operator::Mul) => {
+            product_modes(&modes(&args[0], var)?, &modes(&args[1], var)?)
+        }
+        enums::Token::Op(enums::Operator::Div) if !symbolic::contains_var(&args[1], var) => {
+            modes(&args[0], var)?
+        }
+        enums::Token::Op(enums::Operator::Pow) => {
+            let n = symbolic::as_number(&args.pop().unwrap())?;
+            if !(0.0..=8.0).contains(&n) || n.fract() != 0.0 {
+                return None;
+            }
+            let base = modes(&args.pop().unwrap(), var)?;
+            (0..n as usize).fold(vec![constant], |acc, _| product_modes(&acc, &base))
+        }
+        enums::Token::Func(f) => {
+            let k = slope(&args[0])?;
+            match f {
+                enums::Function::Exp => vec![Mode { rate: k, ..constant }],
+                enums::Function::Sin | enums::Function::Cos => {
+                    vec![Mode { frequency: k.abs(), ..constant }]
+                }
+                enums::Function::Sinh | enums::Function::Cosh => {
+                    vec![Mode { rate: k, ..constant }, Mode { rate: -k, ..constant }]
+                }
+                _ => return None,
+            }
+        }
+        _ => return None,
+    })
+}
+
+// sin a sin b and the like give the sum and difference frequencies.
+fn product_modes(a: &[Mode], b: &[Mode]) -> Vec<Mode> {
+    let mut result = Vec::new();
+    for p in a {
+        for q in b {
+            let degree = p.degree + q.degree;
+            let rate = p.rate + q.rate;
+            result.push(Mode {
+                rate,
+                frequency: p.frequency + q.frequency,
+                degree,
+            });
+            if p.frequency != 0.0 && q.frequency != 0.0 {
+                result.push(Mode {
+                    rate,
+                    frequency: (p.frequency - q.frequency).abs(),
+                    degree,
+                });
+            }
+        }
+    }
+    result
+}
+
+// A particular solution fitted in the span of the modes of g, each shifted
+// by x^s when rate + i frequency is an s-fold root of the characteristic
+// polynomial. The coefficients come from least squares at sample points and
+// the result is only kept if it satisfies the equation there.
+fn undetermined(g: &Expression, coefficients: &[f64], var: &str) -> Option<Expression> {
+    let mut merged: Vec<Mode> = Vec::new();
+    for mode in modes(g, var)? {
+        match merged.iter_mut()
+            .find(|m| m.rate == mode.rate && m.frequency == mode.frequency) {
+            Some(m) => m.degree = m.degree.max(mode.degree),
+            None => merged.push(mode),
+        }
+    }
+    let (a, b, c) = (coefficients[0], coefficients[1], coefficients[2]);
+    let x = symbolic::variable(var);
+    let mut basis = Vec::new();
+    for mode in &merged {
+        // p(z) = a z^2 + b z + c and p'(z) at z = rate + i frequency.
+        let (re, im) = (mode.rate, mode.frequency);
+        let p = (a * (re * re - im * im) + b * re + c, 2.0 * a * re * im + b * im);
+        let dp = (2.0 * a * re + b, 2.0 * a * im);
+        let tolerance = 1e-12 * (a.abs() + b.abs() + c.abs());
+        let shift = if p.0.hypot(p.1) > tolerance {
+            0
+        } else if dp.0.hypot(dp.1) > tolerance {
+            1
+        } else {
+            2
+        };
+        let envelope = exp_linear(&fraction(mode.rate), &x);
+        let trig: Vec<Expression> = if mode.frequency == 0.0 {
+            vec![number(1.0)]
+        } else {
+            let argument = mul(fraction(mode.frequency), x.clone());
+            vec![apply(enums::Function::Cos, argument.clone()),
+                 apply(enums::Function::Sin, argument)]
+        };
+        for j in shift..mode.degree + shift + 1 {
+            for t in &trig {
+                let power = pow(x.clone(), number(j as f64));
+                basis.push(mul(mul(power, t.clone()), envelope.clone()));
+            }
+        }
+    }
+    // L[phi] = a phi'' + b phi' + c phi.
+    let operator: Vec<Expression> = basis.iter()
+        .map(|phi| {
+            let first = differentiate(phi, var);
+            let second = differentiate(&first, var);
+            add(add(mul(number(a), second), mul(number(b), first)), mul(number(c), phi.clone()))
+        })
+        .collect();
+    let points: Vec<f64> = (0..4 * basis.len() + 8).map(|i| -1.0 + 0.17 * i as f64).collect();
+    let mut rows = Vec::with_capacity(points.len());
+    let mut rhs = Vec::with_capacity(points.len());
+    for &point in &points {
+        let mut values = HashMap::new();
+        values.insert(var.to_owned(), point);
+        rows.push(operator.iter().map(|l| eval_postfix_expr(l, &values)).collect::<Vec<f64>>());
+        rhs.push(eval_postfix_expr(g, &values));
+    }
+    let solution = linalg::solve_linear(&linalg::gram(&rows), &linalg::mat_t_vec(&rows, &rhs))?;
+    let largest = linalg::norm_inf(&solution);
+    let mut particular = number(0.0);
+    let mut snapped = Vec::with_capacity(solution.len());
+    for (phi, &k) in basis.iter().zip(&solution) {
+        let k = if k.abs() <= 1e-10 * largest { 0.0 } else { k };
+        // Least squares leaves more rounding than snap allows for.
+        let k = Rational::approximate(k, 10000, 1e-10 * k.abs().max(1.0)).map_or(k, |r| r.to_f64());
+        snapped.push(k);
+        if k != 0.0 {
+            particular = add(particular, mul(fraction(k), phi.clone()));
+        }
+    }
+    let residual = linalg::norm_inf(&linalg::mat_vec(&rows, &snapped)
+        .iter()
+        .zip(&rhs)
+        .map(|(l, r)| l - r)
+        .collect::<Vec<f64>>());
+    if residual.is_finite() && residual <= 1e-8 * linalg::norm_inf(&rhs).max(1.0) {
+        Some(particular)
+    } else {
+        None
+    }
+}
+
+fn constant(name: &str) -> Expression {
+    symbolic::variable(name)
+}
+
+// exp(e), with exp(k ln|u|) = u^k since integrating factors and separated
+// solutions only matter up to a constant factor.
+fn exponential(e: &Expression) -> Expression {
+    let (k, rest) = match *symbolic::root(e) {
+        enums::Token::Op(enums::Operator::Negate) => {
+            (number(-1.0), symbolic::operands(e)[0].clone())
+        }
+        enums::Token::Op(enums::Operator::Mul) => {
+            let mut args = symbolic::operands(e);
+            let b = args.pop().unwrap();
+            (args.pop().unwrap(), b)
+        }
+        _ => (number(1.0), e.clone()),
+    };
+    if symbolic::as_number(&k).is_some() &&
+       *symbolic::root(&rest) == enums::Token::Func(enums::Function::Ln) {
+        let mut u = symbolic::operands(&rest).pop().unwrap();
+        if *symbolic::root(&u) == enums::Token::Func(enums::Function::Abs) {
+            u = symbolic::operands(&u).pop().unwrap();
+        }
+        return pow(u, k);
+    }
+    apply(enums::Function::Exp, e.clone())
+}
+
+// exp(r x), or 1 when r is 0.
+fn exp_linear(r: &Expression, x: &Expression) -> Expression {
+    if symbolic::is_number(r, 0.0) {
+        return number(1.0);
+    }
+    exponential(&mul(r.clone(), x.clone()))
+}
+
+// e exp(u), merged into an exponential factor of e when there is one since
+// the constructors keep exp(a) exp(b) apart.
+fn times_exp(e: &Expression, u: &Expression) -> Expression {
+    if symbolic::is_number(u, 0.0) {
+        return e.clone();
+    }
+    let mut args = symbolic::operands(e);
+    match *symbolic::root(e) {
+        enums::Token::Func(enums::Function::Exp) => {
+            exponential(&add(args.pop().unwrap(), u.clone()))
+        }
+        enums::Token::Op(enums::Operator::Negate) => neg(times_exp(&args[0], u)),
+        enums::Token::Op(enums::Operator::Mul) => {
+            let b = args.pop().unwrap();
+            let a = args.pop().unwrap();
+            if contains_exp(&b) {
+                mul(a, times_exp(&b, u))
+            } else if contains_exp(&a) {
+                mul(times_exp(&a, u), b)
+            } else {
+                mul(e.clone(), exponential(u))
+            }
+        }
+        enums::Token::Op(enums::Operator::Div) => {
+            let b = args.pop().unwrap();
+            div(times_exp(&args.pop().unwrap(), u), b)
+        }
+        enums::Token::Op(op) if op == enums::Operator::Add || op == enums::Operator::Sub => {
+            let b = args.pop().unwrap();
+            let a = args.pop().unwrap();
+            let (a, b) = (times_exp(&a, u), times_exp(&b, u));
+            if op == enums::Operator::Add { add(a, b) } else { sub(a, b) }
+        }
+        _ => mul(e.clone(), exponential(u)),
+    }
+}
+
+// Whether a product has an exponential factor to merge into.
+fn contains_exp(e: &Expression) -> bool {
+    match *symbolic::root(e) {
+        enums::Token::Func(enums::Function::Exp) => true,
+        enums::Token::Op(enums::Operator::Mul) |
+        enums::Token::Op(enums::Operator::Negate) => {
+            symbolic::operands(e).iter().any(contains_exp)
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use expression::parse::parse;
+
+    // The residual of `equation` = 0 with y and its derivatives replaced by
+    // the general solution, at a few points and constants.
+    fn check(equation: &str, kind: Kind) {
+        let equation = parse(equation);
+        let solution = dsolve(&equation, "y", "x").unwrap();
+        assert_eq!(solution.kind, kind);
+        assert_eq!(solution.lhs.to_string(), "y");
+        let first = differentiate(&solution.rhs, "x");
+        let second = differentiate(&first, "x");
+        let mut residual = symbolic::substitute(&equation, "yprimeprime", &second);
+        residual = symbolic::substitute(&residual, "yprime", &first);
+        residual = symbolic::substitute(&residual, "y", &solution.rhs);
+        for &(x, c1, c2) in &[(0.5, 0.7, -1.2), (1.3, -2.0, 0.4)] {
+            let mut values = HashMap::new();
+            values.insert("x".to_owned(), x);
+            values.insert(FIRST.to_owned(), c1);
+            values.insert(SECOND.to_owned(), c2);
+            let r = eval_postfix_expr(&residual, &values);
+            assert!(r.abs() <= 1e-9, "{}: residual {} at x = {}", solution, r, x);
+        }
+    }
+
+    #[test]
+    fn first_order_linear() {
+        check("yprime-x*y", Kind::Linear);
+        check("yprime+y/x-x^2", Kind::Linear);
+        check("x*yprime+2*y-x", Kind::Linear);
+    }
+
+    #[test]
+    fn separable() {
+        check("yprime-y^2", Kind::Separable);
+        check("yprime-x/y", Kind::Separable);
+    }
+
+    #[test]
+    fn constant_coefficients() {
+        check("yprimeprime+2*yprime+y-sin(x)", Kind::ConstantCoefficient);
+        check("yprimeprime-3*yprime+2*y-exp(x)", Kind::ConstantCoefficient);
+        check("yprimeprime+4*y-x^2", Kind::ConstantCoefficient);
+        check("yprimeprime+2*yprime+5*y", Kind::ConstantCoefficient);
+    }
+
+    #[test]
+    fn unsupported_equations() {
+        assert!(dsolve(&parse("y-x"), "y", "x").is_err());
+        assert!(dsolve(&parse("yprime^2-y"), "y", "x").is_err());
+    }
+}
@@ -0,0 +1,174 @@
+use expression::numeric::Numeric;
+
+use std::f64;
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+// Forward mode automatic differentiation: `deriv` carries the derivative of
+// `value` with respect to whichever variable was seeded with a derivative of 1.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Dual {
+    pub value: f64,
+    pub deriv: f64,
+}
+
+impl Dual {
+    pub fn new(value: f64, deriv: f64) -> Self {
+        Dual { value, deriv }
+    }
+
+    pub fn variable(value: f64) -> Self {
+        Dual::new(value, 1.0)
+    }
+
+    pub fn constant(value: f64) -> Self {
+        Dual::new(value, 0.0)
+    }
+
+    // Applies f to the value and the chain rule to the derivative.
+    fn chain(self, value: f64, slope: f64) -> Self {
+        Dual::new(value, self.deriv * slope)
+    }
+}
+
+impl Add for Dual {
+    type Output = Dual;
+    fn add(self, other: Dual) -> Dual {
+        Dual::new(self.value + other.value, self.deriv + other.deriv)
+    }
+}
+
+impl Sub for Dual {
+    type Output = Dual;
+    fn sub(self, other: Dual) -> Dual {
+        Dual::new(self.value - other.value, self.deriv - other.deriv)
+    }
+}
+
+impl Mul for Dual {
+    type Output = Dual;
+    fn mul(self, other: Dual) -> Dual {
+        Dual::new(self.value * other.value,
+                  self.deriv * other.value + self.value * other.deriv)
+    }
+}
+
+impl Div for Dual {
+    type Output = Dual;
+    fn div(self, other: Dual) -> Dual {
+        Dual::new(self.value / other.value,
+                  (self.deriv * other.value - self.value * other.deriv) /
+                  (other.value * other.value))
+    }
+}
+
+impl Neg for Dual {
+    type Output = Dual;
+    fn neg(self) -> Dual {
+        Dual::new(-self.value, -self.deriv)
+    }
+}
+
+impl Numeric for Dual {
+    fn from_f64(x: f64) -> Self {
+        Dual::constant(x)
+    }
+
+    fn pi() -> Self {
+        Dual::constant(f64::consts::PI)
+    }
+
+    fn e() -> Self {
+        Dual::constant(f64::consts::E)
+    }
+
+    fn rem(self, other: Self) -> Self {
+        let q = (self.value / other.value).trunc();
+        Dual::new(self.value % other.value, self.deriv - q * other.deriv)
+    }
+
+    fn pow(self, other: Self) -> Self {
+        let value = self.value.powf(other.value);
+        // Skip the ln term for constant exponents so negative bases still work.
+        let mut deriv = other.value * self.value.powf(other.value - 1.0) * self.deriv;
+        if other.deriv != 0.0 {
+            deriv += value * self.value.ln() * other.deriv;
+        }
+        Dual::new(value, deriv)
+    }
+
+    fn max(self, other: Self) -> Self {
+        if other.value > self.value { other } else { self }
+    }
+
+    fn abs(self) -> Self {
+        self.chain(self.value.abs(), self.value.signum())
+    }
+
+    fn exp(self) -> Self {
+        let value = self.value.exp();
+        self.chain(value, value)
+    }
+
+    fn sqrt(self) -> Self {
+        let value = self.value.sqrt();
+        self.chain(value, 0.5 / value)
+    }
+
+    fn ln(self) -> Self {
+        self.chain(self.value.ln(), self.value.recip())
+    }
+
+    fn sin(self) -> Self {
+        self.chain(self.value.sin(), self.value.cos())
+    }
+
+    fn cos(self) -> Self {
+        self.chain(self.value.cos(), -self.value.sin())
+    }
+
+    fn tan(self) -> Self {
+        let value = self.value.tan();
+        self.chain(value, 1.0 + value * value)
+    }
+
+    fn asin(self) -> Self {
+        self.chain(self.value.asin(),
+                   (1.0 - self.value * self.value).sqrt().recip())
+    }
+
+    fn acos(self) -> Self {
+        self.chain(self.value.acos(),
+                   -(1.0 - self.value * self.value).sqrt().recip())
+    }
+
+    fn atan(self) -> Self {
+        self.chain(self.value.atan(), (1.0 + self.value * self.value).recip())
+    }
+
+    fn sinh(self) -> Self {
+        self.chain(self.value.sinh(), self.value.cosh())
+    }
+
+    fn cosh(self) -> Self {
+        self.chain(self.value.cosh(), self.value.sinh())
+    }
+
+    fn tanh(self) -> Self {
+        let value = self.value.tanh();
+        self.chain(value, 1.0 - value * value)
+    }
+
+    fn asinh(self) -> Self {
+        self.chain(self.value.asinh(),
+                   (self.value * self.value + 1.0).sqrt().recip())
+    }
+
+    fn acosh(self) -> Self {
+        self.chain(self.value.acosh(),
+                   (self.value * self.value - 1.0).sqrt().recip())
+    }
+
+    fn atanh(self) -> Self {
+        self.chain(self.value.atanh(), (1.0 - self.value * self.value).recip())
+    }
+}
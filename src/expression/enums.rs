@@ -11,7 +11,7 @@ pub enum Token {
     Comma,
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum Function {
     Abs,
     Exp,
@@ -47,7 +47,7 @@ pub enum Function {
     Recip,
 }
 
-pub fn map_string_to_func(input: &String) -> Token {
+pub fn map_string_to_func(input: &str) -> Token {
     match &(input.to_lowercase())[..] {
         "abs" => Token::Func(Function::Abs),
         "exp" => Token::Func(Function::Exp),
@@ -81,10 +81,17 @@ pub fn map_string_to_func(input: &String) -> Token {
         "acoth" => Token::Func(Function::Acoth),
         "max" => Token::Func(Function::Max),
         "recip" => Token::Func(Function::Recip),
-        _ => Token::Var(input.clone()),
+        "pi" => Token::Const(Constant::Pi),
+        "e" => Token::Const(Constant::E),
+        _ => Token::Var(input.to_owned()),
     }
 }
 
+// Names that always mean a constant, so they cannot be variables.
+pub fn is_constant(input: &str) -> bool {
+    input == "pi" || input == "e"
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum Constant {
     Pi,
@@ -1,96 +1,48 @@
 use expression;
 use expression::enums;
+use expression::numeric::Numeric;
 
 pub type Expression = expression::Expression;
 
 use std::collections::HashMap;
-use std::f64;
 
 pub fn eval_postfix_expr(expr: &Expression, vars: &HashMap<String, f64>) -> f64 {
-    let mut stack: Vec<f64> = Vec::with_capacity(expr.len() / 2);
+    eval_postfix::<f64>(expr, vars)
+}
+
+pub fn eval_postfix<T: Numeric>(expr: &Expression, vars: &HashMap<String, T>) -> T {
+    let mut stack: Vec<T> = Vec::with_capacity(expr.len() / 2);
     for token in expr.iter() {
-        match token {
-            &enums::Token::Literal(ref x) => stack.push(x.parse::<f64>().unwrap()),
-            &enums::Token::Const(ref x) => {
-                match x {
-                    &enums::Constant::Pi => stack.push(f64::consts::PI),
-                    &enums::Constant::E => stack.push(f64::consts::E),
-                }
-            }
-            &enums::Token::Op(ref x) => {
-                let arg: f64 = stack.pop().unwrap();
-                match x {
-                    &enums::Operator::Negate => stack.push(-1.0f64 * arg),
+        match *token {
+            enums::Token::Literal(ref x) => stack.push(T::from_literal(x)),
+            enums::Token::Const(ref x) => stack.push(eval_constant(x)),
+            enums::Token::Op(ref x) => {
+                let arg: T = stack.pop().unwrap();
+                match *x {
+                    enums::Operator::Negate => stack.push(-arg),
                     _ => {
-                        let arg2 = arg;
                         let arg1 = stack.pop().unwrap();
-                        match x {
-                            &enums::Operator::Add => stack.push(arg1 + arg2),
-                            &enums::Operator::Sub => stack.push(arg1 - arg2),
-                            &enums::Operator::Div => stack.push(arg1 / arg2),
-                            &enums::Operator::Mul => stack.push(arg1 * arg2),
-                            &enums::Operator::Mod => stack.push(arg1 % arg2),
-                            &enums::Operator::Pow => stack.push(arg1.powf(arg2)),
-                            _ => continue, // Should never hit here
-                        }
+                        stack.push(eval_operator(x, arg1, arg));
                     }
                 }
             }
-            &enums::Token::Func(ref x) => {
-                let arg: f64 = stack.pop().unwrap();
-                match x {
-                    &enums::Function::Abs => stack.push(f64::abs(arg)),
-                    &enums::Function::Sqrt => stack.push(f64::sqrt(arg)),
-                    &enums::Function::Ln => stack.push(f64::ln(arg)),
-                    &enums::Function::Log => stack.push(f64::log10(arg)),
-                    &enums::Function::Exp => stack.push(f64::exp(arg)),
-                    &enums::Function::Sin => stack.push(f64::sin(arg)),
-                    &enums::Function::Csc => stack.push(f64::recip(f64::sin(arg))),
-                    &enums::Function::Cos => stack.push(f64::cos(arg)),
-                    &enums::Function::Sec => stack.push(f64::recip(f64::cos(arg))),
-                    &enums::Function::Tan => stack.push(f64::tan(arg)),
-                    &enums::Function::Cot => stack.push(f64::recip(f64::tan(arg))),
-                    &enums::Function::Asin => stack.push(f64::asin(arg)),
-                    &enums::Function::Acsc => stack.push(f64::recip(f64::asin(arg))),
-                    &enums::Function::Acos => stack.push(f64::acos(arg)),
-                    &enums::Function::Asec => stack.push(f64::recip(f64::acos(arg))),
-                    &enums::Function::Atan => stack.push(f64::atan(arg)),
-                    &enums::Function::Acot => stack.push(f64::recip(f64::atan(arg))),
-                    &enums::Function::Sinh => stack.push(f64::sinh(arg)),
-                    &enums::Function::Csch => stack.push(f64::recip(f64::sinh(arg))),
-                    &enums::Function::Cosh => stack.push(f64::cosh(arg)),
-                    &enums::Function::Sech => stack.push(f64::recip(f64::cosh(arg))),
-                    &enums::Function::Tanh => stack.push(f64::tanh(arg)),
-                    &enums::Function::Coth => stack.push(f64::recip(f64::tanh(arg))),
-                    &enums::Function::Asinh => stack.push(f64::asinh(arg)),
-                    &enums::Function::Acsch => stack.push(f64::recip(f64::asinh(arg))),
-                    &enums::Function::Acosh => stack.push(f64::acosh(arg)),
-                    &enums::Function::Asech => stack.push(f64::recip(f64::acosh(arg))),
-                    &enums::Function::Atanh => stack.push(f64::atanh(arg)),
-                    &enums::Function::Acoth => stack.push(f64::recip(f64::atanh(arg))),
-                    &enums::Function::Recip => stack.push(f64::recip(arg)),
-                    &enums::Function::Max => {
-                        let arg2 = arg;
+            enums::Token::Func(ref x) => {
+                let arg: T = stack.pop().unwrap();
+                match *x {
+                    enums::Function::Max | enums::Function::LogBase => {
                         let arg1 = stack.pop().unwrap();
-                        stack.push(arg1.max(arg2));
-                    }
-                    &enums::Function::LogBase => {
-                        let arg2 = arg;
-                        let arg1 = stack.pop().unwrap();
-                        stack.push(arg1.log(arg2)); // logbase(8,2) == 3
+                        stack.push(eval_binary_function(x, arg1, arg));
                     }
+                    _ => stack.push(eval_function(x, arg)),
                 }
             }
-            &enums::Token::Unknown(ref x) => {
-                let _ = x;
+            enums::Token::Unknown(_) => {
                 let _ = stack.pop().unwrap();
             }
-            &enums::Token::Var(ref x) => {
-                let value = vars.get(x);
-                if value.is_some() {
-                    stack.push(*value.unwrap());
-                } else {
-                    stack.push(0.0);
+            enums::Token::Var(ref x) => {
+                match vars.get(x) {
+                    Some(value) => stack.push(*value),
+                    None => stack.push(T::zero()),
                 }
             }
             _ => continue,
@@ -98,3 +50,149 @@ pub fn eval_postfix_expr(expr: &Expression, vars: &HashMap<String, f64>) -> f64
     }
     stack.pop().unwrap()
 }
+
+pub fn eval_constant<T: Numeric>(constant: &enums::Constant) -> T {
+    match *constant {
+        enums::Constant::Pi => T::pi(),
+        enums::Constant::E => T::e(),
+    }
+}
+
+// Negate is unary and handled by the caller.
+pub fn eval_operator<T: Numeric>(op: &enums::Operator, arg1: T, arg2: T) -> T {
+    match *op {
+        enums::Operator::Add => arg1 + arg2,
+        enums::Operator::Sub => arg1 - arg2,
+        enums::Operator::Div => arg1 / arg2,
+        enums::Operator::Mul => arg1 * arg2,
+        enums::Operator::Mod => arg1.rem(arg2),
+        enums::Operator::Pow => arg1.pow(arg2),
+        enums::Operator::Negate => -arg2,
+    }
+}
+
+pub fn eval_binary_function<T: Numeric>(func: &enums::Function, arg1: T, arg2: T) -> T {
+    match *func {
+        enums::Function::Max => arg1.max(arg2),
+        enums::Function::LogBase => arg1.log(arg2), // logbase(8,2) == 3
+        _ => eval_function(func, arg2),
+    }
+}
+
+pub fn eval_function<T: Numeric>(func: &enums::Function, arg: T) -> T {
+    match *func {
+        enums::Function::Abs => arg.abs(),
+        enums::Function::Sqrt => arg.sqrt(),
+        enums::Function::Ln => arg.ln(),
+        enums::Function::Log => arg.log10(),
+        enums::Function::Exp => arg.exp(),
+        enums::Function::Sin => arg.sin(),
+        enums::Function::Csc => arg.csc(),
+        enums::Function::Cos => arg.cos(),
+        enums::Function::Sec => arg.sec(),
+        enums::Function::Tan => arg.tan(),
+        enums::Function::Cot => arg.cot(),
+        enums::Function::Asin => arg.asin(),
+        enums::Function::Acsc => arg.acsc(),
+        enums::Function::Acos => arg.acos(),
+        enums::Function::Asec => arg.asec(),
+        enums::Function::Atan => arg.atan(),
+        enums::Function::Acot => arg.acot(),
+        enums::Function::Sinh => arg.sinh(),
+        enums::Function::Csch => arg.csch(),
+        enums::Function::Cosh => arg.cosh(),
+        enums::Function::Sech => arg.sech(),
+        enums::Function::Tanh => arg.tanh(),
+        enums::Function::Coth => arg.coth(),
+        enums::Function::Asinh => arg.asinh(),
+        enums::Function::Acsch => arg.acsch(),
+        enums::Function::Acosh => arg.acosh(),
+        enums::Function::Asech => arg.asech(),
+        enums::Function::Atanh => arg.atanh(),
+        enums::Function::Acoth => arg.acoth(),
+        enums::Function::Recip => arg.recip(),
+        enums::Function::Max | enums::Function::LogBase => arg, // binary, see eval_binary_function
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use expression::complex::Complex;
+    use expression::dual::Dual;
+    use expression::parse::parse;
+
+    const UNARY: [enums::Function; 30] = [enums::Function::Abs, enums::Function::Exp,
+                                          enums::Function::Sqrt, enums::Function::Ln,
+                                          enums::Function::Log, enums::Function::Sin,
+                                          enums::Function::Csc, enums::Function::Cos,
+                                          enums::Function::Sec, enums::Function::Tan,
+                                          enums::Function::Cot, enums::Function::Asin,
+                                          enums::Function::Acsc, enums::Function::Acos,
+                                          enums::Function::Asec, enums::Function::Atan,
+                                          enums::Function::Acot, enums::Function::Sinh,
+                                          enums::Function::Csch, enums::Function::Cosh,
+                                          enums::Function::Sech, enums::Function::Tanh,
+                                          enums::Function::Coth, enums::Function::Asinh,
+                                          enums::Function::Acsch, enums::Function::Acosh,
+                                          enums::Function::Asech, enums::Function::Atanh,
+                                          enums::Function::Acoth, enums::Function::Recip];
+
+    fn close(x: f64, expected: f64, tolerance: f64) -> bool {
+        (x - expected).abs() <= tolerance * expected.abs().max(1.0)
+    }
+
+    #[test]
+    fn backends_agree_on_every_function() {
+        for f in &UNARY {
+            for &x in &[-0.6, 0.3, 0.7, 1.5, 2.5] {
+                let real: f64 = eval_function(f, x);
+                if !real.is_finite() {
+                    continue;
+                }
+                let single: f32 = eval_function(f, x as f32);
+                assert!(close(single as f64, real, 1e-5), "{:?}({}) in f32", f, x);
+                let z: Complex = eval_function(f, Complex::new(x, 0.0));
+                assert!(close(z.re, real, 1e-12) && z.im.abs() <= 1e-12,
+                        "{:?}({}) = {:?} in Complex",
+                        f,
+                        x,
+                        z);
+                let d: Dual = eval_function(f, Dual::variable(x));
+                let h = 1e-6;
+                let slope = (eval_function::<f64>(f, x + h) - eval_function::<f64>(f, x - h)) /
+                            (2.0 * h);
+                assert!(close(d.value, real, 1e-12), "{:?}({}) in Dual", f, x);
+                assert!(close(d.deriv, slope, 1e-6), "{:?}'({}) in Dual", f, x);
+            }
+        }
+    }
+
+    #[test]
+    fn backends_agree_on_expressions() {
+        let expr = parse("max(x,1)*logbase(8,2)+x^3/(x%2+1)-exp(-x)");
+        for &x in &[0.5, 1.5, 3.25] {
+            let real = eval_postfix_expr(&expr, &[("x".to_owned(), x)].iter().cloned().collect());
+            let vars = [("x".to_owned(), Complex::new(x, 0.0))].iter().cloned().collect();
+            let z: Complex = eval_postfix(&expr, &vars);
+            assert!(close(z.re, real, 1e-12) && z.im.abs() <= 1e-12);
+            let vars = [("x".to_owned(), Dual::variable(x))].iter().cloned().collect();
+            let d: Dual = eval_postfix(&expr, &vars);
+            assert!(close(d.value, real, 1e-12));
+        }
+    }
+
+    #[test]
+    fn inverse_reciprocal_functions() {
+        use std::f64::consts::PI;
+        let cases = [(enums::Function::Acsc, 2.0, PI / 6.0),
+                     (enums::Function::Asec, 2.0, PI / 3.0),
+                     (enums::Function::Acot, 1.0, PI / 4.0),
+                     (enums::Function::Acsch, 2.0, 0.5f64.asinh()),
+                     (enums::Function::Asech, 0.5, 2f64.acosh()),
+                     (enums::Function::Acoth, 2.0, 0.5f64.atanh())];
+        for &(ref f, x, expected) in &cases {
+            assert!(close(eval_function(f, x), expected, 1e-15), "{:?}({})", f, x);
+        }
+    }
+}
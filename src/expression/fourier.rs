@@ -0,0 +1,201 @@
+use expression;
+use expression::bytecode;
+use expression::enums;
+use expression::eval::eval_postfix_expr;
+use expression::integrate::antiderivative;
+use expression::quadrature;
+use expression::series::{fraction, snap};
+use expression::symbolic::{self, add, apply, div, mul, number};
+
+pub type Expression = expression::Expression;
+
+use std::collections::HashMap;
+use std::f64::consts::PI;
+
+const TOLERANCE: f64 = 1e-10;
+
+// f(x) ~ a_0/2 + sum a_k cos(k w x) + b_k sin(k w x) with w = 2 pi / period,
+// from the integrals over one period starting at `start`. b_0 is always 0.
+#[derive(Debug, Clone)]
+pub struct FourierSeries {
+    pub cosines: Vec<f64>,
+    pub sines: Vec<f64>,
+    pub frequency: f64,
+    // How many of the 2n + 1 coefficients came from an antiderivative.
+    pub exact: usize,
+}
+
+pub fn fourier(expr: &Expression,
+               var: &str,
+               period: f64,
+               n: usize,
+               start: f64,
+               values: &HashMap<String, f64>)
+               -> Result<FourierSeries, String> {
+    if !(period > 0.0 && period.is_finite()) {
+        return Err(format!("The period must be positive, got {}", period));
+    }
+    let end = start + period;
+    let frequency = 2.0 * PI / period;
+    let mut f = bytecode::bind(expr, &[var.to_owned()], values)?;
+    let mut series = FourierSeries {
+        cosines: Vec::with_capacity(n + 1),
+        sines: Vec::with_capacity(n + 1),
+        frequency,
+        exact: 0,
+    };
+    for k in 0..n + 1 {
+        let w = k as f64 * frequency;
+        for &trig in &[enums::Function::Cos, enums::Function::Sin] {
+            if k == 0 && trig == enums::Function::Sin {
+                series.sines.push(0.0);
+                continue;
+            }
+            let weight = |x: f64| if trig == enums::Function::Cos { x.cos() } else { x.sin() };
+            let numeric =
+                quadrature::integrate(|x| f.eval1(x) * weight(w * x), start, end, TOLERANCE);
+            let integrand = mul(expr.clone(),
+                                apply(trig, mul(number(w), symbolic::variable(var))));
+            let exact = antiderivative(&integrand, var).and_then(|primitive| {
+                definite(&primitive, var, start, end, values)
+            });
+            let integral = match (exact, numeric) {
+                (Some(exact), Ok(ref numeric)) if (exact - numeric.value).abs() <=
+                                                  1e-6 * numeric.value.abs().max(1.0) => {
+                    series.exact += 1;
+                    exact
+                }
+                (_, Ok(numeric)) => numeric.value,
+                (Some(exact), Err(_)) => {
+                    series.exact += 1;
+                    exact
+                }
+                (None, Err(message)) => return Err(message),
+            };
+            let coefficient = 2.0 * integral / period;
+            if trig == enums::Function::Cos {
+                series.cosines.push(coefficient);
+            } else {
+                series.sines.push(coefficient);
+            }
+        }
+    }
+    chop(&mut series.cosines, &series.sines.clone());
+    chop(&mut series.sines, &series.cosines.clone());
+    Ok(series)
+}
+
+// F(b) - F(a), None unless finite.
+fn definite(primitive: &Expression,
+            var: &str,
+            a: f64,
+            b: f64,
+            values: &HashMap<String, f64>)
+            -> Option<f64> {
+    let mut at = values.clone();
+    at.insert(var.to_owned(), b);
+    let upper = eval_postfix_expr(primitive, &at);
+    at.insert(var.to_owned(), a);
+    let value = upper - eval_postfix_expr(primitive, &at);
+    if value.is_finite() { Some(value) } else { None }
+}
+
+// Coefficients that are rounding noise next to the largest one become 0.
+fn chop(coefficients: &mut [f64], others: &[f64]) {
+    let scale = coefficients.iter().chain(others).fold(0.0f64, |m, c| m.max(c.abs()));
+    for c in coefficients.iter_mut() {
+        if c.abs() <= 1e-10 * scale {
+            *c = 0.0;
+        }
+    }
+}
+
+// x as a fraction, or a fraction times pi, pi^2, 1/pi or 1/pi^2 as they
+// appear in Fourier coefficients, else as a decimal.
+pub fn closed_form(x: f64) -> Expression {
+    if snap(x).is_some() {
+        return fraction(x);
+    }
+    let pi = || symbolic::constant(enums::Constant::Pi);
+    if snap(x / PI).is_some() {
+        return mul(fraction(x / PI), pi());
+    }
+    if snap(x * PI).is_some() {
+        return div(fraction(x * PI), pi());
+    }
+    if snap(x / (PI * PI)).is_some() {
+        return mul(fraction(x / (PI * PI)), symbolic::pow(pi(), number(2.0)));
+    }
+    if snap(x * PI * PI).is_some() {
+        return div(fraction(x * PI * PI), symbolic::pow(pi(), number(2.0)));
+    }
+    number(x)
+}
+
+pub fn to_expression(series: &FourierSeries, var: &str) -> Expression {
+    let x = symbolic::variable(var);
+    let mut result = closed_form(series.cosines[0] / 2.0);
+    for k in 1..series.cosines.len() {
+        let argument = mul(closed_form(k as f64 * series.frequency), x.clone());
+        for &(c, trig) in &[(series.cosines[k], enums::Function::Cos),
+                            (series.sines[k], enums::Function::Sin)] {
+            if c != 0.0 {
+                result = add(result, mul(closed_form(c), apply(trig, argument.clone())));
+            }
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use expression::parse::parse;
+
+    fn series(expr: &str, period: f64, n: usize, start: f64) -> FourierSeries {
+        fourier(&parse(expr), "x", period, n, start, &HashMap::new()).unwrap()
+    }
+
+    fn close(a: &[f64], b: &[f64]) -> bool {
+        a.len() == b.len() && a.iter().zip(b).all(|(x, y)| (x - y).abs() <= 1e-9)
+    }
+
+    #[test]
+    fn sawtooth_and_parabola() {
+        // x = 2 sum (-1)^(k+1) sin(k x)/k on [-pi, pi]
+        let sawtooth = series("x", 2.0 * PI, 3, -PI);
+        assert!(close(&sawtooth.cosines, &[0.0; 4]));
+        assert!(close(&sawtooth.sines, &[0.0, 2.0, -1.0, 2.0 / 3.0]));
+        assert_eq!(sawtooth.exact, 7);
+        assert_eq!(to_expression(&sawtooth, "x").to_string(),
+                   "2*sin(x) - sin(2*x) + 2*sin(3*x)/3");
+        let parabola = series("x^2", 2.0 * PI, 2, -PI);
+        assert!(close(&parabola.cosines, &[2.0 * PI * PI / 3.0, -4.0, 1.0]));
+        assert_eq!(to_expression(&parabola, "x").to_string(), "pi^2/3 - 4*cos(x) + cos(2*x)");
+    }
+
+    #[test]
+    fn quadrature_without_an_antiderivative() {
+        let bell = series("exp(-x^2)", 2.0, 1, -1.0);
+        assert_eq!(bell.exact, 0);
+        // Twice the integral of exp(-x^2) over [-1, 1], which is sqrt(pi) erf(1).
+        assert!((bell.cosines[0] - 1.493648265624854).abs() <= 1e-12);
+        let triangle = series("abs(x)", 2.0, 3, -1.0);
+        let expected = [1.0, -4.0 / (PI * PI), 0.0, -4.0 / (9.0 * PI * PI)];
+        assert!(close(&triangle.cosines, &expected));
+    }
+
+    #[test]
+    fn closed_forms() {
+        assert_eq!(closed_form(0.25).to_string(), "1/4");
+        assert_eq!(closed_form(PI / 3.0).to_string(), "pi/3");
+        assert_eq!(closed_form(-4.0 / (PI * PI)).to_string(), "-(4/pi^2)");
+        assert_eq!(closed_form(2f64.sqrt()).to_string(), "1.4142135623730951");
+    }
+
+    #[test]
+    fn periods_must_be_positive() {
+        assert!(fourier(&parse("x"), "x", 0.0, 2, 0.0, &HashMap::new()).is_err());
+        assert!(fourier(&parse("x"), "x", -1.0, 2, 0.0, &HashMap::new()).is_err());
+    }
+}
@@ -0,0 +1,480 @@
+use expression;
+use expression::derivative::differentiate;
+use expression::enums;
+use expression::eval::eval_postfix_expr;
+use expression::linalg;
+use expression::polynomial;
+use expression::rational::Rational;
+use expression::series::fraction;
+use expression::symbolic::{self, add, apply, div, mul, neg, number, pow, sub};
+
+pub type Expression = expression::Expression;
+
+use std::collections::HashMap;
+
+const MAX_DEPTH: usize = 16;
+
+// Antiderivative of `expr` with respect to `var`, without the constant, from
+// a table of functions of a linear argument u = a x + b, linearity, f'/f,
+// integration by parts of a polynomial times exp, sin, cos, sinh or cosh and
+// product to sum formulas. None when no rule applies.
+pub fn antiderivative(expr: &Expression, var: &str) -> Option<Expression> {
+    integrate(&symbolic::simplify(expr), var, 0)
+}
+
+fn integrate(expr: &Expression, var: &str, depth: usize) -> Option<Expression> {
+    if depth > MAX_DEPTH {
+        return None;
+    }
+    let x = symbolic::variable(var);
+    if !symbolic::contains_var(expr, var) {
+        return Some(mul(expr.clone(), x));
+    }
+    if let Some(result) = rational_integral(expr, var) {
+        return Some(result);
+    }
+    let mut args = symbolic::operands(expr);
+    match *symbolic::root(expr) {
+        enums::Token::Var(_) => Some(div(pow(x, number(2.0)), number(2.0))),
+        enums::Token::Op(enums::Operator::Negate) => {
+            Some(neg(integrate(&args[0], var, depth + 1)?))
+        }
+        enums::Token::Op(op) => {
+            let b = args.pop().unwrap();
+            let a = args.pop().unwrap();
+            match op {
+                enums::Operator::Add => {
+                    Some(add(integrate(&a, var, depth + 1)?, integrate(&b, var, depth + 1)?))
+                }
+                enums::Operator::Sub => {
+                    Some(sub(integrate(&a, var, depth + 1)?, integrate(&b, var, depth + 1)?))
+                }
+                enums::Operator::Mul => product(a, b, var, depth + 1),
+                enums::Operator::Div => quotient(a, b, var, depth + 1),
+                enums::Operator::Pow => power(a, b, var, depth + 1),
+                _ => None,
+            }
+        }
+        enums::Token::Func(f) if args.len() == 1 => function(f, args.pop().unwrap(), var),
+        _ => None,
+    }
+}
+
+// Rational functions of `var` alone: the polynomial part term by term and
+// the rest by partial fractions over the roots of the denominator, where a
+// pair of complex roots gives a logarithm and an arctangent. Repeated
+// complex roots are not handled.
+fn rational_integral(expr: &Expression, var: &str) -> Option<Expression> {
+    let only_var = expr.iter().all(|t| match *t {
+        enums::Token::Var(ref x) => x == var,
+        enums::Token::Const(_) => false,
+        _ => true,
+    });
+    if !only_var {
+        return None;
+    }
+    let (num, den) = polynomial::rational_function(expr, var, &HashMap::new())?;
+    if num.iter().chain(&den).any(|c| !c.is_finite()) || trimmed(&den).is_empty() {
+        return None;
+    }
+    let (num, den) = cancel(&num, &den);
+    let (quotient, remainder) = divide(&num, &den);
+    let x = symbolic::variable(var);
+    let mut result = number(0.0);
+    for (k, c) in quotient.iter().enumerate() {
+        let term = mul(fraction(c / (k + 1) as f64), pow(x.clone(), number((k + 1) as f64)));
+        result = add(result, term);
+    }
+    if remainder.iter().all(|c| *c == 0.0) {
+        return Some(result);
+    }
+    // One column d/f per partial fraction 1/f, solved for the numerators.
+    let mut factors: Vec<(Vec<f64>, usize, bool)> = Vec::new();
+    for (root, multiplicity) in polynomial::roots(&den) {
+        let z = root.value();
+        if z.im.abs() <= 1e-9 * z.norm().max(1.0) {
+            for j in 1..multiplicity + 1 {
+                factors.push((vec![-z.re, 1.0], j, false));
+            }
+        } else if z.im > 0.0 {
+            if multiplicity > 1 {
+                return None;
+            }
+            factors.push((vec![z.re * z.re + z.im * z.im, -2.0 * z.re, 1.0], 1, true));
+        }
+    }
+    let n = den.len() - 1;
+    let mut columns: Vec<Vec<f64>> = Vec::new();
+    for &(ref f, j, quadratic) in &factors {
+        let mut column = den.clone();
+        for _ in 0..j {
+            column = divide(&column, f).0;
+        }
+        if quadratic {
+            let mut shifted = vec![0.0];
+            shifted.extend_from_slice(&column);
+            columns.push(column);
+            columns.push(shifted);
+        } else {
+            columns.push(column);
+        }
+    }
+    if columns.len() != n {
+        return None;
+    }
+    let system: linalg::Matrix = (0..n)
+        .map(|i| columns.iter().map(|c| c.get(i).cloned().unwrap_or(0.0)).collect())
+        .collect();
+    let rhs: Vec<f64> = (0..n).map(|i| remainder.get(i).cloned().unwrap_or(0.0)).collect();
+    let solution = linalg::solve_linear(&system, &rhs)?;
+    let mut unknowns = solution.into_iter();
+    for (f, j, quadratic) in factors {
+        if quadratic {
+            // (B x + C)/((x - a)^2 + b^2)
+            let (c_coef, b_coef) = (unknowns.next()?, unknowns.next()?);
+            let alpha = -f[1] / 2.0;
+            let beta = (f[0] - alpha * alpha).sqrt();
+            let shifted = sub(x.clone(), fraction(alpha));
+            let q = add(pow(shifted.clone(), number(2.0)), fraction(beta * beta));
+            let log = mul(fraction(b_coef / 2.0), apply(enums::Function::Ln, q));
+            let angle = apply(enums::Function::Atan, div(shifted, fraction(beta)));
+            result = add(add(result, log),
+                         mul(fraction((c_coef + b_coef * alpha) / beta), angle));
+            continue;
+        }
+        let a = unknowns.next()?;
+        let shifted = sub(x.clone(), fraction(-f[0]));
+        let term = if j == 1 {
+            mul(fraction(a), apply(enums::Function::Ln, apply(enums::Function::Abs, shifted)))
+        } else {
+            let k = (j - 1) as f64;
+            div(fraction(-a / k), pow(shifted, number(k)))
+        };
+        result = add(result, term);
+    }
+    Some(result)
+}
+
+fn trimmed(p: &[f64]) -> &[f64] {
+    let len = p.iter().rposition(|c| *c != 0.0).map_or(0, |i| i + 1);
+    &p[..len]
+}
+
+// Quotient and remainder in floating point.
+fn divide(a: &[f64], b: &[f64]) -> (Vec<f64>, Vec<f64>) {
+    let b = trimmed(b);
+    let mut remainder = trimmed(a).to_vec();
+    if remainder.len() < b.len() {
+        return (vec![0.0], remainder);
+    }
+    let mut quotient = vec![0.0; remainder.len() - b.len() + 1];
+    for shift in (0..quotient.len()).rev() {
+        let factor = remainder[shift + b.len() - 1] / b[b.len() - 1];
+        quotient[shift] = factor;
+        for (i, c) in b.iter().enumerate() {
+            remainder[shift + i] -= factor * c;
+        }
+    }
+    remainder.truncate(b.len() - 1);
+    (quotient, remainder)
+}
+
+// Removes the common factor of numerator and denominator when both have
+// rational coefficients.
+fn cancel(num: &[f64], den: &[f64]) -> (Vec<f64>, Vec<f64>) {
+    let exact = (polynomial::to_rational(num), polynomial::to_rational(den));
+    if let (Some(n), Some(d)) = exact {
+        let g = polynomial::gcd(&n, &d);
+        if polynomial::degree(&g) > 0 {
+            let to_f64 = |p: Vec<Rational>| p.iter().map(|c| c.to_f64()).collect();
+            return (to_f64(polynomial::divide(&n, &g).0), to_f64(polynomial::divide(&d, &g).0));
+        }
+    }
+    (num.to_vec(), den.to_vec())
+}
+
+// a when expr == a*var + b with a constant a != 0.
+fn linear(expr: &Expression, var: &str) -> Option<Expression> {
+    let slope = differentiate(expr, var);
+    if symbolic::contains_var(&slope, var) || symbolic::is_number(&slope, 0.0) {
+        return None;
+    }
+    Some(slope)
+}
+
+fn is_polynomial(expr: &Expression, var: &str) -> bool {
+    if !symbolic::contains_var(expr, var) {
+        return true;
+    }
+    let args = symbolic::operands(expr);
+    match *symbolic::root(expr) {
+        enums::Token::Var(_) => true,
+        enums::Token::Op(enums::Operator::Negate) => is_polynomial(&args[0], var),
+        enums::Token::Op(enums::Operator::Add) |
+        enums::Token::Op(enums::Operator::Sub) |
+        enums::Token::Op(enums::Operator::Mul) => args.iter().all(|a| is_polynomial(a, var)),
+        enums::Token::Op(enums::Operator::Div) => {
+            is_polynomial(&args[0], var) && !symbolic::contains_var(&args[1], var)
+        }
+        enums::Token::Op(enums::Operator::Pow) => {
+            is_polynomial(&args[0], var) &&
+            symbolic::as_number(&args[1]).is_some_and(|n| n >= 0.0 && n.fract() == 0.0)
+        }
+        _ => false,
+    }
+}
+
+// f(u) for functions whose repeated antiderivatives keep the same shape,
+// as needed for integration by parts.
+fn is_cyclic(expr: &Expression, var: &str) -> bool {
+    let args = symbolic::operands(expr);
+    match *symbolic::root(expr) {
+        enums::Token::Func(enums::Function::Exp) |
+        enums::Token::Func(enums::Function::Sin) |
+        enums::Token::Func(enums::Function::Cos) |
+        enums::Token::Func(enums::Function::Sinh) |
+        enums::Token::Func(enums::Function::Cosh) => linear(&args[0], var).is_some(),
+        enums::Token::Op(enums::Operator::Pow) => {
+            !symbolic::contains_var(&args[0], var) && linear(&args[1], var).is_some()
+        }
+        _ => false,
+    }
+}
+
+// Logarithms and inverse functions of a linear argument, whose derivatives
+// are algebraic.
+fn is_transcendental(expr: &Expression, var: &str) -> bool {
+    use expression::enums::Function::*;
+    match *symbolic::root(expr) {
+        enums::Token::Func(Ln) | enums::Token::Func(Log) | enums::Token::Func(Atan) |
+        enums::Token::Func(Asin) | enums::Token::Func(Acos) | enums::Token::Func(Acot) |
+        enums::Token::Func(Asinh) | enums::Token::Func(Acosh) | enums::Token::Func(Atanh) => {
+            linear(&symbolic::operands(expr)[0], var).is_some()
+        }
+        _ => false,
+    }
+}
+
+fn product(a: Expression, b: Expression, var: &str, depth: usize) -> Option<Expression> {
+    if !symbolic::contains_var(&a, var) {
+        return Some(mul(a, integrate(&b, var, depth)?));
+    }
+    if !symbolic::contains_var(&b, var) {
+        return Some(mul(b, integrate(&a, var, depth)?));
+    }
+    for &(p, g) in &[(&a, &b), (&b, &a)] {
+        if is_polynomial(p, var) && is_cyclic(g, var) {
+            // int p g = p G - int p' G
+            let antiderivative = integrate(g, var, depth)?;
+            let rest = integrate(&mul(differentiate(p, var), antiderivative.clone()), var, depth)?;
+            return Some(sub(mul(p.clone(), antiderivative), rest));
+        }
+    }
+    for &(p, g) in &[(&a, &b), (&b, &a)] {
+        if is_polynomial(p, var) && is_transcendental(g, var) {
+            // int p g = P g - int P g' with an algebraic g'
+            let primitive = integrate(p, var, depth)?;
+            let rest = integrate(&mul(primitive.clone(), differentiate(g, var)), var, depth)?;
+            return Some(sub(mul(primitive, g.clone()), rest));
+        }
+    }
+    if let Some(result) = exp_trig(&a, &b, var).or_else(|| exp_trig(&b, &a, var)) {
+        return Some(result);
+    }
+    if let Some(sum) = product_to_sum(&a, &b, var) {
+        return integrate(&sum, var, depth);
+    }
+    // Distribute over sums.
+    for &(sum, other) in &[(&a, &b), (&b, &a)] {
+        let root = symbolic::root(sum).clone();
+        if root == enums::Token::Op(enums::Operator::Add) ||
+           root == enums::Token::Op(enums::Operator::Sub) {
+            let mut terms = symbolic::operands(sum);
+            let second = mul(terms.pop().unwrap(), other.clone());
+            let first = mul(terms.pop().unwrap(), other.clone());
+            let first = integrate(&first, var, depth)?;
+            let second = integrate(&second, var, depth)?;
+            return Some(symbolic::combine(&root, vec![first, second]).unwrap());
+        }
+    }
+    logarithmic(&mul(a, b), var)
+}
+
+// e^u sin(v) and e^u cos(v) for linear u = p x + q and v = r x + s.
+fn exp_trig(e: &Expression, t: &Expression, var: &str) -> Option<Expression> {
+    if *symbolic::root(e) != enums::Token::Func(enums::Function::Exp) {
+        return None;
+    }
+    let u = symbolic::operands(e).pop().unwrap();
+    let v = symbolic::operands(t).pop()?;
+    let p = linear(&u, var)?;
+    let r = linear(&v, var)?;
+    let norm = add(pow(p.clone(), number(2.0)), pow(r.clone(), number(2.0)));
+    let sin = apply(enums::Function::Sin, v.clone());
+    let cos = apply(enums::Function::Cos, v);
+    let inner = match *symbolic::root(t) {
+        enums::Token::Func(enums::Function::Sin) => sub(mul(p, sin), mul(r, cos)),
+        enums::Token::Func(enums::Function::Cos) => add(mul(p, cos), mul(r, sin)),
+        _ => return None,
+    };
+    Some(div(mul(e.clone(), inner), norm))
+}
+
+// sin A sin B, sin A cos B and cos A cos B as sums of single sines and
+// cosines.
+fn product_to_sum(a: &Expression, b: &Expression, var: &str) -> Option<Expression> {
+    let trig = |e: &Expression| match *symbolic::root(e) {
+        enums::Token::Func(f @ enums::Function::Sin) |
+        enums::Token::Func(f @ enums::Function::Cos) => {
+            let u = symbolic::operands(e).pop().unwrap();
+            linear(&u, var).map(|_| (f, u))
+        }
+        _ => None,
+    };
+    let (f, u) = trig(a)?;
+    let (g, v) = trig(b)?;
+    let difference = sub(u.clone(), v.clone());
+    let sum = add(u, v);
+    let half = |e: Expression| div(e, number(2.0));
+    use expression::enums::Function::{Cos, Sin};
+    Some(match (f, g) {
+        (Sin, Sin) => half(sub(apply(Cos, difference), apply(Cos, sum))),
+        (Cos, Cos) => half(add(apply(Cos, difference), apply(Cos, sum))),
+        (Sin, Cos) => half(add(apply(Sin, sum), apply(Sin, difference))),
+        (Cos, Sin) => half(sub(apply(Sin, sum), apply(Sin, difference))),
+        _ => return None,
+    })
+}
+
+fn quotient(a: Expression, b: Expression, var: &str, depth: usize) -> Option<Expression> {
+    if !symbolic::contains_var(&b, var) {
+        return Some(div(integrate(&a, var, depth)?, b));
+    }
+    if !symbolic::contains_var(&a, var) {
+        if let Some(slope) = linear(&b, var) {
+            let log = apply(enums::Function::Ln, apply(enums::Function::Abs, b));
+            return Some(div(mul(a, log), slope));
+        }
+        if *symbolic::root(&b) == enums::Token::Op(enums::Operator::Pow) {
+            let mut args = symbolic::operands(&b);
+            let exponent = args.pop().unwrap();
+            let base = args.pop().unwrap();
+            let reciprocal = pow(base, neg(exponent));
+            return Some(mul(a, integrate(&reciprocal, var, depth)?));
+        }
+        if *symbolic::root(&b) == enums::Token::Func(enums::Function::Sqrt) {
+            let reciprocal = pow(symbolic::operands(&b).pop().unwrap(), number(-0.5));
+            return Some(mul(a, integrate(&reciprocal, var, depth)?));
+        }
+    }
+    logarithmic(&div(a, b), var)
+}
+
+// c f'/f == c ln|f| when the ratio to f'/f is the same constant at a few
+// sample points.
+fn logarithmic(expr: &Expression, var: &str) -> Option<Expression> {
+    let (numerator, denominator) = match *symbolic::root(expr) {
+        enums::Token::Op(enums::Operator::Div) => {
+            let mut args = symbolic::operands(expr);
+            let b = args.pop().unwrap();
+            (args.pop().unwrap(), b)
+        }
+        _ => return None,
+    };
+    let derivative = differentiate(&denominator, var);
+    let ratio = div(numerator, derivative);
+    let mut values: HashMap<String, f64> = HashMap::new();
+    let mut samples = Vec::new();
+    for x in &[0.37, 0.71, 1.29] {
+        values.insert(var.to_owned(), *x);
+        samples.push(eval_postfix_expr(&ratio, &values));
+    }
+    let c = samples[0];
+    if !c.is_finite() || samples.iter().any(|s| (s - c).abs() > 1e-12 * c.abs().max(1.0)) {
+        return None;
+    }
+    let log = apply(enums::Function::Ln, apply(enums::Function::Abs, denominator));
+    Some(mul(fraction(c), log))
+}
+
+fn power(base: Expression, exponent: Expression, var: &str, depth: usize) -> Option<Expression> {
+    if !symbolic::contains_var(&exponent, var) {
+        if let Some(slope) = linear(&base, var) {
+            if symbolic::is_number(&exponent, -1.0) {
+                let log = apply(enums::Function::Ln, apply(enums::Function::Abs, base));
+                return Some(div(log, slope));
+            }
+            let raised = add(exponent, number(1.0));
+            return Some(div(pow(base, raised.clone()), mul(slope, raised)));
+        }
+        // sin^2 and cos^2 by the double angle formulas, small integer powers
+        // of sums by expanding.
+        let n = symbolic::as_number(&exponent)?;
+        if n == 2.0 {
+            let root = symbolic::root(&base).clone();
+            if root == enums::Token::Func(enums::Function::Sin) ||
+               root == enums::Token::Func(enums::Function::Cos) {
+                let u = symbolic::operands(&base).pop().unwrap();
+                let cos = apply(enums::Function::Cos, mul(number(2.0), u));
+                let sign = if root == enums::Token::Func(enums::Function::Sin) {
+                    -1.0
+                } else {
+                    1.0
+                };
+                let half = div(add(number(1.0), mul(number(sign), cos)), number(2.0));
+                return integrate(&half, var, depth);
+            }
+        }
+        if (2.0..=8.0).contains(&n) && n.fract() == 0.0 {
+            let rest = pow(base.clone(), number(n - 1.0));
+            return product(base, rest, var, depth);
+        }
+        return None;
+    }
+    if !symbolic::contains_var(&base, var) {
+        let slope = linear(&exponent, var)?;
+        let log = apply(enums::Function::Ln, base.clone());
+        return Some(div(pow(base, exponent), mul(slope, log)));
+    }
+    None
+}
+
+// f(u) for u = a x + b, as F(u)/a.
+fn function(f: enums::Function, u: Expression, var: &str) -> Option<Expression> {
+    use expression::enums::Function::*;
+    let slope = linear(&u, var)?;
+    let ln = |e: Expression| apply(Ln, e);
+    let abs = |e: Expression| apply(Abs, e);
+    let call = |g: enums::Function| apply(g, u.clone());
+    let square = pow(u.clone(), number(2.0));
+    let one = || number(1.0);
+    let primitive = match f {
+        Sin => neg(call(Cos)),
+        Cos => call(Sin),
+        Tan => neg(ln(abs(call(Cos)))),
+        Cot => ln(abs(call(Sin))),
+        Sec => ln(abs(add(call(Sec), call(Tan)))),
+        Csc => neg(ln(abs(add(call(Csc), call(Cot))))),
+        Exp => call(Exp),
+        Ln => sub(mul(u.clone(), call(Ln)), u.clone()),
+        Log => div(sub(mul(u.clone(), call(Ln)), u.clone()), ln(number(10.0))),
+        Sqrt => mul(fraction(2.0 / 3.0), pow(u.clone(), number(1.5))),
+        Sinh => call(Cosh),
+        Cosh => call(Sinh),
+        Tanh => ln(call(Cosh)),
+        Coth => ln(abs(call(Sinh))),
+        Sech => apply(Atan, call(Sinh)),
+        Csch => ln(abs(apply(Tanh, div(u.clone(), number(2.0))))),
+        Asin => add(mul(u.clone(), call(Asin)), apply(Sqrt, sub(one(), square))),
+        Acos => sub(mul(u.clone(), call(Acos)), apply(Sqrt, sub(one(), square))),
+        Atan => sub(mul(u.clone(), call(Atan)), div(ln(add(one(), square)), number(2.0))),
+        Acot => add(mul(u.clone(), call(Acot)), div(ln(add(one(), square)), number(2.0))),
+        Asinh => sub(mul(u.clone(), call(Asinh)), apply(Sqrt, add(square, one()))),
+        Acosh => sub(mul(u.clone(), call(Acosh)), apply(Sqrt, sub(square, one()))),
+        Atanh => add(mul(u.clone(), call(Atanh)), div(ln(sub(one(), square)), number(2.0))),
+        Abs => div(mul(u.clone(), call(Abs)), number(2.0)),
+        Recip => ln(abs(u.clone())),
+        _ => return None,
+    };
+    Some(div(primitive, slope))
+}
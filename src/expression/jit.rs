@@ -0,0 +1,422 @@
+use expression;
+use expression::bytecode;
+
+pub type Expression = expression::Expression;
+
+// Expressions compiled to native code with Cranelift when the `jit` feature
+// is enabled. Without it the same API runs the bytecode interpreter, so
+// callers never need to care which one they got.
+pub struct JitFunction {
+    slots: Vec<String>,
+    inner: backend::Compiled,
+}
+
+// `vars` fixes the slot order the same way as bytecode::compile.
+pub fn compile(expr: &Expression, vars: &[String]) -> Result<JitFunction, String> {
+    let program = bytecode::compile::<f64>(expr, vars)?;
+    Ok(JitFunction {
+        slots: program.slots().to_vec(),
+        inner: backend::Compiled::new(program)?,
+    })
+}
+
+impl JitFunction {
+    pub fn slots(&self) -> &[String] {
+        self.slots.as_slice()
+    }
+
+    pub fn slot(&self, name: &str) -> Option<usize> {
+        self.slots.iter().position(|s| s == name)
+    }
+
+    pub fn is_native(&self) -> bool {
+        backend::NATIVE
+    }
+
+    // `args` is indexed by slot, see JitFunction::slots.
+    pub fn call(&mut self, args: &[f64]) -> f64 {
+        assert!(args.len() >= self.slots.len(),
+                "Expected {} arguments but got {}",
+                self.slots.len(),
+                args.len());
+        self.inner.call(args)
+    }
+}
+
+#[cfg(not(feature = "jit"))]
+mod backend {
+    use expression::bytecode::{Machine, Program};
+
+    pub const NATIVE: bool = false;
+
+    pub struct Compiled {
+        program: Program,
+        machine: Machine,
+    }
+
+    impl Compiled {
+        pub fn new(program: Program) -> Result<Self, String> {
+            let machine = Machine::new(&program);
+            Ok(Compiled { program, machine })
+        }
+
+        pub fn call(&mut self, args: &[f64]) -> f64 {
+            self.machine.run(&self.program, args)
+        }
+    }
+}
+
+#[cfg(feature = "jit")]
+mod backend {
+    extern crate cranelift_codegen;
+    extern crate cranelift_frontend;
+    extern crate cranelift_jit;
+    extern crate cranelift_module;
+    extern crate cranelift_native;
+
+    use self::cranelift_codegen::ir::{types, AbiParam, FuncRef, InstBuilder, MemFlags, Value};
+    use self::cranelift_codegen::settings::{self, Configurable};
+    use self::cranelift_frontend::{FunctionBuilder, FunctionBuilderContext};
+    use self::cranelift_jit::{JITBuilder, JITModule};
+    use self::cranelift_module::{default_libcall_names, Linkage, Module};
+
+    use expression::bytecode::{Instruction, Program};
+    use expression::enums;
+
+    use std::collections::HashMap;
+    use std::mem;
+
+    pub const NATIVE: bool = true;
+
+    type NativeFn = extern "C" fn(*const f64) -> f64;
+
+    pub struct Compiled {
+        module: Option<JITModule>,
+        func: NativeFn,
+    }
+
+    impl Compiled {
+        pub fn new(program: Program) -> Result<Self, String> {
+            let mut flags = settings::builder();
+            flags.set("use_colocated_libcalls", "false").map_err(|e| e.to_string())?;
+            flags.set("is_pic", "false").map_err(|e| e.to_string())?;
+            flags.set("opt_level", "speed").map_err(|e| e.to_string())?;
+            let isa = cranelift_native::builder()
+                .map_err(|e| e.to_owned())?
+                .finish(settings::Flags::new(flags))
+                .map_err(|e| e.to_string())?;
+            let mut jit_builder = JITBuilder::with_isa(isa, default_libcall_names());
+            for &(name, _, func) in LIBM_UNARY {
+                jit_builder.symbol(name, func as *const u8);
+            }
+            for &(name, func) in LIBM_BINARY {
+                jit_builder.symbol(name, func as *const u8);
+            }
+            let mut module = JITModule::new(jit_builder);
+
+            let mut ctx = module.make_context();
+            let pointer = module.target_config().pointer_type();
+            ctx.func.signature.params.push(AbiParam::new(pointer));
+            ctx.func.signature.returns.push(AbiParam::new(types::F64));
+            let mut builder_ctx = FunctionBuilderContext::new();
+            {
+                let mut builder = FunctionBuilder::new(&mut ctx.func, &mut builder_ctx);
+                let block = builder.create_block();
+                builder.append_block_params_for_function_params(block);
+                builder.switch_to_block(block);
+                builder.seal_block(block);
+                let args = builder.block_params(block)[0];
+                let result = {
+                    let mut translator = Translator {
+                        module: &mut module,
+                        builder: &mut builder,
+                        imports: HashMap::new(),
+                    };
+                    translator.translate(&program, args)?
+                };
+                builder.ins().return_(&[result]);
+                builder.finalize();
+            }
+            let id = module.declare_function("expression", Linkage::Export, &ctx.func.signature)
+                .map_err(|e| e.to_string())?;
+            module.define_function(id, &mut ctx).map_err(|e| e.to_string())?;
+            module.clear_context(&mut ctx);
+            module.finalize_definitions().map_err(|e| e.to_string())?;
+            let code = module.get_finalized_function(id);
+            // The signature above is exactly NativeFn.
+            let func = unsafe { mem::transmute::<*const u8, NativeFn>(code) };
+            Ok(Compiled {
+                module: Some(module),
+                func,
+            })
+        }
+
+        pub fn call(&mut self, args: &[f64]) -> f64 {
+            (self.func)(args.as_ptr())
+        }
+    }
+
+    impl Drop for Compiled {
+        fn drop(&mut self) {
+            if let Some(module) = self.module.take() {
+                // Nothing can call into the code anymore once self is gone.
+                unsafe { module.free_memory() };
+            }
+        }
+    }
+
+    struct Translator<'a, 'b: 'a> {
+        module: &'a mut JITModule,
+        builder: &'a mut FunctionBuilder<'b>,
+        imports: HashMap<&'static str, FuncRef>,
+    }
+
+    impl<'a, 'b> Translator<'a, 'b> {
+        fn translate(&mut self, program: &Program, args: Value) -> Result<Value, String> {
+            let mut stack: Vec<Value> = Vec::with_capacity(program.stack_size());
+            for instruction in program.code() {
+                match *instruction {
+                    Instruction::Push(x) => {
+                        let value = self.builder.ins().f64const(x);
+                        stack.push(value);
+                    }
+                    Instruction::Load(slot) => {
+                        let value = self.builder
+                            .ins()
+                            .load(types::F64, MemFlags::trusted(), args, (slot * 8) as i32);
+                        stack.push(value);
+                    }
+                    Instruction::Negate => {
+                        let arg = stack.pop().unwrap();
+                        let value = self.builder.ins().fneg(arg);
+                        stack.push(value);
+                    }
+                    Instruction::Binary(op) => {
+                        let arg2 = stack.pop().unwrap();
+                        let arg1 = stack.pop().unwrap();
+                        let value = self.operator(op, arg1, arg2)?;
+                        stack.push(value);
+                    }
+                    Instruction::Func(f) => {
+                        let arg = stack.pop().unwrap();
+                        let value = self.function(f, arg)?;
+                        stack.push(value);
+                    }
+                    Instruction::BinaryFunc(f) => {
+                        let arg2 = stack.pop().unwrap();
+                        let arg1 = stack.pop().unwrap();
+                        let value = match f {
+                            enums::Function::LogBase => {
+                                let num = self.call("ln", &[arg1])?;
+                                let den = self.call("ln", &[arg2])?;
+                                self.builder.ins().fdiv(num, den)
+                            }
+                            _ => self.call("fmax", &[arg1, arg2])?,
+                        };
+                        stack.push(value);
+                    }
+                }
+            }
+            Ok(stack.pop().unwrap())
+        }
+
+        fn operator(&mut self, op: enums::Operator, arg1: Value, arg2: Value) -> Result<Value, String> {
+            Ok(match op {
+                enums::Operator::Add => self.builder.ins().fadd(arg1, arg2),
+                enums::Operator::Sub => self.builder.ins().fsub(arg1, arg2),
+                enums::Operator::Mul => self.builder.ins().fmul(arg1, arg2),
+                enums::Operator::Div => self.builder.ins().fdiv(arg1, arg2),
+                enums::Operator::Mod => self.call("fmod", &[arg1, arg2])?,
+                enums::Operator::Pow => self.call("pow", &[arg1, arg2])?,
+                enums::Operator::Negate => self.builder.ins().fneg(arg2),
+            })
+        }
+
+        fn function(&mut self, f: enums::Function, arg: Value) -> Result<Value, String> {
+            Ok(match f {
+                enums::Function::Abs => self.builder.ins().fabs(arg),
+                enums::Function::Sqrt => self.builder.ins().sqrt(arg),
+                enums::Function::Recip => self.recip(arg),
+                enums::Function::Csc => {
+                    let value = self.call("sin", &[arg])?;
+                    self.recip(value)
+                }
+                enums::Function::Sec => {
+                    let value = self.call("cos", &[arg])?;
+                    self.recip(value)
+                }
+                enums::Function::Cot => {
+                    let value = self.call("tan", &[arg])?;
+                    self.recip(value)
+                }
+                enums::Function::Csch => {
+                    let value = self.call("sinh", &[arg])?;
+                    self.recip(value)
+                }
+                enums::Function::Sech => {
+                    let value = self.call("cosh", &[arg])?;
+                    self.recip(value)
+                }
+                enums::Function::Coth => {
+                    let value = self.call("tanh", &[arg])?;
+                    self.recip(value)
+                }
+                enums::Function::Acsc => {
+                    let value = self.recip(arg);
+                    self.call("asin", &[value])?
+                }
+                enums::Function::Asec => {
+                    let value = self.recip(arg);
+                    self.call("acos", &[value])?
+                }
+                enums::Function::Acot => {
+                    let value = self.recip(arg);
+                    self.call("atan", &[value])?
+                }
+                enums::Function::Acsch => {
+                    let value = self.recip(arg);
+                    self.call("asinh", &[value])?
+                }
+                enums::Function::Asech => {
+                    let value = self.recip(arg);
+                    self.call("acosh", &[value])?
+                }
+                enums::Function::Acoth => {
+                    let value = self.recip(arg);
+                    self.call("atanh", &[value])?
+                }
+                _ => {
+                    let name = match LIBM_UNARY.iter().find(|&&(_, func, _)| func == f) {
+                        Some(&(name, _, _)) => name,
+                        None => return Err(format!("No native implementation of {:?}", f)),
+                    };
+                    self.call(name, &[arg])?
+                }
+            })
+        }
+
+        fn recip(&mut self, arg: Value) -> Value {
+            let one = self.builder.ins().f64const(1.0);
+            self.builder.ins().fdiv(one, arg)
+        }
+
+        fn call(&mut self, name: &'static str, args: &[Value]) -> Result<Value, String> {
+            let func = match self.imports.get(name) {
+                Some(func) => *func,
+                None => {
+                    let mut signature = self.module.make_signature();
+                    for _ in args {
+                        signature.params.push(AbiParam::new(types::F64));
+                    }
+                    signature.returns.push(AbiParam::new(types::F64));
+                    let id = self.module
+                        .declare_function(name, Linkage::Import, &signature)
+                        .map_err(|e| e.to_string())?;
+                    let func = self.module.declare_func_in_func(id, self.builder.func);
+                    self.imports.insert(name, func);
+                    func
+                }
+            };
+            let call = self.builder.ins().call(func, args);
+            Ok(self.builder.inst_results(call)[0])
+        }
+    }
+
+    // Thin wrappers so the generated code calls into the same libm routines
+    // as the interpreter without relying on the dynamic linker to find them.
+    macro_rules! libm_unary {
+        ($($name:ident => $func:ident => $variant:ident),*) => {
+            $(extern "C" fn $name(x: f64) -> f64 { x.$func() })*
+            const LIBM_UNARY: &[(&str, enums::Function, extern "C" fn(f64) -> f64)] = &[
+                $((stringify!($func), enums::Function::$variant, $name)),*
+            ];
+        };
+    }
+
+    libm_unary!(jit_exp => exp => Exp,
+                jit_ln => ln => Ln,
+                jit_log10 => log10 => Log,
+                jit_sin => sin => Sin,
+                jit_cos => cos => Cos,
+                jit_tan => tan => Tan,
+                jit_asin => asin => Asin,
+                jit_acos => acos => Acos,
+                jit_atan => atan => Atan,
+                jit_sinh => sinh => Sinh,
+                jit_cosh => cosh => Cosh,
+                jit_tanh => tanh => Tanh,
+                jit_asinh => asinh => Asinh,
+                jit_acosh => acosh => Acosh,
+                jit_atanh => atanh => Atanh);
+
+    extern "C" fn jit_fmod(x: f64, y: f64) -> f64 {
+        x % y
+    }
+
+    extern "C" fn jit_pow(x: f64, y: f64) -> f64 {
+        x.powf(y)
+    }
+
+    extern "C" fn jit_fmax(x: f64, y: f64) -> f64 {
+        x.max(y)
+    }
+
+    const LIBM_BINARY: &[(&str, extern "C" fn(f64, f64) -> f64)] = &[("fmod", jit_fmod),
+                                                                     ("pow", jit_pow),
+                                                                     ("fmax", jit_fmax)];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use expression::parse::parse;
+
+    const FUNCTIONS: &[&str] = &["abs", "exp", "sqrt", "ln", "log", "sin", "csc", "cos", "sec",
+                                 "tan", "cot", "asin", "acsc", "acos", "asec", "atan", "acot",
+                                 "sinh", "csch", "cosh", "sech", "tanh", "coth", "asinh",
+                                 "acsch", "acosh", "asech", "atanh", "acoth", "recip"];
+
+    fn same(x: f64, expected: f64) -> bool {
+        (x.is_nan() && expected.is_nan()) || x == expected ||
+        (x - expected).abs() <= 1e-14 * expected.abs()
+    }
+
+    fn check(input: &str) {
+        let vars = ["x".to_owned(), "y".to_owned()];
+        let expr = parse(input);
+        let program = bytecode::compile::<f64>(&expr, &vars).unwrap();
+        let mut function = compile(&expr, &vars).unwrap();
+        for &x in &[-2.5, -0.75, 0.0, 0.4, 1.0, 3.5] {
+            for &y in &[-1.5, 0.5, 2.0] {
+                let expected = program.eval(&[x, y]);
+                let got = function.call(&[x, y]);
+                assert!(same(got, expected), "{} at ({}, {}): {} != {}", input, x, y, got, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn matches_the_interpreter() {
+        for f in FUNCTIONS {
+            check(&format!("{}(x)", f));
+        }
+        check("x*x+3*x+2");
+        check("x^y+x%y+max(x,y)");
+        check("logbase(abs(x)+2,2)*cos(y)+tanh(x*y)");
+        check("sin(x)*exp(x/10)+sqrt(abs(x))/(1+y*y)");
+    }
+
+    #[cfg(feature = "jit")]
+    #[test]
+    fn jit_feature_compiles_natively() {
+        let function = compile(&parse("x+1"), &["x".to_owned()]).unwrap();
+        assert!(function.is_native());
+    }
+
+    #[cfg(not(feature = "jit"))]
+    #[test]
+    fn falls_back_to_the_interpreter() {
+        let function = compile(&parse("x+1"), &["x".to_owned()]).unwrap();
+        assert!(!function.is_native());
+    }
+}
@@ -0,0 +1,426 @@
+use expression;
+use expression::derivative::differentiate;
+use expression::enums;
+use expression::eval::{eval_binary_function, eval_function, eval_postfix_expr};
+use expression::polynomial;
+use expression::rational::Rational;
+use expression::symbolic::{self, div, mul, number};
+
+pub type Expression = expression::Expression;
+
+use std::collections::HashMap;
+use std::f64;
+use std::fmt;
+
+const MAX_DEPTH: usize = 8;
+// Limits below this are taken to be exactly zero, so rounding noise in e.g.
+// sin(x) - x does not hide a 0/0 form.
+const ZERO: f64 = 1e-14;
+const SAMPLES: usize = 24;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Point {
+    Finite(f64),
+    PosInf,
+    NegInf,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Direction {
+    Both,
+    Left,
+    Right,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum LimitValue {
+    Finite(f64),
+    PosInf,
+    NegInf,
+    // No limit: the one-sided limits differ or the function oscillates.
+    Undefined,
+}
+
+impl LimitValue {
+    fn from_f64(x: f64) -> LimitValue {
+        if x.is_nan() {
+            LimitValue::Undefined
+        } else if x == f64::INFINITY {
+            LimitValue::PosInf
+        } else if x == f64::NEG_INFINITY {
+            LimitValue::NegInf
+        } else {
+            // No -0 in the output.
+            LimitValue::Finite(x + 0.0)
+        }
+    }
+}
+
+impl fmt::Display for LimitValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LimitValue::Finite(x) => write!(f, "{}", x),
+            LimitValue::PosInf => write!(f, "inf"),
+            LimitValue::NegInf => write!(f, "-inf"),
+            LimitValue::Undefined => write!(f, "undefined"),
+        }
+    }
+}
+
+// The limit of `expr` as `var` approaches `point` from `direction`. Limits at
+// infinity are taken as one-sided limits at 0 after substituting 1/var, and a
+// two-sided limit exists when both one-sided limits agree.
+pub fn limit(expr: &Expression,
+             var: &str,
+             point: Point,
+             direction: Direction,
+             values: &HashMap<String, f64>)
+             -> LimitValue {
+    match point {
+        Point::PosInf | Point::NegInf => {
+            let side = if point == Point::PosInf { 1.0 } else { -1.0 };
+            let reciprocal = div(number(1.0), symbolic::variable(var));
+            let inverted = symbolic::substitute(expr, var, &reciprocal);
+            one_sided(&inverted, var, 0.0, side, values)
+        }
+        Point::Finite(a) => {
+            match direction {
+                Direction::Left => one_sided(expr, var, a, -1.0, values),
+                Direction::Right => one_sided(expr, var, a, 1.0, values),
+                Direction::Both => {
+                    let left = one_sided(expr, var, a, -1.0, values);
+                    let right = one_sided(expr, var, a, 1.0, values);
+                    if agree(left, right) { right } else { LimitValue::Undefined }
+                }
+            }
+        }
+    }
+}
+
+fn agree(left: LimitValue, right: LimitValue) -> bool {
+    match (left, right) {
+        (LimitValue::Finite(l), LimitValue::Finite(r)) => {
+            (l - r).abs() <= 1e-8 * l.abs().max(r.abs()).max(1.0)
+        }
+        (LimitValue::Undefined, _) | (_, LimitValue::Undefined) => false,
+        _ => left == right,
+    }
+}
+
+pub fn one_sided(expr: &Expression,
+                 var: &str,
+                 a: f64,
+                 side: f64,
+                 values: &HashMap<String, f64>)
+                 -> LimitValue {
+    let mut context = Limit {
+        var,
+        a,
+        side,
+        values: values.clone(),
+    };
+    if !context.defined_beside(expr) {
+        return LimitValue::Undefined;
+    }
+    let exact = context.limit(&symbolic::simplify(expr), 0);
+    if !exact.is_nan() {
+        return LimitValue::from_f64(exact);
+    }
+    context.extrapolate(expr)
+}
+
+struct Limit<'a> {
+    var: &'a str,
+    a: f64,
+    // +1 from the right, -1 from the left.
+    side: f64,
+    values: HashMap<String, f64>,
+}
+
+impl<'a> Limit<'a> {
+    fn eval(&mut self, expr: &Expression, x: f64) -> f64 {
+        self.values.insert(self.var.to_owned(), x);
+        eval_postfix_expr(expr, &self.values)
+    }
+
+    fn near(&self, h: f64) -> f64 {
+        self.a + self.side * h * self.a.abs().max(1.0)
+    }
+
+    // False when `expr` is undefined on this side of the point, as sqrt(x)
+    // is left of 0.
+    fn defined_beside(&mut self, expr: &Expression) -> bool {
+        [1e-6, 1e-8, 1e-10].iter().any(|h| {
+            let x = self.near(*h);
+            !self.eval(expr, x).is_nan()
+        })
+    }
+
+    // The sign `expr` settles on next to the point, NaN if it has none.
+    fn side_sign(&mut self, expr: &Expression) -> f64 {
+        let mut sign = f64::NAN;
+        for h in &[1e-6, 1e-8, 1e-10] {
+            let x = self.near(*h);
+            let y = self.eval(expr, x);
+            if y == 0.0 || y.is_nan() || (!sign.is_nan() && y.signum() != sign) {
+                return f64::NAN;
+            }
+            sign = y.signum();
+        }
+        sign
+    }
+
+    // Limit laws over the extended reals. NaN means an indeterminate form
+    // that neither L'Hopital's rule nor a rewrite could settle.
+    fn limit(&mut self, expr: &Expression, depth: usize) -> f64 {
+        if depth > MAX_DEPTH {
+            return f64::NAN;
+        }
+        if !symbolic::contains_var(expr, self.var) {
+            return self.eval(expr, self.a);
+        }
+        let args = symbolic::operands(expr);
+        let result = match *symbolic::root(expr) {
+            enums::Token::Var(_) => self.a,
+            enums::Token::Op(enums::Operator::Negate) => -self.limit(&args[0], depth),
+            enums::Token::Op(enums::Operator::Add) => {
+                self.limit(&args[0], depth) + self.limit(&args[1], depth)
+            }
+            enums::Token::Op(enums::Operator::Sub) => {
+                self.limit(&args[0], depth) - self.limit(&args[1], depth)
+            }
+            enums::Token::Op(enums::Operator::Mul) => {
+                let (la, lb) = (self.limit(&args[0], depth), self.limit(&args[1], depth));
+                if is_zero(la) && lb.is_infinite() {
+                    self.zero_times_infinity(&args[0], &args[1], depth)
+                } else if la.is_infinite() && is_zero(lb) {
+                    self.zero_times_infinity(&args[1], &args[0], depth)
+                } else {
+                    la * lb
+                }
+            }
+            enums::Token::Op(enums::Operator::Div) => self.quotient(expr, depth),
+            enums::Token::Op(enums::Operator::Pow) => self.power(&args[0], &args[1], depth),
+            enums::Token::Op(enums::Operator::Mod) => f64::NAN,
+            enums::Token::Func(f @ enums::Function::Max) |
+            enums::Token::Func(f @ enums::Function::LogBase) => {
+                let (la, lb) = (self.limit(&args[0], depth), self.limit(&args[1], depth));
+                eval_binary_function(&f, la, lb)
+            }
+            enums::Token::Func(f) => {
+                let l = self.limit(&args[0], depth);
+                if l.is_nan() {
+                    return l;
+                }
+                // Rounding turns a pole such as tan at pi/2 into a huge
+                // finite value, the side settles it instead.
+                if at_pole(f, l) { f64::INFINITY } else { eval_function(&f, l) }
+            }
+            _ => self.eval(expr, self.a),
+        };
+        if result.is_infinite() {
+            // Poles such as 1/x or cot(x) take their sign from the side.
+            let sign = self.side_sign(expr);
+            return if sign.is_nan() { sign } else { sign * f64::INFINITY };
+        }
+        result
+    }
+
+    // z*w with z -> 0 and w -> inf, as w/(1/z) first since L'Hopital's rule
+    // tends to do better with the unbounded factor on top, then as z/(1/w).
+    fn zero_times_infinity(&mut self, z: &Expression, w: &Expression, depth: usize) -> f64 {
+        let over = |a: &Expression, b: &Expression| fraction(a, &fraction(&number(1.0), b));
+        let result = self.quotient(&over(w, z), depth + 1);
+        if !result.is_nan() {
+            return result;
+        }
+        self.quotient(&over(z, w), depth + 1)
+    }
+
+    fn quotient(&mut self, expr: &Expression, depth: usize) -> f64 {
+        let args = symbolic::operands(expr);
+        let (num, den) = (&args[0], &args[1]);
+        let (ln, ld) = (self.limit(num, depth), self.limit(den, depth));
+        if ln.is_nan() || ld.is_nan() {
+            return f64::NAN;
+        }
+        let indeterminate = (is_zero(ln) && is_zero(ld)) || (ln.is_infinite() && ld.is_infinite());
+        if !indeterminate {
+            if is_zero(ld) {
+                // c/0 is a pole, the sign comes from the side.
+                return f64::INFINITY;
+            }
+            return ln / ld;
+        }
+        if let Some(reduced) = self.cancel(num, den) {
+            return self.limit(&reduced, depth + 1);
+        }
+        // L'Hopital's rule.
+        let dn = differentiate(num, self.var);
+        let dd = differentiate(den, self.var);
+        self.limit(&symbolic::div(dn, dd), depth + 1)
+    }
+
+    // Cancels the common factor of a rational function, None if `num/den`
+    // is not one or nothing cancels.
+    fn cancel(&mut self, num: &Expression, den: &Expression) -> Option<Expression> {
+        let p = polynomial::to_rational(&polynomial::coefficients(num, self.var, &self.values)?)?;
+        let q = polynomial::to_rational(&polynomial::coefficients(den, self.var, &self.values)?)?;
+        let g = polynomial::gcd(&p, &q);
+        if polynomial::degree(&g) == 0 || g.iter().any(|c| !c.is_valid()) {
+            return None;
+        }
+        let to_f64 = |p: &[Rational]| p.iter().map(|c| c.to_f64()).collect::<Vec<f64>>();
+        let reduced = |p: &[Rational]| {
+            polynomial::to_expression(&to_f64(&polynomial::divide(p, &g).0), self.var)
+        };
+        Some(div(reduced(&p), reduced(&q)))
+    }
+
+    fn power(&mut self, base: &Expression, exponent: &Expression, depth: usize) -> f64 {
+        let (lb, le) = (self.limit(base, depth), self.limit(exponent, depth));
+        let indeterminate = (is_zero(lb) && is_zero(le)) || (lb == 1.0 && le.is_infinite()) ||
+                            (lb.is_infinite() && is_zero(le));
+        if !indeterminate {
+            return lb.powf(le);
+        }
+        // 0^0, 1^inf and inf^0 through exp(e*ln(b)).
+        let log = mul(exponent.clone(), symbolic::apply(enums::Function::Ln, base.clone()));
+        self.limit(&log, depth + 1).exp()
+    }
+
+    // Richardson extrapolation of f(a + h) as h halves towards 0, for
+    // whatever the limit laws could not decide.
+    fn extrapolate(&mut self, expr: &Expression) -> LimitValue {
+        let mut ys: Vec<f64> = Vec::with_capacity(SAMPLES);
+        for k in 0..SAMPLES {
+            let x = self.near(0.1 * 0.5f64.powi(k as i32));
+            ys.push(self.eval(expr, x));
+        }
+        if ys.iter().any(|y| y.is_nan()) {
+            return LimitValue::Undefined;
+        }
+        let tail = &ys[SAMPLES - 8..];
+        // Samples that overflowed, like exp(x) for large x, count as growing.
+        let growing = tail.windows(2).all(|w| w[1].is_infinite() || w[1].abs() > w[0].abs() * 1.2);
+        let same_sign = tail.iter().all(|y| y.signum() == tail[0].signum());
+        if growing && same_sign {
+            return if tail[0] > 0.0 { LimitValue::PosInf } else { LimitValue::NegInf };
+        }
+        // Squeezed to zero, e.g. x*sin(1/x).
+        let early = tail[..4].iter().fold(0.0f64, |m, y| m.max(y.abs()));
+        if tail.iter().all(|y| y.abs() <= 1e-4) && tail[7].abs() <= 0.5 * early {
+            return LimitValue::Finite(0.0);
+        }
+        // Tableau over the samples where rounding has not taken over yet.
+        let start = SAMPLES - 12;
+        let mut best: Option<(f64, f64)> = None;
+        let mut previous_row: Vec<f64> = Vec::new();
+        for (i, y) in ys[start..].iter().enumerate() {
+            let mut row = vec![*y];
+            for j in 1..i + 1 {
+                let factor = 2f64.powi(j as i32);
+                row.push(row[j - 1] + (row[j - 1] - previous_row[j - 1]) / (factor - 1.0));
+            }
+            if i > 0 {
+                let change = (row[i] - previous_row[i - 1]).abs();
+                if best.is_none_or(|(_, c)| change < c) {
+                    best = Some((row[i], change));
+                }
+            }
+            previous_row = row;
+        }
+        match best {
+            Some((estimate, change)) if change <= 1e-8 * estimate.abs().max(1.0) => {
+                LimitValue::Finite(snap(estimate))
+            }
+            _ => {
+                let last = ys[SAMPLES - 1];
+                let settled = (last - ys[SAMPLES - 2]).abs() <= 1e-9 * last.abs().max(1.0);
+                if settled { LimitValue::Finite(snap(last)) } else { LimitValue::Undefined }
+            }
+        }
+    }
+}
+
+// a/b as written, the simplifying constructor would undo the rewrites above.
+fn fraction(a: &Expression, b: &Expression) -> Expression {
+    let mut tokens = a.get_tokens().to_vec();
+    tokens.extend_from_slice(b.get_tokens());
+    tokens.push(enums::Token::Op(enums::Operator::Div));
+    Expression::new(tokens)
+}
+
+// Poles of the trigonometric functions, which f64 multiples of pi miss.
+fn at_pole(f: enums::Function, x: f64) -> bool {
+    let tolerance = 1e-12 * x.abs().max(1.0);
+    match f {
+        enums::Function::Tan | enums::Function::Sec => x.cos().abs() <= tolerance,
+        enums::Function::Cot | enums::Function::Csc => x.sin().abs() <= tolerance,
+        _ => false,
+    }
+}
+
+fn is_zero(x: f64) -> bool {
+    x.abs() <= ZERO
+}
+
+// Rounds extrapolated values to a nearby simple fraction.
+fn snap(x: f64) -> f64 {
+    match Rational::from_f64(x, 1000) {
+        Some(_) => x,
+        None => {
+            let scaled = (x * 1e9).round() / 1e9;
+            match Rational::from_f64(scaled, 1000) {
+                Some(r) => r.to_f64(),
+                None => x,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use expression::parse::parse;
+
+    fn at(expr: &str, a: f64, direction: Direction) -> LimitValue {
+        limit(&parse(expr), "x", Point::Finite(a), direction, &HashMap::new())
+    }
+
+    #[test]
+    fn poles_of_trigonometric_functions() {
+        let half_pi = f64::consts::FRAC_PI_2;
+        assert_eq!(at("tan(x)", half_pi, Direction::Both), LimitValue::Undefined);
+        assert_eq!(at("tan(x)", half_pi, Direction::Left), LimitValue::PosInf);
+        assert_eq!(at("tan(x)", half_pi, Direction::Right), LimitValue::NegInf);
+        assert_eq!(at("sec(x)", half_pi, Direction::Right), LimitValue::NegInf);
+        assert_eq!(at("csc(x)", f64::consts::PI, Direction::Both), LimitValue::Undefined);
+        assert_eq!(at("cot(x)", f64::consts::PI, Direction::Right), LimitValue::PosInf);
+        assert_eq!(at("(x-1.5707963267948966)*tan(x)", half_pi, Direction::Both),
+                   LimitValue::Finite(-1.0));
+    }
+
+    #[test]
+    fn branch_points_and_removable_singularities() {
+        assert_eq!(at("ln(x)", 0.0, Direction::Right), LimitValue::NegInf);
+        assert_eq!(at("ln(x)", 0.0, Direction::Both), LimitValue::Undefined);
+        assert_eq!(at("sqrt(x)", 0.0, Direction::Left), LimitValue::Undefined);
+        assert_eq!(at("sqrt(x)", 0.0, Direction::Right), LimitValue::Finite(0.0));
+        assert_eq!(at("sin(x)/x", 0.0, Direction::Both), LimitValue::Finite(1.0));
+        assert_eq!(at("(1+x)^(1/x)", 0.0, Direction::Both), LimitValue::Finite(f64::consts::E));
+    }
+
+    #[test]
+    fn overflow_at_infinity_diverges() {
+        let inf = |expr: &str, point: Point| {
+            limit(&parse(expr), "x", point, Direction::Both, &HashMap::new())
+        };
+        assert_eq!(inf("exp(x)/x^10", Point::PosInf), LimitValue::PosInf);
+        assert_eq!(inf("-exp(x)/x^10", Point::PosInf), LimitValue::NegInf);
+        assert_eq!(inf("exp(x^2)/x", Point::PosInf), LimitValue::PosInf);
+        assert_eq!(inf("x^10/exp(x)", Point::PosInf), LimitValue::Finite(0.0));
+        assert_eq!(inf("exp(x)/x^10", Point::NegInf), LimitValue::Finite(0.0));
+        assert_eq!(inf("exp(x)*sin(x)", Point::PosInf), LimitValue::Undefined);
+    }
+}
@@ -0,0 +1,90 @@
+use std::f64;
+
+// Small dense matrices stored as rows.
+pub type Matrix = Vec<Vec<f64>>;
+
+pub fn identity(n: usize) -> Matrix {
+    (0..n).map(|i| (0..n).map(|j| if i == j { 1.0 } else { 0.0 }).collect()).collect()
+}
+
+pub fn mat_vec(a: &[Vec<f64>], x: &[f64]) -> Vec<f64> {
+    a.iter().map(|row| dot(row, x)).collect()
+}
+
+// a^T x
+pub fn mat_t_vec(a: &[Vec<f64>], x: &[f64]) -> Vec<f64> {
+    let cols = a.first().map_or(0, |row| row.len());
+    let mut result = vec![0.0; cols];
+    for (row, xi) in a.iter().zip(x) {
+        for (r, aij) in result.iter_mut().zip(row) {
+            *r += aij * xi;
+        }
+    }
+    result
+}
+
+// a^T a
+pub fn gram(a: &[Vec<f64>]) -> Matrix {
+    let cols = a.first().map_or(0, |row| row.len());
+    let mut result = vec![vec![0.0; cols]; cols];
+    for row in a {
+        for i in 0..cols {
+            for j in 0..cols {
+                result[i][j] += row[i] * row[j];
+            }
+        }
+    }
+    result
+}
+
+pub fn dot(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+pub fn norm(x: &[f64]) -> f64 {
+    dot(x, x).sqrt()
+}
+
+pub fn norm_inf(x: &[f64]) -> f64 {
+    x.iter().fold(0.0, |m, v| m.max(v.abs()))
+}
+
+// Gaussian elimination with partial pivoting, None if `a` is singular to
+// working precision.
+pub fn solve_linear(a: &[Vec<f64>], b: &[f64]) -> Option<Vec<f64>> {
+    let n = b.len();
+    let mut a: Matrix = a.to_vec();
+    let mut b: Vec<f64> = b.to_vec();
+    let scale = a.iter().map(|row| norm_inf(row)).fold(0.0, f64::max);
+    if scale == 0.0 || a.iter().any(|row| row.iter().any(|v| !v.is_finite())) {
+        return None;
+    }
+    for k in 0..n {
+        let pivot = (k..n)
+            .max_by(|i, j| a[*i][k].abs().partial_cmp(&a[*j][k].abs()).unwrap())
+            .unwrap();
+        if a[pivot][k].abs() <= 1e-14 * scale {
+            return None;
+        }
+        a.swap(k, pivot);
+        b.swap(k, pivot);
+        let (upper, lower) = a.split_at_mut(k + 1);
+        let pivot_row = &upper[k];
+        for (i, row) in lower.iter_mut().enumerate() {
+            let factor = row[k] / pivot_row[k];
+            if factor == 0.0 {
+                continue;
+            }
+            for (aij, akj) in row[k..].iter_mut().zip(&pivot_row[k..]) {
+                *aij -= factor * akj;
+            }
+            b[k + 1 + i] -= factor * b[k];
+        }
+    }
+    let mut x = vec![0.0; n];
+    for k in (0..n).rev() {
+        let sum: f64 = (k + 1..n).map(|j| a[k][j] * x[j]).sum();
+        x[k] = (b[k] - sum) / a[k][k];
+    }
+    Some(x)
+}
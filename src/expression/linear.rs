@@ -0,0 +1,569 @@
+use expression;
+use expression::enums;
+use expression::polynomial;
+use expression::rational::Rational;
+use expression::symbolic;
+
+pub type Expression = expression::Expression;
+
+use std::collections::BTreeMap;
+
+// Linear systems with symbolic coefficients. Every equation is turned into a
+// polynomial over "atoms": the unknowns, the other variables and any opaque
+// sub-expression such as sin(a) or a^0.5. Polynomial arithmetic is exact, so
+// the divisions in fraction-free elimination cancel completely.
+
+const MAX_POWER: u32 = 32;
+
+// Exponents per atom, trailing zeros trimmed so that comparing monomials as
+// vectors is lexicographic order.
+type Monomial = Vec<u32>;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Poly {
+    terms: BTreeMap<Monomial, Rational>,
+}
+
+impl Poly {
+    pub fn zero() -> Self {
+        Poly { terms: BTreeMap::new() }
+    }
+
+    pub fn constant(c: Rational) -> Self {
+        let mut p = Poly::zero();
+        p.insert(Vec::new(), c);
+        p
+    }
+
+    pub fn atom(index: usize) -> Self {
+        let mut monomial = vec![0; index + 1];
+        monomial[index] = 1;
+        let mut p = Poly::zero();
+        p.insert(monomial, Rational::one());
+        p
+    }
+
+    fn insert(&mut self, monomial: Monomial, c: Rational) {
+        let sum = match self.terms.get(&monomial) {
+            Some(existing) => *existing + c,
+            None => c,
+        };
+        if sum.is_zero() {
+            self.terms.remove(&monomial);
+        } else {
+            self.terms.insert(monomial, sum);
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.terms.is_empty()
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.terms.values().all(|c| c.is_valid())
+    }
+
+    pub fn len(&self) -> usize {
+        self.terms.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+
+    pub fn as_constant(&self) -> Option<Rational> {
+        match self.terms.len() {
+            0 => Some(Rational::zero()),
+            1 => self.terms.get(&Vec::new()).cloned(),
+            _ => None,
+        }
+    }
+
+    // Total degree in the atoms below `count`.
+    pub fn degree_below(&self, count: usize) -> u32 {
+        self.terms
+            .keys()
+            .map(|m| m.iter().take(count).sum::<u32>())
+            .max()
+            .unwrap_or(0)
+    }
+
+    pub fn add(&self, other: &Poly) -> Poly {
+        let mut result = self.clone();
+        for (m, c) in &other.terms {
+            result.insert(m.clone(), *c);
+        }
+        result
+    }
+
+    pub fn neg(&self) -> Poly {
+        Poly { terms: self.terms.iter().map(|(m, c)| (m.clone(), -*c)).collect() }
+    }
+
+    pub fn sub(&self, other: &Poly) -> Poly {
+        self.add(&other.neg())
+    }
+
+    pub fn mul(&self, other: &Poly) -> Poly {
+        let mut result = Poly::zero();
+        for (ma, ca) in &self.terms {
+            for (mb, cb) in &other.terms {
+                result.insert(multiply_monomials(ma, mb), *ca * *cb);
+            }
+        }
+        result
+    }
+
+    pub fn scale(&self, c: Rational) -> Poly {
+        self.mul(&Poly::constant(c))
+    }
+
+    pub fn pow(&self, n: u32) -> Poly {
+        let mut result = Poly::constant(Rational::one());
+        for _ in 0..n {
+            result = result.mul(self);
+        }
+        result
+    }
+
+    fn leading(&self) -> Option<(&Monomial, &Rational)> {
+        self.terms.iter().next_back()
+    }
+
+    // self / divisor when the division leaves no remainder.
+    pub fn exact_div(&self, divisor: &Poly) -> Option<Poly> {
+        let (lead_m, lead_c) = match divisor.leading() {
+            Some((m, c)) => (m.clone(), *c),
+            None => return None,
+        };
+        let mut rest = self.clone();
+        let mut quotient = Poly::zero();
+        while let Some((m, c)) = rest.leading().map(|(m, c)| (m.clone(), *c)) {
+            let shift = divide_monomials(&m, &lead_m)?;
+            let mut term = Poly::zero();
+            term.insert(shift, c / lead_c);
+            rest = rest.sub(&term.mul(divisor));
+            quotient = quotient.add(&term);
+            if !rest.is_valid() {
+                return None;
+            }
+        }
+        Some(quotient)
+    }
+
+    // Terms with exponent `power` on `atom`, with that atom removed.
+    pub fn coefficient(&self, atom: usize, power: u32) -> Poly {
+        let mut result = Poly::zero();
+        for (m, c) in &self.terms {
+            if m.get(atom).cloned().unwrap_or(0) == power {
+                let mut reduced = m.clone();
+                if atom < reduced.len() {
+                    reduced[atom] = 0;
+                }
+                trim(&mut reduced);
+                result.insert(reduced, *c);
+            }
+        }
+        result
+    }
+
+    // The numeric and monomial factor common to every term, with the sign
+    // of the leading term.
+    fn content(&self) -> (Rational, Monomial) {
+        let mut num: i128 = 0;
+        let mut den: i128 = 0;
+        let mut common: Option<Monomial> = None;
+        for (m, c) in &self.terms {
+            num = gcd(num, c.numer());
+            den = gcd(den, c.denom());
+            common = Some(match common {
+                None => m.clone(),
+                Some(prev) => {
+                    let mut g: Monomial = prev.iter().zip(m).map(|(a, b)| *a.min(b)).collect();
+                    trim(&mut g);
+                    g
+                }
+            });
+        }
+        let sign = match self.leading() {
+            Some((_, c)) if c.numer() < 0 => -1,
+            _ => 1,
+        };
+        (Rational::new(sign * num.max(1), den.max(1)), common.unwrap_or_default())
+    }
+
+    // Indices of the atoms that appear in some term.
+    fn atoms(&self) -> Vec<usize> {
+        let mut used: Vec<usize> = Vec::new();
+        for m in self.terms.keys() {
+            for (i, e) in m.iter().enumerate() {
+                if *e > 0 && !used.contains(&i) {
+                    used.push(i);
+                }
+            }
+        }
+        used.sort();
+        used
+    }
+
+    // Coefficients in `atom`, lowest power first, for a polynomial in that
+    // atom alone.
+    fn univariate(&self, atom: usize) -> Vec<Rational> {
+        let degree = self.terms.keys().map(|m| m.get(atom).cloned().unwrap_or(0)).max();
+        let mut coeffs = vec![Rational::zero(); degree.unwrap_or(0) as usize + 1];
+        for (m, c) in &self.terms {
+            coeffs[m.get(atom).cloned().unwrap_or(0) as usize] = *c;
+        }
+        coeffs
+    }
+
+    fn from_univariate(coeffs: &[Rational], atom: usize) -> Poly {
+        let mut result = Poly::zero();
+        for (i, c) in coeffs.iter().enumerate() {
+            let mut monomial = vec![0; atom + 1];
+            monomial[atom] = i as u32;
+            trim(&mut monomial);
+            result.insert(monomial, *c);
+        }
+        result
+    }
+
+    fn divide_term(&self, c: Rational, m: &Monomial) -> Poly {
+        let mut result = Poly::zero();
+        for (tm, tc) in &self.terms {
+            result.insert(divide_monomials(tm, m).unwrap(), *tc / c);
+        }
+        result
+    }
+
+    pub fn to_expression(&self, atoms: &[Expression]) -> Expression {
+        // Highest term first, except that a leading minus sign is avoided
+        // when some term is positive.
+        let mut terms: Vec<(&Monomial, &Rational)> = self.terms.iter().rev().collect();
+        if let Some(first) = terms.iter().position(|&(_, c)| c.numer() > 0) {
+            let term = terms.remove(first);
+            terms.insert(0, term);
+        }
+        let mut result = symbolic::number(0.0);
+        for (m, c) in terms {
+            let mut term = symbolic::number(1.0);
+            for (i, e) in m.iter().enumerate() {
+                if *e > 0 {
+                    let factor = symbolic::pow(atoms[i].clone(), symbolic::number(*e as f64));
+                    term = symbolic::mul(term, factor);
+                }
+            }
+            let coefficient = if c.is_integer() {
+                symbolic::number(c.numer() as f64)
+            } else {
+                symbolic::div(symbolic::number(c.numer() as f64),
+                              symbolic::number(c.denom() as f64))
+            };
+            result = symbolic::add(result, symbolic::mul(coefficient, term));
+        }
+        result
+    }
+}
+
+fn trim(m: &mut Monomial) {
+    while m.last() == Some(&0) {
+        m.pop();
+    }
+}
+
+fn multiply_monomials(a: &[u32], b: &[u32]) -> Monomial {
+    let mut result = vec![0; a.len().max(b.len())];
+    for (i, e) in a.iter().enumerate() {
+        result[i] += *e;
+    }
+    for (i, e) in b.iter().enumerate() {
+        result[i] += *e;
+    }
+    result
+}
+
+fn divide_monomials(a: &[u32], b: &[u32]) -> Option<Monomial> {
+    if b.len() > a.len() {
+        return None;
+    }
+    let mut result = a.to_vec();
+    for (i, e) in b.iter().enumerate() {
+        if result[i] < *e {
+            return None;
+        }
+        result[i] -= *e;
+    }
+    trim(&mut result);
+    Some(result)
+}
+
+fn gcd(a: i128, b: i128) -> i128 {
+    let (mut a, mut b) = (a.abs(), b.abs());
+    while b != 0 {
+        let t = a % b;
+        a = b;
+        b = t;
+    }
+    a
+}
+
+// A quotient of polynomials, kept unreduced apart from shared denominators.
+struct Fraction {
+    num: Poly,
+    den: Poly,
+}
+
+struct Converter<'a> {
+    atoms: Vec<Expression>,
+    unknowns: &'a [String],
+}
+
+impl<'a> Converter<'a> {
+    fn atom(&mut self, expr: Expression) -> Result<Poly, String> {
+        for unknown in self.unknowns {
+            if symbolic::contains_var(&expr, unknown) {
+                return Err(format!("{} is not linear in {}", expr, unknown));
+            }
+        }
+        let index = match self.atoms.iter().position(|a| *a == expr) {
+            Some(index) => index,
+            None => {
+                self.atoms.push(expr);
+                self.atoms.len() - 1
+            }
+        };
+        Ok(Poly::atom(index))
+    }
+
+    fn convert(&mut self, expr: &Expression) -> Result<Fraction, String> {
+        let whole = |num: Poly| {
+            Fraction {
+                num,
+                den: Poly::constant(Rational::one()),
+            }
+        };
+        let args = symbolic::operands(expr);
+        match *symbolic::root(expr) {
+            enums::Token::Literal(ref x) => {
+                let value = x.parse::<f64>().unwrap_or(f64::NAN);
+                match Rational::from_f64(value, 1_000_000_000) {
+                    Some(r) => Ok(whole(Poly::constant(r))),
+                    None => Ok(whole(self.atom(expr.clone())?)),
+                }
+            }
+            enums::Token::Var(ref x) => {
+                match self.atoms.iter().position(|a| *a == symbolic::variable(x)) {
+                    Some(index) => Ok(whole(Poly::atom(index))),
+                    None => Ok(whole(self.atom(expr.clone())?)),
+                }
+            }
+            enums::Token::Op(enums::Operator::Negate) => {
+                let a = self.convert(&args[0])?;
+                Ok(Fraction { num: a.num.neg(), den: a.den })
+            }
+            enums::Token::Op(op @ enums::Operator::Add) |
+            enums::Token::Op(op @ enums::Operator::Sub) |
+            enums::Token::Op(op @ enums::Operator::Mul) |
+            enums::Token::Op(op @ enums::Operator::Div) => {
+                let a = self.convert(&args[0])?;
+                let b = self.convert(&args[1])?;
+                match op {
+                    enums::Operator::Mul => {
+                        Ok(Fraction { num: a.num.mul(&b.num), den: a.den.mul(&b.den) })
+                    }
+                    enums::Operator::Div => {
+                        if b.num.is_zero() {
+                            return Err("Division by zero".to_owned());
+                        }
+                        Ok(Fraction { num: a.num.mul(&b.den), den: a.den.mul(&b.num) })
+                    }
+                    _ => {
+                        let (an, bn, den) = if a.den == b.den {
+                            (a.num, b.num, a.den)
+                        } else {
+                            (a.num.mul(&b.den), b.num.mul(&a.den), a.den.mul(&b.den))
+                        };
+                        let num = match op {
+                            enums::Operator::Add => an.add(&bn),
+                            _ => an.sub(&bn),
+                        };
+                        Ok(Fraction { num, den })
+                    }
+                }
+            }
+            enums::Token::Op(enums::Operator::Pow) => {
+                let exponent = symbolic::as_number(&args[1]);
+                match exponent {
+                    Some(n) if n.fract() == 0.0 && n.abs() <= MAX_POWER as f64 => {
+                        let base = self.convert(&args[0])?;
+                        let k = n.abs() as u32;
+                        if n >= 0.0 {
+                            Ok(Fraction { num: base.num.pow(k), den: base.den.pow(k) })
+                        } else if base.num.is_zero() {
+                            Err("Division by zero".to_owned())
+                        } else {
+                            Ok(Fraction { num: base.den.pow(k), den: base.num.pow(k) })
+                        }
+                    }
+                    _ => Ok(whole(self.atom(expr.clone())?)),
+                }
+            }
+            _ => Ok(whole(self.atom(expr.clone())?)),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum LinearSolution {
+    Unique(Vec<Expression>),
+    // Some equation reduces to 0 = c with c != 0.
+    Inconsistent,
+    // Fewer independent equations than unknowns.
+    Underdetermined,
+}
+
+// Solves equations (each meaning expr = 0) that are linear in `unknowns`
+// with fraction-free Gauss-Jordan elimination: every division in the update
+// a_ij <- (a_kk a_ij - a_ik a_kj) / previous pivot is exact. There may be
+// more or fewer equations than unknowns; a parameter in a pivot is taken to
+// be non-zero.
+pub fn solve_linear_system(exprs: &[Expression],
+                           unknowns: &[String])
+                           -> Result<LinearSolution, String> {
+    let m = exprs.len();
+    let n = unknowns.len();
+    let mut converter = Converter {
+        atoms: unknowns.iter().map(|u| symbolic::variable(u)).collect(),
+        unknowns,
+    };
+    let mut matrix: Vec<Vec<Poly>> = Vec::with_capacity(m);
+    for expr in exprs {
+        let fraction = converter.convert(expr)?;
+        if fraction.den.degree_below(n) > 0 || fraction.num.degree_below(n) > 1 {
+            return Err(format!("{} is not linear in {}", expr, unknowns.join(", ")));
+        }
+        let mut row: Vec<Poly> = (0..n).map(|j| fraction.num.coefficient(j, 1)).collect();
+        let mut constant = fraction.num.clone();
+        for j in 0..n {
+            constant = constant.coefficient(j, 0);
+        }
+        row.push(constant.neg());
+        matrix.push(row);
+    }
+    let mut previous = Poly::constant(Rational::one());
+    // Row of the next pivot, behind k when a column had none.
+    let mut r = 0;
+    for k in 0..n {
+        // Prefer the simplest non-zero pivot.
+        let pivot = (r..m)
+            .filter(|i| !matrix[*i][k].is_zero())
+            .min_by_key(|i| matrix[*i][k].len());
+        let pivot = match pivot {
+            Some(pivot) => pivot,
+            None => continue,
+        };
+        matrix.swap(r, pivot);
+        for i in 0..m {
+            if i == r {
+                continue;
+            }
+            for j in 0..n + 1 {
+                if j == k {
+                    continue;
+                }
+                let cross = matrix[r][k].mul(&matrix[i][j]).sub(&matrix[i][k].mul(&matrix[r][j]));
+                matrix[i][j] = match cross.exact_div(&previous) {
+                    Some(entry) => entry,
+                    None => {
+                        return Err("Coefficients grew too large to eliminate exactly".to_owned())
+                    }
+                };
+            }
+            matrix[i][k] = Poly::zero();
+        }
+        previous = matrix[r][k].clone();
+        r += 1;
+    }
+    // Rows r.. have no unknowns left, so any right hand side there is a
+    // contradiction.
+    if matrix[r..].iter().any(|row| !row[n].is_zero()) {
+        return Ok(LinearSolution::Inconsistent);
+    }
+    if r < n {
+        return Ok(LinearSolution::Underdetermined);
+    }
+    let solutions = (0..n)
+        .map(|i| quotient(&matrix[i][n], &matrix[i][i], &converter.atoms))
+        .collect();
+    Ok(LinearSolution::Unique(solutions))
+}
+
+// num / den with the common numeric and monomial factors cancelled, or the
+// exact quotient when there is one.
+fn quotient(num: &Poly, den: &Poly, atoms: &[Expression]) -> Expression {
+    if let Some(q) = num.exact_div(den) {
+        return q.to_expression(atoms);
+    }
+    // A common factor in a single parameter can be found with the
+    // univariate gcd.
+    let mut used = num.atoms();
+    used.extend(den.atoms());
+    used.sort();
+    used.dedup();
+    if used.len() == 1 {
+        let g = polynomial::gcd(&num.univariate(used[0]), &den.univariate(used[0]));
+        if polynomial::degree(&g) > 0 && g.iter().all(|c| c.is_valid()) {
+            let g = Poly::from_univariate(&g, used[0]);
+            if let (Some(n), Some(d)) = (num.exact_div(&g), den.exact_div(&g)) {
+                return quotient(&n, &d, atoms);
+            }
+        }
+    }
+    let (nc, nm) = num.content();
+    let (dc, dm) = den.content();
+    let common: Monomial = {
+        let mut g: Monomial = nm.iter().zip(&dm).map(|(a, b)| *a.min(b)).collect();
+        trim(&mut g);
+        g
+    };
+    let ratio = nc / dc;
+    let num = num.divide_term(nc, &common);
+    let den = den.divide_term(dc, &common);
+    // Keep the denominator's leading coefficient positive.
+    let num = num.scale(Rational::new(ratio.numer(), 1));
+    let den = den.scale(Rational::new(ratio.denom(), 1));
+    if let Some(c) = den.as_constant() {
+        return num.scale(c.recip()).to_expression(atoms);
+    }
+    symbolic::div(num.to_expression(atoms), den.to_expression(atoms))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use expression::parse::parse;
+
+    fn solve(equations: &[&str], unknowns: &[&str]) -> LinearSolution {
+        let exprs: Vec<Expression> = equations.iter().map(|e| parse(e)).collect();
+        let unknowns: Vec<String> = unknowns.iter().map(|u| u.to_string()).collect();
+        solve_linear_system(&exprs, &unknowns).unwrap()
+    }
+
+    #[test]
+    fn square_systems() {
+        let solution = solve(&["a*x+y-1", "x-b*y"], &["x", "y"]);
+        let expected = vec![parse("b/(a*b+1)"), parse("1/(a*b+1)")];
+        assert_eq!(solution, LinearSolution::Unique(expected));
+        assert_eq!(solve(&["x+y-1", "2*x+2*y-2"], &["x", "y"]),
+                   LinearSolution::Underdetermined);
+    }
+
+    #[test]
+    fn rectangular_systems() {
+        assert_eq!(solve(&["x+y-1"], &["x", "y"]), LinearSolution::Underdetermined);
+        assert_eq!(solve(&["k*x-1", "x-2"], &["x"]), LinearSolution::Inconsistent);
+        assert_eq!(solve(&["x+y-1", "x-y", "2*x-1"], &["x", "y"]),
+                   LinearSolution::Unique(vec![parse("0.5"), parse("0.5")]));
+        assert_eq!(solve(&["x+y-1", "x-y", "x-3"], &["x", "y"]), LinearSolution::Inconsistent);
+    }
+}
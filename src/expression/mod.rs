@@ -1,12 +1,51 @@
+pub mod analysis;
+pub mod batch;
+pub mod bytecode;
+pub mod command;
+pub mod complex;
+pub mod definite;
+pub mod derivative;
+pub mod dsolve;
+pub mod dual;
 pub mod enums;
 pub mod eval;
+pub mod fourier;
+pub mod integrate;
+pub mod jit;
+pub mod linalg;
+pub mod limit;
+pub mod linear;
+pub mod montecarlo;
+pub mod multiple;
+pub mod multivariate;
+pub mod numeric;
+pub mod ode;
+pub mod optimize;
+pub mod parallel;
 pub mod parse;
+pub mod polynomial;
+pub mod power_series;
+pub mod quadrature;
+pub mod rational;
+pub mod series;
+pub mod solve;
+pub mod summation;
+pub mod symbolic;
+pub mod system;
+
+use std::fmt;
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Expression {
     tokens: Vec<enums::Token>,
 }
 
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", symbolic::to_infix(self))
+    }
+}
+
 pub struct ExpressionIter<'a> {
     expr: &'a Expression,
     count: usize,
@@ -23,7 +62,7 @@ impl<'a> Iterator for ExpressionIter<'a> {
 
 impl Expression {
     pub fn new(tokens: Vec<enums::Token>) -> Self {
-        Expression { tokens: tokens }
+        Expression { tokens }
     }
 
     pub fn push(&mut self, token: enums::Token) {
@@ -56,21 +95,11 @@ impl Expression {
     }
 
     pub fn find_first(&self, token: &enums::Token) -> Option<usize> {
-        for i in 0..self.tokens.len() {
-            if self.tokens.get(i).unwrap() == token {
-                return Some(i);
-            }
-        }
-        None
+        self.tokens.iter().position(|t| t == token)
     }
 
     pub fn find_last(&self, token: &enums::Token) -> Option<usize> {
-        for i in (0..self.tokens.len()).rev() {
-            if self.tokens.get(i).unwrap() == token {
-                return Some(i);
-            }
-        }
-        None
+        self.tokens.iter().rposition(|t| t == token)
     }
 
     pub fn split_at(&self, index: usize) -> (&[enums::Token], &[enums::Token]) {
@@ -81,9 +110,13 @@ impl Expression {
         self.tokens.len()
     }
 
-    pub fn iter(&self) -> ExpressionIter {
+    pub fn is_empty(&self) -> bool {
+        self.tokens.is_empty()
+    }
+
+    pub fn iter(&self) -> ExpressionIter<'_> {
         ExpressionIter {
-            expr: &self,
+            expr: self,
             count: 0,
         }
     }
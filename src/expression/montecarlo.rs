@@ -0,0 +1,344 @@
+use expression;
+use expression::bytecode::{self, Program};
+use expression::parallel;
+
+pub type Expression = expression::Expression;
+
+use std::collections::HashMap;
+use std::fmt;
+
+// Monte Carlo integration over a box. Random sampling gives the standard
+// error from the sample variance. Halton and Sobol points are randomized,
+// with a random shift modulo 1 and a random digital shift respectively, and
+// split into independent replicates whose spread gives the standard error.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Sampler {
+    Random,
+    Halton,
+    Sobol,
+}
+
+impl fmt::Display for Sampler {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Sampler::Random => write!(f, "pseudo-random"),
+            Sampler::Halton => write!(f, "Halton"),
+            Sampler::Sobol => write!(f, "Sobol"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Estimate {
+    pub value: f64,
+    pub standard_error: f64,
+    pub samples: usize,
+    pub replicates: usize,
+}
+
+const REPLICATES: usize = 16;
+// Points are generated and evaluated this many at a time.
+const BLOCK: usize = 1 << 14;
+
+// xoshiro256** seeded through splitmix64, so every seed gives a usable state.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: [u64; 4],
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        let mut x = seed;
+        let mut state = [0u64; 4];
+        for s in state.iter_mut() {
+            x = x.wrapping_add(0x9e3779b97f4a7c15);
+            let mut z = x;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+            *s = z ^ (z >> 31);
+        }
+        Rng { state }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let result = self.state[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = self.state[1] << 17;
+        self.state[2] ^= self.state[0];
+        self.state[3] ^= self.state[1];
+        self.state[1] ^= self.state[2];
+        self.state[0] ^= self.state[3];
+        self.state[2] ^= t;
+        self.state[3] = self.state[3].rotate_left(45);
+        result
+    }
+
+    // Uniform on [0, 1) with 53 random bits.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+// Primitive polynomials (degree, coefficients) and initial direction numbers
+// of Joe and Kuo for dimensions 2 and up; dimension 1 is van der Corput.
+const SOBOL_TABLE: [(u32, u32, [u32; 6]); 15] = [(1, 0, [1, 0, 0, 0, 0, 0]),
+                                                 (2, 1, [1, 3, 0, 0, 0, 0]),
+                                                 (3, 1, [1, 3, 1, 0, 0, 0]),
+                                                 (3, 2, [1, 1, 1, 0, 0, 0]),
+                                                 (4, 1, [1, 1, 3, 3, 0, 0]),
+                                                 (4, 4, [1, 3, 5, 13, 0, 0]),
+                                                 (5, 2, [1, 1, 5, 5, 17, 0]),
+                                                 (5, 4, [1, 1, 5, 5, 5, 0]),
+                                                 (5, 7, [1, 1, 7, 11, 19, 0]),
+                                                 (5, 11, [1, 1, 5, 1, 1, 0]),
+                                                 (5, 13, [1, 1, 1, 3, 11, 0]),
+                                                 (5, 14, [1, 3, 5, 5, 31, 0]),
+                                                 (6, 1, [1, 3, 3, 9, 7, 49]),
+                                                 (6, 13, [1, 1, 1, 15, 21, 21]),
+                                                 (6, 16, [1, 3, 1, 13, 27, 49])];
+pub const MAX_SOBOL_DIMENSIONS: usize = SOBOL_TABLE.len() + 1;
+const BITS: usize = 32;
+
+// Sobol points in Gray code order: point n differs from point n - 1 by the
+// direction number of the lowest zero bit of n - 1.
+pub struct Sobol {
+    directions: Vec<[u32; BITS]>,
+    current: Vec<u32>,
+    index: u32,
+}
+
+impl Sobol {
+    pub fn new(dimensions: usize) -> Result<Self, String> {
+        if dimensions > MAX_SOBOL_DIMENSIONS {
+            return Err(format!("Sobol points are available in up to {} dimensions",
+                               MAX_SOBOL_DIMENSIONS));
+        }
+        let mut directions = Vec::with_capacity(dimensions);
+        for d in 0..dimensions {
+            let mut v = [0u32; BITS];
+            if d == 0 {
+                for (i, vi) in v.iter_mut().enumerate() {
+                    *vi = 1 << (BITS - 1 - i);
+                }
+            } else {
+                let (s, a, ref m) = SOBOL_TABLE[d - 1];
+                let s = s as usize;
+                for i in 0..s {
+                    v[i] = m[i] << (BITS - 1 - i);
+                }
+                for i in s..BITS {
+                    v[i] = v[i - s] ^ (v[i - s] >> s);
+                    for k in 1..s {
+                        if (a >> (s - 1 - k)) & 1 == 1 {
+                            v[i] ^= v[i - k];
+                        }
+                    }
+                }
+            }
+            directions.push(v);
+        }
+        Ok(Sobol {
+            current: vec![0; dimensions],
+            directions,
+            index: 0,
+        })
+    }
+
+    // The next point as integers; divide by 2^32 for the coordinates. The
+    // first point is the origin.
+    pub fn next_point(&mut self) -> &[u32] {
+        if self.index > 0 {
+            let bit = (!(self.index - 1)).trailing_zeros() as usize;
+            for (x, v) in self.current.iter_mut().zip(&self.directions) {
+                *x ^= v[bit.min(BITS - 1)];
+            }
+        }
+        self.index = self.index.wrapping_add(1);
+        &self.current
+    }
+}
+
+fn primes(count: usize) -> Vec<u64> {
+    let mut primes: Vec<u64> = Vec::with_capacity(count);
+    let mut n = 2;
+    while primes.len() < count {
+        if primes.iter().take_while(|&&p| p * p <= n).all(|&p| n % p != 0) {
+            primes.push(n);
+        }
+        n += 1;
+    }
+    primes
+}
+
+// The base b digits of n mirrored about the radix point.
+fn radical_inverse(mut n: u64, base: u64) -> f64 {
+    let mut result = 0.0;
+    let mut scale = 1.0 / base as f64;
+    while n > 0 {
+        result += (n % base) as f64 * scale;
+        n /= base;
+        scale /= base as f64;
+    }
+    result
+}
+
+// Integral of `expr` over the box with vars[i] in bounds[i], from `samples`
+// evaluations. The same seed gives the same estimate.
+pub fn integrate(expr: &Expression,
+                 vars: &[String],
+                 bounds: &[(f64, f64)],
+                 samples: usize,
+                 sampler: Sampler,
+                 seed: u64,
+                 values: &HashMap<String, f64>)
+                 -> Result<Estimate, String> {
+    let dimensions = vars.len();
+    if bounds.len() != dimensions {
+        return Err(format!("{} variables for {} intervals", dimensions, bounds.len()));
+    }
+    if bounds.iter().any(|&(a, b)| !a.is_finite() || !b.is_finite()) {
+        return Err("The bounds must be finite".to_owned());
+    }
+    let replicates = if sampler == Sampler::Random { 1 } else { REPLICATES };
+    if samples < 2 * replicates {
+        return Err(format!("At least {} samples are needed", 2 * replicates));
+    }
+    let program: Program = bytecode::compile(expr, vars)?;
+    let constants: Vec<Vec<f64>> = program.slots()[dimensions..]
+        .iter()
+        .map(|s| vec![*values.get(s).unwrap_or(&0.0)])
+        .collect();
+    let volume: f64 = bounds.iter().map(|&(a, b)| b - a).product();
+    let per_replicate = samples / replicates;
+    let mut rng = Rng::new(seed);
+    let bases = primes(dimensions);
+    let mut means = Vec::with_capacity(replicates);
+    // Running mean and sum of squared deviations (Welford).
+    let (mut mean, mut squares, mut count) = (0.0, 0.0, 0usize);
+    for _ in 0..replicates {
+        let shifts: Vec<f64> = (0..dimensions).map(|_| rng.next_f64()).collect();
+        let digital: Vec<u32> = (0..dimensions).map(|_| rng.next_u64() as u32).collect();
+        let mut sobol = if sampler == Sampler::Sobol {
+            Some(Sobol::new(dimensions)?)
+        } else {
+            None
+        };
+        let mut sum = 0.0;
+        let mut done = 0;
+        while done < per_replicate {
+            let len = BLOCK.min(per_replicate - done);
+            let mut columns: Vec<Vec<f64>> = vec![Vec::with_capacity(len); dimensions];
+            for i in 0..len {
+                if let Some(ref mut sobol) = sobol {
+                    for (d, x) in sobol.next_point().iter().enumerate() {
+                        columns[d].push((x ^ digital[d]) as f64 / (1u64 << BITS) as f64);
+                    }
+                    continue;
+                }
+                // Halton points skip the origin, which every base shares.
+                let index = (done + i + 1) as u64;
+                for (d, column) in columns.iter_mut().enumerate() {
+                    column.push(match sampler {
+                        Sampler::Halton => (radical_inverse(index, bases[d]) + shifts[d]).fract(),
+                        _ => rng.next_f64(),
+                    });
+                }
+            }
+            for (column, &(a, b)) in columns.iter_mut().zip(bounds) {
+                for u in column.iter_mut() {
+                    *u = a + (b - a) * *u;
+                }
+            }
+            let slices: Vec<&[f64]> =
+                columns.iter().chain(&constants).map(|c| c.as_slice()).collect();
+            let fx = parallel::eval_parallel(&program, &slices, 0)?;
+            for (i, y) in fx.iter().enumerate() {
+                if !y.is_finite() {
+                    let point: Vec<String> = columns.iter().map(|c| c[i].to_string()).collect();
+                    return Err(format!("The integrand is not finite at ({})", point.join(", ")));
+                }
+                sum += y;
+                count += 1;
+                let delta = y - mean;
+                mean += delta / count as f64;
+                squares += delta * (y - mean);
+            }
+            done += len;
+        }
+        means.push(sum / per_replicate as f64);
+    }
+    let (value, error) = if replicates == 1 {
+        (mean, (squares / (count - 1) as f64 / count as f64).sqrt())
+    } else {
+        let average = means.iter().sum::<f64>() / replicates as f64;
+        let spread = means.iter().map(|m| (m - average).powi(2)).sum::<f64>() /
+                     (replicates - 1) as f64;
+        (average, (spread / replicates as f64).sqrt())
+    };
+    Ok(Estimate {
+        value: volume * value,
+        standard_error: volume.abs() * error,
+        samples: per_replicate * replicates,
+        replicates,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use expression::parse::parse;
+
+    fn estimate(expr: &str, dimensions: usize, samples: usize, sampler: Sampler, seed: u64)
+                -> Result<Estimate, String> {
+        let vars: Vec<String> = (1..dimensions + 1).map(|i| format!("x{}", i)).collect();
+        let bounds = vec![(0.0, 1.0); dimensions];
+        integrate(&parse(expr), &vars, &bounds, samples, sampler, seed, &HashMap::new())
+    }
+
+    #[test]
+    fn every_sampler_converges() {
+        // The integral of x1 x2 x3 over the unit cube is 1/8.
+        for &sampler in &[Sampler::Random, Sampler::Halton, Sampler::Sobol] {
+            let found = estimate("x1*x2*x3", 3, 1 << 16, sampler, 7).unwrap();
+            assert!(found.standard_error > 0.0 && found.standard_error < 1e-2);
+            assert!((found.value - 0.125).abs() <= 5.0 * found.standard_error, "{}", sampler);
+        }
+        // Quasi-random points are far more accurate for smooth integrands.
+        let random = estimate("x1*x2*x3", 3, 1 << 16, Sampler::Random, 7).unwrap();
+        let sobol = estimate("x1*x2*x3", 3, 1 << 16, Sampler::Sobol, 7).unwrap();
+        assert!(sobol.standard_error < 0.1 * random.standard_error);
+    }
+
+    #[test]
+    fn seeds_are_reproducible() {
+        let a = estimate("exp(x1+x2)", 2, 4096, Sampler::Halton, 42).unwrap();
+        let b = estimate("exp(x1+x2)", 2, 4096, Sampler::Halton, 42).unwrap();
+        let c = estimate("exp(x1+x2)", 2, 4096, Sampler::Halton, 43).unwrap();
+        assert_eq!(a.value, b.value);
+        assert!(a.value != c.value);
+        assert_eq!(a.replicates, REPLICATES);
+        assert_eq!(a.samples, 4096);
+    }
+
+    #[test]
+    fn sobol_points() {
+        let mut sobol = Sobol::new(2).unwrap();
+        let scale = (1u64 << BITS) as f64;
+        let points: Vec<Vec<f64>> =
+            (0..4).map(|_| sobol.next_point().iter().map(|x| *x as f64 / scale).collect()).collect();
+        assert_eq!(points,
+                   vec![vec![0.0, 0.0], vec![0.5, 0.5], vec![0.75, 0.25], vec![0.25, 0.75]]);
+        assert!(Sobol::new(MAX_SOBOL_DIMENSIONS + 1).is_err());
+    }
+
+    #[test]
+    fn bad_boxes_are_errors() {
+        let vars = ["x".to_owned()];
+        let expr = parse("x");
+        let values = HashMap::new();
+        let infinite = [(0.0, f64::INFINITY)];
+        assert!(integrate(&expr, &vars, &infinite, 1000, Sampler::Random, 1, &values).is_err());
+        assert!(integrate(&expr, &vars, &[], 1000, Sampler::Random, 1, &values).is_err());
+        assert!(estimate("x1", 1, 10, Sampler::Sobol, 1).is_err());
+        assert!(estimate("ln(x1-0.5)", 1, 1000, Sampler::Sobol, 1).is_err());
+    }
+}
@@ -0,0 +1,80 @@
+use expression;
+use expression::bytecode;
+use expression::quadrature::{self, Quadrature};
+use expression::symbolic;
+
+pub type Expression = expression::Expression;
+
+use std::collections::HashMap;
+
+const TOLERANCE: f64 = 1e-10;
+
+// The integral of `expr` with vars[0] running between the first pair of
+// limits, vars[1] between the second and so on. The limits of each variable
+// may depend on the variables before it.
+pub fn integrate(expr: &Expression,
+                 vars: &[String],
+                 limits: &[(Expression, Expression)],
+                 values: &HashMap<String, f64>)
+                 -> Result<Quadrature, String> {
+    if vars.len() != limits.len() {
+        return Err(format!("{} variables for {} pairs of limits", vars.len(), limits.len()));
+    }
+    let mut bounds = Vec::with_capacity(limits.len());
+    for (i, (lower, upper)) in limits.iter().enumerate() {
+        for inner in &vars[i..] {
+            if symbolic::contains_var(lower, inner) || symbolic::contains_var(upper, inner) {
+                return Err(format!("The limits of {} cannot depend on {}", vars[i], inner));
+            }
+        }
+        bounds.push((bytecode::bind(lower, &vars[..i], values)?,
+                     bytecode::bind(upper, &vars[..i], values)?));
+    }
+    let mut f = bytecode::bind(expr, vars, values)?;
+    quadrature::integrate_nested(|point| f.eval(point),
+                                 |i, outer| (bounds[i].0.eval(outer), bounds[i].1.eval(outer)),
+                                 vars.len(),
+                                 TOLERANCE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use expression::parse::parse;
+    use std::f64::consts::PI;
+
+    fn iterated(expr: &str, vars: &[&str], limits: &[(&str, &str)]) -> Result<f64, String> {
+        let vars: Vec<String> = vars.iter().map(|v| v.to_string()).collect();
+        let limits: Vec<(Expression, Expression)> =
+            limits.iter().map(|&(a, b)| (parse(a), parse(b))).collect();
+        integrate(&parse(expr), &vars, &limits, &HashMap::new()).map(|q| q.value)
+    }
+
+    #[test]
+    fn rectangles_and_triangles() {
+        let square = iterated("x*y", &["x", "y"], &[("0", "1"), ("0", "1")]).unwrap();
+        assert!((square - 0.25).abs() <= 1e-12);
+        let triangle = iterated("1", &["x", "y"], &[("0", "1"), ("0", "x")]).unwrap();
+        assert!((triangle - 0.5).abs() <= 1e-12);
+        let wedge = iterated("exp(x+y)", &["x", "y"], &[("0", "1"), ("x", "2*x")]).unwrap();
+        let exact = ((3f64).exp() - 1.0) / 3.0 - ((2f64).exp() - 1.0) / 2.0;
+        assert!((wedge - exact).abs() <= 1e-10);
+    }
+
+    #[test]
+    fn volume_of_the_unit_ball() {
+        let ball = iterated("1",
+                            &["x", "y", "z"],
+                            &[("-1", "1"),
+                              ("-sqrt(1-x^2)", "sqrt(1-x^2)"),
+                              ("-sqrt(1-x^2-y^2)", "sqrt(1-x^2-y^2)")])
+            .unwrap();
+        assert!((ball - 4.0 * PI / 3.0).abs() <= 1e-6);
+    }
+
+    #[test]
+    fn limits_may_only_use_outer_variables() {
+        assert!(iterated("1", &["x", "y"], &[("0", "y"), ("0", "1")]).is_err());
+        assert!(iterated("1", &["x", "y"], &[("0", "1")]).is_err());
+    }
+}
@@ -0,0 +1,118 @@
+use expression;
+use expression::derivative::differentiate;
+use expression::eval::eval_postfix_expr;
+
+pub type Expression = expression::Expression;
+
+use std::collections::HashMap;
+
+// Vectors and matrices of expressions, as rows.
+pub type ExprVector = Vec<Expression>;
+pub type ExprMatrix = Vec<Vec<Expression>>;
+
+// d^n f / d vars[0] d vars[1] ... taken in the order given.
+pub fn partial(expr: &Expression, vars: &[String]) -> Expression {
+    vars.iter().fold(expr.clone(), |f, var| differentiate(&f, var))
+}
+
+pub fn gradient(expr: &Expression, vars: &[String]) -> ExprVector {
+    vars.iter().map(|var| differentiate(expr, var)).collect()
+}
+
+// One row per function, one column per variable.
+pub fn jacobian(exprs: &[Expression], vars: &[String]) -> ExprMatrix {
+    exprs.iter().map(|f| gradient(f, vars)).collect()
+}
+
+// Only the upper triangle is differentiated, the rest is mirrored.
+pub fn hessian(expr: &Expression, vars: &[String]) -> ExprMatrix {
+    let first = gradient(expr, vars);
+    let n = vars.len();
+    let mut rows: ExprMatrix = vec![Vec::with_capacity(n); n];
+    for i in 0..n {
+        for j in 0..n {
+            let entry = if j < i {
+                rows[j][i].clone()
+            } else {
+                differentiate(&first[i], &vars[j])
+            };
+            rows[i].push(entry);
+        }
+    }
+    rows
+}
+
+pub fn eval_vector(vector: &[Expression], values: &HashMap<String, f64>) -> Vec<f64> {
+    vector.iter().map(|e| eval_postfix_expr(e, values)).collect()
+}
+
+pub fn eval_matrix(matrix: &[Vec<Expression>], values: &HashMap<String, f64>) -> Vec<Vec<f64>> {
+    matrix.iter().map(|row| eval_vector(row, values)).collect()
+}
+
+pub fn format_vector<T: ToString>(vector: &[T]) -> String {
+    let entries: Vec<String> = vector.iter().map(|e| e.to_string()).collect();
+    format!("[{}]", entries.join(", "))
+}
+
+pub fn format_matrix<T: ToString>(matrix: &[Vec<T>]) -> String {
+    let rows: Vec<String> = matrix.iter().map(|row| format_vector(row)).collect();
+    rows.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use expression::parse::parse;
+
+    fn names(vars: &[&str]) -> Vec<String> {
+        vars.iter().map(|v| v.to_string()).collect()
+    }
+
+    fn point(values: &[(&str, f64)]) -> HashMap<String, f64> {
+        values.iter().map(|&(k, v)| (k.to_owned(), v)).collect()
+    }
+
+    fn close(a: &[f64], b: &[f64]) -> bool {
+        a.len() == b.len() && a.iter().zip(b).all(|(x, y)| (x - y).abs() <= 1e-12)
+    }
+
+    #[test]
+    fn gradients_and_jacobians() {
+        let at = point(&[("x", 2.0), ("y", 3.0), ("z", 0.5)]);
+        let f = parse("x^2*y+sin(z)");
+        let grad = eval_vector(&gradient(&f, &names(&["x", "y", "z"])), &at);
+        assert!(close(&grad, &[12.0, 4.0, 0.5f64.cos()]));
+        let fs = [parse("x*y"), parse("x+y^2")];
+        let jac = eval_matrix(&jacobian(&fs, &names(&["x", "y"])), &at);
+        assert!(close(&jac[0], &[3.0, 2.0]));
+        assert!(close(&jac[1], &[1.0, 6.0]));
+    }
+
+    #[test]
+    fn hessians_are_symmetric() {
+        let at = point(&[("x", 2.0), ("y", 3.0)]);
+        let h = eval_matrix(&hessian(&parse("x^3*y^2+exp(x*y)"), &names(&["x", "y"])), &at);
+        let e = 6.0f64.exp();
+        assert!((h[0][0] - (6.0 * 2.0 * 9.0 + 9.0 * e)).abs() <= 1e-9);
+        assert!((h[0][1] - (6.0 * 4.0 * 3.0 + (1.0 + 6.0) * e)).abs() <= 1e-9);
+        assert_eq!(h[0][1], h[1][0]);
+        assert!((h[1][1] - (2.0 * 8.0 + 4.0 * e)).abs() <= 1e-9);
+    }
+
+    #[test]
+    fn mixed_partials_commute() {
+        let at = point(&[("x", 0.7), ("y", -1.3)]);
+        let f = parse("sin(x*y)*x^2");
+        let xyx = eval_postfix_expr(&partial(&f, &names(&["x", "y", "x"])), &at);
+        let xxy = eval_postfix_expr(&partial(&f, &names(&["x", "x", "y"])), &at);
+        assert!((xyx - xxy).abs() <= 1e-12);
+        assert_eq!(eval_postfix_expr(&partial(&f, &[]), &at), eval_postfix_expr(&f, &at));
+    }
+
+    #[test]
+    fn formatting() {
+        assert_eq!(format_vector(&[1.0, 2.5]), "[1, 2.5]");
+        assert_eq!(format_matrix(&[vec![1, 2], vec![3, 4]]), "[1, 2]\n[3, 4]");
+    }
+}
@@ -0,0 +1,233 @@
+use std::f32;
+use std::f64;
+use std::fmt::Debug;
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+// Everything the evaluator needs from a number type. Only the primitive
+// functions are required, the reciprocal trig/hyperbolic functions and their
+// inverses are derived from them.
+pub trait Numeric:
+    Copy
+    + Debug
+    + PartialEq
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + Mul<Output = Self>
+    + Div<Output = Self>
+    + Neg<Output = Self>
+{
+    fn from_f64(x: f64) -> Self;
+
+    fn pi() -> Self;
+    fn e() -> Self;
+
+    fn rem(self, other: Self) -> Self;
+    fn pow(self, other: Self) -> Self;
+    fn max(self, other: Self) -> Self;
+
+    fn abs(self) -> Self;
+    fn exp(self) -> Self;
+    fn sqrt(self) -> Self;
+    fn ln(self) -> Self;
+
+    fn sin(self) -> Self;
+    fn cos(self) -> Self;
+    fn tan(self) -> Self;
+    fn asin(self) -> Self;
+    fn acos(self) -> Self;
+    fn atan(self) -> Self;
+
+    fn sinh(self) -> Self;
+    fn cosh(self) -> Self;
+    fn tanh(self) -> Self;
+    fn asinh(self) -> Self;
+    fn acosh(self) -> Self;
+    fn atanh(self) -> Self;
+
+    // Literals are kept as strings by the parser so exact backends can
+    // override this to avoid going through f64.
+    fn from_literal(literal: &str) -> Self {
+        Self::from_f64(literal.parse::<f64>().unwrap())
+    }
+
+    fn zero() -> Self {
+        Self::from_f64(0.0)
+    }
+
+    fn one() -> Self {
+        Self::from_f64(1.0)
+    }
+
+    fn recip(self) -> Self {
+        Self::one() / self
+    }
+
+    fn log10(self) -> Self {
+        self.ln() / Self::from_f64(10.0).ln()
+    }
+
+    fn log(self, base: Self) -> Self {
+        self.ln() / base.ln()
+    }
+
+    fn csc(self) -> Self {
+        self.sin().recip()
+    }
+
+    fn sec(self) -> Self {
+        self.cos().recip()
+    }
+
+    fn cot(self) -> Self {
+        self.tan().recip()
+    }
+
+    fn acsc(self) -> Self {
+        self.recip().asin()
+    }
+
+    fn asec(self) -> Self {
+        self.recip().acos()
+    }
+
+    fn acot(self) -> Self {
+        self.recip().atan()
+    }
+
+    fn csch(self) -> Self {
+        self.sinh().recip()
+    }
+
+    fn sech(self) -> Self {
+        self.cosh().recip()
+    }
+
+    fn coth(self) -> Self {
+        self.tanh().recip()
+    }
+
+    fn acsch(self) -> Self {
+        self.recip().asinh()
+    }
+
+    fn asech(self) -> Self {
+        self.recip().acosh()
+    }
+
+    fn acoth(self) -> Self {
+        self.recip().atanh()
+    }
+}
+
+macro_rules! impl_numeric_for_float {
+    ($t:ident) => {
+        impl Numeric for $t {
+            fn from_f64(x: f64) -> Self {
+                x as $t
+            }
+
+            fn from_literal(literal: &str) -> Self {
+                literal.parse::<$t>().unwrap()
+            }
+
+            fn pi() -> Self {
+                $t::consts::PI
+            }
+
+            fn e() -> Self {
+                $t::consts::E
+            }
+
+            fn rem(self, other: Self) -> Self {
+                self % other
+            }
+
+            fn pow(self, other: Self) -> Self {
+                self.powf(other)
+            }
+
+            fn max(self, other: Self) -> Self {
+                $t::max(self, other)
+            }
+
+            fn recip(self) -> Self {
+                $t::recip(self)
+            }
+
+            fn log10(self) -> Self {
+                $t::log10(self)
+            }
+
+            fn log(self, base: Self) -> Self {
+                $t::log(self, base)
+            }
+
+            fn abs(self) -> Self {
+                $t::abs(self)
+            }
+
+            fn exp(self) -> Self {
+                $t::exp(self)
+            }
+
+            fn sqrt(self) -> Self {
+                $t::sqrt(self)
+            }
+
+            fn ln(self) -> Self {
+                $t::ln(self)
+            }
+
+            fn sin(self) -> Self {
+                $t::sin(self)
+            }
+
+            fn cos(self) -> Self {
+                $t::cos(self)
+            }
+
+            fn tan(self) -> Self {
+                $t::tan(self)
+            }
+
+            fn asin(self) -> Self {
+                $t::asin(self)
+            }
+
+            fn acos(self) -> Self {
+                $t::acos(self)
+            }
+
+            fn atan(self) -> Self {
+                $t::atan(self)
+            }
+
+            fn sinh(self) -> Self {
+                $t::sinh(self)
+            }
+
+            fn cosh(self) -> Self {
+                $t::cosh(self)
+            }
+
+            fn tanh(self) -> Self {
+                $t::tanh(self)
+            }
+
+            fn asinh(self) -> Self {
+                $t::asinh(self)
+            }
+
+            fn acosh(self) -> Self {
+                $t::acosh(self)
+            }
+
+            fn atanh(self) -> Self {
+                $t::atanh(self)
+            }
+        }
+    };
+}
+
+impl_numeric_for_float!(f32);
+impl_numeric_for_float!(f64);
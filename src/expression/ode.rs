@@ -0,0 +1,558 @@
+use expression;
+use expression::bytecode;
+use expression::linalg::{self, Matrix};
+use expression::system;
+
+pub type Expression = expression::Expression;
+
+use std::collections::HashMap;
+use std::f64;
+use std::fmt;
+
+// Initial value problems y' = f(x, y) for a vector y. Integration starts
+// with the embedded Dormand-Prince 5(4) pair: the fifth order solution is
+// kept, the difference to the fourth order one controls the step size and
+// the last stage is reused as the first stage of the next step. When the
+// steps are limited by stability rather than accuracy, which is what makes a
+// problem stiff, it switches to RODAS3, a linearly implicit Rosenbrock 3(2)
+// method that is L-stable and stiffly accurate. It solves four linear
+// systems with I/(gamma h) - J per step, J coming from the symbolic or, when
+// that blows up, the automatic derivative of the right hand sides. J is kept
+// across accepted steps with only its x column refreshed.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Method {
+    DormandPrince,
+    Rosenbrock,
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Method::DormandPrince => write!(f, "Dormand-Prince RK45"),
+            Method::Rosenbrock => write!(f, "Rosenbrock"),
+        }
+    }
+}
+
+impl Method {
+    fn order(&self) -> f64 {
+        match *self {
+            Method::DormandPrince => 5.0,
+            Method::Rosenbrock => 3.0,
+        }
+    }
+
+    // The number of steps grows like tolerance^(-1/order), so TOLERANCE is
+    // loosened tenfold for every order below Dormand-Prince's.
+    fn tolerance(&self) -> f64 {
+        TOLERANCE * 10f64.powf(Method::DormandPrince.order() - self.order())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OdeSolution {
+    pub xs: Vec<f64>,
+    pub ys: Vec<Vec<f64>>,
+    // The method of the last step, and where it took over from Dormand-Prince
+    // if the problem turned out to be stiff.
+    pub method: Method,
+    pub switched: Option<f64>,
+    pub steps: usize,
+    pub rejected: usize,
+    pub evaluations: usize,
+    pub jacobians: usize,
+}
+
+pub const TOLERANCE: f64 = 1e-9;
+const MAX_STEPS: usize = 100_000;
+// Dormand-Prince steps with h times the dominant eigenvalue past the edge of
+// the stability region; this many in a row, with no more than a few stable
+// ones in between, mean the problem is stiff.
+const STABILITY_EDGE: f64 = 1.0;
+const STIFF_STEPS: usize = 15;
+const NONSTIFF_STEPS: usize = 6;
+// Rosenbrock keeps its Jacobian for up to this many accepted steps.
+const JACOBIAN_AGE: usize = 20;
+
+// Butcher tableau of Dormand and Prince.
+const C: [f64; 7] = [0.0, 1.0 / 5.0, 3.0 / 10.0, 4.0 / 5.0, 8.0 / 9.0, 1.0, 1.0];
+const A: [[f64; 6]; 7] = [[0.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+                          [1.0 / 5.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+                          [3.0 / 40.0, 9.0 / 40.0, 0.0, 0.0, 0.0, 0.0],
+                          [44.0 / 45.0, -56.0 / 15.0, 32.0 / 9.0, 0.0, 0.0, 0.0],
+                          [19372.0 / 6561.0, -25360.0 / 2187.0, 64448.0 / 6561.0,
+                           -212.0 / 729.0, 0.0, 0.0],
+                          [9017.0 / 3168.0, -355.0 / 33.0, 46732.0 / 5247.0, 49.0 / 176.0,
+                           -5103.0 / 18656.0, 0.0],
+                          [35.0 / 384.0, 0.0, 500.0 / 1113.0, 125.0 / 192.0, -2187.0 / 6784.0,
+                           11.0 / 84.0]];
+// Fifth order weights minus the fourth order ones.
+const E: [f64; 7] = [71.0 / 57600.0,
+                     0.0,
+                     -71.0 / 16695.0,
+                     71.0 / 1920.0,
+                     -17253.0 / 339200.0,
+                     22.0 / 525.0,
+                     -1.0 / 40.0];
+
+// Rosenbrock stages solve (I/(GAMMA h) - J) k_i = f(x + RA[i] h, y_i) +
+// sum RC[i][j] k_j / h + RG[i] h df/dx with y_i = y + sum RY[i][j] k_j.
+const GAMMA: f64 = 0.5;
+const RY: [[f64; 3]; 4] = [[0.0, 0.0, 0.0], [0.0, 0.0, 0.0], [2.0, 0.0, 0.0], [2.0, 0.0, 1.0]];
+const RC: [[f64; 3]; 4] =
+    [[0.0, 0.0, 0.0], [4.0, 0.0, 0.0], [1.0, -1.0, 0.0], [1.0, -1.0, -8.0 / 3.0]];
+const RA: [f64; 4] = [0.0, 0.0, 1.0, 1.0];
+const RG: [f64; 4] = [0.5, 1.5, 0.0, 0.0];
+const RB: [f64; 4] = [2.0, 0.0, 1.0, 1.0];
+const RE: [f64; 4] = [0.0, 0.0, 0.0, 1.0];
+
+// The right hand sides compiled over (x, y_1, ..., y_n), with their
+// Jacobian with respect to all of them for the implicit method.
+pub struct System {
+    rhs: Vec<bytecode::Bound>,
+    jacobian: system::System,
+    point: Vec<f64>,
+    pub evaluations: usize,
+    pub jacobians: usize,
+}
+
+impl System {
+    pub fn new(exprs: &[Expression],
+               var: &str,
+               unknowns: &[String],
+               values: &HashMap<String, f64>)
+               -> Result<Self, String> {
+        if exprs.len() != unknowns.len() {
+            return Err(format!("{} equations for {} unknowns", exprs.len(), unknowns.len()));
+        }
+        let mut free = vec![var.to_owned()];
+        free.extend_from_slice(unknowns);
+        let rhs = exprs.iter()
+            .map(|e| bytecode::bind(e, &free, values))
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(System {
+            rhs,
+            jacobian: system::System::new(exprs, &free, values)?,
+            point: vec![0.0; free.len()],
+            evaluations: 0,
+            jacobians: 0,
+        })
+    }
+
+    pub fn dimension(&self) -> usize {
+        self.rhs.len()
+    }
+
+    pub fn eval(&mut self, x: f64, y: &[f64], out: &mut [f64]) {
+        self.point[0] = x;
+        self.point[1..].copy_from_slice(y);
+        for (f, o) in self.rhs.iter_mut().zip(out.iter_mut()) {
+            *o = f.eval(&self.point);
+        }
+        self.evaluations += 1;
+    }
+
+    // Row i holds df_i/dx followed by df_i/dy_j.
+    pub fn jacobian(&mut self, x: f64, y: &[f64]) -> Matrix {
+        self.point[0] = x;
+        self.point[1..].copy_from_slice(y);
+        self.jacobians += 1;
+        let point = self.point.clone();
+        self.jacobian.jacobian(&point)
+    }
+}
+
+// Weighted RMS norm with absolute and relative tolerance `tolerance`.
+pub fn error_norm(error: &[f64], y: &[f64], y_new: &[f64], tolerance: f64) -> f64 {
+    let sum: f64 = error.iter()
+        .zip(y.iter().zip(y_new))
+        .map(|(e, (a, b))| {
+            let scale = tolerance + tolerance * a.abs().max(b.abs());
+            (e / scale).powi(2)
+        })
+        .sum();
+    (sum / error.len().max(1) as f64).sqrt()
+}
+
+// Hairer's starting step from the size of y, f and a trial Euler step.
+pub fn initial_step(system: &mut System, x: f64, y: &[f64], f: &[f64], span: f64) -> f64 {
+    let n = y.len();
+    let scale: Vec<f64> = y.iter().map(|v| TOLERANCE + TOLERANCE * v.abs()).collect();
+    let rms = |v: &[f64]| -> f64 {
+        let sum: f64 = v.iter().zip(&scale).map(|(a, s)| (a / s).powi(2)).sum();
+        (sum / n.max(1) as f64).sqrt()
+    };
+    let (d0, d1) = (rms(y), rms(f));
+    let h0 = if d0 < 1e-5 || d1 < 1e-5 { 1e-6 } else { 0.01 * d0 / d1 };
+    let h0 = h0.min(span.abs());
+    let trial: Vec<f64> = y.iter().zip(f).map(|(a, b)| a + h0 * span.signum() * b).collect();
+    let mut f1 = vec![0.0; n];
+    system.eval(x + h0 * span.signum(), &trial, &mut f1);
+    let difference: Vec<f64> = f1.iter().zip(f).map(|(a, b)| a - b).collect();
+    let d2 = rms(&difference) / h0;
+    let h1 = if d1.max(d2) <= 1e-15 {
+        (h0 * 1e-3).max(1e-6)
+    } else {
+        (0.01 / d1.max(d2)).powf(0.2)
+    };
+    (100.0 * h0).min(h1).min(span.abs())
+}
+
+// A step of size h from (x, y) that has yet to be accepted.
+struct Trial {
+    y: Vec<f64>,
+    error: f64,
+    // |h| times the dominant eigenvalue of the Jacobian, Dormand-Prince only.
+    stiffness: f64,
+}
+
+// k[0] must hold f(x, y); k[6] holds f(x + h, y_new) on return.
+fn dormand_prince(system: &mut System, x: f64, y: &[f64], h: f64, k: &mut [Vec<f64>]) -> Trial {
+    let n = y.len();
+    let mut stage = vec![0.0; n];
+    let mut sixth = vec![0.0; n];
+    for i in 1..7 {
+        for j in 0..n {
+            stage[j] = y[j] + h * (0..i).map(|s| A[i][s] * k[s][j]).sum::<f64>();
+        }
+        let (_, rest) = k.split_at_mut(i);
+        system.eval(x + C[i] * h, &stage, &mut rest[0]);
+        if i == 5 {
+            sixth.copy_from_slice(&stage);
+        }
+    }
+    // The seventh stage is evaluated at the fifth order solution, and the
+    // last two stages are both at x + h, so comparing them estimates the
+    // dominant eigenvalue. A mostly growing mode, as near a blow-up, is not
+    // stiffness and needs small steps whatever the method.
+    let error: Vec<f64> =
+        (0..n).map(|j| h * (0..7).map(|s| E[s] * k[s][j]).sum::<f64>()).collect();
+    let change: Vec<f64> = k[6].iter().zip(&k[5]).map(|(a, b)| a - b).collect();
+    let distance: Vec<f64> = stage.iter().zip(&sixth).map(|(a, b)| a - b).collect();
+    let (change_norm, distance_norm) = (linalg::norm(&change), linalg::norm(&distance));
+    let growing = linalg::dot(&change, &distance) > 0.5 * change_norm * distance_norm;
+    let stiffness = if distance_norm > 0.0 && !growing {
+        h.abs() * change_norm / distance_norm
+    } else {
+        0.0
+    };
+    Trial {
+        error: error_norm(&error, y, &stage, Method::DormandPrince.tolerance()),
+        y: stage,
+        stiffness,
+    }
+}
+
+// `f` is f(x, y) and `jacobian` the Jacobian there. A singular iteration
+// matrix gives an infinite error so that the step is retried smaller.
+fn rosenbrock(system: &mut System,
+              x: f64,
+              y: &[f64],
+              f: &[f64],
+              h: f64,
+              jacobian: &[Vec<f64>])
+              -> Trial {
+    let n = y.len();
+    // The method's stability function has a pole at h lambda = 1 / GAMMA, so
+    // a step that gets near it along a growing direction is not trusted.
+    let jf: Vec<f64> =
+        (0..n).map(|i| (0..n).map(|j| jacobian[i][j + 1] * f[j]).sum::<f64>()).collect();
+    let growth = linalg::dot(f, &jf) / linalg::dot(f, f);
+    if GAMMA * h.abs() * growth > 0.5 {
+        return Trial {
+            y: y.to_vec(),
+            error: f64::INFINITY,
+            stiffness: 0.0,
+        };
+    }
+    let matrix: Matrix = (0..n)
+        .map(|i| {
+            (0..n)
+                .map(|j| if i == j { 1.0 / (GAMMA * h) } else { 0.0 } - jacobian[i][j + 1])
+                .collect()
+        })
+        .collect();
+    let mut k: Vec<Vec<f64>> = Vec::with_capacity(4);
+    let mut stage_f = f.to_vec();
+    let mut stage = vec![0.0; n];
+    for i in 0..4 {
+        // Stages at the same point as the one before share its f.
+        if i > 0 && (RY[i] != RY[i - 1] || RA[i] != RA[i - 1]) {
+            for j in 0..n {
+                stage[j] = y[j] + (0..i).map(|s| RY[i][s] * k[s][j]).sum::<f64>();
+            }
+            system.eval(x + RA[i] * h, &stage, &mut stage_f);
+        }
+        let rhs: Vec<f64> = (0..n)
+            .map(|j| {
+                let coupling = (0..i).map(|s| RC[i][s] * k[s][j]).sum::<f64>();
+                stage_f[j] + coupling / h + RG[i] * h * jacobian[j][0]
+            })
+            .collect();
+        match linalg::solve_linear(&matrix, &rhs) {
+            Some(solution) => k.push(solution),
+            None => {
+                return Trial {
+                    y: y.to_vec(),
+                    error: f64::INFINITY,
+                    stiffness: 0.0,
+                }
+            }
+        }
+    }
+    let y_new: Vec<f64> =
+        (0..n).map(|j| y[j] + (0..4).map(|s| RB[s] * k[s][j]).sum::<f64>()).collect();
+    let error: Vec<f64> = (0..n).map(|j| (0..4).map(|s| RE[s] * k[s][j]).sum::<f64>()).collect();
+    Trial {
+        error: error_norm(&error, y, &y_new, Method::Rosenbrock.tolerance()),
+        y: y_new,
+        stiffness: 0.0,
+    }
+}
+
+fn underflow(x: f64, finite: bool) -> String {
+    if finite {
+        format!("The step size underflowed at x = {}, the problem may be singular there", x)
+    } else {
+        format!("The solution is not finite near x = {}", x)
+    }
+}
+
+// Solves from (x0, y0) to x_end and records y at `samples` equal intervals.
+// Starting with Dormand-Prince lets the solver switch to Rosenbrock when the
+// problem turns out to be stiff; starting with Rosenbrock keeps it.
+pub fn solve(system: &mut System,
+             x0: f64,
+             y0: &[f64],
+             x_end: f64,
+             samples: usize,
+             method: Method)
+             -> Result<OdeSolution, String> {
+    let n = system.dimension();
+    if y0.len() != n {
+        return Err(format!("{} initial values for {} equations", y0.len(), n));
+    }
+    let samples = samples.max(1);
+    let span = x_end - x0;
+    let targets: Vec<f64> =
+        (0..samples + 1).map(|i| x0 + span * i as f64 / samples as f64).collect();
+    let mut solution = OdeSolution {
+        xs: vec![x0],
+        ys: vec![y0.to_vec()],
+        method,
+        switched: None,
+        steps: 0,
+        rejected: 0,
+        evaluations: 0,
+        jacobians: 0,
+    };
+    if span == 0.0 {
+        return Ok(solution);
+    }
+    let direction = span.signum();
+    let mut x = x0;
+    let mut y = y0.to_vec();
+    let mut k: Vec<Vec<f64>> = vec![vec![0.0; n]; 7];
+    system.eval(x, &y, &mut k[0]);
+    let mut h = initial_step(system, x, &y, &k[0].clone(), span);
+    let mut jacobian: Option<Matrix> = None;
+    // Accepted steps since the Jacobian was evaluated, and how many it may
+    // be kept for, which halves whenever an old Jacobian fails a step.
+    let mut jacobian_age = 0;
+    let mut max_age = JACOBIAN_AGE;
+    let (mut stiff, mut nonstiff) = (0, 0);
+    let mut growth = 5.0;
+    for target in targets.into_iter().skip(1) {
+        while (target - x) * direction > 0.0 {
+            if solution.steps + solution.rejected >= MAX_STEPS {
+                return Err(format!("Gave up after {} steps at x = {}", MAX_STEPS, x));
+            }
+            // Land exactly on the next sample.
+            let remaining = (target - x).abs();
+            let last = h >= remaining;
+            let step = direction * if last { remaining } else { h };
+            let trial = match solution.method {
+                Method::DormandPrince => dormand_prince(system, x, &y, step, &mut k),
+                Method::Rosenbrock => {
+                    if let Some(ref mut jacobian) = jacobian {
+                        if jacobian_age > 0 {
+                            // df/dx changes along the solution even when
+                            // df/dy does not, so it is refreshed by a
+                            // difference.
+                            let dx = f64::EPSILON.sqrt() * x.abs().max(1.0);
+                            let mut ahead = vec![0.0; n];
+                            system.eval(x + dx, &y, &mut ahead);
+                            for (i, row) in jacobian.iter_mut().enumerate() {
+                                row[0] = (ahead[i] - k[0][i]) / dx;
+                            }
+                        }
+                    } else {
+                        jacobian = Some(system.jacobian(x, &y));
+                        jacobian_age = 0;
+                    }
+                    rosenbrock(system, x, &y, &k[0].clone(), step, jacobian.as_ref().unwrap())
+                }
+            };
+            let finite = trial.error.is_finite() && trial.y.iter().all(|v| v.is_finite());
+            if finite && trial.error <= 1.0 {
+                x = if last { target } else { x + step };
+                y = trial.y;
+                match solution.method {
+                    Method::DormandPrince => {
+                        let last_stage = k[6].clone();
+                        k[0] = last_stage;
+                    }
+                    Method::Rosenbrock => {
+                        system.eval(x, &y, &mut k[0]);
+                        jacobian_age += 1;
+                        if jacobian_age >= max_age {
+                            jacobian = None;
+                        }
+                    }
+                }
+                solution.steps += 1;
+                if trial.stiffness > STABILITY_EDGE {
+                    nonstiff = 0;
+                    stiff += 1;
+                } else {
+                    nonstiff += 1;
+                    if nonstiff == NONSTIFF_STEPS {
+                        stiff = 0;
+                    }
+                }
+                if stiff == STIFF_STEPS {
+                    solution.method = Method::Rosenbrock;
+                    solution.switched = Some(x);
+                }
+                if !last {
+                    let exponent = -1.0 / solution.method.order();
+                    h *= if trial.error == 0.0 {
+                        growth
+                    } else {
+                        (0.9 * trial.error.powf(exponent)).clamp(0.2, growth)
+                    };
+                    // Accepted steps too small to move x mean a singularity.
+                    if h < 1e-14 * x.abs().max(1.0) {
+                        return Err(underflow(x, true));
+                    }
+                }
+                growth = 5.0;
+                continue;
+            }
+            solution.rejected += 1;
+            // The rejection may be down to a Jacobian from an earlier point.
+            if jacobian_age > 0 {
+                jacobian = None;
+                max_age = (max_age / 2).max(1);
+            }
+            // No growth straight after a rejection.
+            growth = 1.0;
+            h *= if finite {
+                (0.9 * trial.error.powf(-1.0 / solution.method.order())).clamp(0.2, 1.0)
+            } else {
+                0.25
+            };
+            if h < 1e-14 * x.abs().max(1.0) {
+                return Err(underflow(x, finite));
+            }
+        }
+        solution.xs.push(target);
+        solution.ys.push(y.clone());
+    }
+    solution.evaluations = system.evaluations;
+    solution.jacobians = system.jacobians;
+    Ok(solution)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use expression::parse::parse;
+
+    fn system(exprs: &[&str], unknowns: &[&str]) -> System {
+        let exprs: Vec<Expression> = exprs.iter().map(|e| parse(e)).collect();
+        let unknowns: Vec<String> = unknowns.iter().map(|u| u.to_string()).collect();
+        System::new(&exprs, "x", &unknowns, &HashMap::new()).unwrap()
+    }
+
+    #[test]
+    fn exponential_decay() {
+        let mut decay = system(&["-y"], &["y"]);
+        let solution = solve(&mut decay, 0.0, &[1.0], 5.0, 5, Method::DormandPrince).unwrap();
+        assert_eq!(solution.xs, vec![0.0, 1.0, 2.0, 3.0, 4.0, 5.0]);
+        for (x, y) in solution.xs.iter().zip(&solution.ys) {
+            assert!((y[0] - (-x).exp()).abs() <= 1e-8, "at {}", x);
+        }
+        assert_eq!(solution.method, Method::DormandPrince);
+        assert!(solution.evaluations >= 6 * solution.steps);
+    }
+
+    #[test]
+    fn oscillator_backwards_in_x() {
+        // y'' = -y as a system, from x = 0 back to x = -pi.
+        let mut oscillator = system(&["v", "-y"], &["y", "v"]);
+        let solution =
+            solve(&mut oscillator, 0.0, &[0.0, 1.0], -f64::consts::PI, 2, Method::DormandPrince)
+                .unwrap();
+        let end = solution.ys.last().unwrap();
+        assert!(end[0].abs() <= 1e-8 && (end[1] + 1.0).abs() <= 1e-8);
+        let middle = &solution.ys[1];
+        assert!((middle[0] + 1.0).abs() <= 1e-8 && middle[1].abs() <= 1e-8);
+    }
+
+    #[test]
+    fn mismatched_sizes_are_errors() {
+        let exprs = [parse("y")];
+        let unknowns = ["y".to_owned(), "z".to_owned()];
+        assert!(System::new(&exprs, "x", &unknowns, &HashMap::new()).is_err());
+        let mut one = system(&["y"], &["y"]);
+        assert!(solve(&mut one, 0.0, &[1.0, 2.0], 1.0, 1, Method::DormandPrince).is_err());
+    }
+
+    #[test]
+    fn blow_up_is_an_error() {
+        // y' = y^2, y(0) = 1 has y = 1/(1 - x), which blows up at x = 1.
+        for &method in &[Method::DormandPrince, Method::Rosenbrock] {
+            let mut sys = system(&["y^2"], &["y"]);
+            let message = solve(&mut sys, 0.0, &[1.0], 2.0, 10, method).unwrap_err();
+            let at = message.split("x = ").nth(1).unwrap().split(',').next().unwrap();
+            let at: f64 = at.parse().unwrap();
+            assert!((at - 1.0).abs() < 1e-6, "{}", message);
+        }
+        let mut sys = system(&["y^2"], &["y"]);
+        let solution = solve(&mut sys, 0.0, &[1.0], 0.99, 1, Method::DormandPrince).unwrap();
+        assert!((solution.ys[1][0] - 100.0).abs() < 1e-4);
+        assert_eq!(solution.switched, None);
+    }
+
+    #[test]
+    fn robertson_switches_to_rosenbrock() {
+        let mut sys = system(&["-0.04*a+10000*b*c",
+                               "0.04*a-10000*b*c-30000000*b^2",
+                               "30000000*b^2"],
+                             &["a", "b", "c"]);
+        let solution = solve(&mut sys, 0.0, &[1.0, 0.0, 0.0], 40.0, 1, Method::DormandPrince)
+            .unwrap();
+        assert_eq!(solution.method, Method::Rosenbrock);
+        assert!(solution.switched.is_some());
+        let expected = [0.7158270687193, 9.185534764529e-6, 0.2841637457];
+        for (y, e) in solution.ys[1].iter().zip(&expected) {
+            assert!((y - e).abs() < 1e-6 * e, "{} vs {}", y, e);
+        }
+        assert!(solution.steps < 2000);
+    }
+
+    #[test]
+    fn stiff_linear_problem_reuses_its_jacobian() {
+        // y = (10^6 cos x + 1000 sin x - 10^6 e^(-1000 x)) / (10^6 + 1)
+        let mut sys = system(&["-1000*(y-cos(x))"], &["y"]);
+        let solution = solve(&mut sys, 0.0, &[0.0], 1.0, 5, Method::DormandPrince).unwrap();
+        assert_eq!(solution.method, Method::Rosenbrock);
+        for (x, y) in solution.xs.iter().zip(&solution.ys) {
+            let exact = (1e6 * x.cos() + 1000.0 * x.sin() - 1e6 * (-1000.0 * x).exp()) / 1000001.0;
+            assert!((y[0] - exact).abs() <= 1e-7, "at {}", x);
+        }
+        assert!(solution.steps < 1000, "{} steps", solution.steps);
+        assert!(solution.jacobians < 50, "{} Jacobians", solution.jacobians);
+    }
+}
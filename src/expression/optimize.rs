@@ -0,0 +1,574 @@
+use expression;
+use expression::bytecode::{self, Bound};
+use expression::enums::{Function, Operator, Token};
+use expression::linalg::{self, Matrix};
+use expression::symbolic;
+use expression::system::System;
+
+pub type Expression = expression::Expression;
+
+use std::collections::HashMap;
+use std::f64;
+use std::fmt;
+
+const MAX_ITERATIONS: usize = 1000;
+const MAX_SIMPLEX_ITERATIONS: usize = 20000;
+// Nelder-Mead starts over from its best point until a restart stops moving.
+const MAX_RESTARTS: usize = 4;
+// BFGS stops when the gradient times the size of x is this small relative to
+// the value, and still counts as converged when it stalls below ACCEPTED.
+const TOLERANCE: f64 = 1e-10;
+const ACCEPTED: f64 = 1e-6;
+// Values past this are taken to mean there is no minimum.
+const UNBOUNDED: f64 = 1e100;
+const UNBOUNDED_BELOW: &str = "The function is unbounded below";
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Method {
+    Bfgs,
+    NelderMead,
+    Brent,
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Method::Bfgs => write!(f, "BFGS"),
+            Method::NelderMead => write!(f, "Nelder-Mead"),
+            Method::Brent => write!(f, "Brent"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Minimum {
+    pub x: Vec<f64>,
+    pub value: f64,
+    pub iterations: usize,
+    // Function evaluations, not counting gradients.
+    pub evaluations: usize,
+    pub converged: bool,
+    // The method that produced `x`, BFGS unless it had to give up.
+    pub method: Method,
+}
+
+// The function to minimize, counting evaluations. The gradient comes from
+// the symbolic or dual number Jacobian of a one equation system, built the
+// first time it is needed.
+struct Objective<'a> {
+    function: Bound,
+    gradient: Option<System>,
+    expr: &'a Expression,
+    vars: &'a [String],
+    values: &'a HashMap<String, f64>,
+    evaluations: usize,
+}
+
+impl<'a> Objective<'a> {
+    fn new(expr: &'a Expression,
+           vars: &'a [String],
+           values: &'a HashMap<String, f64>)
+           -> Result<Objective<'a>, String> {
+        Ok(Objective {
+            function: bytecode::bind(expr, vars, values)?,
+            gradient: None,
+            expr,
+            vars,
+            values,
+            evaluations: 0,
+        })
+    }
+
+    fn value(&mut self, x: &[f64]) -> Result<f64, String> {
+        self.evaluations += 1;
+        let value = self.function.eval(x);
+        if value < -UNBOUNDED {
+            return Err(UNBOUNDED_BELOW.to_owned());
+        }
+        Ok(value)
+    }
+
+    fn gradient(&mut self, x: &[f64]) -> Result<Vec<f64>, String> {
+        if self.gradient.is_none() {
+            let exprs = ::std::slice::from_ref(self.expr);
+            self.gradient = Some(System::new(exprs, self.vars, self.values)?);
+        }
+        let system = self.gradient.as_mut().unwrap();
+        Ok(system.jacobian(x).remove(0))
+    }
+}
+
+fn is_finite(v: &[f64]) -> bool {
+    v.iter().all(|x| x.is_finite())
+}
+
+// abs, max and mod have kinks where a gradient method gets stuck.
+fn is_smooth(expr: &Expression) -> bool {
+    !expr.get_tokens().iter().any(|t| {
+        matches!(*t,
+                 Token::Func(Function::Abs) | Token::Func(Function::Max) |
+                 Token::Op(Operator::Mod))
+    })
+}
+
+// Minimizes `expr` over `vars` from `start`. Without a method, smooth
+// functions use BFGS and fall back to Nelder-Mead from the best point so far
+// when it fails to converge; functions with kinks use Nelder-Mead.
+pub fn minimize(expr: &Expression,
+                vars: &[String],
+                start: &[f64],
+                method: Option<Method>,
+                values: &HashMap<String, f64>)
+                -> Result<Minimum, String> {
+    if vars.len() != start.len() {
+        return Err(format!("{} variables but {} starting values", vars.len(), start.len()));
+    }
+    if vars.is_empty() {
+        return Err("Nothing to minimize".to_owned());
+    }
+    let mut objective = Objective::new(expr, vars, values)?;
+    let f0 = objective.value(start)?;
+    if !f0.is_finite() {
+        return Err("The function is undefined at the starting point".to_owned());
+    }
+    let method = method.unwrap_or(if is_smooth(expr) { Method::Bfgs } else { Method::NelderMead });
+    match method {
+        Method::Bfgs => {
+            let bfgs = bfgs(&mut objective, start.to_vec(), f0)?;
+            if bfgs.converged {
+                return Ok(bfgs);
+            }
+            let mut simplex = nelder_mead(&mut objective, &bfgs.x)?;
+            simplex.iterations += bfgs.iterations;
+            Ok(simplex)
+        }
+        Method::NelderMead => nelder_mead(&mut objective, start),
+        Method::Brent => Err("Brent's method needs an interval like [a,b]".to_owned()),
+    }
+}
+
+// The maximum of `expr`, found as the minimum of -expr.
+pub fn maximize(expr: &Expression,
+                vars: &[String],
+                start: &[f64],
+                method: Option<Method>,
+                values: &HashMap<String, f64>)
+                -> Result<Minimum, String> {
+    let mut maximum = minimize(&symbolic::neg(expr.clone()), vars, start, method, values)
+        .map_err(unbounded_above)?;
+    maximum.value = -maximum.value;
+    Ok(maximum)
+}
+
+fn unbounded_above(message: String) -> String {
+    if message == UNBOUNDED_BELOW {
+        "The function is unbounded above".to_owned()
+    } else {
+        message
+    }
+}
+
+// Quasi-Newton with the BFGS update of the inverse Hessian and a
+// backtracking line search. The update is skipped when the curvature
+// condition fails, which keeps the approximation positive definite.
+fn bfgs(objective: &mut Objective, x: Vec<f64>, f: f64) -> Result<Minimum, String> {
+    let n = x.len();
+    let mut x = x;
+    let mut f = f;
+    let mut g = objective.gradient(&x)?;
+    let mut h: Matrix = linalg::identity(n);
+    let mut scaled = false;
+    let mut iterations = 0;
+    while iterations < MAX_ITERATIONS && is_finite(&g) {
+        if stationary(&x, f, &g, TOLERANCE) {
+            break;
+        }
+        iterations += 1;
+        let mut p: Vec<f64> = linalg::mat_vec(&h, &g).iter().map(|v| -v).collect();
+        let mut slope = linalg::dot(&g, &p);
+        let descent = slope < 0.0;
+        if !descent {
+            h = linalg::identity(n);
+            p = g.iter().map(|v| -v).collect();
+            slope = -linalg::dot(&g, &g);
+        }
+        // Armijo condition.
+        let mut t = 1.0;
+        let mut accepted = None;
+        while t >= 1e-20 {
+            let trial: Vec<f64> = x.iter().zip(&p).map(|(xi, pi)| xi + t * pi).collect();
+            let f_trial = objective.value(&trial)?;
+            if f_trial.is_finite() && f_trial <= f + 1e-4 * t * slope {
+                accepted = Some((trial, f_trial));
+                break;
+            }
+            t *= 0.5;
+        }
+        let (next, f_next) = match accepted {
+            Some(point) => point,
+            None => break,
+        };
+        let g_next = objective.gradient(&next)?;
+        let s: Vec<f64> = next.iter().zip(&x).map(|(a, b)| a - b).collect();
+        let y: Vec<f64> = g_next.iter().zip(&g).map(|(a, b)| a - b).collect();
+        let sy = linalg::dot(&s, &y);
+        if sy > 1e-10 * linalg::norm(&s) * linalg::norm(&y) {
+            if !scaled {
+                // Shanno's scaling of the first approximation.
+                h = linalg::identity(n);
+                let scale = sy / linalg::dot(&y, &y);
+                for (i, row) in h.iter_mut().enumerate() {
+                    row[i] = scale;
+                }
+                scaled = true;
+            }
+            // H + ((sy + yHy) ss' - Hys' - sy'H) / sy, with H symmetric.
+            let hy = linalg::mat_vec(&h, &y);
+            let yhy = linalg::dot(&y, &hy);
+            for i in 0..n {
+                for j in 0..n {
+                    h[i][j] += ((sy + yhy) * s[i] * s[j] / sy - hy[i] * s[j] - s[i] * hy[j]) / sy;
+                }
+            }
+        }
+        let moved = linalg::norm_inf(&s) <= f64::EPSILON * linalg::norm_inf(&next).max(1.0);
+        x = next;
+        f = f_next;
+        g = g_next;
+        if moved {
+            break;
+        }
+    }
+    Ok(Minimum {
+        converged: is_finite(&g) && stationary(&x, f, &g, ACCEPTED),
+        x,
+        value: f,
+        iterations,
+        evaluations: objective.evaluations,
+        method: Method::Bfgs,
+    })
+}
+
+// A relative test, unchanged by scaling x or f.
+fn stationary(x: &[f64], f: f64, g: &[f64], tolerance: f64) -> bool {
+    linalg::norm_inf(g) * linalg::norm_inf(x).max(1.0) <= tolerance * f.abs().max(1.0)
+}
+
+// Nelder-Mead with the dimension dependent coefficients of Gao and Han,
+// restarted from the best vertex until a restart no longer moves it, since
+// the simplex can collapse away from a minimum.
+fn nelder_mead(objective: &mut Objective, start: &[f64]) -> Result<Minimum, String> {
+    let mut best = start.to_vec();
+    let mut value = objective.value(&best)?;
+    let mut iterations = 0;
+    let mut converged = false;
+    for _ in 0..MAX_RESTARTS {
+        let (x, f, steps, done) = simplex(objective, &best, value)?;
+        iterations += steps;
+        converged = done;
+        let size = best.iter().zip(&x).map(|(a, b)| (a - b).abs()).fold(0.0, f64::max);
+        let still = size <= 1e-8 * linalg::norm_inf(&x).max(1.0) &&
+                    (value - f).abs() <= 1e-12 * f.abs().max(1.0);
+        best = x;
+        value = f;
+        if still || !done {
+            break;
+        }
+    }
+    Ok(Minimum {
+        x: best,
+        value,
+        iterations,
+        evaluations: objective.evaluations,
+        converged,
+        method: Method::NelderMead,
+    })
+}
+
+fn simplex(objective: &mut Objective,
+           start: &[f64],
+           f0: f64)
+           -> Result<(Vec<f64>, f64, usize, bool), String> {
+    let n = start.len();
+    let dimension = n as f64;
+    let (reflect, expand) = (1.0, 1.0 + 2.0 / dimension);
+    let contract = 0.75 - 0.5 / dimension;
+    let shrink = 1.0 - 1.0 / dimension.max(2.0);
+    // The initial simplex steps 5% along each axis, as fminsearch does.
+    let mut vertices = vec![(start.to_vec(), f0)];
+    for i in 0..n {
+        let mut x = start.to_vec();
+        x[i] = if x[i] == 0.0 { 0.00025 } else { 1.05 * x[i] };
+        let f = objective.value(&x)?;
+        vertices.push((x, f));
+    }
+    let mut iterations = 0;
+    let mut converged = false;
+    while iterations < MAX_SIMPLEX_ITERATIONS {
+        vertices.sort_by(|a, b| order(a.1, b.1));
+        let (ref best, f_best) = vertices[0];
+        let size = vertices[1..]
+            .iter()
+            .flat_map(|v| v.0.iter().zip(best).map(|(a, b)| (a - b).abs()))
+            .fold(0.0, f64::max);
+        let spread = vertices[n].1 - f_best;
+        if size <= 1e-10 * linalg::norm_inf(best).max(1.0) &&
+           spread <= 1e-12 * f_best.abs().max(1e-12) {
+            converged = true;
+            break;
+        }
+        iterations += 1;
+        let mut centroid = vec![0.0; n];
+        for vertex in &vertices[..n] {
+            for (c, x) in centroid.iter_mut().zip(&vertex.0) {
+                *c += x / dimension;
+            }
+        }
+        let toward = |t: f64, x: &[f64]| -> Vec<f64> {
+            centroid.iter().zip(x).map(|(c, xi)| c + t * (xi - c)).collect()
+        };
+        let worst = vertices[n].clone();
+        let second = vertices[n - 1].1;
+        let xr = toward(-reflect, &worst.0);
+        let fr = finite_or_max(objective.value(&xr)?);
+        if fr < f_best {
+            let xe = toward(-reflect * expand, &worst.0);
+            let fe = finite_or_max(objective.value(&xe)?);
+            vertices[n] = if fe < fr { (xe, fe) } else { (xr, fr) };
+            continue;
+        }
+        if fr < second {
+            vertices[n] = (xr, fr);
+            continue;
+        }
+        let (xc, fc) = if fr < worst.1 {
+            let xc = toward(-reflect * contract, &worst.0);
+            let fc = finite_or_max(objective.value(&xc)?);
+            (xc, fc)
+        } else {
+            let xc = toward(contract, &worst.0);
+            let fc = finite_or_max(objective.value(&xc)?);
+            (xc, fc)
+        };
+        if fc < fr.min(worst.1) {
+            vertices[n] = (xc, fc);
+            continue;
+        }
+        let best = vertices[0].0.clone();
+        for vertex in vertices.iter_mut().skip(1) {
+            let x: Vec<f64> =
+                best.iter().zip(&vertex.0).map(|(b, xi)| b + shrink * (xi - b)).collect();
+            let f = finite_or_max(objective.value(&x)?);
+            *vertex = (x, f);
+        }
+    }
+    vertices.sort_by(|a, b| order(a.1, b.1));
+    let (x, f) = vertices.swap_remove(0);
+    Ok((x, f, iterations, converged))
+}
+
+// Undefined points rank as the worst possible so the simplex moves away.
+fn finite_or_max(f: f64) -> f64 {
+    if f.is_nan() { f64::INFINITY } else { f }
+}
+
+fn order(a: f64, b: f64) -> ::std::cmp::Ordering {
+    a.partial_cmp(&b).unwrap_or(::std::cmp::Ordering::Equal)
+}
+
+// The minimum of `expr` on [a, b] with Brent's method.
+pub fn minimize_bounded(expr: &Expression,
+                        var: &str,
+                        a: f64,
+                        b: f64,
+                        values: &HashMap<String, f64>)
+                        -> Result<Minimum, String> {
+    let valid = a.is_finite() && b.is_finite() && a < b;
+    if !valid {
+        return Err(format!("Expected an interval with a < b but got [{}, {}]", a, b));
+    }
+    let mut function = bytecode::bind(expr, &[var.to_owned()], values)?;
+    let minimum = brent(&mut |x| finite_or_max(function.eval1(x)), a, b);
+    if minimum.value < -UNBOUNDED {
+        return Err(UNBOUNDED_BELOW.to_owned());
+    }
+    if !minimum.value.is_finite() {
+        return Err(format!("The function is undefined on [{}, {}]", a, b));
+    }
+    Ok(minimum)
+}
+
+// Brent's method on [a, b]: golden section search sped up with parabolic
+// interpolation where it behaves. It finds a local minimum inside the
+// interval, which is then compared with the ends.
+pub fn brent(f: &mut dyn FnMut(f64) -> f64, a: f64, b: f64) -> Minimum {
+    let mut evaluations = 0;
+    let mut f = |x: f64| {
+        evaluations += 1;
+        f(x)
+    };
+    let golden = 0.5 * (3.0 - 5f64.sqrt());
+    let (mut low, mut high) = (a, b);
+    let mut x = low + golden * (high - low);
+    let (mut w, mut v) = (x, x);
+    let mut fx = f(x);
+    let (mut fw, mut fv) = (fx, fx);
+    // The last two steps; parabolic steps must shrink faster than these.
+    let (mut d, mut e) = (0.0f64, 0.0f64);
+    let mut iterations = 0;
+    let mut converged = false;
+    while iterations < MAX_ITERATIONS {
+        let middle = 0.5 * (low + high);
+        let tol = f64::EPSILON.sqrt() * x.abs() + 1e-12;
+        if (x - middle).abs() <= 2.0 * tol - 0.5 * (high - low) {
+            converged = true;
+            break;
+        }
+        iterations += 1;
+        let mut parabolic = false;
+        if e.abs() > tol {
+            let r = (x - w) * (fx - fv);
+            let mut q = (x - v) * (fx - fw);
+            let mut p = (x - v) * q - (x - w) * r;
+            q = 2.0 * (q - r);
+            if q > 0.0 {
+                p = -p;
+            }
+            q = q.abs();
+            let previous = e;
+            if p.abs() < (0.5 * q * previous).abs() && p > q * (low - x) && p < q * (high - x) {
+                e = d;
+                d = p / q;
+                let u = x + d;
+                if u - low < 2.0 * tol || high - u < 2.0 * tol {
+                    d = if x < middle { tol } else { -tol };
+                }
+                parabolic = true;
+            }
+        }
+        if !parabolic {
+            e = if x < middle { high - x } else { low - x };
+            d = golden * e;
+        }
+        let u = if d.abs() >= tol { x + d } else { x + tol * d.signum() };
+        let fu = f(u);
+        if fu <= fx {
+            if u < x {
+                high = x;
+            } else {
+                low = x;
+            }
+            v = w;
+            fv = fw;
+            w = x;
+            fw = fx;
+            x = u;
+            fx = fu;
+        } else {
+            if u < x {
+                low = u;
+            } else {
+                high = u;
+            }
+            if fu <= fw || w == x {
+                v = w;
+                fv = fw;
+                w = u;
+                fw = fu;
+            } else if fu <= fv || v == x || v == w {
+                v = u;
+                fv = fu;
+            }
+        }
+    }
+    for end in &[a, b] {
+        let f_end = f(*end);
+        if f_end < fx {
+            x = *end;
+            fx = f_end;
+        }
+    }
+    Minimum {
+        x: vec![x],
+        value: fx,
+        iterations,
+        evaluations,
+        converged,
+        method: Method::Brent,
+    }
+}
+
+pub fn maximize_bounded(expr: &Expression,
+                        var: &str,
+                        a: f64,
+                        b: f64,
+                        values: &HashMap<String, f64>)
+                        -> Result<Minimum, String> {
+    let mut maximum = minimize_bounded(&symbolic::neg(expr.clone()), var, a, b, values)
+        .map_err(unbounded_above)?;
+    maximum.value = -maximum.value;
+    Ok(maximum)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use expression::parse::parse;
+
+    fn min(expr: &str, vars: &[&str], start: &[f64], method: Option<Method>)
+           -> Result<Minimum, String> {
+        let vars: Vec<String> = vars.iter().map(|v| v.to_string()).collect();
+        minimize(&parse(expr), &vars, start, method, &HashMap::new())
+    }
+
+    #[test]
+    fn bfgs_finds_the_rosenbrock_valley_floor() {
+        let found = min("(1-x)^2+100*(y-x^2)^2", &["x", "y"], &[-1.2, 1.0], None).unwrap();
+        assert!(found.converged);
+        assert_eq!(found.method, Method::Bfgs);
+        assert!((found.x[0] - 1.0).abs() <= 1e-6 && (found.x[1] - 1.0).abs() <= 1e-6);
+        assert!(found.value <= 1e-12);
+    }
+
+    #[test]
+    fn kinks_use_nelder_mead() {
+        let found = min("abs(x-1)+abs(y+2)", &["x", "y"], &[0.0, 0.0], None).unwrap();
+        assert_eq!(found.method, Method::NelderMead);
+        assert!((found.x[0] - 1.0).abs() <= 1e-6 && (found.x[1] + 2.0).abs() <= 1e-6);
+        let forced = min("(x-3)^2+1", &["x"], &[0.0], Some(Method::NelderMead)).unwrap();
+        assert_eq!(forced.method, Method::NelderMead);
+        assert!((forced.x[0] - 3.0).abs() <= 1e-6 && (forced.value - 1.0).abs() <= 1e-10);
+    }
+
+    #[test]
+    fn brent_compares_the_ends() {
+        let values = HashMap::new();
+        let inside = minimize_bounded(&parse("cos(x)"), "x", 0.0, 6.0, &values).unwrap();
+        assert_eq!(inside.method, Method::Brent);
+        assert!((inside.x[0] - ::std::f64::consts::PI).abs() <= 1e-7);
+        assert!((inside.value + 1.0).abs() <= 1e-12);
+        let end = maximize_bounded(&parse("x^2"), "x", -1.0, 2.0, &values).unwrap();
+        assert_eq!(end.x, vec![2.0]);
+        assert_eq!(end.value, 4.0);
+    }
+
+    #[test]
+    fn maximize_negates_the_value() {
+        let vars = ["x".to_owned()];
+        let found = maximize(&parse("3-(x-2)^2"), &vars, &[0.0], None, &HashMap::new()).unwrap();
+        assert!((found.x[0] - 2.0).abs() <= 1e-6);
+        assert!((found.value - 3.0).abs() <= 1e-12);
+    }
+
+    #[test]
+    fn unbounded_and_bad_input_are_errors() {
+        assert_eq!(min("x", &["x"], &[0.0], None), Err(UNBOUNDED_BELOW.to_owned()));
+        let vars = ["x".to_owned()];
+        let above = maximize(&parse("x^2"), &vars, &[1.0], None, &HashMap::new());
+        assert_eq!(above, Err("The function is unbounded above".to_owned()));
+        assert!(min("x^2+y^2", &["x", "y"], &[1.0], None).is_err());
+        assert!(min("ln(x)", &["x"], &[-1.0], None).is_err());
+        assert!(min("x^2", &["x"], &[1.0], Some(Method::Brent)).is_err());
+        assert!(minimize_bounded(&parse("x^2"), "x", 1.0, -1.0, &HashMap::new()).is_err());
+    }
+}
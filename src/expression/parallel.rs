@@ -0,0 +1,111 @@
+use expression::batch::{self, BatchMachine};
+use expression::bytecode::Program;
+
+use std::thread;
+
+// Rows are split into one contiguous chunk per thread, each thread works on
+// its own copy of the program and writes straight into its part of the
+// output, so results come back in row order regardless of scheduling.
+
+// `threads` of 0 uses every available core.
+pub fn thread_count(threads: usize) -> usize {
+    if threads > 0 {
+        return threads;
+    }
+    thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+}
+
+// Same contract as batch::eval_batch.
+pub fn eval_parallel(program: &Program,
+                     columns: &[&[f64]],
+                     threads: usize)
+                     -> Result<Vec<f64>, String> {
+    let rows = batch::batch_len(program, columns)?;
+    let mut out: Vec<f64> = vec![0.0; rows];
+    let chunk = chunk_size(rows, threads);
+    thread::scope(|scope| for (i, out_chunk) in out.chunks_mut(chunk).enumerate() {
+        scope.spawn(move || {
+            let program = program.clone();
+            let start = i * chunk;
+            let local: Vec<&[f64]> = columns.iter()
+                .map(|c| if c.len() == 1 { *c } else { &c[start..start + out_chunk.len()] })
+                .collect();
+            BatchMachine::new(&program).run(&program, &local, out_chunk);
+        });
+    });
+    Ok(out)
+}
+
+// Evaluates `program` at every point of the grid spanned by `axes` (one axis
+// per slot). Points are returned in row-major order, the last axis varying
+// fastest.
+pub fn eval_grid(program: &Program, axes: &[&[f64]], threads: usize) -> Result<Vec<f64>, String> {
+    if axes.len() != program.slots().len() {
+        return Err(format!("Expected {} axes ({}) but got {}",
+                           program.slots().len(),
+                           program.slots().join(", "),
+                           axes.len()));
+    }
+    let rows: usize = axes.iter().map(|a| a.len()).product();
+    let mut out: Vec<f64> = vec![0.0; rows];
+    if rows == 0 {
+        return Ok(out);
+    }
+    let chunk = chunk_size(rows, threads);
+    thread::scope(|scope| for (i, out_chunk) in out.chunks_mut(chunk).enumerate() {
+        scope.spawn(move || {
+            let program = program.clone();
+            let columns = grid_columns(axes, i * chunk, out_chunk.len());
+            let local: Vec<&[f64]> = columns.iter().map(|c| c.as_slice()).collect();
+            BatchMachine::new(&program).run(&program, &local, out_chunk);
+        });
+    });
+    Ok(out)
+}
+
+// The coordinates of grid points start..start + len, one column per axis.
+fn grid_columns(axes: &[&[f64]], start: usize, len: usize) -> Vec<Vec<f64>> {
+    let mut columns: Vec<Vec<f64>> = axes.iter().map(|_| Vec::with_capacity(len)).collect();
+    for index in start..start + len {
+        let mut rest = index;
+        for (axis, column) in axes.iter().zip(columns.iter_mut()).rev() {
+            column.push(axis[rest % axis.len()]);
+            rest /= axis.len();
+        }
+    }
+    columns
+}
+
+fn chunk_size(rows: usize, threads: usize) -> usize {
+    let threads = thread_count(threads);
+    rows.div_ceil(threads).max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use expression::batch::eval_batch;
+    use expression::bytecode::compile;
+    use expression::parse::parse;
+
+    #[test]
+    fn threads_do_not_change_the_result() {
+        let vars = ["x".to_owned(), "y".to_owned()];
+        let program = compile::<f64>(&parse("exp(x/10)*cos(y)+x*y"), &vars).unwrap();
+        let xs: Vec<f64> = (0..5000).map(|i| i as f64 / 500.0).collect();
+        let ys: Vec<f64> = (0..5000).map(|i| (i % 13) as f64).collect();
+        let serial = eval_batch(&program, &[&xs, &ys]).unwrap();
+        for &threads in &[1, 3, 8, 0] {
+            assert_eq!(eval_parallel(&program, &[&xs, &ys], threads).unwrap(), serial);
+        }
+    }
+
+    #[test]
+    fn grids_are_row_major() {
+        let vars = ["x".to_owned(), "y".to_owned()];
+        let program = compile::<f64>(&parse("10*x+y"), &vars).unwrap();
+        let grid = eval_grid(&program, &[&[1.0, 2.0], &[3.0, 4.0, 5.0]], 2).unwrap();
+        assert_eq!(grid, vec![13.0, 14.0, 15.0, 23.0, 24.0, 25.0]);
+        assert!(eval_grid(&program, &[&[1.0]], 2).is_err());
+    }
+}
@@ -6,15 +6,18 @@ use expression::enums;
 
 type Expression = expression::Expression;
 
-pub fn parse_input(input: &String,
+pub fn parse_input(input: &str,
                    numeric_regex: &Regex,
                    function_regex: &Regex)
                    -> (String, Result<expression::Expression, String>) {
     let (variable, expr) = string_to_expr(input, numeric_regex, function_regex);
+    if enums::is_constant(&variable) {
+        return (String::new(), Err(format!("{} is a constant and cannot be assigned", variable)));
+    }
     convert_to_postfix(input, variable, expr)
 }
 
-fn string_to_expr(input: &String,
+fn string_to_expr(input: &str,
                   numeric_regex: &Regex,
                   function_regex: &Regex)
                   -> (String, Expression) {
@@ -49,7 +52,9 @@ fn string_to_expr(input: &String,
                     expr.push(x);
                     expr.push(enums::Token::Op(enums::Operator::Sub));
                     builder = String::new();
-                } else if builder.len() == 0 {
+                } else if expr.get_tokens().last() == Some(&enums::Token::Close) {
+                    expr.push(enums::Token::Op(enums::Operator::Sub)); // (a)-b
+                } else {
                     expr.push(enums::Token::Op(enums::Operator::Negate));
                 }
             }
@@ -89,7 +94,7 @@ fn string_to_expr(input: &String,
                 expr.push(enums::Token::Op(enums::Operator::Mod));
             }
             '=' => {
-                if builder.len() > 0 {
+                if !builder.is_empty() {
                     variable = builder.clone();
                     builder = String::new();
                 }
@@ -105,7 +110,7 @@ fn string_to_expr(input: &String,
     (variable, expr)
 }
 
-fn convert_to_postfix(input: &String,
+fn convert_to_postfix(input: &str,
                       variable: String,
                       expr: Expression)
                       -> (String, Result<Expression, String>) {
@@ -113,13 +118,19 @@ fn convert_to_postfix(input: &String,
     let mut out_queue: Vec<enums::Token> = Vec::with_capacity(input.len());
     for i in 0..expr.len() {
         let current_token = expr.get_token(i);
-        match current_token {
-            &enums::Token::Literal(ref x) => out_queue.push(enums::Token::Literal(x.clone())),
-            &enums::Token::Func(ref x) => op_stack.push(enums::Token::Func(x.clone())),
-            &enums::Token::Comma => {
+        match *current_token {
+            enums::Token::Literal(ref x) => {
+                if x.parse::<f64>().is_err() {
+                    return (variable, Err(format!("Invalid numeric literal: {}", x)));
+                }
+                out_queue.push(enums::Token::Literal(x.clone()))
+            }
+            enums::Token::Const(c) => out_queue.push(enums::Token::Const(c)),
+            enums::Token::Func(ref x) => op_stack.push(enums::Token::Func(*x)),
+            enums::Token::Comma => {
                 loop {
                     let stack_token = op_stack.pop();
-                    if !stack_token.is_some() {
+                    if stack_token.is_none() {
                         return (variable,
                                 Err("Malformed Expression, comma but no Parenthesis".to_owned()));
                     }
@@ -133,29 +144,21 @@ fn convert_to_postfix(input: &String,
                     }
                 }
             }
-            &enums::Token::Op(ref o1) => {
+            enums::Token::Op(ref o1) => {
                 loop {
-                    if op_stack.len() < 1 {
+                    if op_stack.is_empty() {
                         break;
                     }
                     let o2 = op_stack.pop().unwrap(); // top of stack, must exist based off of previous if
-                    match o1 {
-                        &enums::Operator::Negate => {
-                            op_stack.push(o2);
+                    match *o1 {
+                        enums::Operator::Negate |
+                        enums::Operator::Pow => {
+                            op_stack.push(o2); // -x^2 == -(x^2)
                             break;
                         }
-                        &enums::Operator::Pow => {
-                            match o2 {
-                                enums::Token::Op(enums::Operator::Negate) => out_queue.push(o2),
-                                _ => {
-                                    op_stack.push(o2);
-                                    break;
-                                }
-                            }
-                        }
-                        &enums::Operator::Mul |
-                        &enums::Operator::Div |
-                        &enums::Operator::Mod => {
+                        enums::Operator::Mul |
+                        enums::Operator::Div |
+                        enums::Operator::Mod => {
                             match o2 {
                                 enums::Token::Op(enums::Operator::Negate) |
                                 enums::Token::Op(enums::Operator::Pow) |
@@ -168,8 +171,8 @@ fn convert_to_postfix(input: &String,
                                 }
                             }
                         }
-                        &enums::Operator::Add |
-                        &enums::Operator::Sub => {
+                        enums::Operator::Add |
+                        enums::Operator::Sub => {
                             match o2 {
                                 enums::Token::Op(enums::Operator::Negate) |
                                 enums::Token::Op(enums::Operator::Pow) |
@@ -186,13 +189,13 @@ fn convert_to_postfix(input: &String,
                         }
                     }
                 }
-                op_stack.push(enums::Token::Op(o1.clone()));
+                op_stack.push(enums::Token::Op(*o1));
             }
-            &enums::Token::Open => op_stack.push(enums::Token::Open),
-            &enums::Token::Close => {
+            enums::Token::Open => op_stack.push(enums::Token::Open),
+            enums::Token::Close => {
                 loop {
                     let stack_token = op_stack.pop();
-                    if !stack_token.is_some() {
+                    if stack_token.is_none() {
                         return (variable,
                                 Err("Malformed Expression, found a ) without (".to_owned()));
                     }
@@ -202,44 +205,111 @@ fn convert_to_postfix(input: &String,
                         _ => out_queue.push(stack_token),
                     }
                 }
-                if op_stack.len() > 0 {
+                if !op_stack.is_empty() {
                     let next_stack_token = op_stack.pop().unwrap(); // must exist based off of previous if
                     match next_stack_token {
-                        enums::Token::Func(ref x) => out_queue.push(enums::Token::Func(x.clone())),
+                        enums::Token::Func(ref x) => out_queue.push(enums::Token::Func(*x)),
                         _ => op_stack.push(next_stack_token),
                     }
                 }
             }
-            &enums::Token::Var(ref x) => out_queue.push(enums::Token::Var(x.clone())),
-            &enums::Token::Unknown(ref x) => {
+            enums::Token::Var(ref x) => out_queue.push(enums::Token::Var(x.clone())),
+            enums::Token::Unknown(ref x) => {
                 let mut message: String = "You either misspelled a function, or it is not yet \
                                            implemented. The unknown string was: ".to_owned();
                 message.push_str(x);
                 return (variable, Err(message));
             }
-            _ => break,
         }
     }
-    while op_stack.len() > 0 {
-        out_queue.push(op_stack.pop().unwrap()); // The item must exist
+    while let Some(token) = op_stack.pop() {
+        out_queue.push(token);
     }
     (variable, Ok(Expression::new(out_queue)))
 }
 
-fn decide_what_to_push(builder: &String,
+fn decide_what_to_push(builder: &str,
                        numeric_regex: &Regex,
                        function_regex: &Regex)
                        -> Option<enums::Token> {
-    if builder.len() > 0 {
+    if !builder.is_empty() {
         let to_push;
-        if numeric_regex.is_match(&builder[..]) {
-            to_push = enums::Token::Literal(builder.clone());
-        } else if function_regex.is_match(&builder[..]) {
-            to_push = enums::map_string_to_func(&builder);
+        if numeric_regex.is_match(builder) {
+            to_push = enums::Token::Literal(builder.to_owned());
+        } else if function_regex.is_match(builder) || enums::is_constant(builder) {
+            to_push = enums::map_string_to_func(builder);
         } else {
-            to_push = enums::Token::Var(builder.clone());
+            to_push = enums::Token::Var(builder.to_owned());
         }
         return Some(to_push);
     }
     None
 }
+
+// The numeric and function regexes parse_input expects. Literals start with
+// a digit or a decimal point, so names like x1 are variables.
+pub fn token_regexes() -> (Regex, Regex) {
+    (Regex::new(r"^\.?\d").unwrap(), Regex::new(r"[a-zA-Z]{2,}").unwrap())
+}
+
+// Parses an expression the way the REPL does, for tests.
+#[cfg(test)]
+pub fn parse(input: &str) -> Expression {
+    let (numeric_regex, function_regex) = token_regexes();
+    parse_input(input, &numeric_regex, &function_regex).1.unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use expression::eval::eval_postfix_expr;
+
+    use std::collections::HashMap;
+
+    fn value(input: &str) -> f64 {
+        eval_postfix_expr(&parse(input), &HashMap::new())
+    }
+
+    #[test]
+    fn negation_binds_looser_than_powers() {
+        assert_eq!(value("-2^2"), -4.0);
+        assert_eq!(value("(-2)^2"), 4.0);
+        assert_eq!(value("2^-1"), 0.5);
+        assert_eq!(value("2^3^2"), 512.0);
+        assert_eq!(value("-3+5"), 2.0);
+        assert_eq!(value("2*-3"), -6.0);
+    }
+
+    #[test]
+    fn minus_after_a_parenthesis_subtracts() {
+        assert_eq!(value("(1)-2"), -1.0);
+        assert_eq!(value("(2+3)-(1+1)"), 3.0);
+        assert_eq!(value("sin(0)-1"), -1.0);
+        assert_eq!(value("(4)*-(1)"), -4.0);
+    }
+
+    #[test]
+    fn pi_and_e_are_constants() {
+        let constants = [enums::Token::Const(enums::Constant::E),
+                         enums::Token::Const(enums::Constant::Pi),
+                         enums::Token::Op(enums::Operator::Mul)];
+        assert_eq!(parse("e*pi").get_tokens(), &constants[..]);
+        assert_eq!(value("pi"), std::f64::consts::PI);
+        assert_eq!(value("e^2"), std::f64::consts::E.powi(2));
+        let (numeric_regex, function_regex) = token_regexes();
+        assert!(parse_input("e=2", &numeric_regex, &function_regex).1.is_err());
+        assert!(parse_input("pi=3", &numeric_regex, &function_regex).1.is_err());
+    }
+
+    #[test]
+    fn names_may_contain_digits() {
+        let tokens = [enums::Token::Var("x1".to_owned()),
+                      enums::Token::Literal("2.5".to_owned()),
+                      enums::Token::Op(enums::Operator::Mul)];
+        assert_eq!(parse("x1*2.5").get_tokens(), &tokens[..]);
+        assert_eq!(value(".5+x2"), 0.5);
+        let (numeric_regex, function_regex) = token_regexes();
+        assert!(parse_input("2x", &numeric_regex, &function_regex).1.is_err());
+        assert!(parse_input("1.2.3", &numeric_regex, &function_regex).1.is_err());
+    }
+}
@@ -0,0 +1,872 @@
+use expression;
+use expression::complex::Complex;
+use expression::enums;
+use expression::eval::eval_postfix_expr;
+use expression::numeric::Numeric;
+use expression::rational::Rational;
+use expression::symbolic;
+
+pub type Expression = expression::Expression;
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::f64;
+
+// Polynomials are stored as coefficient vectors, lowest degree first.
+
+const MAX_DEGREE: usize = 64;
+const ABERTH_ITERATIONS: usize = 500;
+// Coefficients that are multiples of 1/BINARY_DENOMINATOR are exact.
+const BINARY_DENOMINATOR: i128 = 1 << 20;
+// Candidates p/q the rational root test tries before leaving linear factors
+// to the numeric roots.
+const MAX_CANDIDATES: usize = 20_000;
+
+// Coefficients of `expr` as a polynomial in `var`, None if it is not one.
+// Sub-expressions free of `var` are evaluated with `values`.
+pub fn coefficients(expr: &Expression,
+                    var: &str,
+                    values: &HashMap<String, f64>)
+                    -> Option<Vec<f64>> {
+    let mut coeffs = poly_of(expr, var, values)?;
+    while coeffs.len() > 1 && *coeffs.last().unwrap() == 0.0 {
+        coeffs.pop();
+    }
+    if coeffs.iter().all(|c| c.is_finite()) {
+        Some(coeffs)
+    } else {
+        None
+    }
+}
+
+fn poly_of(expr: &Expression, var: &str, values: &HashMap<String, f64>) -> Option<Vec<f64>> {
+    if !symbolic::contains_var(expr, var) {
+        return Some(vec![eval_postfix_expr(expr, values)]);
+    }
+    let mut args = symbolic::operands(expr);
+    match *symbolic::root(expr) {
+        enums::Token::Var(_) => Some(vec![0.0, 1.0]),
+        enums::Token::Op(enums::Operator::Negate) => {
+            Some(poly_of(&args[0], var, values)?.iter().map(|c| -c).collect())
+        }
+        enums::Token::Op(op) => {
+            let b = args.pop().unwrap();
+            let a = args.pop().unwrap();
+            match op {
+                enums::Operator::Add => {
+                    Some(add(&poly_of(&a, var, values)?, &poly_of(&b, var, values)?))
+                }
+                enums::Operator::Sub => {
+                    let negated: Vec<f64> = poly_of(&b, var, values)?.iter().map(|c| -c).collect();
+                    Some(add(&poly_of(&a, var, values)?, &negated))
+                }
+                enums::Operator::Mul => {
+                    Some(mul(&poly_of(&a, var, values)?, &poly_of(&b, var, values)?))
+                }
+                enums::Operator::Div if !symbolic::contains_var(&b, var) => {
+                    let divisor = eval_postfix_expr(&b, values);
+                    Some(poly_of(&a, var, values)?.iter().map(|c| c / divisor).collect())
+                }
+                enums::Operator::Pow if !symbolic::contains_var(&b, var) => {
+                    let n = eval_postfix_expr(&b, values);
+                    if n < 0.0 || n.fract() != 0.0 || n as usize > MAX_DEGREE {
+                        return None;
+                    }
+                    let base = poly_of(&a, var, values)?;
+                    let mut result = vec![1.0];
+                    for _ in 0..n as usize {
+                        result = mul(&result, &base);
+                    }
+                    Some(result)
+                }
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+// `expr` as numerator / denominator, both as coefficients in `var`, None
+// if it is not a rational function. Nothing is cancelled.
+pub fn rational_function(expr: &Expression,
+                         var: &str,
+                         values: &HashMap<String, f64>)
+                         -> Option<(Vec<f64>, Vec<f64>)> {
+    if !symbolic::contains_var(expr, var) {
+        return Some((vec![eval_postfix_expr(expr, values)], vec![1.0]));
+    }
+    let mut args = symbolic::operands(expr);
+    match *symbolic::root(expr) {
+        enums::Token::Var(_) => Some((vec![0.0, 1.0], vec![1.0])),
+        enums::Token::Op(enums::Operator::Negate) => {
+            let (n, d) = rational_function(&args[0], var, values)?;
+            Some((n.iter().map(|c| -c).collect(), d))
+        }
+        enums::Token::Op(op) => {
+            let b = args.pop().unwrap();
+            let a = args.pop().unwrap();
+            if op == enums::Operator::Pow {
+                let n = eval_postfix_expr(&b, values);
+                if symbolic::contains_var(&b, var) || n.fract() != 0.0 ||
+                   n.abs() as usize > MAX_DEGREE {
+                    return None;
+                }
+                let (num, den) = rational_function(&a, var, values)?;
+                let (mut p, mut q) = (vec![1.0], vec![1.0]);
+                for _ in 0..n.abs() as usize {
+                    p = mul(&p, &num);
+                    q = mul(&q, &den);
+                }
+                return Some(if n < 0.0 { (q, p) } else { (p, q) });
+            }
+            let (an, ad) = rational_function(&a, var, values)?;
+            let (bn, bd) = rational_function(&b, var, values)?;
+            match op {
+                enums::Operator::Add => Some((add(&mul(&an, &bd), &mul(&bn, &ad)), mul(&ad, &bd))),
+                enums::Operator::Sub => {
+                    let negated: Vec<f64> = mul(&bn, &ad).iter().map(|c| -c).collect();
+                    Some((add(&mul(&an, &bd), &negated), mul(&ad, &bd)))
+                }
+                enums::Operator::Mul => Some((mul(&an, &bn), mul(&ad, &bd))),
+                enums::Operator::Div => Some((mul(&an, &bd), mul(&ad, &bn))),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+fn add(a: &[f64], b: &[f64]) -> Vec<f64> {
+    let mut result = vec![0.0; a.len().max(b.len())];
+    for (i, c) in a.iter().enumerate() {
+        result[i] += *c;
+    }
+    for (i, c) in b.iter().enumerate() {
+        result[i] += *c;
+    }
+    result
+}
+
+fn mul(a: &[f64], b: &[f64]) -> Vec<f64> {
+    let mut result = vec![0.0; a.len() + b.len() - 1];
+    for (i, x) in a.iter().enumerate() {
+        for (j, y) in b.iter().enumerate() {
+            result[i + j] += x * y;
+        }
+    }
+    result
+}
+
+pub fn degree<T>(coeffs: &[T]) -> usize {
+    coeffs.len().saturating_sub(1)
+}
+
+// Horner evaluation, also returning the derivative.
+pub fn eval_with_derivative(coeffs: &[Complex], z: Complex) -> (Complex, Complex) {
+    let mut p = Complex::zero();
+    let mut dp = Complex::zero();
+    for c in coeffs.iter().rev() {
+        dp = dp * z + p;
+        p = p * z + *c;
+    }
+    (p, dp)
+}
+
+// All complex roots with multiplicity: closed form up to degree 4, Aberth-
+// Ehrlich iteration beyond. Either way a few Newton steps polish the result.
+pub fn complex_roots(coeffs: &[f64]) -> Vec<Complex> {
+    let coeffs: Vec<Complex> = coeffs.iter().map(|c| Complex::from_f64(*c)).collect();
+    let roots = match degree(&coeffs) {
+        0 => Vec::new(),
+        1 => vec![-coeffs[0] / coeffs[1]],
+        2 => quadratic(coeffs[2], coeffs[1], coeffs[0]),
+        3 => cubic(coeffs[3], coeffs[2], coeffs[1], coeffs[0]),
+        4 => quartic(coeffs[4], coeffs[3], coeffs[2], coeffs[1], coeffs[0]),
+        _ => aberth(&coeffs),
+    };
+    roots.into_iter().map(|z| polish(&coeffs, z)).collect()
+}
+
+fn polish(coeffs: &[Complex], z: Complex) -> Complex {
+    let mut z = z;
+    for _ in 0..3 {
+        let (p, dp) = eval_with_derivative(coeffs, z);
+        if dp.norm() == 0.0 {
+            break;
+        }
+        let next = z - p / dp;
+        if !next.re.is_finite() || !next.im.is_finite() ||
+           eval_with_derivative(coeffs, next).0.norm() >= p.norm() {
+            break;
+        }
+        z = next;
+    }
+    // Snap imaginary parts that are pure rounding noise.
+    if z.im.abs() <= 1e-12 * z.re.abs().max(1.0) {
+        z.im = 0.0;
+    }
+    z
+}
+
+fn quadratic(a: Complex, b: Complex, c: Complex) -> Vec<Complex> {
+    let disc = (b * b - a * c.scale(4.0)).sqrt();
+    // Pick the sign that avoids cancellation, then use Vieta for the other.
+    let q = if (b.conj() * disc).re >= 0.0 {
+        (b + disc).scale(-0.5)
+    } else {
+        (b - disc).scale(-0.5)
+    };
+    if q.norm() == 0.0 {
+        return vec![Complex::zero(), Complex::zero()];
+    }
+    vec![q / a, c / q]
+}
+
+// Cardano's formula on the depressed cubic t^3 + p t + q.
+fn cubic(a: Complex, b: Complex, c: Complex, d: Complex) -> Vec<Complex> {
+    let (b, c, d) = (b / a, c / a, d / a);
+    let shift = b.scale(-1.0 / 3.0);
+    let p = c - b * b.scale(1.0 / 3.0);
+    let q = b * b * b.scale(2.0 / 27.0) - b * c.scale(1.0 / 3.0) + d;
+    let disc = (q * q.scale(0.25) + p * p * p.scale(1.0 / 27.0)).sqrt();
+    let mut u = (q.scale(-0.5) + disc).pow(Complex::from_f64(1.0 / 3.0));
+    if u.norm() < 1e-300 {
+        u = (q.scale(-0.5) - disc).pow(Complex::from_f64(1.0 / 3.0));
+    }
+    let omega = Complex::new(-0.5, 3f64.sqrt() / 2.0);
+    let mut roots = Vec::with_capacity(3);
+    let mut uk = u;
+    for _ in 0..3 {
+        let t = if uk.norm() < 1e-300 {
+            Complex::zero()
+        } else {
+            uk - p / uk.scale(3.0)
+        };
+        roots.push(t + shift);
+        uk = uk * omega;
+    }
+    roots
+}
+
+// Ferrari's method: split the depressed quartic into two quadratics using a
+// root of the resolvent cubic.
+fn quartic(a: Complex, b: Complex, c: Complex, d: Complex, e: Complex) -> Vec<Complex> {
+    let (b, c, d, e) = (b / a, c / a, d / a, e / a);
+    let shift = b.scale(-0.25);
+    let b2 = b * b;
+    let p = c - b2.scale(3.0 / 8.0);
+    let q = d - b * c.scale(0.5) + b2 * b.scale(1.0 / 8.0);
+    let r = e - b * d.scale(0.25) + b2 * c.scale(1.0 / 16.0) - b2 * b2.scale(3.0 / 256.0);
+    let one = Complex::one();
+    let mut roots: Vec<Complex> = Vec::with_capacity(4);
+    if q.norm() < 1e-14 * (1.0 + p.norm() + r.norm()) {
+        // Biquadratic: y^4 + p y^2 + r.
+        for y2 in quadratic(one, p, r) {
+            let y = y2.sqrt();
+            roots.push(y + shift);
+            roots.push(-y + shift);
+        }
+        return roots;
+    }
+    // 8m^3 + 8p m^2 + (2p^2 - 8r) m - q^2 = 0, any root with m != 0.
+    let resolvent = cubic(Complex::from_f64(8.0),
+                          p.scale(8.0),
+                          p * p.scale(2.0) - r.scale(8.0),
+                          -(q * q));
+    let m = resolvent.iter()
+        .cloned()
+        .fold(Complex::zero(), |best, z| if z.norm() > best.norm() { z } else { best });
+    let s = m.scale(2.0).sqrt();
+    let half_p = p.scale(0.5);
+    let t = q / s.scale(2.0);
+    for y in quadratic(one, s, half_p + m - t) {
+        roots.push(y + shift);
+    }
+    for y in quadratic(one, -s, half_p + m + t) {
+        roots.push(y + shift);
+    }
+    roots
+}
+
+// Simultaneous Aberth-Ehrlich iteration for every root of a polynomial of
+// any degree.
+pub fn aberth(coeffs: &[Complex]) -> Vec<Complex> {
+    let n = degree(coeffs);
+    let lead = coeffs[n];
+    // Start on a circle inside the Cauchy bound, offset so no guess is real.
+    let radius = 1.0 + coeffs[..n].iter().map(|c| (*c / lead).norm()).fold(0.0, f64::max);
+    let radius = radius.min(1e6) * 0.5;
+    let mut roots: Vec<Complex> = (0..n)
+        .map(|k| {
+            Complex::from_polar(radius,
+                                2.0 * f64::consts::PI * k as f64 / n as f64 + 0.4)
+        })
+        .collect();
+    for _ in 0..ABERTH_ITERATIONS {
+        let mut converged = true;
+        for k in 0..n {
+            let z = roots[k];
+            let (p, dp) = eval_with_derivative(coeffs, z);
+            if p.norm() == 0.0 {
+                continue;
+            }
+            let ratio = p / dp;
+            let mut sum = Complex::zero();
+            for (j, other) in roots.iter().enumerate() {
+                if j != k {
+                    sum = sum + (z - *other).recip();
+                }
+            }
+            let step = ratio / (Complex::one() - ratio * sum);
+            if !step.re.is_finite() || !step.im.is_finite() {
+                continue;
+            }
+            roots[k] = z - step;
+            if step.norm() > 1e-14 * z.norm().max(1.0) {
+                converged = false;
+            }
+        }
+        if converged {
+            break;
+        }
+    }
+    roots
+}
+
+// Exact polynomials over the rationals.
+
+// None unless every coefficient is exactly a fraction, see exact_rational.
+pub fn to_rational(coeffs: &[f64]) -> Option<Vec<Rational>> {
+    coeffs.iter().map(|c| exact_rational(*c)).collect()
+}
+
+// `x` as a fraction when that is exact rather than a close approximation: a
+// binary fraction with a small denominator, or a fraction with a small
+// denominator that rounds back to exactly `x`, like 1/3.
+fn exact_rational(x: f64) -> Option<Rational> {
+    if !x.is_finite() || x.abs() > 1e18 {
+        return None;
+    }
+    let scaled = x * BINARY_DENOMINATOR as f64;
+    if scaled.fract() == 0.0 {
+        return Some(Rational::new(scaled as i128, BINARY_DENOMINATOR));
+    }
+    Rational::from_f64(x, 1_000_000).filter(|r| r.to_f64() == x)
+}
+
+fn trim(p: &mut Vec<Rational>) {
+    while p.len() > 1 && p.last().unwrap().is_zero() {
+        p.pop();
+    }
+}
+
+fn is_zero(p: &[Rational]) -> bool {
+    p.iter().all(|c| c.is_zero())
+}
+
+fn monic(p: &[Rational]) -> Vec<Rational> {
+    let lead = *p.last().unwrap();
+    p.iter().map(|c| *c / lead).collect()
+}
+
+pub fn rational_derivative(p: &[Rational]) -> Vec<Rational> {
+    if p.len() < 2 {
+        return vec![Rational::zero()];
+    }
+    p.iter()
+        .enumerate()
+        .skip(1)
+        .map(|(i, c)| *c * Rational::from_integer(i as i128))
+        .collect()
+}
+
+// Quotient and remainder of a / b.
+pub fn divide(a: &[Rational], b: &[Rational]) -> (Vec<Rational>, Vec<Rational>) {
+    let mut rem: Vec<Rational> = a.to_vec();
+    trim(&mut rem);
+    let db = degree(b);
+    if rem.len() < b.len() {
+        return (vec![Rational::zero()], rem);
+    }
+    let mut quot = vec![Rational::zero(); rem.len() - db];
+    let lead = b[db];
+    while rem.len() > db && !is_zero(&rem) {
+        let shift = rem.len() - 1 - db;
+        let factor = *rem.last().unwrap() / lead;
+        quot[shift] = factor;
+        for (i, c) in b.iter().enumerate() {
+            rem[shift + i] = rem[shift + i] - factor * *c;
+        }
+        rem.pop();
+        trim(&mut rem);
+        if rem.iter().any(|c| !c.is_valid()) {
+            break;
+        }
+    }
+    (quot, rem)
+}
+
+// Monic gcd, or 1 when the coefficients overflow, which only loses a common
+// factor that could have been cancelled.
+pub fn gcd(a: &[Rational], b: &[Rational]) -> Vec<Rational> {
+    checked_gcd(a, b).unwrap_or_else(|| vec![Rational::one()])
+}
+
+// Euclid's algorithm on primitive parts: every remainder is scaled back to
+// coprime integer coefficients, which keeps them from growing the way they
+// do over the rationals. None on overflow.
+fn checked_gcd(a: &[Rational], b: &[Rational]) -> Option<Vec<Rational>> {
+    let (mut a, mut b) = (primitive(a)?.1, primitive(b)?.1);
+    trim(&mut a);
+    trim(&mut b);
+    if is_zero(&a) {
+        std::mem::swap(&mut a, &mut b);
+    }
+    while !is_zero(&b) {
+        let (_, r) = divide(&a, &b);
+        if r.iter().any(|c| !c.is_valid()) {
+            return None;
+        }
+        a = b;
+        b = primitive(&r)?.1;
+    }
+    let result = monic(&a);
+    if result.iter().all(|c| c.is_valid()) { Some(result) } else { None }
+}
+
+// Yun's algorithm: p == c * prod(f_i^i) with every f_i square free and
+// pairwise coprime. Returns (f_i, i) for the non-constant f_i, None on
+// overflow.
+pub fn square_free(p: &[Rational]) -> Option<Vec<(Vec<Rational>, usize)>> {
+    let mut factors: Vec<(Vec<Rational>, usize)> = Vec::new();
+    let dp = rational_derivative(p);
+    let a0 = checked_gcd(p, &dp)?;
+    let mut b = divide(p, &a0).0;
+    let mut c = divide(&dp, &a0).0;
+    let mut d = sub(&c, &rational_derivative(&b));
+    let mut i = 1;
+    while degree(&b) > 0 {
+        let a = checked_gcd(&b, &d)?;
+        if degree(&a) > 0 {
+            factors.push((monic(&a), i));
+        }
+        b = divide(&b, &a).0;
+        c = divide(&d, &a).0;
+        d = sub(&c, &rational_derivative(&b));
+        if b.iter().chain(&d).any(|c| !c.is_valid()) {
+            return None;
+        }
+        i += 1;
+        if i > MAX_DEGREE {
+            break;
+        }
+    }
+    Some(factors)
+}
+
+fn sub(a: &[Rational], b: &[Rational]) -> Vec<Rational> {
+    let mut result = vec![Rational::zero(); a.len().max(b.len())];
+    for (i, c) in a.iter().enumerate() {
+        result[i] = result[i] + *c;
+    }
+    for (i, c) in b.iter().enumerate() {
+        result[i] = result[i] - *c;
+    }
+    trim(&mut result);
+    result
+}
+
+fn eval_rational(p: &[Rational], x: Rational) -> Rational {
+    p.iter().rev().fold(Rational::zero(), |acc, c| acc * x + *c)
+}
+
+// Scales p to integer coefficients with no common factor and a positive
+// leading coefficient, returning the scale factor as well. None for invalid
+// coefficients or on overflow.
+pub fn primitive(p: &[Rational]) -> Option<(Rational, Vec<Rational>)> {
+    if p.iter().any(|c| !c.is_valid()) {
+        return None;
+    }
+    let mut lcm: i128 = 1;
+    let mut g: i128 = 0;
+    for c in p {
+        lcm = (lcm / gcd_i128(lcm, c.denom())).checked_mul(c.denom())?;
+    }
+    let mut ints: Vec<i128> = Vec::with_capacity(p.len());
+    for c in p {
+        ints.push(c.numer().checked_mul(lcm / c.denom())?);
+    }
+    for n in &ints {
+        g = gcd_i128(g, *n);
+    }
+    if g == 0 {
+        return Some((Rational::one(), p.to_vec()));
+    }
+    if *ints.last().unwrap() < 0 {
+        g = -g;
+    }
+    let content = Rational::new(g, lcm);
+    Some((content, ints.iter().map(|n| Rational::from_integer(n / g)).collect()))
+}
+
+fn gcd_i128(a: i128, b: i128) -> i128 {
+    let (mut a, mut b) = (a.abs(), b.abs());
+    while b != 0 {
+        let t = a % b;
+        a = b;
+        b = t;
+    }
+    a
+}
+
+fn divisors(n: i128) -> Vec<i128> {
+    let n = n.abs();
+    let mut small: Vec<i128> = Vec::new();
+    let mut large: Vec<i128> = Vec::new();
+    let mut d: i128 = 1;
+    while d * d <= n && d <= 1_000_000 {
+        if n % d == 0 {
+            small.push(d);
+            if d * d != n {
+                large.push(n / d);
+            }
+        }
+        d += 1;
+    }
+    large.reverse();
+    small.extend(large);
+    small
+}
+
+#[derive(Debug, Clone)]
+pub struct Factorization {
+    pub content: Rational,
+    // Primitive integer factors with their multiplicity.
+    pub factors: Vec<(Vec<Rational>, usize)>,
+}
+
+// Factors over the rationals: square-free decomposition, then linear factors
+// of each square-free part from the rational root test and quadratic factors
+// from pairs of numeric roots. What is left is returned as is, it has no
+// rational roots but may still split into factors of degree three or more.
+// None when the coefficients are not exact fractions or overflow.
+pub fn factor(coeffs: &[f64]) -> Option<Factorization> {
+    let p = to_rational(coeffs)?;
+    let (content, p) = primitive(&p)?;
+    let mut factors: Vec<(Vec<Rational>, usize)> = Vec::new();
+    for (f, multiplicity) in square_free(&p)? {
+        let (_, f) = primitive(&f)?;
+        for g in split(f)? {
+            factors.push((g, multiplicity));
+        }
+    }
+    if factors.iter().any(|factor| factor.0.iter().any(|c| !c.is_valid())) || !content.is_valid() {
+        return None;
+    }
+    factors.sort_by_key(|&(ref f, m)| (degree(f), m));
+    Some(Factorization { content, factors })
+}
+
+// Splits a primitive square-free polynomial into rational linear and
+// quadratic factors where it can. Linear factors come from the rational root
+// test, or from the numeric roots when it has too many candidates.
+fn split(p: Vec<Rational>) -> Option<Vec<Vec<Rational>>> {
+    let mut found: Vec<Vec<Rational>> = Vec::new();
+    let mut rest = p;
+    let mut first_degree = 1;
+    if let Some(roots) = rational_roots(&rest) {
+        for r in roots {
+            let f = vec![Rational::from_integer(-r.numer()), Rational::from_integer(r.denom())];
+            rest = divide(&rest, &f).0;
+            found.push(f);
+        }
+        first_degree = 2;
+    }
+    for factor_degree in first_degree..3 {
+        if degree(&rest) <= factor_degree {
+            break;
+        }
+        let approx: Vec<f64> = rest.iter().map(|c| c.to_f64()).collect();
+        let roots = complex_roots(&approx);
+        let leads = divisors(rest.last().unwrap().numer());
+        let mut used = vec![false; roots.len()];
+        for i in 0..roots.len() {
+            if used[i] {
+                continue;
+            }
+            let partners: Vec<usize> = if factor_degree == 1 {
+                vec![i]
+            } else {
+                (i + 1..roots.len()).filter(|j| !used[*j]).collect()
+            };
+            for j in partners {
+                let candidate = match candidate_factor(&roots, i, j, &leads) {
+                    Some(candidate) => candidate,
+                    None => continue,
+                };
+                let (quot, rem) = divide(&rest, &candidate);
+                if is_zero(&rem) && quot.iter().all(|c| c.is_valid()) {
+                    found.push(candidate);
+                    rest = quot;
+                    used[i] = true;
+                    used[j] = true;
+                    break;
+                }
+            }
+        }
+    }
+    if degree(&rest) > 0 {
+        found.push(primitive(&rest)?.1);
+    }
+    Some(found)
+}
+
+// Every rational root p/q of a square-free integer polynomial, with p
+// dividing the lowest and q the leading coefficient. None when there are too
+// many candidates to try or the arithmetic overflows.
+fn rational_roots(f: &[Rational]) -> Option<Vec<Rational>> {
+    let mut roots: Vec<Rational> = Vec::new();
+    let lowest = f.iter().position(|c| !c.is_zero())?;
+    if lowest > 0 {
+        roots.push(Rational::zero());
+    }
+    let f = &f[lowest..];
+    if degree(f) == 0 {
+        return Some(roots);
+    }
+    let (a0, an) = (f[0].numer(), f[degree(f)].numer());
+    if a0.abs() > 1_000_000_000_000 || an.abs() > 1_000_000_000_000 {
+        return None;
+    }
+    let (ps, qs) = (divisors(a0), divisors(an));
+    if ps.len() * qs.len() > MAX_CANDIDATES {
+        return None;
+    }
+    for q in &qs {
+        for p in &ps {
+            if gcd_i128(*p, *q) != 1 {
+                continue;
+            }
+            for candidate in &[Rational::new(*p, *q), Rational::new(-*p, *q)] {
+                let value = eval_rational(f, *candidate);
+                if !value.is_valid() {
+                    return None;
+                }
+                if value.is_zero() {
+                    roots.push(*candidate);
+                }
+            }
+        }
+    }
+    Some(roots)
+}
+
+// Integer linear (i == j) or quadratic factor with roots[i] and roots[j], if
+// one with a leading coefficient from `leads` has integer coefficients.
+fn candidate_factor(roots: &[Complex],
+                    i: usize,
+                    j: usize,
+                    leads: &[i128])
+                    -> Option<Vec<Rational>> {
+    let (sum, product) = if i == j {
+        if roots[i].im != 0.0 {
+            return None;
+        }
+        (roots[i], Complex::zero())
+    } else {
+        (roots[i] + roots[j], roots[i] * roots[j])
+    };
+    if sum.im.abs() > 1e-6 || product.im.abs() > 1e-6 {
+        return None;
+    }
+    for lead in leads {
+        let l = *lead as f64;
+        let b = -sum.re * l;
+        let c = product.re * l;
+        if (b - b.round()).abs() > 1e-6 * l.max(1.0) || (c - c.round()).abs() > 1e-6 * l.max(1.0) {
+            continue;
+        }
+        let lead = Rational::from_integer(*lead);
+        let b = Rational::from_integer(b.round() as i128);
+        let c = Rational::from_integer(c.round() as i128);
+        return Some(if i == j { vec![b, lead] } else { vec![c, b, lead] });
+    }
+    None
+}
+
+// Exact roots of a linear factor.
+pub fn linear_root(f: &[Rational]) -> Option<Rational> {
+    if degree(f) == 1 {
+        Some(-f[0] / f[1])
+    } else {
+        None
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum PolyRoot {
+    Exact(Rational),
+    Approx(Complex),
+}
+
+impl PolyRoot {
+    pub fn value(&self) -> Complex {
+        match *self {
+            PolyRoot::Exact(r) => Complex::from_f64(r.to_f64()),
+            PolyRoot::Approx(z) => z,
+        }
+    }
+}
+
+// Every root with its multiplicity, exact where it is rational.
+pub fn roots(coeffs: &[f64]) -> Vec<(PolyRoot, usize)> {
+    let mut result: Vec<(PolyRoot, usize)> = Vec::new();
+    match factor(coeffs) {
+        Some(factorization) => {
+            for (f, multiplicity) in factorization.factors {
+                if let Some(r) = linear_root(&f) {
+                    debug_assert!(eval_rational(&f, r).is_zero());
+                    result.push((PolyRoot::Exact(r), multiplicity));
+                    continue;
+                }
+                let approx: Vec<f64> = f.iter().map(|c| c.to_f64()).collect();
+                for z in complex_roots(&approx) {
+                    result.push((PolyRoot::Approx(z), multiplicity));
+                }
+            }
+        }
+        None => {
+            for z in complex_roots(coeffs) {
+                result.push((PolyRoot::Approx(z), 1));
+            }
+        }
+    }
+    result.sort_by(|a, b| {
+        let (za, zb) = (a.0.value(), b.0.value());
+        (za.re, za.im).partial_cmp(&(zb.re, zb.im)).unwrap_or(Ordering::Equal)
+    });
+    result
+}
+
+// c_0 + c_1 x + ... as an expression in `var`, highest power first.
+pub fn to_expression(coeffs: &[f64], var: &str) -> Expression {
+    let mut expr = symbolic::number(0.0);
+    for (i, c) in coeffs.iter().enumerate().rev() {
+        let power = symbolic::pow(symbolic::variable(var), symbolic::number(i as f64));
+        let term = symbolic::mul(symbolic::number(*c), power);
+        expr = symbolic::add(expr, term);
+    }
+    expr
+}
+
+pub fn factorization_to_string(factorization: &Factorization, var: &str) -> String {
+    let mut parts: Vec<String> = Vec::new();
+    if factorization.content != Rational::one() || factorization.factors.is_empty() {
+        parts.push(format!("{}", factorization.content));
+    }
+    let negated = parts.len() == 1 && parts[0] == "-1" && !factorization.factors.is_empty();
+    if negated {
+        parts.clear();
+    }
+    let alone = !negated && parts.is_empty() && factorization.factors.len() == 1;
+    for &(ref f, multiplicity) in &factorization.factors {
+        let coeffs: Vec<f64> = f.iter().map(|c| c.to_f64()).collect();
+        let mut text = format!("{}", to_expression(&coeffs, var));
+        let single_term = f.iter().filter(|c| !c.is_zero()).count() == 1;
+        if (!single_term && (!alone || multiplicity > 1)) || (multiplicity > 1 && degree(f) > 1) {
+            text = format!("({})", text);
+        }
+        if multiplicity > 1 {
+            text = format!("{}^{}", text, multiplicity);
+        }
+        parts.push(text);
+    }
+    if negated {
+        format!("-{}", parts.join("*"))
+    } else {
+        parts.join("*")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use expression::parse::parse;
+    use std::collections::HashMap;
+    use std::f64::consts::PI;
+
+    fn factored(coeffs: &[f64]) -> String {
+        factorization_to_string(&factor(coeffs).unwrap(), "x")
+    }
+
+    #[test]
+    fn factorizations() {
+        assert_eq!(factored(&[0.0, -2.0, 0.0, 2.0]), "2*x*(x - 1)*(x + 1)");
+        assert_eq!(factored(&[1.0, -2.0, 2.0, -2.0, 1.0]), "(x - 1)^2*(x^2 + 1)");
+        assert_eq!(factored(&[-4.0, 0.0, 0.0, 0.0, 1.0]), "(x^2 + 2)*(x^2 - 2)");
+        assert_eq!(factored(&[-2.0, 0.0, 0.0, 1.0]), "x^3 - 2");
+    }
+
+    #[test]
+    fn rational_roots_are_exact() {
+        // (x - 1)(x - 2)...(x - 6)
+        let coeffs = [720.0, -1764.0, 1624.0, -735.0, 175.0, -21.0, 1.0];
+        let found = roots(&coeffs);
+        assert_eq!(found.len(), 6);
+        for (k, &(root, multiplicity)) in found.iter().enumerate() {
+            match root {
+                PolyRoot::Exact(r) => assert_eq!(r, Rational::new(k as i128 + 1, 1)),
+                PolyRoot::Approx(z) => panic!("{} + {}i is not exact", z.re, z.im),
+            }
+            assert_eq!(multiplicity, 1);
+        }
+        let repeated = roots(&[1.0, -2.0, 2.0, -2.0, 1.0]);
+        assert_eq!(repeated.len(), 3);
+        assert_eq!(repeated[2].1, 2);
+    }
+
+    #[test]
+    fn roots_of_unity() {
+        let found = complex_roots(&[-1.0, 0.0, 0.0, 0.0, 0.0, 1.0]);
+        assert_eq!(found.len(), 5);
+        for k in 0..5 {
+            let angle = 2.0 * PI * k as f64 / 5.0;
+            let expected = Complex::new(angle.cos(), angle.sin());
+            assert!(found.iter().any(|z| (*z - expected).norm() <= 1e-12));
+        }
+    }
+
+    #[test]
+    fn irrational_coefficients_are_rejected() {
+        assert!(to_rational(&[-PI, 0.0, 1.0]).is_none());
+        assert!(factor(&[-PI, 0.0, 1.0]).is_none());
+        assert!(factor(&[-0.1 - 0.2, 1.0]).is_none());
+        assert_eq!(factored(&[0.0, -1.0 / 3.0, 0.0, 1.0]), "1/3*x*(3*x^2 - 1)");
+        assert_eq!(factored(&[0.375, 1.0]), "1/8*(8*x + 3)");
+    }
+
+    #[test]
+    fn repeated_factors_keep_their_multiplicity() {
+        let expr = parse("(x-1)^2*(2*x+3)^3*(7*x^3-5*x+11)^2");
+        let coeffs = coefficients(&expr, "x", &HashMap::new()).unwrap();
+        assert_eq!(factored(&coeffs), "(x - 1)^2*(2*x + 3)^3*(7*x^3 - 5*x + 11)^2");
+        let found = roots(&coeffs);
+        assert_eq!(found.len(), 5);
+        assert!(found.iter().all(|&(_, multiplicity)| multiplicity >= 2));
+        assert!(found.iter().any(|r| match *r {
+            (PolyRoot::Exact(x), 3) => x == Rational::new(-3, 2),
+            _ => false,
+        }));
+        assert!(found.iter().any(|r| match *r {
+            (PolyRoot::Exact(x), 2) => x == Rational::one(),
+            _ => false,
+        }));
+    }
+
+    #[test]
+    fn overflow_is_not_a_factorization() {
+        let huge = Rational::new(1, 1 << 100);
+        assert!(primitive(&[huge, Rational::new(1, (1 << 100) - 1)]).is_none());
+        assert!(primitive(&[Rational::new(1, 0), Rational::one()]).is_none());
+    }
+}
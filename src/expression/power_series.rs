@@ -0,0 +1,652 @@
+use expression;
+use expression::enums;
+use expression::eval;
+
+pub type Expression = expression::Expression;
+
+use std::collections::HashMap;
+use std::f64;
+use std::ops::{Add, Mul, Neg, Sub};
+
+// Truncated power series c_0 + c_1 t + ... + c_(n-1) t^(n-1) + O(t^n). Every
+// coefficient stored is exact up to rounding; operations that lose terms
+// (dividing by a series that vanishes at 0) return fewer coefficients, so
+// the length is always the number of known terms. Alongside each coefficient
+// is a running bound on its rounding error, propagated to first order
+// through every operation, which tells noise from small coefficients.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Series {
+    coeffs: Vec<f64>,
+    errors: Vec<f64>,
+}
+
+// Coefficients this small relative to the largest one count as zero when
+// looking for the leading term of a divisor.
+const NEGLIGIBLE: f64 = 1e-13;
+
+// Returned by operations without a Taylor series at the point, expand puts
+// the sub-expression and point into the message.
+const NOT_ANALYTIC: &str = "not analytic";
+
+// The rounding error of a single operation with result x.
+fn rounding(x: f64) -> f64 {
+    f64::EPSILON * x.abs()
+}
+
+impl Series {
+    // Coefficients that are only off by their own rounding.
+    pub fn new(coeffs: Vec<f64>) -> Self {
+        let errors = coeffs.iter().map(|c| rounding(*c)).collect();
+        Series { coeffs, errors }
+    }
+
+    fn with_errors(coeffs: Vec<f64>, errors: Vec<f64>) -> Self {
+        Series { coeffs, errors }
+    }
+
+    pub fn constant(c: f64, terms: usize) -> Self {
+        let mut coeffs = vec![0.0; terms.max(1)];
+        coeffs[0] = c;
+        Series::new(coeffs)
+    }
+
+    // a + t, the expansion variable around a.
+    pub fn variable(a: f64, terms: usize) -> Self {
+        let mut series = Series::constant(a, terms);
+        if terms > 1 {
+            series.coeffs[1] = 1.0;
+        }
+        series
+    }
+
+    pub fn coeffs(&self) -> &[f64] {
+        &self.coeffs
+    }
+
+    pub fn terms(&self) -> usize {
+        self.coeffs.len()
+    }
+
+    pub fn value(&self) -> f64 {
+        self.coeffs[0]
+    }
+
+    pub fn truncate(mut self, terms: usize) -> Self {
+        self.coeffs.truncate(terms.max(1));
+        self.errors.truncate(terms.max(1));
+        self
+    }
+
+    pub fn scale(&self, c: f64) -> Series {
+        let coeffs: Vec<f64> = self.coeffs.iter().map(|x| x * c).collect();
+        let errors =
+            self.errors.iter().zip(&coeffs).map(|(e, x)| e * c.abs() + rounding(*x)).collect();
+        Series::with_errors(coeffs, errors)
+    }
+
+    // Zeroes coefficients that are no larger than their rounding error, such
+    // as the odd terms of x/(exp(x) - 1) or everything past the constant in
+    // sin(x)^2 + cos(x)^2.
+    pub fn chop(&self) -> Series {
+        let coeffs = self.coeffs
+            .iter()
+            .zip(&self.errors)
+            .map(|(c, e)| if c.abs() <= *e { 0.0 } else { *c })
+            .collect();
+        Series::with_errors(coeffs, self.errors.clone())
+    }
+
+    pub fn shift(&self, c: f64) -> Series {
+        let mut result = self.clone();
+        result.coeffs[0] += c;
+        result.errors[0] += rounding(result.coeffs[0]);
+        result
+    }
+
+    // d/dt, one term shorter.
+    pub fn derivative(&self) -> Series {
+        if self.terms() < 2 {
+            return Series::constant(0.0, 1);
+        }
+        let coeffs = self.coeffs.iter().enumerate().skip(1).map(|(k, c)| k as f64 * c).collect();
+        let errors = self.errors.iter().enumerate().skip(1).map(|(k, e)| k as f64 * e).collect();
+        Series::with_errors(coeffs, errors)
+    }
+
+    // Antiderivative with constant term c0, one term longer.
+    pub fn integral(&self, c0: f64) -> Series {
+        let mut coeffs = Vec::with_capacity(self.terms() + 1);
+        coeffs.push(c0);
+        coeffs.extend(self.coeffs.iter().enumerate().map(|(k, c)| c / (k + 1) as f64));
+        let mut errors = Vec::with_capacity(self.terms() + 1);
+        errors.push(rounding(c0));
+        errors.extend(self.errors
+            .iter()
+            .zip(&coeffs[1..])
+            .enumerate()
+            .map(|(k, (e, c))| e / (k + 1) as f64 + rounding(*c)));
+        Series::with_errors(coeffs, errors)
+    }
+
+    // Index of the first coefficient that is not negligible.
+    fn valuation(&self) -> Option<usize> {
+        let scale = self.coeffs.iter().fold(0.0f64, |m, c| m.max(c.abs()));
+        self.coeffs.iter().position(|c| c.abs() > NEGLIGIBLE * scale)
+    }
+
+    pub fn recip(&self) -> Result<Series, String> {
+        Series::constant(1.0, self.terms()).checked_div(self)
+    }
+
+    // self / other. When other vanishes at 0 the same number of leading
+    // terms of self must vanish too (a removable singularity such as
+    // sin(t)/t); both are shifted down and that many terms are lost.
+    pub fn checked_div(&self, other: &Series) -> Result<Series, String> {
+        let v = match other.valuation() {
+            Some(v) => v,
+            None => return Err("Division by a series that vanishes".to_owned()),
+        };
+        let scale = self.coeffs.iter().fold(0.0f64, |m, c| m.max(c.abs())).max(1.0);
+        if self.coeffs.iter().take(v).any(|c| c.abs() > 1e-10 * scale) {
+            return Err("Pole in the expansion, the function has no Taylor series here".to_owned());
+        }
+        let start = v.min(self.terms());
+        let (a, a_errors) = (&self.coeffs[start..], &self.errors[start..]);
+        let (b, b_errors) = (&other.coeffs[v..], &other.errors[v..]);
+        let n = a.len().min(b.len());
+        if n == 0 {
+            return Err("Every term was lost to a removable singularity".to_owned());
+        }
+        // q_k b_0 = a_k - sum_(j<k) q_j b_(k-j)
+        let mut q: Vec<f64> = Vec::with_capacity(n);
+        let mut errors: Vec<f64> = Vec::with_capacity(n);
+        for k in 0..n {
+            let sum: f64 = (0..k).map(|j| q[j] * b[k - j]).sum();
+            let size: f64 = a[k].abs() + (0..k).map(|j| (q[j] * b[k - j]).abs()).sum::<f64>();
+            let propagated: f64 =
+                (0..k).map(|j| errors[j] * b[k - j].abs() + q[j].abs() * b_errors[k - j]).sum();
+            let value = (a[k] - sum) / b[0];
+            errors.push((a_errors[k] + propagated + (k + 2) as f64 * rounding(size)) / b[0].abs() +
+                        value.abs() * b_errors[0] / b[0].abs());
+            q.push(value);
+        }
+        Ok(Series::with_errors(q, errors))
+    }
+
+    pub fn exp(&self) -> Series {
+        let (u, u_errors) = (&self.coeffs, &self.errors);
+        let n = self.terms();
+        let mut w = vec![0.0; n];
+        let mut errors = vec![0.0; n];
+        w[0] = u[0].exp();
+        errors[0] = w[0] * u_errors[0] + rounding(w[0]);
+        for k in 1..n {
+            let sum: f64 = (1..k + 1).map(|j| j as f64 * u[j] * w[k - j]).sum();
+            w[k] = sum / k as f64;
+            let size: f64 = (1..k + 1).map(|j| j as f64 * (u[j] * w[k - j]).abs()).sum();
+            let propagated: f64 = (1..k + 1)
+                .map(|j| j as f64 * (u_errors[j] * w[k - j].abs() + u[j].abs() * errors[k - j]))
+                .sum();
+            errors[k] = (propagated + (k + 2) as f64 * rounding(size)) / k as f64;
+        }
+        Series::with_errors(w, errors)
+    }
+
+    pub fn ln(&self) -> Result<Series, String> {
+        let u0 = self.value();
+        if u0 <= 0.0 {
+            return Err(NOT_ANALYTIC.to_owned());
+        }
+        let mut result = self.derivative().checked_div(self)?.integral(u0.ln());
+        result.errors[0] += self.errors[0] / u0;
+        Ok(result)
+    }
+
+    // u^alpha for a constant alpha, from u w' = alpha u' w.
+    pub fn powf(&self, alpha: f64) -> Result<Series, String> {
+        if alpha.fract() == 0.0 && (0.0..=64.0).contains(&alpha) {
+            return Ok(self.powi(alpha as u32));
+        }
+        let (u, u_errors) = (&self.coeffs, &self.errors);
+        if u[0] == 0.0 {
+            return Err(NOT_ANALYTIC.to_owned());
+        }
+        let n = self.terms();
+        let mut w = vec![0.0; n];
+        let mut errors = vec![0.0; n];
+        w[0] = u[0].powf(alpha);
+        errors[0] = (alpha * w[0] / u[0]).abs() * u_errors[0] + rounding(w[0]);
+        for k in 1..n {
+            let factor = |j: usize| (alpha + 1.0) * j as f64 - k as f64;
+            let sum: f64 = (1..k + 1).map(|j| factor(j) * u[j] * w[k - j]).sum();
+            w[k] = sum / (k as f64 * u[0]);
+            let size: f64 = (1..k + 1).map(|j| (factor(j) * u[j] * w[k - j]).abs()).sum();
+            let propagated: f64 = (1..k + 1)
+                .map(|j| {
+                    factor(j).abs() * (u_errors[j] * w[k - j].abs() + u[j].abs() * errors[k - j])
+                })
+                .sum();
+            errors[k] = (propagated + (k + 3) as f64 * rounding(size)) / (k as f64 * u[0].abs()) +
+                        (w[k] / u[0]).abs() * u_errors[0];
+        }
+        Ok(Series::with_errors(w, errors))
+    }
+
+    pub fn powi(&self, n: u32) -> Series {
+        let mut result = Series::constant(1.0, self.terms());
+        let mut base = self.clone();
+        let mut n = n;
+        while n > 0 {
+            if n & 1 == 1 {
+                result = &result * &base;
+            }
+            base = &base * &base;
+            n >>= 1;
+        }
+        result
+    }
+
+    pub fn pow(&self, exponent: &Series) -> Result<Series, String> {
+        if exponent.coeffs.iter().skip(1).all(|c| *c == 0.0) {
+            return self.powf(exponent.value());
+        }
+        Ok((exponent * &self.ln()?).exp())
+    }
+
+    // sin and cos together, from s' = u' c and c' = -u' s.
+    pub fn sin_cos(&self) -> (Series, Series) {
+        self.trig_pair(-1.0)
+    }
+
+    pub fn sinh_cosh(&self) -> (Series, Series) {
+        self.trig_pair(1.0)
+    }
+
+    fn trig_pair(&self, sign: f64) -> (Series, Series) {
+        let n = self.terms();
+        let (u, u_errors) = (&self.coeffs, &self.errors);
+        let mut s = vec![0.0; n];
+        let mut c = vec![0.0; n];
+        let mut s_errors = vec![0.0; n];
+        let mut c_errors = vec![0.0; n];
+        if sign < 0.0 {
+            s[0] = u[0].sin();
+            c[0] = u[0].cos();
+        } else {
+            s[0] = u[0].sinh();
+            c[0] = u[0].cosh();
+        }
+        s_errors[0] = c[0].abs() * u_errors[0] + rounding(s[0]);
+        c_errors[0] = s[0].abs() * u_errors[0] + rounding(c[0]);
+        for k in 1..n {
+            let (mut ss, mut cs) = (0.0, 0.0);
+            let (mut s_size, mut c_size) = (0.0, 0.0);
+            let (mut s_propagated, mut c_propagated) = (0.0, 0.0);
+            for j in 1..k + 1 {
+                let ju = j as f64 * u[j];
+                let ju_error = j as f64 * u_errors[j];
+                ss += ju * c[k - j];
+                cs += ju * s[k - j];
+                s_size += (ju * c[k - j]).abs();
+                c_size += (ju * s[k - j]).abs();
+                s_propagated += ju_error * c[k - j].abs() + ju.abs() * c_errors[k - j];
+                c_propagated += ju_error * s[k - j].abs() + ju.abs() * s_errors[k - j];
+            }
+            s[k] = ss / k as f64;
+            c[k] = sign * cs / k as f64;
+            s_errors[k] = (s_propagated + (k + 2) as f64 * rounding(s_size)) / k as f64;
+            c_errors[k] = (c_propagated + (k + 2) as f64 * rounding(c_size)) / k as f64;
+        }
+        (Series::with_errors(s, s_errors), Series::with_errors(c, c_errors))
+    }
+
+    // f(u) from f(u0) and f'(u) as the integral of f'(u) u'.
+    fn with_derivative(&self, f0: f64, df: Series) -> Series {
+        let slope = df.value().abs();
+        let mut result = (&df.truncate(self.terms() - 1) * &self.derivative())
+            .integral(f0)
+            .truncate(self.terms());
+        result.errors[0] += slope * self.errors[0];
+        result
+    }
+
+    // The sign of the series near 0, which must not change there.
+    fn sign(&self) -> Result<f64, String> {
+        match self.valuation() {
+            Some(v) if v % 2 == 0 => Ok(self.coeffs[v].signum()),
+            _ => Err(NOT_ANALYTIC.to_owned()),
+        }
+    }
+
+    pub fn apply(&self, f: enums::Function) -> Result<Series, String> {
+        let n = self.terms();
+        let u0 = self.value();
+        let one = Series::constant(1.0, n);
+        let square = self * self;
+        // 1 - u^2, u^2 - 1 and 1 + u^2 for the inverse functions.
+        let one_minus_square = &one - &square;
+        let square_minus_one = &square - &one;
+        let one_plus_square = &one + &square;
+        let checked = |value: f64| {
+            if value.is_finite() { Ok(value) } else { Err(NOT_ANALYTIC.to_owned()) }
+        };
+        Ok(match f {
+            enums::Function::Abs => self.scale(self.sign()?),
+            enums::Function::Exp => self.exp(),
+            enums::Function::Sqrt => self.powf(0.5)?,
+            enums::Function::Ln => self.ln()?,
+            enums::Function::Log => self.ln()?.scale(1.0 / 10f64.ln()),
+            enums::Function::Recip => self.recip()?,
+            enums::Function::Sin => self.sin_cos().0,
+            enums::Function::Cos => self.sin_cos().1,
+            enums::Function::Tan => {
+                let (s, c) = self.sin_cos();
+                s.checked_div(&c)?
+            }
+            enums::Function::Csc => self.sin_cos().0.recip()?,
+            enums::Function::Sec => self.sin_cos().1.recip()?,
+            enums::Function::Cot => {
+                let (s, c) = self.sin_cos();
+                c.checked_div(&s)?
+            }
+            enums::Function::Sinh => self.sinh_cosh().0,
+            enums::Function::Cosh => self.sinh_cosh().1,
+            enums::Function::Tanh => {
+                let (s, c) = self.sinh_cosh();
+                s.checked_div(&c)?
+            }
+            enums::Function::Csch => self.sinh_cosh().0.recip()?,
+            enums::Function::Sech => self.sinh_cosh().1.recip()?,
+            enums::Function::Coth => {
+                let (s, c) = self.sinh_cosh();
+                c.checked_div(&s)?
+            }
+            enums::Function::Asin => {
+                self.with_derivative(checked(u0.asin())?, one_minus_square.powf(-0.5)?)
+            }
+            enums::Function::Acos => {
+                self.with_derivative(checked(u0.acos())?,
+                                     one_minus_square.powf(-0.5)?.scale(-1.0))
+            }
+            enums::Function::Atan => self.with_derivative(u0.atan(), one_plus_square.recip()?),
+            enums::Function::Acot => {
+                self.with_derivative(checked(u0.recip().atan())?,
+                                     one_plus_square.recip()?.scale(-1.0))
+            }
+            enums::Function::Asinh => {
+                self.with_derivative(u0.asinh(), one_plus_square.powf(-0.5)?)
+            }
+            enums::Function::Acosh => {
+                self.with_derivative(checked(u0.acosh())?, square_minus_one.powf(-0.5)?)
+            }
+            enums::Function::Atanh => {
+                self.with_derivative(checked(u0.atanh())?, one_minus_square.recip()?)
+            }
+            enums::Function::Acoth => {
+                self.with_derivative(checked(u0.recip().atanh())?,
+                                     one_minus_square.recip()?)
+            }
+            // d/du asec(u) == 1/(|u| sqrt(u^2 - 1)), acsc is its negative.
+            enums::Function::Asec | enums::Function::Acsc => {
+                let root = square_minus_one.powf(0.5)?.scale(self.sign()?);
+                let derivative = (self * &root).recip()?;
+                if f == enums::Function::Asec {
+                    self.with_derivative(checked(u0.recip().acos())?, derivative)
+                } else {
+                    self.with_derivative(checked(u0.recip().asin())?,
+                                         derivative.scale(-1.0))
+                }
+            }
+            // d/du acsch(u) == -1/(|u| sqrt(1 + u^2))
+            enums::Function::Acsch => {
+                let root = one_plus_square.powf(0.5)?.scale(self.sign()?);
+                self.with_derivative(checked(u0.recip().asinh())?,
+                                     (self * &root).recip()?.scale(-1.0))
+            }
+            // d/du asech(u) == -1/(u sqrt(1 - u^2))
+            enums::Function::Asech => {
+                let root = one_minus_square.powf(0.5)?;
+                self.with_derivative(checked(u0.recip().acosh())?,
+                                     (self * &root).recip()?.scale(-1.0))
+            }
+            enums::Function::Max | enums::Function::LogBase => {
+                return Err(format!("{:?} takes two arguments", f))
+            }
+        })
+    }
+
+    pub fn apply2(&self, f: enums::Function, other: &Series) -> Result<Series, String> {
+        match f {
+            // The larger one near 0, comparing term by term when they touch.
+            enums::Function::Max => {
+                let difference = self - other;
+                match difference.valuation() {
+                    Some(v) if difference.coeffs[v] < 0.0 => Ok(other.clone()),
+                    _ => Ok(self.clone()),
+                }
+            }
+            enums::Function::LogBase => self.ln()?.checked_div(&other.ln()?),
+            _ => self.apply(f),
+        }
+    }
+
+    // a % b where trunc(a/b) is constant near 0.
+    pub fn rem(&self, other: &Series) -> Result<Series, String> {
+        let quotient = (self.value() / other.value()).trunc();
+        if !quotient.is_finite() {
+            return Err("Remainder by a series that vanishes".to_owned());
+        }
+        Ok(self - &other.scale(quotient))
+    }
+
+    // self(inner(t)) for self expanded around inner(0), by Horner's rule in
+    // inner(t) - inner(0).
+    pub fn compose(&self, inner: &Series) -> Series {
+        let n = self.terms().min(inner.terms());
+        let delta = inner.shift(-inner.value()).truncate(n);
+        let mut result = Series::constant(0.0, n);
+        for k in (0..n).rev() {
+            result = (&result * &delta).shift(self.coeffs[k]);
+            result.errors[0] += self.errors[k];
+        }
+        result
+    }
+}
+
+fn zip_with(a: &Series, b: &Series, f: &dyn Fn(f64, f64) -> f64) -> Series {
+    let coeffs: Vec<f64> = a.coeffs.iter().zip(&b.coeffs).map(|(x, y)| f(*x, *y)).collect();
+    let errors = (0..coeffs.len())
+        .map(|k| a.errors[k] + b.errors[k] + rounding(coeffs[k]))
+        .collect();
+    Series::with_errors(coeffs, errors)
+}
+
+impl Add for &Series {
+    type Output = Series;
+    fn add(self, other: &Series) -> Series {
+        zip_with(self, other, &|x, y| x + y)
+    }
+}
+
+impl Sub for &Series {
+    type Output = Series;
+    fn sub(self, other: &Series) -> Series {
+        zip_with(self, other, &|x, y| x - y)
+    }
+}
+
+impl Mul for &Series {
+    type Output = Series;
+    fn mul(self, other: &Series) -> Series {
+        let n = self.terms().min(other.terms());
+        let (a, b) = (&self.coeffs, &other.coeffs);
+        let coeffs = (0..n).map(|k| (0..k + 1).map(|j| a[j] * b[k - j]).sum()).collect();
+        let errors = (0..n)
+            .map(|k| {
+                (0..k + 1)
+                    .map(|j| {
+                        self.errors[j] * b[k - j].abs() + a[j].abs() * other.errors[k - j] +
+                        (k + 1) as f64 * rounding(a[j] * b[k - j])
+                    })
+                    .sum()
+            })
+            .collect();
+        Series::with_errors(coeffs, errors)
+    }
+}
+
+impl Neg for &Series {
+    type Output = Series;
+    fn neg(self) -> Series {
+        self.scale(-1.0)
+    }
+}
+
+// Expansion of `expr` in powers of (var - a) with `terms` coefficients, or
+// fewer when removable singularities ate some of them. Other variables take
+// their values from `values`.
+pub fn expand(expr: &Expression,
+              var: &str,
+              a: f64,
+              terms: usize,
+              values: &HashMap<String, f64>)
+              -> Result<Series, String> {
+    let mut stack: Vec<Series> = Vec::with_capacity(expr.len() / 2);
+    // Where the sub-expression of each stack entry starts in `expr`, to name
+    // it in errors.
+    let mut starts: Vec<usize> = Vec::with_capacity(expr.len() / 2);
+    for (i, token) in expr.iter().enumerate() {
+        let series = match *token {
+            enums::Token::Literal(ref x) => {
+                starts.push(i);
+                match x.parse::<f64>() {
+                    Ok(value) => Ok(Series::constant(value, terms)),
+                    Err(_) => return Err(format!("Invalid numeric literal: {}", x)),
+                }
+            }
+            enums::Token::Const(ref c) => {
+                starts.push(i);
+                Ok(Series::constant(eval::eval_constant(c), terms))
+            }
+            enums::Token::Var(ref x) => {
+                starts.push(i);
+                if x == var {
+                    Ok(Series::variable(a, terms))
+                } else {
+                    Ok(Series::constant(*values.get(x).unwrap_or(&0.0), terms))
+                }
+            }
+            enums::Token::Op(enums::Operator::Negate) => Ok(-&stack.pop().unwrap()),
+            enums::Token::Op(op) => {
+                starts.pop();
+                let b = stack.pop().unwrap();
+                let a = stack.pop().unwrap();
+                match op {
+                    enums::Operator::Add => Ok(&a + &b),
+                    enums::Operator::Sub => Ok(&a - &b),
+                    enums::Operator::Mul => Ok(&a * &b),
+                    enums::Operator::Div => a.checked_div(&b),
+                    enums::Operator::Mod => a.rem(&b),
+                    enums::Operator::Pow => a.pow(&b),
+                    enums::Operator::Negate => Ok(-&b),
+                }
+            }
+            enums::Token::Func(f @ enums::Function::Max) |
+            enums::Token::Func(f @ enums::Function::LogBase) => {
+                starts.pop();
+                let b = stack.pop().unwrap();
+                let a = stack.pop().unwrap();
+                a.apply2(f, &b)
+            }
+            enums::Token::Func(f) => stack.pop().unwrap().apply(f),
+            _ => return Err(format!("Unexpected token {:?}", token)),
+        };
+        let series = series.map_err(|e| {
+            if e == NOT_ANALYTIC {
+                let start = *starts.last().unwrap_or(&i);
+                let sub = Expression::new(expr.get_tokens()[start..i + 1].to_vec());
+                format!("{} is not analytic at {} = {}", sub, var, a)
+            } else {
+                e
+            }
+        })?;
+        if !series.value().is_finite() {
+            return Err(format!("The expression is undefined at {} = {}", var, a));
+        }
+        stack.push(series);
+    }
+    stack.pop().ok_or_else(|| "Empty expression".to_owned())
+}
+
+// Like expand, but retries with extra terms until `terms` of them survive
+// the removable singularities.
+pub fn expand_exact(expr: &Expression,
+                    var: &str,
+                    a: f64,
+                    terms: usize,
+                    values: &HashMap<String, f64>)
+                    -> Result<Series, String> {
+    let mut padding = 8;
+    loop {
+        let series = expand(expr, var, a, terms + padding, values)?;
+        if series.terms() >= terms {
+            return Ok(series.truncate(terms));
+        }
+        if padding > 64 {
+            return Err(format!("Could not recover {} terms of the expansion", terms));
+        }
+        padding *= 2;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use expression::parse::parse;
+
+    fn coefficients(expr: &str, a: f64, terms: usize) -> Vec<f64> {
+        expand_exact(&parse(expr), "x", a, terms, &HashMap::new()).unwrap().chop().coeffs().to_vec()
+    }
+
+    #[test]
+    fn degree_sixty_keeps_every_coefficient() {
+        let exp = coefficients("exp(x)", 0.0, 61);
+        let sin = coefficients("sin(x)", 0.0, 61);
+        let cos = coefficients("cos(x)", 0.0, 61);
+        let mut factorial = 1.0;
+        for k in 0..61 {
+            if k > 0 {
+                factorial *= k as f64;
+            }
+            let exact = 1.0 / factorial;
+            assert!((exp[k] - exact).abs() < 1e-13 * exact, "exp at {}", k);
+            let (odd, even) = if k % 2 == 1 { (sin[k], cos[k]) } else { (cos[k], sin[k]) };
+            let sign = if k % 4 < 2 { 1.0 } else { -1.0 };
+            assert!((odd - sign * exact).abs() < 1e-13 * exact, "sin or cos at {}", k);
+            assert_eq!(even, 0.0);
+        }
+    }
+
+    #[test]
+    fn rounding_noise_is_chopped() {
+        let one = coefficients("sin(x)^2+cos(x)^2", 0.0, 31);
+        assert_eq!(one[0], 1.0);
+        assert!(one[1..].iter().all(|c| *c == 0.0));
+        // x/(exp(x) - 1) has the Bernoulli numbers, zero at odd k > 1.
+        let bernoulli = coefficients("x/(exp(x)-1)", 0.0, 13);
+        let b12 = -691.0 / 1307674368000.0;
+        assert!((bernoulli[12] - b12).abs() < 1e-12 * b12.abs());
+        for k in (3..13).step_by(2) {
+            assert_eq!(bernoulli[k], 0.0);
+        }
+    }
+
+    #[test]
+    fn errors_name_the_sub_expression() {
+        let error = |expr: &str, a: f64| expand(&parse(expr), "x", a, 4, &HashMap::new()).unwrap_err();
+        assert_eq!(error("sqrt(x)", 0.0), "sqrt(x) is not analytic at x = 0");
+        assert_eq!(error("2*ln(x-1)", 1.0), "ln(x - 1) is not analytic at x = 1");
+        assert_eq!(error("abs(x)+1", 0.0), "abs(x) is not analytic at x = 0");
+        assert!(expand(&parse("abs(x^2)"), "x", 0.0, 4, &HashMap::new()).is_ok());
+    }
+}
@@ -0,0 +1,712 @@
+use std::cell::Cell;
+use std::f64;
+
+// Adaptive Gauss-Kronrod quadrature. Each interval is integrated with the
+// 15 point Kronrod rule and the embedded 7 point Gauss rule, their difference
+// being the error estimate, and the interval with the largest error is
+// bisected until the total meets the tolerance.
+#[derive(Debug, Clone, Copy)]
+pub struct Quadrature {
+    pub value: f64,
+    pub error: f64,
+    pub evaluations: usize,
+}
+
+const MAX_INTERVALS: usize = 2000;
+
+// Nodes on [0, 1) of the Kronrod rule, largest first, and their weights.
+// Every other node from the second on is also a Gauss node.
+const KRONROD_NODES: [f64; 8] = [0.9914553711208126,
+                                 0.9491079123427585,
+                                 0.8648644233597691,
+                                 0.7415311855993945,
+                                 0.5860872354676911,
+                                 0.4058451513773972,
+                                 0.20778495500789848,
+                                 0.0];
+const KRONROD_WEIGHTS: [f64; 8] = [0.022935322010529224,
+                                   0.06309209262997856,
+                                   0.10479001032225019,
+                                   0.14065325971552592,
+                                   0.1690047266392679,
+                                   0.19035057806478542,
+                                   0.20443294007529889,
+                                   0.20948214108472782];
+const GAUSS_WEIGHTS: [f64; 4] = [0.1294849661688697,
+                                 0.27970539148927664,
+                                 0.3818300505051189,
+                                 0.4179591836734694];
+
+#[derive(Debug, Clone, Copy)]
+struct Interval {
+    a: f64,
+    b: f64,
+    value: f64,
+    error: f64,
+}
+
+// Err holds a point where f is not finite.
+fn kronrod<F: FnMut(f64) -> f64 + ?Sized>(f: &mut F, a: f64, b: f64) -> Result<Interval, f64> {
+    let center = 0.5 * (a + b);
+    let half = 0.5 * (b - a);
+    let mut kronrod = 0.0;
+    let mut gauss = 0.0;
+    for (i, (node, weight)) in KRONROD_NODES.iter().zip(&KRONROD_WEIGHTS).enumerate() {
+        let points = [center - half * node, center + half * node];
+        let count = if *node == 0.0 { 1 } else { 2 };
+        for x in &points[..count] {
+            let y = f(*x);
+            if !y.is_finite() {
+                return Err(*x);
+            }
+            kronrod += weight * y;
+            if i % 2 == 1 {
+                gauss += GAUSS_WEIGHTS[i / 2] * y;
+            }
+        }
+    }
+    Ok(Interval {
+        a,
+        b,
+        value: kronrod * half,
+        error: ((kronrod - gauss) * half).abs(),
+    })
+}
+
+// Integral of f over [a, b] to within `tolerance` relative to the value, or
+// absolute when the value is below 1. The estimate is returned even when the
+// tolerance could not be met; compare `error` to decide.
+pub fn integrate<F: FnMut(f64) -> f64>(mut f: F,
+                                       a: f64,
+                                       b: f64,
+                                       tolerance: f64)
+                                       -> Result<Quadrature, String> {
+    if !a.is_finite() || !b.is_finite() {
+        return Err(format!("The interval [{}, {}] must be finite", a, b));
+    }
+    match adaptive(&mut f, a, b, tolerance) {
+        Outcome::Converged(result) |
+        Outcome::Stalled(result, _) => Ok(result),
+        Outcome::Singular(x) => Err(format!("The integrand is not finite at {}", x)),
+    }
+}
+
+enum Outcome {
+    Converged(Quadrature),
+    // The best estimate and where the bisection got stuck.
+    Stalled(Quadrature, f64),
+    Singular(f64),
+}
+
+fn adaptive(f: &mut dyn FnMut(f64) -> f64, a: f64, b: f64, tolerance: f64) -> Outcome {
+    if a == b {
+        return Outcome::Converged(Quadrature {
+            value: 0.0,
+            error: 0.0,
+            evaluations: 0,
+        });
+    }
+    if a > b {
+        return match adaptive(f, b, a, tolerance) {
+            Outcome::Converged(result) => {
+                Outcome::Converged(Quadrature { value: -result.value, ..result })
+            }
+            Outcome::Stalled(result, x) => {
+                Outcome::Stalled(Quadrature { value: -result.value, ..result }, x)
+            }
+            singular => singular,
+        };
+    }
+    let mut intervals = match kronrod(f, a, b) {
+        Ok(interval) => vec![interval],
+        Err(x) => return Outcome::Singular(x),
+    };
+    let mut evaluations = 15;
+    loop {
+        let value: f64 = intervals.iter().map(|i| i.value).sum();
+        let error: f64 = intervals.iter().map(|i| i.error).sum();
+        let (worst, _) = intervals.iter()
+            .enumerate()
+            .max_by(|x, y| x.1.error.partial_cmp(&y.1.error).unwrap())
+            .unwrap();
+        let interval = intervals[worst];
+        let result = Quadrature {
+            value,
+            error,
+            evaluations,
+        };
+        if error <= tolerance * value.abs().max(1.0) {
+            return Outcome::Converged(result);
+        }
+        let too_narrow = interval.b - interval.a <= 1e-12 * (b - a);
+        if intervals.len() >= MAX_INTERVALS || too_narrow {
+            return Outcome::Stalled(result, 0.5 * (interval.a + interval.b));
+        }
+        let middle = 0.5 * (interval.a + interval.b);
+        match (kronrod(f, interval.a, middle), kronrod(f, middle, interval.b)) {
+            (Ok(left), Ok(right)) => {
+                intervals[worst] = left;
+                intervals.push(right);
+            }
+            (Err(x), _) | (_, Err(x)) => return Outcome::Singular(x),
+        }
+        evaluations += 30;
+    }
+}
+
+// Iterated integral over a_i(x_1..x_i-1) <= x_i <= b_i(x_1..x_i-1) for
+// i = 1..dimensions, where `bounds(i, outer)` gives the limits of x_i at the
+// outer coordinates. Inner integrals are done to a tenth of the tolerance,
+// and the error estimate adds the outer one to the width of the outer
+// interval times the largest inner one.
+pub fn integrate_nested<F, B>(mut f: F,
+                              mut bounds: B,
+                              dimensions: usize,
+                              tolerance: f64)
+                              -> Result<Quadrature, String>
+    where F: FnMut(&[f64]) -> f64,
+          B: FnMut(usize, &[f64]) -> (f64, f64)
+{
+    let mut point = vec![0.0; dimensions];
+    nested(&mut f, &mut bounds, &mut point, 0, tolerance)
+}
+
+fn nested<F, B>(f: &mut F,
+                bounds: &mut B,
+                point: &mut [f64],
+                level: usize,
+                tolerance: f64)
+                -> Result<Quadrature, String>
+    where F: FnMut(&[f64]) -> f64,
+          B: FnMut(usize, &[f64]) -> (f64, f64)
+{
+    let (a, b) = bounds(level, &point[..level]);
+    if level + 1 == point.len() {
+        return integrate(|x| {
+                             point[level] = x;
+                             f(point)
+                         },
+                         a,
+                         b,
+                         tolerance);
+    }
+    let mut inner_error = 0.0f64;
+    let mut evaluations = 0;
+    let mut failure = None;
+    let outer = integrate(|x| {
+                              point[level] = x;
+                              match nested(f, bounds, point, level + 1, 0.1 * tolerance) {
+                                  Ok(inner) => {
+                                      inner_error = inner_error.max(inner.error);
+                                      evaluations += inner.evaluations;
+                                      inner.value
+                                  }
+                                  Err(message) => {
+                                      failure.get_or_insert(message);
+                                      f64::NAN
+                                  }
+                              }
+                          },
+                          a,
+                          b,
+                          tolerance);
+    if let Some(message) = failure {
+        return Err(message);
+    }
+    let outer = outer?;
+    Ok(Quadrature {
+        value: outer.value,
+        error: outer.error + (b - a).abs() * inner_error,
+        evaluations,
+    })
+}
+
+// Integral over an interval that may be infinite or contain integrable
+// singularities. Infinite ends are mapped onto a finite interval. Where the
+// adaptive rule fails, at a point where the integrand is not finite or where
+// the bisection gets stuck, the interval is split there and the pieces with
+// a singular end are done by tanh-sinh quadrature, whose nodes cluster
+// doubly exponentially at the ends. With `principal` the singular points are
+// excluded symmetrically, giving the Cauchy principal value, and an integral
+// over the whole line is taken as the limit over [-R, R]. An integrand that
+// keeps changing sign towards an infinite end, such as sin(x)/x, is instead
+// integrated between its zeros and the alternating series of pieces summed.
+#[derive(Debug, Clone)]
+pub struct Improper {
+    pub quadrature: Quadrature,
+    // The interior and end points where the integrand is singular.
+    pub singularities: Vec<f64>,
+}
+
+const MAX_SINGULARITIES: usize = 16;
+const TANH_SINH_LEVELS: usize = 8;
+// Beyond this the nodes are closer to the ends than any double resolves.
+const TANH_SINH_EXTENT: f64 = 6.0;
+// An integrable singularity leaves the outermost terms of the tanh-sinh sum
+// negligible next to the largest one; a divergent one does not.
+const DIVERGENT_TAIL: f64 = 1e-4;
+// Samples per window when looking for sign changes towards infinity, and
+// the numbers of them that make an integrand oscillate at a resolved rate.
+const OSCILLATION_SAMPLES: usize = 1000;
+const MIN_SIGN_CHANGES: usize = 8;
+const MAX_SIGN_CHANGES: usize = 250;
+// Pieces between zeros summed before giving up, and the partial sums the
+// Euler transform averages.
+const MAX_PIECES: usize = 1024;
+const EULER_SUMS: usize = 32;
+
+pub fn integrate_improper<F: FnMut(f64) -> f64>(mut f: F,
+                                                a: f64,
+                                                b: f64,
+                                                tolerance: f64,
+                                                principal: bool)
+                                                -> Result<Improper, String> {
+    if a.is_nan() || b.is_nan() {
+        return Err("The bounds must be numbers".to_owned());
+    }
+    if a > b {
+        let mut result = integrate_improper(f, b, a, tolerance, principal)?;
+        result.quadrature.value = -result.quadrature.value;
+        return Ok(result);
+    }
+    let mut result = Improper {
+        quadrature: Quadrature {
+            value: 0.0,
+            error: 0.0,
+            evaluations: 0,
+        },
+        singularities: Vec::new(),
+    };
+    if a == b {
+        return Ok(result);
+    }
+    match (a.is_finite(), b.is_finite()) {
+        (true, true) => segment(&mut f, a, b, tolerance, principal, &|x| x, &mut result)?,
+        (true, false) => half_line(&mut f, a, 1.0, tolerance, principal, &mut result)?,
+        (false, true) => half_line(&mut f, b, -1.0, tolerance, principal, &mut result)?,
+        (false, false) => {
+            // x = t / (1 - t^2) maps (-1, 1) onto the line.
+            let jacobian = |t: f64| (1.0 + t * t) / ((1.0 - t * t) * (1.0 - t * t));
+            let map = |t: f64| t / (1.0 - t * t);
+            let oscillates = zero_spacing(&mut |s| f(s)).is_some() ||
+                             zero_spacing(&mut |s| f(-s)).is_some();
+            if oscillates && !principal {
+                half_line(&mut f, 0.0, -1.0, tolerance, principal, &mut result)?;
+                half_line(&mut f, 0.0, 1.0, tolerance, principal, &mut result)?
+            } else if oscillates {
+                let mut paired = |s: f64| f(s) + f(-s);
+                half_line(&mut paired, 0.0, 1.0, tolerance, principal, &mut result)?
+            } else if principal {
+                let mut g = |t: f64| (f(map(t)) + f(-map(t))) * jacobian(t);
+                segment(&mut g, 0.0, 1.0, tolerance, principal, &map, &mut result)?
+            } else {
+                // Each half on its own, so that opposite infinities do not
+                // cancel.
+                let mut g = |t: f64| f(map(t)) * jacobian(t);
+                segment(&mut g, -1.0, 0.0, tolerance, principal, &map, &mut result)?;
+                segment(&mut g, 0.0, 1.0, tolerance, principal, &map, &mut result)?
+            }
+        }
+    }
+    result.singularities.sort_by(|x, y| x.partial_cmp(y).unwrap());
+    result.singularities.dedup();
+    Ok(result)
+}
+
+// Adds the integral of f over [a, inf) when `direction` is 1, or over
+// (-inf, a] when it is -1, to `result`.
+fn half_line(f: &mut dyn FnMut(f64) -> f64,
+             a: f64,
+             direction: f64,
+             tolerance: f64,
+             principal: bool,
+             result: &mut Improper)
+             -> Result<(), String> {
+    let mut g = |s: f64| f(a + direction * s);
+    if let Some(spacing) = zero_spacing(&mut g) {
+        return oscillatory(&mut g, spacing, tolerance, principal, &|s| a + direction * s, result);
+    }
+    let mut mapped = |t: f64| g(t / (1.0 - t)) / ((1.0 - t) * (1.0 - t));
+    segment(&mut mapped,
+            0.0,
+            1.0,
+            tolerance,
+            principal,
+            &|t| a + direction * t / (1.0 - t),
+            result)
+}
+
+// The typical distance between the sign changes of f far out on [0, inf),
+// None unless it keeps changing sign at a rate that samples over windows
+// [w, 2w] resolve, with sign changes no more than a few times as far apart
+// as each other.
+fn zero_spacing(f: &mut dyn FnMut(f64) -> f64) -> Option<f64> {
+    for &w in &[1.0, 10.0, 100.0, 1000.0] {
+        let mut changes: Vec<f64> = Vec::new();
+        let mut previous = 0.0;
+        for i in 0..OSCILLATION_SAMPLES + 1 {
+            let x = w + w * i as f64 / OSCILLATION_SAMPLES as f64;
+            let y = f(x);
+            if !y.is_finite() || y == 0.0 {
+                continue;
+            }
+            if previous * y < 0.0 {
+                changes.push(x);
+            }
+            previous = y;
+        }
+        if changes.len() > MAX_SIGN_CHANGES {
+            return None;
+        }
+        if changes.len() < MIN_SIGN_CHANGES {
+            continue;
+        }
+        let gaps: Vec<f64> = changes.windows(2).map(|c| c[1] - c[0]).collect();
+        let shortest = gaps.iter().cloned().fold(f64::INFINITY, f64::min);
+        let longest = gaps.iter().cloned().fold(0.0, f64::max);
+        if longest > 4.0 * shortest {
+            return None;
+        }
+        return Some((changes[changes.len() - 1] - changes[0]) / gaps.len() as f64);
+    }
+    None
+}
+
+// The integral of f over [0, inf) as the integral up to its first zero plus
+// the pieces between consecutive zeros, which alternate in sign. Their
+// partial sums are accelerated by repeated averaging, the Euler transform.
+// That also sums pieces that do not shrink, as for sin(x), so those are
+// checked for first. `map` takes points back to the original variable.
+fn oscillatory(f: &mut dyn FnMut(f64) -> f64,
+               spacing: f64,
+               tolerance: f64,
+               principal: bool,
+               map: &dyn Fn(f64) -> f64,
+               result: &mut Improper)
+               -> Result<(), String> {
+    let step = spacing / 16.0;
+    // The next zero after x, bracketed on a grid of `step` and bisected.
+    // Zeros may be sparser near 0 than far out, as for sin(x^2), and a
+    // sign change where f grows instead is a pole, which is passed over.
+    let next_zero = |f: &mut dyn FnMut(f64) -> f64, x: f64| -> Result<f64, String> {
+        let mut low = x + step;
+        let mut y_low = f(low);
+        for _ in 0..4096 {
+            let high = low + step;
+            let y_high = f(high);
+            if y_low * y_high < 0.0 {
+                let (mut a, mut b) = (low, high);
+                for _ in 0..100 {
+                    let middle = 0.5 * (a + b);
+                    if middle <= a || middle >= b {
+                        break;
+                    }
+                    if f(middle) * y_low > 0.0 {
+                        a = middle;
+                    } else {
+                        b = middle;
+                    }
+                }
+                if f(a).abs() <= y_low.abs().max(y_high.abs()) {
+                    return Ok(a);
+                }
+            }
+            low = high;
+            y_low = y_high;
+        }
+        Err(format!("The integrand stopped oscillating near {}", map(low)))
+    };
+    let mut zero = next_zero(f, 0.0)?;
+    segment(f, 0.0, zero, tolerance, principal, map, result)?;
+    let mut pieces: Vec<f64> = Vec::new();
+    let mut partial: Vec<f64> = Vec::new();
+    let mut error = 0.0;
+    let mut previous = f64::NAN;
+    while pieces.len() < MAX_PIECES {
+        let end = next_zero(f, zero)?;
+        let mut piece = Improper {
+            quadrature: Quadrature {
+                value: 0.0,
+                error: 0.0,
+                evaluations: 0,
+            },
+            singularities: Vec::new(),
+        };
+        segment(f, zero, end, 0.1 * tolerance, principal, map, &mut piece)?;
+        result.quadrature.evaluations += piece.quadrature.evaluations;
+        result.singularities.extend(piece.singularities);
+        error += piece.quadrature.error;
+        zero = end;
+        pieces.push(piece.quadrature.value);
+        partial.push(partial.last().unwrap_or(&0.0) + piece.quadrature.value);
+        let n = pieces.len();
+        if n < EULER_SUMS || !n.is_multiple_of(8) {
+            continue;
+        }
+        if pieces[n - 1].abs() >= (1.0 - 1e-6) * pieces[n / 4].abs() {
+            return Err(format!("The integral diverges, the integrand oscillates without dying out \
+                                towards {}",
+                               if map(1.0) > map(0.0) { "inf" } else { "-inf" }));
+        }
+        let estimate = euler(&partial[n - EULER_SUMS..]);
+        let change = (estimate - previous).abs();
+        previous = estimate;
+        if change <= tolerance * estimate.abs().max(1.0) {
+            accumulate(result,
+                       Quadrature {
+                           value: estimate,
+                           error: error + change,
+                           evaluations: 0,
+                       });
+            return Ok(());
+        }
+    }
+    Err(format!("The oscillating integral did not converge after {} periods", MAX_PIECES / 2))
+}
+
+// The limit of an alternating series from its last partial sums by
+// averaging neighbours until one value is left.
+fn euler(partial: &[f64]) -> f64 {
+    let mut sums = partial.to_vec();
+    while sums.len() > 1 {
+        sums = sums.windows(2).map(|w| 0.5 * (w[0] + w[1])).collect();
+    }
+    sums[0]
+}
+
+// Adds the integral of f over [a, b] to `result`. `map` takes points of
+// [a, b] back to the original variable for reporting.
+fn segment(f: &mut dyn FnMut(f64) -> f64,
+           a: f64,
+           b: f64,
+           tolerance: f64,
+           principal: bool,
+           map: &dyn Fn(f64) -> f64,
+           result: &mut Improper)
+           -> Result<(), String> {
+    let (partial, x) = match adaptive(f, a, b, tolerance) {
+        Outcome::Converged(quadrature) => {
+            accumulate(result, quadrature);
+            return Ok(());
+        }
+        Outcome::Stalled(quadrature, x) => (Some(quadrature), x),
+        Outcome::Singular(x) => (None, x),
+    };
+    if let Some(quadrature) = partial {
+        result.quadrature.evaluations += quadrature.evaluations;
+    }
+    let width = b - a;
+    let x = locate(f, x, a, b);
+    if x - a <= 1e-6 * width || b - x <= 1e-6 * width {
+        let end = if x - a < b - x { a } else { b };
+        result.singularities.push(map(end) + 0.0);
+        let quadrature = tanh_sinh(f, a, b, tolerance)
+            .ok_or_else(|| format!("The integral diverges at {}", map(end)))?;
+        accumulate(result, quadrature);
+        return Ok(());
+    }
+    if result.singularities.len() >= MAX_SINGULARITIES {
+        return Err(format!("The integral could not be shown to converge near {}", map(x)));
+    }
+    result.singularities.push(map(x) + 0.0);
+    if !principal {
+        segment(f, a, x, tolerance, principal, map, result)?;
+        return segment(f, x, b, tolerance, principal, map, result);
+    }
+    // Pair x - t with x + t for t up to the nearer end, where the singular
+    // parts cancel. The cancellation needs both points exact, so t is taken
+    // as a multiple of the spacing of doubles around the pair.
+    let reach = (x - a).min(b - x);
+    if x - reach > a {
+        segment(f, a, x - reach, tolerance, principal, map, result)?;
+    }
+    if x + reach < b {
+        segment(f, x + reach, b, tolerance, principal, map, result)?;
+    }
+    let spacing = if x == 0.0 {
+        0.0
+    } else {
+        (x.abs() + reach).log2().floor().exp2() * f64::EPSILON
+    };
+    // Which of the pair was larger last, to tell where a singularity of the
+    // paired integrand lies.
+    let right = Cell::new(true);
+    let mut paired = |t: f64| {
+        let t = if spacing > 0.0 { (t / spacing).round() * spacing } else { t };
+        if t == 0.0 {
+            return 0.0;
+        }
+        let (above, below) = (f(x + t), f(x - t));
+        right.set(above.abs() >= below.abs());
+        above + below
+    };
+    segment(&mut paired,
+            0.0,
+            reach,
+            tolerance,
+            principal,
+            &|t| map(if right.get() { x + t } else { x - t }),
+            result)
+}
+
+fn accumulate(result: &mut Improper, quadrature: Quadrature) {
+    result.quadrature.value += quadrature.value;
+    result.quadrature.error += quadrature.error;
+    result.quadrature.evaluations += quadrature.evaluations;
+}
+
+// Bisection only pins a singularity down to a short interval around x. A
+// pole, where 1/f changes sign, is found to full precision by bisecting on
+// 1/f; otherwise prefer a simple fraction nearby, since singularities are
+// usually at one.
+fn locate(f: &mut dyn FnMut(f64) -> f64, x: f64, a: f64, b: f64) -> f64 {
+    let width = b - a;
+    let (mut low, mut high) = ((x - 1e-9 * width).max(a), (x + 1e-9 * width).min(b));
+    let reciprocal = |f: &mut dyn FnMut(f64) -> f64, x: f64| 1.0 / f(x);
+    let mut x = x;
+    let g_low = reciprocal(f, low);
+    if g_low * reciprocal(f, high) < 0.0 {
+        for _ in 0..100 {
+            let middle = 0.5 * (low + high);
+            if middle <= low || middle >= high {
+                break;
+            }
+            if reciprocal(f, middle) * g_low > 0.0 {
+                low = middle;
+            } else {
+                high = middle;
+            }
+        }
+        x = 0.5 * (low + high);
+    }
+    let tolerance = 1e-10 * width.max(x.abs());
+    for denominator in 1..1000 {
+        let candidate = (x * denominator as f64).round() / denominator as f64;
+        if (candidate - x).abs() <= tolerance {
+            return candidate;
+        }
+    }
+    x
+}
+
+// Tanh-sinh quadrature: x = c + h tanh(pi/2 sinh t), summed over t with the
+// step halved each level until two levels agree. None when the terms at the
+// ends do not die out, meaning the integral diverges.
+fn tanh_sinh(f: &mut dyn FnMut(f64) -> f64, a: f64, b: f64, tolerance: f64) -> Option<Quadrature> {
+    let half = 0.5 * (b - a);
+    let mut evaluations = 0;
+    let mut sum = 0.0;
+    // The largest term, and at either end the outermost one with its t and
+    // whether nodes beyond it rounded onto the end.
+    let mut largest = 0.0f64;
+    let mut outermost = [(0.0, 0.0f64, false); 2];
+    let mut previous = f64::NAN;
+    let mut step = 1.0;
+    let mut estimate = 0.0;
+    let mut difference = f64::INFINITY;
+    for level in 0..TANH_SINH_LEVELS + 1 {
+        let mut k = if level == 0 { 0 } else { 1 };
+        while k as f64 * step <= TANH_SINH_EXTENT {
+            let t = k as f64 * step;
+            k += if level == 0 { 1 } else { 2 };
+            let u = f64::consts::FRAC_PI_2 * t.sinh();
+            // The distance of the nodes from the ends, without the
+            // cancellation in 1 - tanh(u).
+            let distance = 2.0 * half / ((2.0 * u).exp() + 1.0);
+            let weight = half * f64::consts::FRAC_PI_2 * t.cosh() / (u.cosh() * u.cosh());
+            let nodes = if t == 0.0 {
+                vec![(0, a + half)]
+            } else {
+                vec![(0, a + distance), (1, b - distance)]
+            };
+            for (end, x) in nodes {
+                if x <= a || x >= b {
+                    outermost[end].2 = true;
+                    continue;
+                }
+                let y = f(x);
+                evaluations += 1;
+                // So close to the end the integrand may overflow or be
+                // evaluated at the end itself after rounding; the terms
+                // further in show whether it diverges.
+                if !y.is_finite() {
+                    if distance > 1e-8 * half {
+                        return None;
+                    }
+                    outermost[end].2 = true;
+                    continue;
+                }
+                let term = (y * weight).abs();
+                largest = largest.max(term);
+                if t >= outermost[end].0 {
+                    outermost[end] = (t, term, outermost[end].2);
+                }
+                sum += y * weight;
+            }
+        }
+        estimate = sum * step;
+        difference = (estimate - previous).abs();
+        previous = estimate;
+        step *= 0.5;
+        if level < 3 {
+            continue;
+        }
+        if outermost.iter().any(|&(_, term, _)| term > DIVERGENT_TAIL * largest) {
+            return None;
+        }
+        if difference <= tolerance * estimate.abs().max(1.0) {
+            break;
+        }
+    }
+    // What lies closer to an end than a double resolves is left out; the
+    // outermost term bounds it.
+    let truncation: f64 = outermost.iter()
+        .filter(|&&(_, _, dropped)| dropped)
+        .map(|&(_, term, _)| term)
+        .sum();
+    Some(Quadrature {
+        value: estimate,
+        error: difference + truncation,
+        evaluations,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn improper(f: fn(f64) -> f64, a: f64, b: f64, principal: bool) -> Result<f64, String> {
+        integrate_improper(f, a, b, 1e-10, principal).map(|r| r.quadrature.value)
+    }
+
+    fn close(x: f64, expected: f64) -> bool {
+        (x - expected).abs() <= 1e-8 * expected.abs().max(1.0)
+    }
+
+    #[test]
+    fn improper_integrals() {
+        let inf = f64::INFINITY;
+        assert!(close(improper(|x| 1.0 / x.sqrt(), 0.0, 1.0, false).unwrap(), 2.0));
+        assert!(close(improper(|x| x.ln(), 0.0, 1.0, false).unwrap(), -1.0));
+        assert!(close(improper(|x| 1.0 / (x * x), 1.0, inf, false).unwrap(), 1.0));
+        let gauss = f64::consts::PI.sqrt();
+        assert!(close(improper(|x| (-x * x).exp(), -inf, inf, false).unwrap(), gauss));
+        assert!(improper(|x| 1.0 / x, 0.0, 1.0, false).is_err());
+        assert!(close(improper(|x| 1.0 / x, -1.0, 2.0, true).unwrap(), 2f64.ln()));
+    }
+
+    #[test]
+    fn oscillatory_integrals() {
+        let inf = f64::INFINITY;
+        let half_pi = f64::consts::FRAC_PI_2;
+        assert!(close(improper(|x| x.sin() / x, 0.0, inf, false).unwrap(), half_pi));
+        assert!(close(improper(|x| x.sin() / x, -inf, inf, false).unwrap(), 2.0 * half_pi));
+        assert!(close(improper(|x| x.sin() / x.sqrt(), 0.0, inf, false).unwrap(), half_pi.sqrt()));
+        let fresnel = (f64::consts::PI / 8.0).sqrt();
+        assert!(close(improper(|x| (x * x).sin(), 0.0, inf, false).unwrap(), fresnel));
+        let expected = half_pi / 1f64.exp();
+        assert!(close(improper(|x| x.cos() / (1.0 + x * x), 0.0, inf, false).unwrap(), expected));
+        assert!(improper(|x| x.sin(), 0.0, inf, false).is_err());
+        assert!(improper(|x| x * x.sin(), 0.0, inf, false).is_err());
+    }
+}
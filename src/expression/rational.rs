@@ -0,0 +1,195 @@
+use std::fmt;
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+// Exact fractions for polynomial algebra. Overflow does not panic, it turns
+// the value into an invalid fraction (denominator 0) that propagates through
+// every later operation like NaN does for floats, so callers only have to
+// check the end result.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Rational {
+    num: i128,
+    den: i128,
+}
+
+const INVALID: Rational = Rational { num: 0, den: 0 };
+
+fn gcd(a: i128, b: i128) -> i128 {
+    let (mut a, mut b) = (a.abs(), b.abs());
+    while b != 0 {
+        let t = a % b;
+        a = b;
+        b = t;
+    }
+    a
+}
+
+impl Rational {
+    pub fn new(num: i128, den: i128) -> Self {
+        if den == 0 {
+            return INVALID;
+        }
+        let g = gcd(num, den).max(1);
+        let sign = if den < 0 { -1 } else { 1 };
+        Rational {
+            num: sign * num / g,
+            den: sign * den / g,
+        }
+    }
+
+    pub fn from_integer(n: i128) -> Self {
+        Rational { num: n, den: 1 }
+    }
+
+    pub fn zero() -> Self {
+        Rational::from_integer(0)
+    }
+
+    pub fn one() -> Self {
+        Rational::from_integer(1)
+    }
+
+    // Best continued fraction approximation with a denominator below
+    // `max_den`, None unless it matches `x` to about 12 significant digits.
+    pub fn from_f64(x: f64, max_den: i128) -> Option<Self> {
+        Rational::approximate(x, max_den, 1e-12 * x.abs().max(1.0))
+    }
+
+    // The first convergent of x within `tolerance`.
+    pub fn approximate(x: f64, max_den: i128, tolerance: f64) -> Option<Self> {
+        if !x.is_finite() || x.abs() > 1e18 {
+            return None;
+        }
+        let (mut p0, mut q0, mut p1, mut q1): (i128, i128, i128, i128) = (0, 1, 1, 0);
+        let mut rest = x;
+        for _ in 0..64 {
+            let a = rest.floor();
+            let p2 = (a as i128).checked_mul(p1)?.checked_add(p0)?;
+            let q2 = (a as i128).checked_mul(q1)?.checked_add(q0)?;
+            if q2 > max_den {
+                break;
+            }
+            p0 = p1;
+            q0 = q1;
+            p1 = p2;
+            q1 = q2;
+            if (x - p1 as f64 / q1 as f64).abs() <= tolerance {
+                return Some(Rational::new(p1, q1));
+            }
+            let frac = rest - a;
+            if frac == 0.0 {
+                break;
+            }
+            rest = frac.recip();
+        }
+        None
+    }
+
+    pub fn numer(&self) -> i128 {
+        self.num
+    }
+
+    pub fn denom(&self) -> i128 {
+        self.den
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.den != 0
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.is_valid() && self.num == 0
+    }
+
+    pub fn is_integer(&self) -> bool {
+        self.den == 1
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        if !self.is_valid() {
+            return f64::NAN;
+        }
+        self.num as f64 / self.den as f64
+    }
+
+    pub fn abs(self) -> Self {
+        Rational {
+            num: self.num.abs(),
+            den: self.den,
+        }
+    }
+
+    pub fn recip(self) -> Self {
+        Rational::new(self.den, self.num)
+    }
+
+    fn checked(num: Option<i128>, den: Option<i128>) -> Self {
+        match (num, den) {
+            (Some(num), Some(den)) => Rational::new(num, den),
+            _ => INVALID,
+        }
+    }
+}
+
+impl fmt::Display for Rational {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !self.is_valid() {
+            write!(f, "NaN")
+        } else if self.den == 1 {
+            write!(f, "{}", self.num)
+        } else {
+            write!(f, "{}/{}", self.num, self.den)
+        }
+    }
+}
+
+impl Add for Rational {
+    type Output = Rational;
+    fn add(self, other: Rational) -> Rational {
+        if !self.is_valid() || !other.is_valid() {
+            return INVALID;
+        }
+        let g = gcd(self.den, other.den);
+        let num = self.num
+            .checked_mul(other.den / g)
+            .and_then(|a| other.num.checked_mul(self.den / g).and_then(|b| a.checked_add(b)));
+        Rational::checked(num, self.den.checked_mul(other.den / g))
+    }
+}
+
+impl Sub for Rational {
+    type Output = Rational;
+    fn sub(self, other: Rational) -> Rational {
+        self + (-other)
+    }
+}
+
+impl Mul for Rational {
+    type Output = Rational;
+    fn mul(self, other: Rational) -> Rational {
+        if !self.is_valid() || !other.is_valid() {
+            return INVALID;
+        }
+        let g1 = gcd(self.num, other.den).max(1);
+        let g2 = gcd(other.num, self.den).max(1);
+        Rational::checked((self.num / g1).checked_mul(other.num / g2),
+                          (self.den / g2).checked_mul(other.den / g1))
+    }
+}
+
+impl Div for Rational {
+    type Output = Rational;
+    #[allow(clippy::suspicious_arithmetic_impl)]
+    fn div(self, other: Rational) -> Rational {
+        self * other.recip()
+    }
+}
+
+impl Neg for Rational {
+    type Output = Rational;
+    fn neg(self) -> Rational {
+        Rational {
+            num: -self.num,
+            den: self.den,
+        }
+    }
+}
@@ -0,0 +1,386 @@
+use expression;
+use expression::bytecode;
+use expression::derivative::differentiate;
+use expression::enums;
+use expression::eval::eval_postfix_expr;
+use expression::linalg;
+use expression::power_series;
+use expression::rational::Rational;
+use expression::symbolic::{self, add, div, mul, number, pow, sub};
+
+pub type Expression = expression::Expression;
+
+use std::collections::HashMap;
+
+const BOUND_SAMPLES: usize = 2000;
+// Up to this order the coefficients are derived symbolically, beyond it (or
+// when the derivatives get too large or singular) with power series.
+const SYMBOLIC_ORDER: usize = 8;
+const MAX_DERIVATIVE_SIZE: usize = 2000;
+
+// Taylor coefficients f^(k)(a)/k! for k = 0..n, kept symbolic so values such
+// as sin(1) or parameters stay exact.
+pub fn taylor_coefficients(expr: &Expression,
+                           var: &str,
+                           a: f64,
+                           n: usize,
+                           values: &HashMap<String, f64>)
+                           -> Result<Vec<Expression>, String> {
+    let point = number(a);
+    let mut at_a = values.clone();
+    at_a.insert(var.to_owned(), a);
+    let mut coefficients = Vec::with_capacity(n + 1);
+    let mut derivative = symbolic::simplify(expr);
+    let mut factorial = 1.0;
+    for k in 0..n + 1 {
+        if k > 0 {
+            derivative = differentiate(&derivative, var);
+            factorial *= k as f64;
+        }
+        if derivative.len() > MAX_DERIVATIVE_SIZE {
+            return Err(format!("The derivative of order {} is too large", k));
+        }
+        if !eval_postfix_expr(&derivative, &at_a).is_finite() {
+            return Err(format!("The derivative of order {} is undefined at {} = {}", k, var, a));
+        }
+        let value = symbolic::substitute(&derivative, var, &point);
+        coefficients.push(match symbolic::as_number(&value) {
+            Some(x) => fraction(x / factorial),
+            None => div(value, number(factorial)),
+        });
+    }
+    Ok(coefficients)
+}
+
+// The same coefficients from power series arithmetic, which also fills in
+// removable singularities such as x/(exp(x)-1) at 0.
+pub fn series_coefficients(expr: &Expression,
+                           var: &str,
+                           a: f64,
+                           n: usize,
+                           values: &HashMap<String, f64>)
+                           -> Result<Vec<Expression>, String> {
+    let series = power_series::expand_exact(expr, var, a, n + 1, values)?;
+    let coefficients = series.chop()
+        .coeffs()
+        .iter()
+        .enumerate()
+        .map(|(k, c)| snap_coefficient(*c, k).map_or_else(|| number(*c), rational))
+        .collect();
+    Ok(coefficients)
+}
+
+// The fraction p/q that x is up to rounding, for |p|*q up to about 1e12.
+// A generic real has convergents with |x - p/q| near 1/q^2, so the relative
+// tolerance of 1e-14 rejects values such as sin(1).
+pub fn snap(x: f64) -> Option<Rational> {
+    if x == 0.0 {
+        return Some(Rational::zero());
+    }
+    let max_den = (1e12 / x.abs()).sqrt().clamp(1.0, 1e15) as i128;
+    Rational::approximate(x, max_den, 1e-14 * x.abs())
+}
+
+// The k-th Taylor coefficient as a fraction, also when only k! c_k has a
+// small height as for exp, tan or ln at high orders.
+pub fn snap_coefficient(c: f64, k: usize) -> Option<Rational> {
+    if let Some(r) = snap(c) {
+        return Some(r);
+    }
+    let factorial = (1..k as i128 + 1).fold(Rational::one(), |f, i| f * Rational::from_integer(i));
+    if !factorial.is_valid() {
+        return None;
+    }
+    snap(c * factorial.to_f64()).map(|r| r / factorial).filter(|r| r.is_valid())
+}
+
+// x as p/q when it is a fraction, so 1/6 does not print as
+// 0.16666666666666666 nor 1/2 as 0.5.
+pub fn fraction(x: f64) -> Expression {
+    match snap(x) {
+        Some(r) => rational(r),
+        None => number(x),
+    }
+}
+
+fn rational(r: Rational) -> Expression {
+    if r.is_integer() {
+        return number(r.numer() as f64);
+    }
+    let mut tokens = number(r.numer().abs() as f64).get_tokens().to_vec();
+    tokens.extend_from_slice(number(r.denom() as f64).get_tokens());
+    tokens.push(enums::Token::Op(enums::Operator::Div));
+    let quotient = Expression::new(tokens);
+    if r.numer() < 0 { symbolic::neg(quotient) } else { quotient }
+}
+
+// sum c_k (x - a)^k
+pub fn polynomial(coefficients: &[Expression], var: &str, a: f64) -> Expression {
+    let shifted = sub(symbolic::variable(var), number(a));
+    let mut result = number(0.0);
+    for (k, c) in coefficients.iter().enumerate() {
+        let term = mul(c.clone(), pow(shifted.clone(), number(k as f64)));
+        result = add(result, term);
+    }
+    result
+}
+
+pub fn taylor(expr: &Expression,
+              var: &str,
+              a: f64,
+              n: usize,
+              values: &HashMap<String, f64>)
+              -> Result<Expression, String> {
+    let symbolic = if n <= SYMBOLIC_ORDER {
+        taylor_coefficients(expr, var, a, n, values).ok()
+    } else {
+        None
+    };
+    let coefficients = match symbolic {
+        Some(coefficients) => coefficients,
+        None => series_coefficients(expr, var, a, n, values)?,
+    };
+    Ok(polynomial(&coefficients, var, a))
+}
+
+// The [m/n] Pade approximant P/Q around a, with Q(a) = 1 and P - fQ
+// vanishing to order m + n. Q solves the Hankel system
+// sum_(j=1..n) q_j c_(k-j) = -c_k for k = m+1..m+n, exactly when the Taylor
+// coefficients are fractions since it is badly conditioned. When it is
+// singular (e.g. [1/1] of cos) the denominator degree is lowered until not.
+pub fn pade(expr: &Expression,
+            var: &str,
+            a: f64,
+            m: usize,
+            n: usize,
+            values: &HashMap<String, f64>)
+            -> Result<Expression, String> {
+    let series = power_series::expand_exact(expr, var, a, m + n + 1, values)?.chop();
+    let c = series.coeffs();
+    let exact: Option<Vec<Rational>> =
+        c.iter().enumerate().map(|(k, x)| snap_coefficient(*x, k)).collect();
+    for n in (0..n + 1).rev() {
+        let approximant = exact.as_ref()
+            .and_then(|exact| pade_rational(exact, m, n))
+            .or_else(|| pade_float(c, m, n));
+        if let Some((p, q)) = approximant {
+            return Ok(div(polynomial(&p, var, a), polynomial(&q, var, a)));
+        }
+    }
+    unreachable!()
+}
+
+// c_(m+i-j) for i, j = 1..n, zero below the constant term.
+fn hankel<T: Copy>(c: &[T], m: usize, n: usize, zero: T) -> Vec<Vec<T>> {
+    (1..n + 1)
+        .map(|i| (1..n + 1).map(|j| if m + i >= j { c[m + i - j] } else { zero }).collect())
+        .collect()
+}
+
+// In floating point the variable is rescaled to t = rho s first so the
+// last coefficient is about 1, which keeps the system from looking singular
+// when the coefficients decay like 1/k!.
+fn pade_float(c: &[f64], m: usize, n: usize) -> Option<(Vec<Expression>, Vec<Expression>)> {
+    let rho = match c.iter().rposition(|x| *x != 0.0) {
+        Some(k) if k > 0 => c[k].abs().powf(-1.0 / k as f64),
+        _ => 1.0,
+    };
+    let d: Vec<f64> = c.iter().enumerate().map(|(k, x)| x * rho.powi(k as i32)).collect();
+    let mut q = vec![1.0];
+    if n > 0 {
+        let rhs: Vec<f64> = (1..n + 1).map(|i| -d[m + i]).collect();
+        q.extend(linalg::solve_linear(&hankel(&d, m, n, 0.0), &rhs)?);
+    }
+    let p: Vec<f64> = (0..m + 1)
+        .map(|k| (0..n.min(k) + 1).map(|j| q[j] * d[k - j]).sum())
+        .collect();
+    // After rescaling the coefficients are comparable, so the ones that are
+    // negligible next to the largest are rounding noise.
+    let fractions = |coeffs: Vec<f64>| -> Vec<Expression> {
+        let largest = coeffs.iter().fold(0.0f64, |m, x| m.max(x.abs()));
+        coeffs.iter()
+            .enumerate()
+            .map(|(k, x)| {
+                if x.abs() < 1e-12 * largest {
+                    number(0.0)
+                } else {
+                    fraction(x / rho.powi(k as i32))
+                }
+            })
+            .collect()
+    };
+    Some((fractions(p), fractions(q)))
+}
+
+fn pade_rational(c: &[Rational], m: usize, n: usize) -> Option<(Vec<Expression>, Vec<Expression>)> {
+    let mut q = vec![Rational::one()];
+    if n > 0 {
+        let rhs: Vec<Rational> = (1..n + 1).map(|i| -c[m + i]).collect();
+        q.extend(solve_rational(hankel(c, m, n, Rational::zero()), rhs)?);
+    }
+    let p: Vec<Rational> = (0..m + 1)
+        .map(|k| (0..n.min(k) + 1).fold(Rational::zero(), |sum, j| sum + q[j] * c[k - j]))
+        .collect();
+    if p.iter().chain(&q).any(|r| !r.is_valid()) {
+        return None;
+    }
+    Some((p.into_iter().map(rational).collect(), q.into_iter().map(rational).collect()))
+}
+
+// Gaussian elimination over the rationals, None when singular or when the
+// entries overflow.
+fn solve_rational(mut a: Vec<Vec<Rational>>, mut b: Vec<Rational>) -> Option<Vec<Rational>> {
+    let n = b.len();
+    for k in 0..n {
+        let pivot = (k..n).find(|i| !a[*i][k].is_zero())?;
+        a.swap(k, pivot);
+        b.swap(k, pivot);
+        let (upper, lower) = a.split_at_mut(k + 1);
+        let pivot_row = &upper[k];
+        for (i, row) in lower.iter_mut().enumerate() {
+            let factor = row[k] / pivot_row[k];
+            for (aij, akj) in row[k..].iter_mut().zip(&pivot_row[k..]) {
+                *aij = *aij - factor * *akj;
+            }
+            b[k + 1 + i] = b[k + 1 + i] - factor * b[k];
+        }
+    }
+    let mut x = vec![Rational::zero(); n];
+    for k in (0..n).rev() {
+        let sum = (k + 1..n).fold(b[k], |sum, j| sum - a[k][j] * x[j]);
+        x[k] = sum / a[k][k];
+        if !x[k].is_valid() {
+            return None;
+        }
+    }
+    Some(x)
+}
+
+// Lagrange remainder bound max|f^(n+1)| * max|x - a|^(n+1) / (n+1)! over
+// [lo, hi], with the maximum of the derivative estimated by sampling between
+// a and the interval.
+pub fn remainder_bound(expr: &Expression,
+                       var: &str,
+                       a: f64,
+                       n: usize,
+                       lo: f64,
+                       hi: f64,
+                       values: &HashMap<String, f64>)
+                       -> Result<f64, String> {
+    if lo > hi {
+        return Err(format!("Empty interval [{}, {}]", lo, hi));
+    }
+    let mut derivative = symbolic::simplify(expr);
+    let mut factorial = 1.0;
+    for k in 1..n + 2 {
+        derivative = differentiate(&derivative, var);
+        factorial *= k as f64;
+    }
+    let mut f = bytecode::bind(&derivative, &[var.to_owned()], values)?;
+    let (start, end) = (lo.min(a), hi.max(a));
+    let mut max_derivative: f64 = 0.0;
+    for i in 0..BOUND_SAMPLES + 1 {
+        let x = start + (end - start) * i as f64 / BOUND_SAMPLES as f64;
+        let y = f.eval1(x).abs();
+        if y.is_nan() {
+            return Err(format!("The derivative of order {} is undefined at {} = {}",
+                               n + 1,
+                               var,
+                               x));
+        }
+        max_derivative = max_derivative.max(y);
+    }
+    let reach = (lo - a).abs().max((hi - a).abs());
+    Ok(max_derivative * reach.powi(n as i32 + 1) / factorial)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use expression::parse::parse;
+
+    fn expand(expr: &str, a: f64, n: usize) -> Result<String, String> {
+        taylor(&parse(expr), "x", a, n, &HashMap::new()).map(|t| t.to_string())
+    }
+
+    fn coefficients(expr: &str, a: f64, n: usize) -> Vec<f64> {
+        taylor_coefficients(&parse(expr), "x", a, n, &HashMap::new())
+            .unwrap()
+            .iter()
+            .map(|c| eval_postfix_expr(c, &HashMap::new()))
+            .collect()
+    }
+
+    #[test]
+    fn known_expansions() {
+        assert_eq!(expand("exp(x)", 0.0, 4).unwrap(), "1 + x + x^2/2 + x^3/6 + x^4/24");
+        assert_eq!(expand("ln(x)", 1.0, 3).unwrap(), "x - 1 - (x - 1)^2/2 + (x - 1)^3/3");
+        let sine = coefficients("sin(x)", 0.0, 5);
+        let expected = [0.0, 1.0, 0.0, -1.0 / 6.0, 0.0, 1.0 / 120.0];
+        assert!(sine.iter().zip(&expected).all(|(c, e)| (c - e).abs() <= 1e-15));
+    }
+
+    #[test]
+    fn removable_singularities_use_power_series() {
+        // x/(exp(x)-1) = 1 - x/2 + x^2/12 - ...
+        let found: Vec<f64> =
+            series_coefficients(&parse("x/(exp(x)-1)"), "x", 0.0, 2, &HashMap::new())
+                .unwrap()
+                .iter()
+                .map(|c| eval_postfix_expr(c, &HashMap::new()))
+                .collect();
+        let expected = [1.0, -0.5, 1.0 / 12.0];
+        assert!(found.iter().zip(&expected).all(|(c, e)| (c - e).abs() <= 1e-9));
+    }
+
+    #[test]
+    fn singular_points_are_errors() {
+        let err = expand("sqrt(x)", 0.0, 3).unwrap_err();
+        assert!(err.contains("x = 0"), "{}", err);
+        assert!(expand("1/x", 0.0, 2).is_err());
+    }
+
+    #[test]
+    fn remainder_bounds() {
+        // |e^t| <= e on [-1, 1], so |R_3| <= e/24.
+        let bound = remainder_bound(&parse("exp(x)"), "x", 0.0, 3, -1.0, 1.0, &HashMap::new())
+            .unwrap();
+        assert!((bound - std::f64::consts::E / 24.0).abs() <= 1e-12);
+        let actual = 1f64.exp() - (1.0 + 1.0 + 0.5 + 1.0 / 6.0);
+        assert!(actual <= bound);
+        assert!(remainder_bound(&parse("x"), "x", 0.0, 1, 1.0, -1.0, &HashMap::new()).is_err());
+    }
+
+    fn approximant(expr: &str, a: f64, m: usize, n: usize) -> Expression {
+        pade(&parse(expr), "x", a, m, n, &HashMap::new()).unwrap()
+    }
+
+    fn at(expr: &Expression, x: f64) -> f64 {
+        let mut values = HashMap::new();
+        values.insert("x".to_owned(), x);
+        eval_postfix_expr(expr, &values)
+    }
+
+    #[test]
+    fn exact_pade_approximants() {
+        assert_eq!(approximant("exp(x)", 0.0, 2, 2).to_string(),
+                   "(1 + x/2 + x^2/12)/(1 - x/2 + x^2/12)");
+        assert_eq!(approximant("sin(x)", 0.0, 3, 2).to_string(), "(x - 7*x^3/60)/(1 + x^2/20)");
+        assert_eq!(approximant("1/(1-x)", 0.0, 0, 1).to_string(), "1/(1 - x)");
+    }
+
+    #[test]
+    fn pade_matches_the_function_to_its_order() {
+        // [3/3] of exp agrees to O(x^7) near a, whatever a is.
+        for &a in &[0.0, 1.0, -0.7] {
+            let r = approximant("exp(x)", a, 3, 3);
+            for &h in &[0.1, -0.05] {
+                let x: f64 = a + h;
+                assert!((at(&r, x) - x.exp()).abs() <= 1e-8 * x.exp(), "a = {}", a);
+            }
+        }
+        // The Hankel system of cos is singular for [1/1], which drops to [1/0].
+        let r = approximant("cos(x)", 0.0, 1, 1);
+        assert!((at(&r, 0.01) - 0.01f64.cos()).abs() <= 1e-4);
+    }
+}
@@ -0,0 +1,300 @@
+use expression;
+use expression::bytecode;
+use expression::derivative;
+
+pub type Expression = expression::Expression;
+
+use std::collections::HashMap;
+use std::f64;
+
+const MAX_ITERATIONS: usize = 200;
+const SCAN_SAMPLES: usize = 2000;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Root {
+    pub x: f64,
+    pub residual: f64,
+    pub iterations: usize,
+}
+
+// Brent's method on the bracket [a, b], trying a Newton step from the best
+// point first whenever a derivative is available. Newton steps are held to
+// the same safeguards as Brent's interpolation steps so the bracket is never
+// lost.
+pub fn brent(f: &mut dyn FnMut(f64) -> f64,
+             mut df: Option<&mut dyn FnMut(f64) -> f64>,
+             a: f64,
+             b: f64)
+             -> Result<Root, String> {
+    let (mut a, mut b) = (a, b);
+    let mut fa = f(a);
+    let mut fb = f(b);
+    if fa == 0.0 {
+        return Ok(Root { x: a, residual: 0.0, iterations: 0 });
+    }
+    if fb == 0.0 {
+        return Ok(Root { x: b, residual: 0.0, iterations: 0 });
+    }
+    if fa.signum() == fb.signum() || fa.is_nan() || fb.is_nan() {
+        return Err(format!("f({}) and f({}) do not bracket a root", a, b));
+    }
+    let (mut c, mut fc) = (a, fa);
+    let mut d = b - a;
+    let mut e = d;
+    for iteration in 1..MAX_ITERATIONS + 1 {
+        if fb.signum() == fc.signum() {
+            c = a;
+            fc = fa;
+            d = b - a;
+            e = d;
+        }
+        if fc.abs() < fb.abs() {
+            a = b;
+            b = c;
+            c = a;
+            fa = fb;
+            fb = fc;
+            fc = fa;
+        }
+        let tol = 2.0 * f64::EPSILON * b.abs() + 0.5e-15;
+        let xm = 0.5 * (c - b);
+        if xm.abs() <= tol || fb == 0.0 {
+            return Ok(Root { x: b, residual: fb, iterations: iteration });
+        }
+        let mut step: Option<f64> = None;
+        if e.abs() >= tol && fa.abs() > fb.abs() {
+            if let Some(ref mut df) = df {
+                let newton = -fb / df(b);
+                if newton.is_finite() && newton * xm > 0.0 && newton.abs() < 2.0 * xm.abs() &&
+                   newton.abs() < 0.5 * e.abs() {
+                    step = Some(newton);
+                }
+            }
+            if step.is_none() {
+                let s = fb / fa;
+                let (mut p, mut q);
+                if a == c {
+                    p = 2.0 * xm * s;
+                    q = 1.0 - s;
+                } else {
+                    let qa = fa / fc;
+                    let r = fb / fc;
+                    p = s * (2.0 * xm * qa * (qa - r) - (b - a) * (r - 1.0));
+                    q = (qa - 1.0) * (r - 1.0) * (s - 1.0);
+                }
+                if p > 0.0 {
+                    q = -q;
+                }
+                p = p.abs();
+                if 2.0 * p < (3.0 * xm * q - (tol * q).abs()).min((e * q).abs()) {
+                    step = Some(p / q);
+                }
+            }
+        }
+        match step {
+            Some(s) => {
+                e = d;
+                d = s;
+            }
+            None => {
+                d = xm;
+                e = d;
+            }
+        }
+        a = b;
+        fa = fb;
+        b += if d.abs() > tol { d } else { tol.copysign(xm) };
+        fb = f(b);
+    }
+    Err(format!("No convergence after {} iterations, last estimate {}", MAX_ITERATIONS, b))
+}
+
+// Damped Newton iteration for when no bracket can be found (e.g. double
+// roots like x^2).
+pub fn newton(f: &mut dyn FnMut(f64) -> f64,
+              df: &mut dyn FnMut(f64) -> f64,
+              x0: f64)
+              -> Result<Root, String> {
+    let mut x = x0;
+    let mut fx = f(x);
+    for iteration in 1..MAX_ITERATIONS + 1 {
+        if fx == 0.0 {
+            return Ok(Root { x, residual: 0.0, iterations: iteration });
+        }
+        let slope = df(x);
+        if slope == 0.0 || !slope.is_finite() {
+            return Err(format!("Derivative vanished at x = {}", x));
+        }
+        let mut step = -fx / slope;
+        let mut next = x + step;
+        let mut f_next = f(next);
+        while (f_next.abs() >= fx.abs() || f_next.is_nan()) &&
+              step.abs() > f64::EPSILON * x.abs().max(1.0) {
+            step *= 0.5;
+            next = x + step;
+            f_next = f(next);
+        }
+        x = next;
+        fx = f_next;
+        if step.abs() <= 4.0 * f64::EPSILON * x.abs().max(1.0) {
+            return Ok(Root { x, residual: fx, iterations: iteration });
+        }
+    }
+    Err(format!("No convergence after {} iterations, last estimate {}", MAX_ITERATIONS, x))
+}
+
+// Walks outwards from `guess` until f changes sign, nearest bracket first.
+pub fn find_bracket(f: &mut dyn FnMut(f64) -> f64, guess: f64) -> Option<(f64, f64)> {
+    let f0 = f(guess);
+    let mut h = (guess.abs() * 0.01).max(0.01);
+    let (mut left, mut right) = (guess, guess);
+    let (mut f_left, mut f_right) = (f0, f0);
+    for _ in 0..80 {
+        let next_right = guess + h;
+        let f_next = f(next_right);
+        if f_next.signum() != f_right.signum() && f_next.is_finite() && f_right.is_finite() {
+            return Some((right, next_right));
+        }
+        right = next_right;
+        f_right = f_next;
+        let next_left = guess - h;
+        let f_next = f(next_left);
+        if f_next.signum() != f_left.signum() && f_next.is_finite() && f_left.is_finite() {
+            return Some((next_left, left));
+        }
+        left = next_left;
+        f_left = f_next;
+        h *= 1.6;
+    }
+    None
+}
+
+// Root of `expr` in `var` near `guess`, with other variables taken from
+// `values`.
+pub fn solve(expr: &Expression,
+             var: &str,
+             guess: f64,
+             values: &HashMap<String, f64>)
+             -> Result<Root, String> {
+    let (mut f, mut df) = functions(expr, var, values)?;
+    let mut f = |x: f64| f.eval1(x);
+    let mut df = |x: f64| df.eval1(x);
+    if f(guess) == 0.0 {
+        return Ok(Root { x: guess, residual: 0.0, iterations: 0 });
+    }
+    match find_bracket(&mut f, guess) {
+        Some((a, b)) => refine(&mut f, &mut df, a, b),
+        None => newton(&mut f, &mut df, guess),
+    }
+}
+
+pub fn solve_bracketed(expr: &Expression,
+                       var: &str,
+                       a: f64,
+                       b: f64,
+                       values: &HashMap<String, f64>)
+                       -> Result<Root, String> {
+    let (mut f, mut df) = functions(expr, var, values)?;
+    let mut f = |x: f64| f.eval1(x);
+    let mut df = |x: f64| df.eval1(x);
+    refine(&mut f, &mut df, a, b)
+}
+
+// Every sign change root of `expr` on [a, b]. Sign changes across poles
+// (where |f| grows instead of vanishing) are discarded.
+pub fn roots(expr: &Expression,
+             var: &str,
+             a: f64,
+             b: f64,
+             values: &HashMap<String, f64>)
+             -> Result<Vec<Root>, String> {
+    if a >= b || a.is_nan() || b.is_nan() {
+        return Err(format!("Empty interval [{}, {}]", a, b));
+    }
+    let (mut f, mut df) = functions(expr, var, values)?;
+    let xs: Vec<f64> = (0..SCAN_SAMPLES + 1)
+        .map(|i| a + (b - a) * i as f64 / SCAN_SAMPLES as f64)
+        .collect();
+    let ys: Vec<f64> = xs.iter().map(|x| f.eval1(*x)).collect();
+    let mut f = |x: f64| f.eval1(x);
+    let mut df = |x: f64| df.eval1(x);
+    let mut found: Vec<Root> = Vec::new();
+    for i in 0..SCAN_SAMPLES {
+        let (x0, x1, y0, y1) = (xs[i], xs[i + 1], ys[i], ys[i + 1]);
+        if !y0.is_finite() || !y1.is_finite() {
+            continue;
+        }
+        if y0 == 0.0 {
+            found.push(Root { x: x0, residual: 0.0, iterations: 0 });
+            continue;
+        }
+        if y0.signum() == y1.signum() || y1 == 0.0 {
+            continue;
+        }
+        if let Ok(root) = refine(&mut f, &mut df, x0, x1) {
+            found.push(root);
+        }
+    }
+    if ys[SCAN_SAMPLES] == 0.0 {
+        found.push(Root { x: b, residual: 0.0, iterations: 0 });
+    }
+    Ok(found)
+}
+
+// Brent's method on [a, b], rejecting a sign change across a pole, where |f|
+// ends up larger than at either end instead of vanishing.
+fn refine(f: &mut dyn FnMut(f64) -> f64,
+          df: &mut dyn FnMut(f64) -> f64,
+          a: f64,
+          b: f64)
+          -> Result<Root, String> {
+    let smallest = f(a).abs().min(f(b).abs());
+    let root = brent(f, Some(df), a, b)?;
+    if root.residual.abs() > smallest {
+        let x = (root.x * 1e12).round() / 1e12 + 0.0;
+        return Err(format!("The sign change at {} is a pole, not a root", x));
+    }
+    Ok(root)
+}
+
+fn functions(expr: &Expression,
+             var: &str,
+             values: &HashMap<String, f64>)
+             -> Result<(bytecode::Bound, bytecode::Bound), String> {
+    let free = vec![var.to_owned()];
+    let derivative = derivative::differentiate(expr, var);
+    Ok((bytecode::bind(expr, &free, values)?, bytecode::bind(&derivative, &free, values)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use expression::parse::parse;
+    use std::f64::consts::PI;
+
+    #[test]
+    fn sign_change_roots() {
+        let values = HashMap::new();
+        let found = roots(&parse("sin(x)"), "x", 1.0, 10.0, &values).unwrap();
+        let xs: Vec<f64> = found.iter().map(|r| r.x).collect();
+        assert_eq!(xs.len(), 3);
+        for (k, x) in xs.iter().enumerate() {
+            assert!((x - (k + 1) as f64 * PI).abs() <= 1e-12);
+        }
+        // The sign change at the pole is not a root.
+        assert!(roots(&parse("1/x"), "x", -1.0, 1.0, &values).unwrap().is_empty());
+    }
+
+    #[test]
+    fn roots_near_a_guess() {
+        let values = HashMap::new();
+        let dottie = solve(&parse("cos(x)-x"), "x", 1.0, &values).unwrap();
+        assert!((dottie.x - 0.7390851332151607).abs() <= 1e-14);
+        let cube_root = solve_bracketed(&parse("x^3-2"), "x", 0.0, 2.0, &values).unwrap();
+        assert!((cube_root.x - 2f64.cbrt()).abs() <= 1e-14);
+        // Sign changes across poles are not roots.
+        assert!(solve(&parse("1/x"), "x", 1.0, &values).is_err());
+        assert!(solve(&parse("tan(x)"), "x", 1.5, &values).is_err());
+        assert!(solve_bracketed(&parse("1/x"), "x", -1.0, 1.0, &values).is_err());
+    }
+}
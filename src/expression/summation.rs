@@ -0,0 +1,485 @@
+use expression;
+use expression::bytecode;
+use expression::definite;
+use expression::eval::eval_postfix_expr;
+use expression::polynomial;
+use expression::rational::Rational;
+use expression::series::fraction;
+use expression::symbolic::{self, add, div, mul, number, pow, sub};
+
+pub type Expression = expression::Expression;
+
+use std::collections::HashMap;
+use std::fmt;
+
+// Finite sums and products are added up term by term. Infinite series are
+// summed directly when the terms die out fast, and otherwise accelerated:
+// alternating series by the Euler transform, series of terms of one sign by
+// Richardson extrapolation of the partial sums at n, 2n, 4n, ... Products
+// go through the series of logarithms.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Method {
+    Direct,
+    Euler,
+    Richardson,
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Method::Direct => write!(f, "direct summation"),
+            Method::Euler => write!(f, "Euler transform"),
+            Method::Richardson => write!(f, "Richardson extrapolation"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Series {
+    pub value: f64,
+    pub error: f64,
+    pub terms: usize,
+    pub method: Method,
+}
+
+const MAX_TERMS: i64 = 100_000_000;
+// Terms summed before deciding how to accelerate.
+const DIRECT_TERMS: usize = 4096;
+// Partial sums the Euler transform averages.
+const EULER_SUMS: usize = 64;
+// Richardson uses the partial sums at RICHARDSON_START * 2^j.
+const RICHARDSON_START: usize = 16;
+const RICHARDSON_LEVELS: usize = 16;
+
+// Terms of a series or factors of a product as a function of the index.
+struct Terms {
+    f: bytecode::Bound,
+    start: i64,
+}
+
+impl Terms {
+    fn new(expr: &Expression,
+           index: &str,
+           start: i64,
+           values: &HashMap<String, f64>)
+           -> Result<Self, String> {
+        Ok(Terms {
+            f: bytecode::bind(expr, &[index.to_owned()], values)?,
+            start,
+        })
+    }
+
+    // The term at start + i.
+    fn at(&mut self, i: usize) -> Result<f64, String> {
+        let k = self.start + i as i64;
+        let y = self.f.eval1(k as f64);
+        if y.is_finite() {
+            Ok(y)
+        } else {
+            Err(format!("The term is not finite at {}", k))
+        }
+    }
+}
+
+pub fn sum(expr: &Expression,
+           index: &str,
+           a: i64,
+           b: i64,
+           values: &HashMap<String, f64>)
+           -> Result<f64, String> {
+    let mut terms = Terms::new(expr, index, a, values)?;
+    let count = range(a, b)?;
+    let mut total = 0.0;
+    // Compensated summation, the terms may be many.
+    let mut compensation = 0.0;
+    for i in 0..count {
+        let y = terms.at(i)? - compensation;
+        let t = total + y;
+        compensation = (t - total) - y;
+        total = t;
+    }
+    Ok(total)
+}
+
+pub fn product(expr: &Expression,
+               index: &str,
+               a: i64,
+               b: i64,
+               values: &HashMap<String, f64>)
+               -> Result<f64, String> {
+    let mut factors = Terms::new(expr, index, a, values)?;
+    let mut total = 1.0;
+    for i in 0..range(a, b)? {
+        total *= factors.at(i)?;
+    }
+    Ok(total)
+}
+
+fn range(a: i64, b: i64) -> Result<usize, String> {
+    if b < a {
+        return Ok(0);
+    }
+    if b - a >= MAX_TERMS {
+        return Err(format!("At most {} terms can be added up", MAX_TERMS));
+    }
+    Ok((b - a + 1) as usize)
+}
+
+pub fn infinite_sum(expr: &Expression,
+                    index: &str,
+                    a: i64,
+                    values: &HashMap<String, f64>)
+                    -> Result<Series, String> {
+    let mut terms = Terms::new(expr, index, a, values)?;
+    accelerate(&mut |i| terms.at(i))
+}
+
+// exp of the sum of the logarithms, so the factors must be positive apart
+// from a zero one, which makes the product 0.
+pub fn infinite_product(expr: &Expression,
+                        index: &str,
+                        a: i64,
+                        values: &HashMap<String, f64>)
+                        -> Result<Series, String> {
+    let mut factors = Terms::new(expr, index, a, values)?;
+    let mut zero = None;
+    let result = accelerate(&mut |i| {
+        let y = factors.at(i)?;
+        if y == 0.0 {
+            zero = Some(i);
+        }
+        if y < 0.0 {
+            return Err(format!("The factor at {} is negative", a + i as i64));
+        }
+        Ok(if y == 0.0 { 0.0 } else { y.ln() })
+    });
+    if let Some(i) = zero {
+        return Ok(Series {
+            value: 0.0,
+            error: 0.0,
+            terms: i + 1,
+            method: Method::Direct,
+        });
+    }
+    let logarithm = result?;
+    let value = logarithm.value.exp();
+    Ok(Series {
+        value,
+        error: value * logarithm.error,
+        ..logarithm
+    })
+}
+
+fn accelerate(term: &mut dyn FnMut(usize) -> Result<f64, String>) -> Result<Series, String> {
+    let mut partial = Vec::with_capacity(DIRECT_TERMS);
+    let mut total = 0.0;
+    // Terms that no longer change the sum in a row.
+    let mut negligible = 0;
+    for i in 0..DIRECT_TERMS {
+        let t = term(i)?;
+        total += t;
+        partial.push(total);
+        negligible = if t.abs() <= 1e-17 * total.abs() { negligible + 1 } else { 0 };
+        if negligible == 8 {
+            return Ok(Series {
+                value: total,
+                error: rounding(total, i + 1),
+                terms: i + 1,
+                method: Method::Direct,
+            });
+        }
+    }
+    let tail: Vec<f64> = partial.windows(2).rev().take(32).map(|w| w[1] - w[0]).collect();
+    if tail.iter().all(|&t| t == 0.0) {
+        return Ok(Series {
+            value: total,
+            error: 0.0,
+            terms: DIRECT_TERMS,
+            method: Method::Direct,
+        });
+    }
+    if tail.windows(2).all(|w| w[0] * w[1] < 0.0) {
+        return euler(&partial, &tail);
+    }
+    if tail.iter().all(|&t| t > 0.0) || tail.iter().all(|&t| t < 0.0) {
+        return richardson(term, partial);
+    }
+    Err("The terms change sign irregularly, which neither accelerator handles".to_owned())
+}
+
+// Repeated averaging of consecutive partial sums, which is the Euler
+// transform of an alternating series. The terms must shrink to 0.
+fn euler(partial: &[f64], tail: &[f64]) -> Result<Series, String> {
+    let (last, earlier) = (tail[0].abs(), tail[tail.len() - 1].abs());
+    if last >= earlier {
+        return Err("The series diverges, its terms do not go to 0".to_owned());
+    }
+    let mut sums: Vec<f64> = partial[partial.len() - EULER_SUMS..].to_vec();
+    let mut previous = sums[sums.len() - 1];
+    let mut error = f64::INFINITY;
+    let mut value = previous;
+    while sums.len() > 1 {
+        sums = sums.windows(2).map(|w| 0.5 * (w[0] + w[1])).collect();
+        let estimate = sums[sums.len() - 1];
+        let change = (estimate - previous).abs();
+        // Averaging stops helping once the changes reach rounding noise.
+        if change < error {
+            error = change;
+            value = estimate;
+        }
+        previous = estimate;
+    }
+    Ok(Series {
+        value,
+        error: error.max(rounding(value, partial.len())),
+        terms: partial.len(),
+        method: Method::Euler,
+    })
+}
+
+// The rounding error to expect in a sum of `terms` terms.
+fn rounding(value: f64, terms: usize) -> f64 {
+    (terms as f64).sqrt() * f64::EPSILON * value.abs()
+}
+
+// S(n) - S = c_0 n^-p + c_1 n^-(p+1) + ... for terms that behave like a
+// power of k, with p estimated from the partial sums at n, 2n and 4n and
+// each column of the extrapolation table removing the next power. Levels
+// that only change the sum by rounding noise are left out. Terms that are a
+// power k^-(p+1) give p more precisely themselves, which matters when p is
+// small and the tail is many times the last change; without such a power,
+// as for 1/(k ln(k)), a tiny p cannot be told from divergence.
+fn richardson(term: &mut dyn FnMut(usize) -> Result<f64, String>,
+              partial: Vec<f64>)
+              -> Result<Series, String> {
+    let mut count = partial.len();
+    let mut total = partial[count - 1];
+    let mut compensation = 0.0;
+    let mut sums = vec![partial[RICHARDSON_START - 1]];
+    let mut n = RICHARDSON_START;
+    while sums.len() <= RICHARDSON_LEVELS {
+        n *= 2;
+        while count < n {
+            let y = term(count)? - compensation;
+            let t = total + y;
+            compensation = (t - total) - y;
+            total = t;
+            count += 1;
+        }
+        let sum = if n <= partial.len() { partial[n - 1] } else { total };
+        if (sum - sums[sums.len() - 1]).abs() <= 1e-14 * sum.abs() {
+            break;
+        }
+        sums.push(sum);
+    }
+    let m = sums.len();
+    if m < 3 {
+        return Ok(Series {
+            value: total,
+            error: (total - sums[m - 1]).abs(),
+            terms: count,
+            method: Method::Direct,
+        });
+    }
+    let ratio = (sums[m - 1] - sums[m - 2]) / (sums[m - 2] - sums[m - 3]);
+    if !(ratio > 0.0 && ratio < 1.0) {
+        return Err("The series diverges".to_owned());
+    }
+    // log2 t(i)/t(2i) at i and i/2, extrapolated in 1/i since the index
+    // is off from k by the starting value. Pure powers give the same value
+    // at both up to that offset.
+    let mut decay = |i: usize| -> Result<f64, String> {
+        Ok((term(i - 1)? / term(2 * i - 1)?).log2())
+    };
+    let (near, far) = (decay(count / 4)?, decay(count / 2)?);
+    let s = 2.0 * far - near;
+    if s <= 1.0 {
+        return Err("The series diverges, its terms shrink no faster than 1/k".to_owned());
+    }
+    let power = (far - near).abs() <= 1e-4;
+    let mut p = -ratio.log2();
+    if power && (s - 1.0 - p).abs() <= 0.1 * p {
+        p = s - 1.0;
+    } else if ratio >= 0.99 {
+        return Err("The series converges too slowly to accelerate, if it converges at all"
+            .to_owned());
+    }
+    // Powers are usually whole or half, which the estimate is rounded to.
+    if (2.0 * p - (2.0 * p).round()).abs() < 0.02 && (2.0 * p).round() > 0.0 {
+        p = 0.5 * (2.0 * p).round();
+    }
+    // The best column is the one whose last two entries agree most closely,
+    // and its error is no smaller than its change from the column before,
+    // which catches terms the expansion does not fit, like ln(k)/k^2. The
+    // partial sums themselves are off by the whole tail, which for slowly
+    // converging series such as 1/k^1.01 is far more than the last change.
+    let mut column = sums;
+    let mut value = column[m - 1];
+    let mut error = (column[m - 1] - column[m - 2]).abs() / (2f64.powf(p) - 1.0);
+    let mut best = error;
+    for i in 0..m - 2 {
+        let factor = 2f64.powf(p + i as f64) - 1.0;
+        let next: Vec<f64> = column.windows(2).map(|w| w[1] + (w[1] - w[0]) / factor).collect();
+        let last = next.len() - 1;
+        let change = (next[last] - next[last - 1]).abs();
+        if change < best {
+            best = change;
+            value = next[last];
+            error = change.max((next[last] - column[last + 1]).abs());
+        }
+        column = next;
+    }
+    Ok(Series {
+        value,
+        error: error.max(rounding(value, count)),
+        terms: count,
+        method: Method::Richardson,
+    })
+}
+
+// Closed form of the sum from `a` to `b`, or to infinity when `b` is None,
+// for polynomial and geometric terms. `a` must be an integer.
+pub fn closed_sum(expr: &Expression,
+                  index: &str,
+                  a: i64,
+                  b: Option<&Expression>,
+                  values: &HashMap<String, f64>)
+                  -> Option<Expression> {
+    // Polynomial coefficients are numbers, so parameters must have values.
+    let assigned = symbolic::variables(expr).iter().all(|v| v == index || values.contains_key(v));
+    if let (Some(b), true) = (b, assigned) {
+        if let Some(result) = polynomial_sum(expr, index, a, b, values) {
+            return Some(definite::exact(&result));
+        }
+    }
+    geometric_sum(expr, index, a, b, values)
+}
+
+// sum p(k) for k = a..m is a polynomial in m of one degree more, found from
+// its values at m = a - 1, ..., a + deg p by Newton's forward differences.
+fn polynomial_sum(expr: &Expression,
+                  index: &str,
+                  a: i64,
+                  b: &Expression,
+                  values: &HashMap<String, f64>)
+                  -> Option<Expression> {
+    let p = polynomial::to_rational(&polynomial::coefficients(expr, index, values)?)?;
+    let eval = |k: i64| {
+        p.iter().rev().fold(Rational::zero(), |acc, c| acc * Rational::from_integer(k as i128) + *c)
+    };
+    let points = p.len() + 1;
+    let mut differences: Vec<Rational> = Vec::with_capacity(points);
+    let mut running = Rational::zero();
+    for j in 0..points {
+        if j > 0 {
+            running = running + eval(a + j as i64 - 1);
+        }
+        differences.push(running);
+    }
+    // Coefficients in m of sum_j (Delta^j S)(a - 1) binomial(m - a + 1, j).
+    let mut coeffs = vec![Rational::zero(); points];
+    let mut basis = vec![Rational::one()];
+    let origin = Rational::from_integer((a - 1) as i128);
+    for j in 0..points {
+        let leading = differences[0];
+        for (c, b) in coeffs.iter_mut().zip(&basis) {
+            *c = *c + leading * *b;
+        }
+        differences = differences.windows(2).map(|w| w[1] - w[0]).collect();
+        // basis *= (m - origin - j) / (j + 1)
+        let shift = origin + Rational::from_integer(j as i128);
+        let scale = Rational::from_integer(j as i128 + 1).recip();
+        let mut next = vec![Rational::zero(); basis.len() + 1];
+        for (i, b) in basis.iter().enumerate() {
+            next[i + 1] = next[i + 1] + *b * scale;
+            next[i] = next[i] - *b * shift * scale;
+        }
+        basis = next;
+        if differences.is_empty() {
+            break;
+        }
+    }
+    if coeffs.iter().any(|c| !c.is_valid()) {
+        return None;
+    }
+    let mut result = number(0.0);
+    for (i, c) in coeffs.iter().enumerate().rev() {
+        let term = mul(fraction(c.to_f64()), pow(b.clone(), number(i as f64)));
+        result = add(result, term);
+    }
+    Some(result)
+}
+
+// t(a) (1 - r^(b - a + 1)) / (1 - r) when t(k + 1) = r t(k), and t(a) / (1 - r)
+// to infinity when |r| < 1.
+fn geometric_sum(expr: &Expression,
+                 index: &str,
+                 a: i64,
+                 b: Option<&Expression>,
+                 values: &HashMap<String, f64>)
+                 -> Option<Expression> {
+    let at = |k: i64| symbolic::simplify(&symbolic::substitute(expr, index, &number(k as f64)));
+    let first = at(a);
+    let ratio = definite::exact(&div(at(a + 1), first.clone()));
+    if symbolic::contains_var(&ratio, index) || symbolic::is_number(&ratio, 1.0) {
+        return None;
+    }
+    // Check the ratio at a few indices, with made up values for anything
+    // unassigned so a parameter left at 0 proves nothing.
+    let mut check = values.clone();
+    for (i, var) in symbolic::variables(expr).iter().enumerate() {
+        if var != index && !values.contains_key(var) {
+            check.insert(var.clone(), 0.6180339887 + 0.1 * i as f64);
+        }
+    }
+    let r = eval_postfix_expr(&ratio, &check);
+    for k in a..a + 5 {
+        check.insert(index.to_owned(), k as f64);
+        let t = eval_postfix_expr(expr, &check);
+        check.insert(index.to_owned(), (k + 1) as f64);
+        let next = eval_postfix_expr(expr, &check);
+        let close = (next - r * t).abs() <= 1e-12 * next.abs().max(t.abs());
+        if !close {
+            return None;
+        }
+    }
+    let remainder = sub(number(1.0), ratio.clone());
+    match b {
+        Some(b) => {
+            let count = symbolic::simplify(&add(sub(b.clone(), number(a as f64)), number(1.0)));
+            Some(div(mul(first, sub(number(1.0), pow(ratio, count))), remainder))
+        }
+        None if eval_postfix_expr(&ratio, values).abs() < 1.0 => {
+            Some(definite::exact(&div(first, remainder)))
+        }
+        None => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use expression::parse::parse;
+    use std::f64::consts::PI;
+
+    fn p_series(p: &str) -> Result<Series, String> {
+        infinite_sum(&parse(&format!("1/k^{}", p)), "k", 1, &HashMap::new())
+    }
+
+    #[test]
+    fn p_series_converge_however_slowly() {
+        let zeta = [("2", PI * PI / 6.0), ("1.5", 2.612375348685488), ("1.01", 100.577943338497)];
+        for &(p, expected) in &zeta {
+            let series = p_series(p).unwrap();
+            assert!((series.value - expected).abs() <= 1e-9 * expected, "{}", p);
+            assert!(series.error <= 1e-9 * expected, "{}", p);
+        }
+    }
+
+    #[test]
+    fn p_series_diverge_from_one_down() {
+        assert!(p_series("1").is_err());
+        assert!(p_series("0.999").is_err());
+        assert!(p_series("0.5").is_err());
+    }
+}
@@ -0,0 +1,552 @@
+use expression;
+use expression::enums;
+use expression::eval;
+
+pub type Expression = expression::Expression;
+
+use std::collections::HashMap;
+
+// Building blocks for manipulating postfix expressions symbolically. The
+// constructors below do light simplification as they go (constant folding,
+// identities, collecting like terms) so derived expressions stay readable.
+
+pub fn arity(token: &enums::Token) -> usize {
+    match *token {
+        enums::Token::Op(enums::Operator::Negate) => 1,
+        enums::Token::Op(_) => 2,
+        enums::Token::Func(enums::Function::Max) |
+        enums::Token::Func(enums::Function::LogBase) => 2,
+        enums::Token::Func(_) => 1,
+        _ => 0,
+    }
+}
+
+// Index of the first token of the sub-expression ending at `end`.
+pub fn span_start(tokens: &[enums::Token], end: usize) -> usize {
+    let mut needed: usize = 1;
+    let mut i = end;
+    loop {
+        needed = needed - 1 + arity(&tokens[i]);
+        if needed == 0 || i == 0 {
+            return i;
+        }
+        i -= 1;
+    }
+}
+
+pub fn root(expr: &Expression) -> &enums::Token {
+    expr.get_token(expr.len() - 1)
+}
+
+// The operands of the root token, in order.
+pub fn operands(expr: &Expression) -> Vec<Expression> {
+    let tokens = expr.get_tokens();
+    let mut result: Vec<Expression> = Vec::new();
+    if tokens.is_empty() {
+        return result;
+    }
+    let mut end = tokens.len() - 1;
+    for _ in 0..arity(root(expr)) {
+        // A malformed expression runs out of tokens before operands.
+        if end == 0 {
+            break;
+        }
+        let start = span_start(tokens, end - 1);
+        result.push(Expression::new(tokens[start..end].to_vec()));
+        end = start;
+    }
+    result.reverse();
+    result
+}
+
+pub fn number(x: f64) -> Expression {
+    if x == 0.0 {
+        return Expression::new(vec![enums::Token::Literal("0".to_owned())]);
+    }
+    if x < 0.0 {
+        return Expression::new(vec![enums::Token::Literal(literal(-x)),
+                                    enums::Token::Op(enums::Operator::Negate)]);
+    }
+    Expression::new(vec![enums::Token::Literal(literal(x))])
+}
+
+// Scientific notation where plain digits would run long, e.g. 1/20!.
+fn literal(x: f64) -> String {
+    if (1e-6..1e15).contains(&x) {
+        format!("{}", x)
+    } else {
+        format!("{:e}", x)
+    }
+}
+
+pub fn variable(name: &str) -> Expression {
+    Expression::new(vec![enums::Token::Var(name.to_owned())])
+}
+
+pub fn constant(c: enums::Constant) -> Expression {
+    Expression::new(vec![enums::Token::Const(c)])
+}
+
+// A literal or a negated literal.
+pub fn as_number(expr: &Expression) -> Option<f64> {
+    match *expr.get_tokens() {
+        [enums::Token::Literal(ref x)] => x.parse::<f64>().ok(),
+        [enums::Token::Literal(ref x), enums::Token::Op(enums::Operator::Negate)] => {
+            x.parse::<f64>().ok().map(|x| -x)
+        }
+        _ => None,
+    }
+}
+
+pub fn is_number(expr: &Expression, value: f64) -> bool {
+    as_number(expr) == Some(value)
+}
+
+pub fn contains_var(expr: &Expression, var: &str) -> bool {
+    expr.iter().any(|t| match *t {
+        enums::Token::Var(ref x) => x == var,
+        _ => false,
+    })
+}
+
+// Variable names in order of first appearance.
+pub fn variables(expr: &Expression) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    for token in expr.iter() {
+        if let enums::Token::Var(ref x) = *token {
+            if !names.contains(x) {
+                names.push(x.clone());
+            }
+        }
+    }
+    names
+}
+
+// No variables at all, so it evaluates to a single number.
+pub fn is_numeric(expr: &Expression) -> bool {
+    !expr.iter().any(|t| matches!(*t, enums::Token::Var(_) | enums::Token::Unknown(_)))
+}
+
+fn nice(x: f64) -> bool {
+    x.is_finite() && x.abs() < 1e12 && (x * 1e6).round() == x * 1e6
+}
+
+fn join(args: &[&Expression], token: enums::Token) -> Expression {
+    let mut tokens: Vec<enums::Token> = Vec::new();
+    for arg in args {
+        tokens.extend_from_slice(arg.get_tokens());
+    }
+    tokens.push(token);
+    Expression::new(tokens)
+}
+
+fn binary(a: &Expression, b: &Expression, op: enums::Operator) -> Expression {
+    join(&[a, b], enums::Token::Op(op))
+}
+
+// Splits c*x into (c, x), anything else is (1, x).
+fn coefficient(expr: &Expression) -> (f64, Expression) {
+    if let Some(x) = as_number(expr) {
+        return (x, number(1.0));
+    }
+    if *root(expr) == enums::Token::Op(enums::Operator::Mul) {
+        let args = operands(expr);
+        if let Some(c) = as_number(&args[0]) {
+            return (c, args[1].clone());
+        }
+    }
+    if *root(expr) == enums::Token::Op(enums::Operator::Negate) {
+        let (c, rest) = coefficient(&operands(expr)[0]);
+        return (-c, rest);
+    }
+    (1.0, expr.clone())
+}
+
+// Splits x^n into (x, n), anything else is (x, 1).
+fn power(expr: &Expression) -> (Expression, Expression) {
+    if *root(expr) == enums::Token::Op(enums::Operator::Pow) {
+        let mut args = operands(expr);
+        let exponent = args.pop().unwrap();
+        return (args.pop().unwrap(), exponent);
+    }
+    (expr.clone(), number(1.0))
+}
+
+pub fn add(a: Expression, b: Expression) -> Expression {
+    if let (Some(x), Some(y)) = (as_number(&a), as_number(&b)) {
+        return number(x + y);
+    }
+    if is_number(&a, 0.0) {
+        return b;
+    }
+    if is_number(&b, 0.0) {
+        return a;
+    }
+    if *root(&b) == enums::Token::Op(enums::Operator::Negate) {
+        return sub(a, operands(&b).pop().unwrap());
+    }
+    if let Some(y) = as_number(&b) {
+        if y < 0.0 {
+            return sub(a, number(-y));
+        }
+    }
+    let (ca, ra) = coefficient(&a);
+    let (cb, rb) = coefficient(&b);
+    if ra == rb && as_number(&ra).is_none() {
+        return mul(number(ca + cb), ra);
+    }
+    binary(&a, &b, enums::Operator::Add)
+}
+
+pub fn sub(a: Expression, b: Expression) -> Expression {
+    if let (Some(x), Some(y)) = (as_number(&a), as_number(&b)) {
+        return number(x - y);
+    }
+    if is_number(&b, 0.0) {
+        return a;
+    }
+    if is_number(&a, 0.0) {
+        return neg(b);
+    }
+    if a == b {
+        return number(0.0);
+    }
+    if *root(&b) == enums::Token::Op(enums::Operator::Negate) {
+        return add(a, operands(&b).pop().unwrap());
+    }
+    let (ca, ra) = coefficient(&a);
+    let (cb, rb) = coefficient(&b);
+    if ra == rb && as_number(&ra).is_none() {
+        return mul(number(ca - cb), ra);
+    }
+    binary(&a, &b, enums::Operator::Sub)
+}
+
+pub fn mul(a: Expression, b: Expression) -> Expression {
+    if let (Some(x), Some(y)) = (as_number(&a), as_number(&b)) {
+        return number(x * y);
+    }
+    if is_number(&a, 0.0) || is_number(&b, 0.0) {
+        return number(0.0);
+    }
+    if is_number(&a, 1.0) {
+        return b;
+    }
+    if is_number(&b, 1.0) {
+        return a;
+    }
+    if is_number(&a, -1.0) {
+        return neg(b);
+    }
+    if is_number(&b, -1.0) {
+        return neg(a);
+    }
+    if *root(&a) == enums::Token::Op(enums::Operator::Negate) && as_number(&a).is_none() {
+        return neg(mul(operands(&a).pop().unwrap(), b));
+    }
+    if *root(&b) == enums::Token::Op(enums::Operator::Negate) && as_number(&b).is_none() {
+        return neg(mul(a, operands(&b).pop().unwrap()));
+    }
+    // a*(b/c) == (a*b)/c keeps a single fraction bar.
+    if *root(&b) == enums::Token::Op(enums::Operator::Div) {
+        let mut args = operands(&b);
+        let denominator = args.pop().unwrap();
+        return div(mul(a, args.pop().unwrap()), denominator);
+    }
+    if *root(&a) == enums::Token::Op(enums::Operator::Div) {
+        let mut args = operands(&a);
+        let denominator = args.pop().unwrap();
+        return div(mul(args.pop().unwrap(), b), denominator);
+    }
+    // Keep numeric factors in front: c1*(c2*x) == (c1*c2)*x.
+    if as_number(&b).is_some() {
+        return mul(b, a);
+    }
+    if let Some(x) = as_number(&a) {
+        let (c, rest) = coefficient(&b);
+        if c != 1.0 {
+            return mul(number(x * c), rest);
+        }
+        if x < 0.0 {
+            return neg(mul(number(-x), b));
+        }
+        return binary(&a, &b, enums::Operator::Mul);
+    }
+    let (ca, ra) = coefficient(&a);
+    if ca != 1.0 {
+        return mul(number(ca), mul(ra, b));
+    }
+    let (cb, rb) = coefficient(&b);
+    if cb != 1.0 {
+        return mul(number(cb), mul(a, rb));
+    }
+    let (base_a, exp_a) = power(&a);
+    let (base_b, exp_b) = power(&b);
+    if base_a == base_b && as_number(&base_a).is_none() {
+        return pow(base_a, add(exp_a, exp_b));
+    }
+    binary(&a, &b, enums::Operator::Mul)
+}
+
+pub fn div(a: Expression, b: Expression) -> Expression {
+    if let (Some(x), Some(y)) = (as_number(&a), as_number(&b)) {
+        if nice(x / y) {
+            return number(x / y);
+        }
+    }
+    if is_number(&a, 0.0) {
+        return number(0.0);
+    }
+    if is_number(&b, 1.0) {
+        return a;
+    }
+    if is_number(&b, -1.0) {
+        return neg(a);
+    }
+    if a == b {
+        return number(1.0);
+    }
+    if *root(&a) == enums::Token::Op(enums::Operator::Negate) {
+        return neg(div(operands(&a).pop().unwrap(), b));
+    }
+    if *root(&b) == enums::Token::Op(enums::Operator::Negate) {
+        return neg(div(a, operands(&b).pop().unwrap()));
+    }
+    // Nested fractions: a/(b/c) == (a*c)/b and (a/b)/c == a/(b*c).
+    if *root(&b) == enums::Token::Op(enums::Operator::Div) {
+        let mut args = operands(&b);
+        let c = args.pop().unwrap();
+        return div(mul(a, c), args.pop().unwrap());
+    }
+    if *root(&a) == enums::Token::Op(enums::Operator::Div) {
+        let mut args = operands(&a);
+        let denominator = args.pop().unwrap();
+        return div(args.pop().unwrap(), mul(denominator, b));
+    }
+    let (ca, ra) = coefficient(&a);
+    let (cb, rb) = coefficient(&b);
+    if ca != 1.0 && cb != 1.0 && nice(ca / cb) {
+        return mul(number(ca / cb), div(ra, rb));
+    }
+    let (base_a, exp_a) = power(&a);
+    let (base_b, exp_b) = power(&b);
+    if base_a == base_b && as_number(&base_a).is_none() {
+        return pow(base_a, sub(exp_a, exp_b));
+    }
+    binary(&a, &b, enums::Operator::Div)
+}
+
+pub fn rem(a: Expression, b: Expression) -> Expression {
+    if let (Some(x), Some(y)) = (as_number(&a), as_number(&b)) {
+        return number(x % y);
+    }
+    binary(&a, &b, enums::Operator::Mod)
+}
+
+pub fn pow(a: Expression, b: Expression) -> Expression {
+    if let (Some(x), Some(y)) = (as_number(&a), as_number(&b)) {
+        if nice(x.powf(y)) {
+            return number(x.powf(y));
+        }
+    }
+    if is_number(&b, 0.0) || is_number(&a, 1.0) {
+        return number(1.0);
+    }
+    if is_number(&b, 1.0) {
+        return a;
+    }
+    if is_number(&a, 0.0) && as_number(&b).map(|y| y > 0.0).unwrap_or(false) {
+        return number(0.0);
+    }
+    // (x^c1)^c2 == x^(c1*c2) only holds for integer c2.
+    if *root(&a) == enums::Token::Op(enums::Operator::Pow) {
+        if let Some(y) = as_number(&b) {
+            if y.fract() == 0.0 {
+                let mut args = operands(&a);
+                let exponent = args.pop().unwrap();
+                return pow(args.pop().unwrap(), mul(exponent, b));
+            }
+        }
+    }
+    binary(&a, &b, enums::Operator::Pow)
+}
+
+pub fn neg(a: Expression) -> Expression {
+    if let Some(x) = as_number(&a) {
+        return number(-x);
+    }
+    if *root(&a) == enums::Token::Op(enums::Operator::Negate) {
+        return operands(&a).pop().unwrap();
+    }
+    if *root(&a) == enums::Token::Op(enums::Operator::Sub) {
+        let mut args = operands(&a);
+        let rhs = args.pop().unwrap();
+        return sub(rhs, args.pop().unwrap());
+    }
+    join(&[&a], enums::Token::Op(enums::Operator::Negate))
+}
+
+// Folds f(number) only when the result is exact enough to print nicely, so
+// sin(1) stays sin(1) but ln(1) becomes 0.
+pub fn apply(f: enums::Function, a: Expression) -> Expression {
+    if f == enums::Function::Ln && a == constant(enums::Constant::E) {
+        return number(1.0);
+    }
+    if let Some(x) = as_number(&a) {
+        let value: f64 = eval::eval_function(&f, x);
+        if value.is_finite() && value.fract() == 0.0 {
+            return number(value);
+        }
+    }
+    join(&[&a], enums::Token::Func(f))
+}
+
+pub fn apply2(f: enums::Function, a: Expression, b: Expression) -> Expression {
+    if let (Some(x), Some(y)) = (as_number(&a), as_number(&b)) {
+        let value: f64 = eval::eval_binary_function(&f, x, y);
+        if value.is_finite() && value.fract() == 0.0 {
+            return number(value);
+        }
+    }
+    join(&[&a, &b], enums::Token::Func(f))
+}
+
+// Rebuilds the expression bottom up through the simplifying constructors.
+pub fn simplify(expr: &Expression) -> Expression {
+    if expr.is_empty() {
+        return expr.clone();
+    }
+    rebuild(expr, &|e| e.clone())
+}
+
+// Replaces every occurrence of `var` with `value`.
+pub fn substitute(expr: &Expression, var: &str, value: &Expression) -> Expression {
+    let mut values: HashMap<String, Expression> = HashMap::new();
+    values.insert(var.to_owned(), value.clone());
+    substitute_all(expr, &values)
+}
+
+pub fn substitute_all(expr: &Expression, values: &HashMap<String, Expression>) -> Expression {
+    rebuild(expr, &|e| match *root(e) {
+        enums::Token::Var(ref x) => values.get(x).cloned().unwrap_or_else(|| e.clone()),
+        _ => e.clone(),
+    })
+}
+
+// Applies `leaf` to every operand-less token and recombines the results.
+fn rebuild<F: Fn(&Expression) -> Expression>(expr: &Expression, leaf: &F) -> Expression {
+    let args: Vec<Expression> = operands(expr).iter().map(|a| rebuild(a, leaf)).collect();
+    combine(root(expr), args).unwrap_or_else(|| leaf(expr))
+}
+
+// Applies the token at the root of an expression to already built operands,
+// None for leaves.
+pub fn combine(token: &enums::Token, mut args: Vec<Expression>) -> Option<Expression> {
+    let last = args.pop();
+    let first = args.pop();
+    Some(match (token, first, last) {
+        (&enums::Token::Op(enums::Operator::Negate), _, Some(a)) => neg(a),
+        (&enums::Token::Op(op), Some(a), Some(b)) => {
+            match op {
+                enums::Operator::Add => add(a, b),
+                enums::Operator::Sub => sub(a, b),
+                enums::Operator::Mul => mul(a, b),
+                enums::Operator::Div => div(a, b),
+                enums::Operator::Mod => rem(a, b),
+                enums::Operator::Pow => pow(a, b),
+                enums::Operator::Negate => neg(b),
+            }
+        }
+        (&enums::Token::Func(f), Some(a), Some(b)) => apply2(f, a, b),
+        (&enums::Token::Func(f), None, Some(a)) => apply(f, a),
+        _ => return None,
+    })
+}
+
+fn precedence(token: &enums::Token) -> u8 {
+    match *token {
+        enums::Token::Op(enums::Operator::Add) |
+        enums::Token::Op(enums::Operator::Sub) => 1,
+        enums::Token::Op(enums::Operator::Mul) |
+        enums::Token::Op(enums::Operator::Div) |
+        enums::Token::Op(enums::Operator::Mod) => 2,
+        enums::Token::Op(enums::Operator::Negate) => 3,
+        enums::Token::Op(enums::Operator::Pow) => 4,
+        _ => 5,
+    }
+}
+
+fn operator_symbol(op: enums::Operator) -> &'static str {
+    match op {
+        enums::Operator::Add => " + ",
+        enums::Operator::Sub => " - ",
+        enums::Operator::Mul => "*",
+        enums::Operator::Div => "/",
+        enums::Operator::Mod => " % ",
+        enums::Operator::Pow => "^",
+        enums::Operator::Negate => "-",
+    }
+}
+
+pub fn function_name(f: enums::Function) -> String {
+    format!("{:?}", f).to_lowercase()
+}
+
+fn constant_name(c: enums::Constant) -> &'static str {
+    match c {
+        enums::Constant::Pi => "pi",
+        enums::Constant::E => "e",
+    }
+}
+
+// Infix form that parse_input reads back as the same expression.
+pub fn to_infix(expr: &Expression) -> String {
+    if expr.is_empty() {
+        return String::new();
+    }
+    let token = root(expr);
+    let args = operands(expr);
+    match *token {
+        enums::Token::Literal(ref x) => x.clone(),
+        enums::Token::Var(ref x) => x.clone(),
+        enums::Token::Unknown(ref x) => x.clone(),
+        enums::Token::Const(c) => constant_name(c).to_owned(),
+        enums::Token::Func(f) => {
+            let inner: Vec<String> = args.iter().map(to_infix).collect();
+            format!("{}({})", function_name(f), inner.join(", "))
+        }
+        enums::Token::Op(enums::Operator::Negate) => {
+            let inner = &args[0];
+            if precedence(root(inner)) < 4 {
+                format!("-({})", to_infix(inner))
+            } else {
+                format!("-{}", to_infix(inner))
+            }
+        }
+        enums::Token::Op(op) => {
+            let p = precedence(token);
+            let right_assoc = op == enums::Operator::Pow;
+            let lp = precedence(root(&args[0]));
+            let rp = precedence(root(&args[1]));
+            let left_parens = lp < p || (right_assoc && lp == p) ||
+                              (p > 1 && as_number(&args[0]).map(|x| x < 0.0).unwrap_or(false));
+            let right_parens = rp < p ||
+                               (rp == p && !right_assoc && op != enums::Operator::Add &&
+                                op != enums::Operator::Mul) ||
+                               (rp == 3 && p > 1);
+            format!("{}{}{}",
+                    parenthesize(&args[0], left_parens),
+                    operator_symbol(op),
+                    parenthesize(&args[1], right_parens))
+        }
+        _ => String::new(),
+    }
+}
+
+fn parenthesize(expr: &Expression, parens: bool) -> String {
+    if parens {
+        format!("({})", to_infix(expr))
+    } else {
+        to_infix(expr)
+    }
+}
@@ -0,0 +1,346 @@
+use expression;
+use expression::bytecode::{self, Machine, Program};
+use expression::derivative;
+use expression::dual::Dual;
+use expression::linalg::{self, Matrix};
+
+pub type Expression = expression::Expression;
+
+use std::collections::HashMap;
+use std::f64;
+use std::fmt;
+
+const MAX_ITERATIONS: usize = 200;
+const TOLERANCE: f64 = 1e-12;
+// Iterations that stall with every residual below this still count as
+// converged.
+const ACCEPTED: f64 = 1e-9;
+// Symbolic Jacobian entries that grow past this many times the size of their
+// equation are computed with dual numbers instead.
+const MAX_SYMBOLIC_GROWTH: usize = 20;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Method {
+    Newton,
+    LevenbergMarquardt,
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Method::Newton => write!(f, "Newton"),
+            Method::LevenbergMarquardt => write!(f, "Levenberg-Marquardt"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct SystemSolution {
+    pub x: Vec<f64>,
+    pub residuals: Vec<f64>,
+    pub iterations: usize,
+    pub converged: bool,
+    // The method that produced `x`, Newton unless it had to give up.
+    pub method: Method,
+}
+
+impl SystemSolution {
+    pub fn residual_norm(&self) -> f64 {
+        linalg::norm(&self.residuals)
+    }
+}
+
+// F and its Jacobian at a point. Each entry of the Jacobian is differentiated
+// symbolically unless the result blows up, in which case that equation is
+// differentiated in forward mode with dual numbers, one variable at a time.
+pub struct System {
+    equations: Vec<bytecode::Bound>,
+    rows: Vec<JacobianRow>,
+    n: usize,
+}
+
+enum JacobianRow {
+    Symbolic(Vec<bytecode::Bound>),
+    Automatic(Program<Dual>, Machine<Dual>, Vec<Dual>),
+}
+
+impl System {
+    pub fn new(exprs: &[Expression],
+               vars: &[String],
+               values: &HashMap<String, f64>)
+               -> Result<System, String> {
+        let mut equations = Vec::with_capacity(exprs.len());
+        let mut rows = Vec::with_capacity(exprs.len());
+        for expr in exprs {
+            equations.push(bytecode::bind(expr, vars, values)?);
+            let partials: Vec<Expression> =
+                vars.iter().map(|v| derivative::differentiate(expr, v)).collect();
+            if partials.iter().all(|d| d.len() <= MAX_SYMBOLIC_GROWTH * expr.len().max(1)) {
+                let mut row = Vec::with_capacity(vars.len());
+                for partial in &partials {
+                    row.push(bytecode::bind(partial, vars, values)?);
+                }
+                rows.push(JacobianRow::Symbolic(row));
+            } else {
+                let program = bytecode::compile::<Dual>(expr, vars)?;
+                let args: Vec<Dual> = program.slots()
+                    .iter()
+                    .map(|s| Dual::constant(*values.get(s).unwrap_or(&0.0)))
+                    .collect();
+                rows.push(JacobianRow::Automatic(program.clone(), Machine::new(&program), args));
+            }
+        }
+        Ok(System { equations, rows, n: vars.len() })
+    }
+
+    pub fn residuals(&mut self, x: &[f64]) -> Vec<f64> {
+        self.equations.iter_mut().map(|f| f.eval(x)).collect()
+    }
+
+    pub fn jacobian(&mut self, x: &[f64]) -> Matrix {
+        let n = self.n;
+        self.rows
+            .iter_mut()
+            .map(|row| {
+                match *row {
+                    JacobianRow::Symbolic(ref mut partials) => {
+                        partials.iter_mut().map(|d| d.eval(x)).collect()
+                    }
+                    JacobianRow::Automatic(ref program, ref mut machine, ref mut args) => {
+                        (0..n)
+                            .map(|j| {
+                                for (i, xi) in x.iter().enumerate() {
+                                    args[i] = Dual::new(*xi, if i == j { 1.0 } else { 0.0 });
+                                }
+                                machine.run(program, args).deriv
+                            })
+                            .collect()
+                    }
+                }
+            })
+            .collect()
+    }
+}
+
+fn merit(f: &[f64]) -> f64 {
+    0.5 * linalg::dot(f, f)
+}
+
+fn is_finite(v: &[f64]) -> bool {
+    v.iter().all(|x| x.is_finite())
+}
+
+// Solves F(x) = 0 for as many equations as unknowns with Newton's method and
+// a backtracking line search on |F|^2. When the Jacobian turns singular or
+// the line search stalls, Levenberg-Marquardt takes over from the best point
+// so far. Non-square systems go straight to Levenberg-Marquardt, which then
+// finds a least squares solution.
+pub fn solve_system(exprs: &[Expression],
+                    vars: &[String],
+                    guess: &[f64],
+                    values: &HashMap<String, f64>)
+                    -> Result<SystemSolution, String> {
+    if vars.len() != guess.len() {
+        return Err(format!("{} variables but {} starting values", vars.len(), guess.len()));
+    }
+    if exprs.is_empty() || vars.is_empty() {
+        return Err("Nothing to solve".to_owned());
+    }
+    let mut system = System::new(exprs, vars, values)?;
+    let f0 = system.residuals(guess);
+    if !is_finite(&f0) {
+        return Err("The equations are undefined at the starting point".to_owned());
+    }
+    if exprs.len() == vars.len() {
+        let newton = newton(&mut system, guess.to_vec(), f0);
+        if newton.converged {
+            return Ok(newton);
+        }
+        let iterations = newton.iterations;
+        let mut lm = levenberg_marquardt(&mut system, newton.x, newton.residuals);
+        lm.iterations += iterations;
+        return Ok(lm);
+    }
+    Ok(levenberg_marquardt(&mut system, guess.to_vec(), f0))
+}
+
+fn newton(system: &mut System, x: Vec<f64>, f: Vec<f64>) -> SystemSolution {
+    let mut x = x;
+    let mut f = f;
+    let mut iterations = 0;
+    while iterations < MAX_ITERATIONS {
+        if linalg::norm_inf(&f) <= TOLERANCE {
+            break;
+        }
+        iterations += 1;
+        let jacobian = system.jacobian(&x);
+        let minus_f: Vec<f64> = f.iter().map(|v| -v).collect();
+        let step = match linalg::solve_linear(&jacobian, &minus_f) {
+            Some(step) if is_finite(&step) => step,
+            _ => break,
+        };
+        // Armijo condition on phi = |F|^2 / 2, whose slope along a Newton
+        // step is -2 phi.
+        let phi = merit(&f);
+        let mut t = 1.0;
+        let mut accepted = None;
+        while t >= 1e-10 {
+            let trial: Vec<f64> = x.iter().zip(&step).map(|(xi, si)| xi + t * si).collect();
+            let f_trial = system.residuals(&trial);
+            if is_finite(&f_trial) && merit(&f_trial) <= (1.0 - 2e-4 * t) * phi {
+                accepted = Some((trial, f_trial));
+                break;
+            }
+            t *= 0.5;
+        }
+        let (next, f_next) = match accepted {
+            Some(point) => point,
+            None => break,
+        };
+        let moved = linalg::norm_inf(&step) * t;
+        x = next;
+        f = f_next;
+        if moved <= f64::EPSILON * linalg::norm_inf(&x).max(1.0) {
+            break;
+        }
+    }
+    SystemSolution {
+        converged: linalg::norm_inf(&f) <= ACCEPTED,
+        x,
+        residuals: f,
+        iterations,
+        method: Method::Newton,
+    }
+}
+
+// Levenberg-Marquardt with Nielsen's damping update, minimizing |F|^2.
+fn levenberg_marquardt(system: &mut System, x: Vec<f64>, f: Vec<f64>) -> SystemSolution {
+    let mut x = x;
+    let mut f = f;
+    let mut jacobian = system.jacobian(&x);
+    let mut jtj = linalg::gram(&jacobian);
+    let mut gradient = linalg::mat_t_vec(&jacobian, &f);
+    let mut mu = 1e-3 * (0..jtj.len()).map(|i| jtj[i][i]).fold(0.0, f64::max).max(1e-12);
+    let mut nu = 2.0;
+    let mut iterations = 0;
+    while iterations < MAX_ITERATIONS {
+        if linalg::norm_inf(&f) <= TOLERANCE || linalg::norm_inf(&gradient) <= 1e-15 {
+            break;
+        }
+        iterations += 1;
+        let mut damped = jtj.clone();
+        for (i, row) in damped.iter_mut().enumerate() {
+            row[i] += mu;
+        }
+        let minus_g: Vec<f64> = gradient.iter().map(|v| -v).collect();
+        let step = match linalg::solve_linear(&damped, &minus_g) {
+            Some(step) if is_finite(&step) => step,
+            _ => {
+                mu *= nu;
+                nu *= 2.0;
+                continue;
+            }
+        };
+        if linalg::norm(&step) <= f64::EPSILON * (linalg::norm(&x) + f64::EPSILON) {
+            break;
+        }
+        let trial: Vec<f64> = x.iter().zip(&step).map(|(xi, si)| xi + si).collect();
+        let f_trial = system.residuals(&trial);
+        // Gain ratio of the actual to the predicted decrease.
+        let predicted = 0.5 * linalg::dot(&step, &step.iter()
+            .zip(&minus_g)
+            .map(|(s, g)| mu * s + g)
+            .collect::<Vec<f64>>());
+        let actual = merit(&f) - merit(&f_trial);
+        let rho = if is_finite(&f_trial) && predicted > 0.0 { actual / predicted } else { -1.0 };
+        if rho > 0.0 {
+            x = trial;
+            f = f_trial;
+            jacobian = system.jacobian(&x);
+            jtj = linalg::gram(&jacobian);
+            gradient = linalg::mat_t_vec(&jacobian, &f);
+            mu *= (1.0f64 / 3.0).max(1.0 - (2.0 * rho - 1.0).powi(3));
+            nu = 2.0;
+        } else {
+            mu *= nu;
+            nu *= 2.0;
+            if !mu.is_finite() {
+                break;
+            }
+        }
+    }
+    SystemSolution {
+        converged: linalg::norm_inf(&f) <= ACCEPTED,
+        x,
+        residuals: f,
+        iterations,
+        method: Method::LevenbergMarquardt,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use expression::parse::parse;
+
+    fn solve(exprs: &[&str], vars: &[&str], guess: &[f64]) -> Result<SystemSolution, String> {
+        let exprs: Vec<Expression> = exprs.iter().map(|e| parse(e)).collect();
+        let vars: Vec<String> = vars.iter().map(|v| v.to_string()).collect();
+        solve_system(&exprs, &vars, guess, &HashMap::new())
+    }
+
+    #[test]
+    fn newton_finds_intersections() {
+        let found = solve(&["x^2+y^2-4", "x*y-1"], &["x", "y"], &[2.0, 0.5]).unwrap();
+        assert!(found.converged);
+        assert_eq!(found.method, Method::Newton);
+        assert!(found.residual_norm() <= 1e-12);
+        let (x, y) = (found.x[0], found.x[1]);
+        assert!((x - (2.0 + 3.0f64.sqrt()).sqrt()).abs() <= 1e-12);
+        assert!((x * y - 1.0).abs() <= 1e-12);
+    }
+
+    #[test]
+    fn automatic_jacobian_rows() {
+        // The derivative of a long product is much longer than the product,
+        // so this row is differentiated with dual numbers instead.
+        let factors: Vec<String> = (1..41).map(|k| format!("(1+(x-y)/{})", k)).collect();
+        let product = format!("{}-1", factors.join("*"));
+        let exprs = [parse(&product), parse("x+y-2")];
+        let vars = ["x".to_owned(), "y".to_owned()];
+        let system = System::new(&exprs, &vars, &HashMap::new()).unwrap();
+        match system.rows[0] {
+            JacobianRow::Automatic(..) => {}
+            JacobianRow::Symbolic(_) => panic!("the product was differentiated symbolically"),
+        }
+        let found = solve(&[&product, "x+y-2"], &["x", "y"], &[1.2, 0.7]).unwrap();
+        assert!(found.converged);
+        assert!((found.x[0] - 1.0).abs() <= 1e-12 && (found.x[1] - 1.0).abs() <= 1e-12);
+    }
+
+    #[test]
+    fn least_squares_for_overdetermined_systems() {
+        let found = solve(&["x-1", "x-3", "y-2"], &["x", "y"], &[0.0, 0.0]).unwrap();
+        assert_eq!(found.method, Method::LevenbergMarquardt);
+        assert!(!found.converged);
+        assert!((found.x[0] - 2.0).abs() <= 1e-8);
+        assert!((found.x[1] - 2.0).abs() <= 1e-8);
+        assert!((found.residual_norm() - 2.0f64.sqrt()).abs() <= 1e-8);
+    }
+
+    #[test]
+    fn no_real_solution_is_not_converged() {
+        let found = solve(&["x^2+1"], &["x"], &[1.0]).unwrap();
+        assert!(!found.converged);
+        assert_eq!(found.method, Method::LevenbergMarquardt);
+        assert!(found.residual_norm() >= 1.0 - 1e-9);
+    }
+
+    #[test]
+    fn bad_input_is_an_error() {
+        assert!(solve(&["x-1"], &["x", "y"], &[0.0]).is_err());
+        assert!(solve(&[], &[], &[]).is_err());
+        assert!(solve(&["1/x"], &["x"], &[0.0]).is_err());
+    }
+}
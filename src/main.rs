@@ -1,32 +1,32 @@
 #![allow(dead_code)]
 
-extern crate regex;
-use regex::Regex;
+extern crate rust_calculus;
 
 use std::io;
 use std::io::Write;
 
 use std::collections::HashMap;
 
-mod expression;
+use rust_calculus::expression;
 
 type Expression = expression::Expression;
 
+use expression::command;
 use expression::eval::eval_postfix_expr;
-use expression::parse::parse_input;
+use expression::parse::{self, parse_input};
 
-fn strip_white_space(input: &String) -> String {
+fn strip_white_space(input: &str) -> String {
     input.split_whitespace().collect::<Vec<&str>>().join("")
 }
 
 fn main() {
-    let numeric_regex: Regex = Regex::new(r"\d+\.\d+|\d+").unwrap();
-    let function_regex: Regex = Regex::new(r"[a-zA-Z]{2,}").unwrap();
+    let (numeric_regex, function_regex) = parse::token_regexes();
     println!("Welcome to Rust-Calculus!");
     println!("To evaluate an expression, simply type one in and hit RETURN.");
     println!("To set a variable, simply type VAR_NAME=EXPRESSION and hit RETURN.");
     println!("To define a function, simply type FUNC_NAME=EXPRESSION and hit RETURN.");
-    println!("Valid commands are: sym_int, int, sym_def, and def.");
+    println!("pi and e are constants and cannot be used as variable names.");
+    println!("Valid commands are: {}.", command::COMMANDS.join(", "));
     println!("Type 'quit' to exit.");
     let mut input = String::new();
     let stdin = io::stdin();
@@ -39,7 +39,7 @@ fn main() {
         stdout.flush().ok();
         if let Err(x) = stdin.read_line(&mut input) {
             println!("There was a problem reading stdin: {:?}", x);
-            print!("Exiting...");;
+            print!("Exiting...");
             break;
         }
         input = strip_white_space(&input).to_lowercase();
@@ -47,8 +47,23 @@ fn main() {
             println!("Exiting...");
             break;
         }
+        let context = command::Context {
+            numeric_regex: &numeric_regex,
+            function_regex: &function_regex,
+            variables: &variables,
+        };
+        if let Some(result) = command::run_command(&input, &context) {
+            match result {
+                Ok(output) => println!("{}", output),
+                Err(message) => {
+                    println!("Encountered an error while running {}: {}", input, message);
+                    println!("Try Again...(type 'quit' to exit)");
+                }
+            }
+            continue;
+        }
         let (var, expr) = parse_input(&input, &numeric_regex, &function_regex);
-        if var.len() > 0 {
+        if !var.is_empty() {
             var_expr = true;
             if !variables.contains_key(&var) {
                 variables.insert(var.clone(), 0.0);
@@ -56,20 +71,21 @@ fn main() {
         } else {
             var_expr = false;
         }
-        if expr.is_ok() {
-            let my_expression = expr.unwrap();
-            let result = eval_postfix_expr(&my_expression, &variables);
-            if var_expr {
-                variables.insert(var.clone(), result);
-                println!("{} = {}", &var, &result);
-            } else {
-                println!("{}", &result);
+        match expr {
+            Ok(my_expression) => {
+                let result = eval_postfix_expr(&my_expression, &variables);
+                if var_expr {
+                    variables.insert(var.clone(), result);
+                    println!("{} = {}", &var, &result);
+                } else {
+                    println!("{}", &result);
+                }
+            }
+            Err(message) => {
+                println!("Encountered an error while parsing: {:?}", message);
+                println!("Try Again...(type 'quit' to exit)");
+                continue;
             }
-        } else {
-            println!("Encountered an error while parsing: {:?}",
-                     expr.unwrap_err());
-            println!("Try Again...(type 'quit' to exit)");
-            continue;
         }
     }
 }